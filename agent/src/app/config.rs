@@ -6,6 +6,46 @@ fn default_bind_port() -> u16 {
     3000
 }
 
+fn default_sample_interval_secs() -> u64 {
+    1
+}
+
+fn default_channel_capacity() -> usize {
+    16
+}
+
+fn default_allowed_log_paths() -> Vec<String> {
+    vec!["/var/log/**".to_string()]
+}
+
+fn default_journal_directories() -> Vec<String> {
+    vec!["/var/log/journal".to_string(), "/run/log/journal".to_string()]
+}
+
+fn default_file_roots() -> Vec<String> {
+    vec!["/etc".to_string(), "/var/log".to_string()]
+}
+
+fn default_backup_dir() -> String {
+    "/var/lib/guardian/backups".to_string()
+}
+
+fn default_backup_keep() -> usize {
+    10
+}
+
+fn default_max_upload_bytes() -> u64 {
+    64 * 1024 * 1024
+}
+
+fn default_upload_timeout_secs() -> u64 {
+    600
+}
+
+fn default_shell() -> String {
+    "/bin/bash".to_string()
+}
+
 fn default_log_level() -> String {
     "info".to_string()
 }
@@ -24,11 +64,124 @@ impl Default for ServerConfig {
     }
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct SamplerConfig {
+    #[serde(default = "default_sample_interval_secs")]
+    pub interval_secs: u64,
+
+    #[serde(default = "default_channel_capacity")]
+    pub channel_capacity: usize,
+}
+
+impl Default for SamplerConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: default_sample_interval_secs(),
+            channel_capacity: default_channel_capacity(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ProcessesConfig {
+    #[serde(default)]
+    pub deny_names: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SystemdConfig {
+    #[serde(default)]
+    pub allowed_units: Vec<String>,
+
+    pub bus_address: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LogsConfig {
+    #[serde(default = "default_allowed_log_paths")]
+    pub allowed_paths: Vec<String>,
+
+    #[serde(default = "default_journal_directories")]
+    pub journal_directories: Vec<String>,
+}
+
+impl Default for LogsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_paths: default_allowed_log_paths(),
+            journal_directories: default_journal_directories(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FilesConfig {
+    #[serde(default = "default_file_roots")]
+    pub roots: Vec<String>,
+
+    #[serde(default = "default_backup_dir")]
+    pub backup_dir: String,
+
+    #[serde(default = "default_backup_keep")]
+    pub backup_keep: usize,
+
+    #[serde(default = "default_max_upload_bytes")]
+    pub max_upload_bytes: u64,
+
+    /// 書き込みのリクエストは本文を受け取り終えるまで返せないので、他より長い制限時間を使う
+    #[serde(default = "default_upload_timeout_secs")]
+    pub upload_timeout_secs: u64,
+}
+
+impl Default for FilesConfig {
+    fn default() -> Self {
+        Self {
+            roots: default_file_roots(),
+            backup_dir: default_backup_dir(),
+            backup_keep: default_backup_keep(),
+            max_upload_bytes: default_max_upload_bytes(),
+            upload_timeout_secs: default_upload_timeout_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TerminalConfig {
+    #[serde(default = "default_shell")]
+    pub shell: String,
+}
+
+impl Default for TerminalConfig {
+    fn default() -> Self {
+        Self {
+            shell: default_shell(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     #[serde(default)]
     pub server: ServerConfig,
 
+    #[serde(default)]
+    pub sampler: SamplerConfig,
+
+    #[serde(default)]
+    pub processes: ProcessesConfig,
+
+    #[serde(default)]
+    pub systemd: SystemdConfig,
+
+    #[serde(default)]
+    pub logs: LogsConfig,
+
+    #[serde(default)]
+    pub files: FilesConfig,
+
+    #[serde(default)]
+    pub terminal: TerminalConfig,
+
     #[serde(rename = "log_level", default = "default_log_level")]
     pub log_level: String,
 }
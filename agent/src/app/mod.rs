@@ -1,3 +1,5 @@
 pub mod config;
 pub mod runner;
+pub mod sampler;
 pub mod shutdown;
+pub mod state;
@@ -1,4 +1,4 @@
-use crate::app::{config::Config, shutdown::shutdown_signal};
+use crate::app::{config::Config, sampler::Sampler, shutdown::shutdown_signal, state::AppState};
 use std::{
     net::{Ipv4Addr, SocketAddr},
     time::Duration,
@@ -10,7 +10,7 @@ use axum::{
     middleware::Next,
     response::{IntoResponse, Response},
     Router,
-    routing::get,
+    routing::{get, post, put},
     http::StatusCode,
 };
 use indicatif::{ProgressBar, ProgressStyle};
@@ -39,24 +39,58 @@ impl App {
         );
         pb.set_message("Starting...");
 
+        let sampler = Sampler::new(self.config.sampler.channel_capacity);
+        let sampler_handle = sampler.spawn(self.config.sampler.interval_secs);
+
+        let state = AppState {
+            config: self.config.clone(),
+            sampler,
+        };
+
         let api_router = Router::new()
             .route("/health", get(StatusCode::OK))
             .route("/metrics", get(crate::handles::metrics::sse_handler))
-            .route("/info", get(crate::handles::info::get_server_information));
+            .route("/metrics/stats", get(crate::handles::metrics::get_sampler_stats))
+            .route("/info", get(crate::handles::info::get_server_information))
+            .route("/exec", post(crate::handles::exec::run_command))
+            .route("/files", get(crate::handles::files::list_directory))
+            .route("/files/stat", get(crate::handles::files::stat_file))
+            .route("/files/download", get(crate::handles::files::download_file))
+            .route("/logs/tail", get(crate::handles::logs::tail_log))
+            .route("/logs/search", get(crate::handles::logs::search_log))
+            .route("/logs/journal", get(crate::handles::logs::read_journal))
+            .route("/packages", get(crate::handles::packages::get_packages))
+            .route("/packages/updates", get(crate::handles::packages::get_package_updates))
+            .route("/packages/jobs", post(crate::handles::packages::run_package_job))
+            .route("/processes", get(crate::handles::processes::get_processes))
+            .route("/processes/{pid}/signal", post(crate::handles::processes::signal_process))
+            .route("/services", get(crate::handles::services::get_services))
+            .route("/services/{name}", get(crate::handles::services::get_service))
+            .route("/services/{name}/action", post(crate::handles::services::run_service_action))
+            .route("/terminal", get(crate::handles::terminal::open_terminal))
+            .route("/wol", post(crate::handles::wol::relay_magic_packet))
+            .layer(TimeoutLayer::with_status_code(StatusCode::REQUEST_TIMEOUT, Duration::from_secs(10)));
+        // アップロードは本文を受け取り終えるまで応答できないので、別の制限時間にする
+        let upload_router = Router::new()
+            .route("/files/content", put(crate::handles::files::write_file))
+            .layer(TimeoutLayer::with_status_code(
+                StatusCode::REQUEST_TIMEOUT,
+                Duration::from_secs(self.config.files.upload_timeout_secs.max(1)),
+            ));
 
         let app = Router::new()
-            .nest("/api/agent/v1", api_router)
+            .nest("/api/agent/v1", api_router.merge(upload_router))
             .layer((
                 TraceLayer::new_for_http(),
-                TimeoutLayer::with_status_code(StatusCode::REQUEST_TIMEOUT, Duration::from_secs(10)),
                 axum::middleware::from_fn(whitelist)
-            ));
+            ))
+            .with_state(state);
 
         let listener =
-            TcpListener::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, self.config.server.port.clone()))).await?;
+            TcpListener::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, self.config.server.port))).await?;
 
         pb.finish_and_clear();
-        println!("{}", format!("{} Ready!\n", "✔".green()));
+        println!("{} Ready!\n", "✔".green());
 
         axum::serve(
             listener,
@@ -66,6 +100,7 @@ impl App {
             .await
             .context("failed to start server")?;
 
+        sampler_handle.abort();
         info!("Server shutting down gracefully.");
         Ok(())
     }
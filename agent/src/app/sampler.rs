@@ -0,0 +1,91 @@
+use crate::collectors::Collector;
+use common::agent::metrics::SamplerStats;
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::{
+    sync::broadcast,
+    task::JoinHandle,
+    time::{Instant, MissedTickBehavior},
+};
+
+/// Samples `ServerMetrics` once per interval and fans the serialized snapshot out to every subscriber.
+pub struct Sampler {
+    tx: broadcast::Sender<Arc<str>>,
+    dropped_messages: AtomicU64,
+}
+
+impl Sampler {
+    pub fn new(capacity: usize) -> Arc<Self> {
+        let (tx, _) = broadcast::channel(capacity);
+        Arc::new(Self {
+            tx,
+            dropped_messages: AtomicU64::new(0),
+        })
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<str>> {
+        self.tx.subscribe()
+    }
+
+    pub fn record_dropped(&self, count: u64) {
+        self.dropped_messages.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> SamplerStats {
+        SamplerStats {
+            subscribers: self.tx.receiver_count() as u64,
+            dropped_messages: self.dropped_messages.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn spawn(self: &Arc<Self>, interval_secs: u64) -> JoinHandle<()> {
+        let sampler = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut collector = Collector::new();
+            let period = Duration::from_secs(interval_secs.max(1));
+
+            // キリの良いタイミング（小数点以下が0）から計測を開始する
+            let mut interval = tokio::time::interval_at(Instant::now() + until_next_second(), period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+            loop {
+                interval.tick().await;
+
+                let metrics = match collector.collect().await {
+                    Ok(metrics) => metrics,
+                    Err(e) => {
+                        tracing::error!("Failed to collect metrics: {}", e);
+                        continue;
+                    }
+                };
+
+                if sampler.tx.receiver_count() == 0 {
+                    continue;
+                }
+
+                match serde_json::to_string(&metrics) {
+                    Ok(msg) => {
+                        tracing::debug!("Sending JSON: {}", msg);
+                        let _ = sampler.tx.send(Arc::from(msg));
+                    }
+                    Err(e) => {
+                        tracing::error!("Failed to serialize JSON: {}", e);
+                    }
+                }
+            }
+        })
+    }
+}
+
+fn until_next_second() -> Duration {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Duration::from_secs(1) - Duration::from_nanos(now.subsec_nanos() as u64)
+}
@@ -0,0 +1,8 @@
+use crate::app::{config::Config, sampler::Sampler};
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Config,
+    pub sampler: Arc<Sampler>,
+}
@@ -0,0 +1,112 @@
+use common::agent::metrics::{Cpu, CpuBreakdown, CpuCore};
+
+use anyhow::{Context, Result};
+use sysinfo::System;
+
+const STAT_PATH: &str = "/proc/stat";
+
+#[derive(Clone, Copy, Default)]
+struct CpuTimes {
+    user: u64,
+    nice: u64,
+    system: u64,
+    idle: u64,
+    iowait: u64,
+    irq: u64,
+    softirq: u64,
+    steal: u64,
+}
+
+impl CpuTimes {
+    // guest / guest_nice は user / nice に含まれているため合計しない
+    fn total(&self) -> u64 {
+        self.user + self.nice + self.system + self.idle + self.iowait + self.irq + self.softirq + self.steal
+    }
+}
+
+/// `/proc/stat` の前回値を保持し、差分から内訳を求める
+pub struct CpuCollector {
+    previous: Option<CpuTimes>,
+}
+
+impl CpuCollector {
+    pub fn new() -> Self {
+        Self { previous: None }
+    }
+
+    pub async fn get_cpu_metrics(&mut self, sys: &mut System) -> Result<Cpu> {
+        sys.refresh_cpu_all();
+        let usage_percent = sys.cpus().iter().map(|cpu| cpu.cpu_usage()).sum::<f32>() / sys.cpus().len() as f32;
+        let cores = System::physical_core_count().context("failed to get physical core count")? as u64;
+        let threads = sys.cpus().len() as u64;
+
+        let per_core = sys
+            .cpus()
+            .iter()
+            .map(|cpu| CpuCore {
+                name: cpu.name().to_string(),
+                usage_percent: cpu.cpu_usage(),
+                frequency_mhz: cpu.frequency(),
+            })
+            .collect::<Vec<CpuCore>>();
+
+        let breakdown = self.get_cpu_breakdown().await?;
+
+        Ok(Cpu {
+            usage_percent,
+            cores,
+            threads,
+            breakdown,
+            per_core,
+        })
+    }
+
+    async fn get_cpu_breakdown(&mut self) -> Result<CpuBreakdown> {
+        let content = tokio::fs::read_to_string(STAT_PATH)
+            .await
+            .with_context(|| format!("failed to read {}", STAT_PATH))?;
+        let current = parse_stat(&content).context("aggregate cpu line not found in /proc/stat")?;
+        let previous = self.previous.replace(current).unwrap_or_default();
+
+        let total = current.total().saturating_sub(previous.total());
+        let percent = |cur: u64, prev: u64| {
+            if total == 0 {
+                0.0
+            } else {
+                cur.saturating_sub(prev) as f32 * 100.0 / total as f32
+            }
+        };
+
+        Ok(CpuBreakdown {
+            user_percent: percent(current.user, previous.user),
+            nice_percent: percent(current.nice, previous.nice),
+            system_percent: percent(current.system, previous.system),
+            idle_percent: percent(current.idle, previous.idle),
+            iowait_percent: percent(current.iowait, previous.iowait),
+            irq_percent: percent(current.irq, previous.irq),
+            softirq_percent: percent(current.softirq, previous.softirq),
+            steal_percent: percent(current.steal, previous.steal),
+        })
+    }
+}
+
+fn parse_stat(content: &str) -> Option<CpuTimes> {
+    let line = content.lines().find(|line| line.starts_with("cpu "))?;
+    let fields = line
+        .split_whitespace()
+        .skip(1)
+        .map(|v| v.parse::<u64>().unwrap_or(0))
+        .collect::<Vec<u64>>();
+    let field = |i: usize| fields.get(i).copied().unwrap_or(0);
+
+    Some(CpuTimes {
+        user: field(0),
+        nice: field(1),
+        system: field(2),
+        idle: field(3),
+        iowait: field(4),
+        irq: field(5),
+        softirq: field(6),
+        steal: field(7),
+    })
+}
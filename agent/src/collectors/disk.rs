@@ -0,0 +1,59 @@
+use common::agent::metrics::{Disk, Inode};
+use std::{ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt, path::Path};
+
+use anyhow::{Context, Result};
+use sysinfo::Disks;
+
+pub async fn get_disk_metrics() -> Result<Vec<Disk>> {
+    let mut disks = Disks::new();
+    disks.refresh(true);
+
+    let storage = disks
+        .iter()
+        .map(|disk| Disk {
+            mount: disk.mount_point().to_string_lossy().to_string(),
+            total_bytes: disk.total_space(),
+            used_bytes: disk.total_space() - disk.available_space(),
+            free_bytes: disk.available_space(),
+            device: disk.name().to_string_lossy().to_string(),
+        })
+        .collect::<Vec<Disk>>();
+    Ok(storage)
+}
+
+pub async fn get_inode_metrics() -> Result<Vec<Inode>> {
+    let mut disks = Disks::new();
+    disks.refresh(true);
+
+    let inodes = disks
+        .iter()
+        .filter_map(|disk| {
+            let mount = disk.mount_point();
+            match statvfs(mount) {
+                Ok(stat) => Some(Inode {
+                    mount: mount.to_string_lossy().to_string(),
+                    total: stat.f_files,
+                    used: stat.f_files.saturating_sub(stat.f_ffree),
+                    free: stat.f_favail,
+                }),
+                Err(e) => {
+                    tracing::warn!("Failed to statvfs {}: {}", mount.display(), e);
+                    None
+                }
+            }
+        })
+        .collect::<Vec<Inode>>();
+    Ok(inodes)
+}
+
+fn statvfs(path: &Path) -> Result<libc::statvfs> {
+    let c_path = CString::new(path.as_os_str().as_bytes()).context("mount point contains a NUL byte")?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: c_path は NUL 終端された有効な文字列で、stat は書き込み先として十分な領域を持つ
+    let ret = unsafe { libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    // SAFETY: statvfs が成功した場合、stat は初期化済み
+    Ok(unsafe { stat.assume_init() })
+}
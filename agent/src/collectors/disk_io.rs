@@ -0,0 +1,127 @@
+use common::agent::metrics::DiskIo;
+use std::{collections::HashMap, time::Instant};
+
+use anyhow::{Context, Result};
+
+const DISKSTATS_PATH: &str = "/proc/diskstats";
+const SECTOR_SIZE: u64 = 512;
+
+#[derive(Clone, Copy)]
+struct Counters {
+    reads_completed: u64,
+    sectors_read: u64,
+    read_ticks_ms: u64,
+    writes_completed: u64,
+    sectors_written: u64,
+    write_ticks_ms: u64,
+    io_ticks_ms: u64,
+    weighted_io_ticks_ms: u64,
+}
+
+/// `/proc/diskstats` の前回値との差分からブロックデバイスごとの I/O を求める
+pub struct DiskIoCollector {
+    previous: HashMap<String, Counters>,
+    sampled_at: Option<Instant>,
+}
+
+impl DiskIoCollector {
+    pub fn new() -> Self {
+        Self {
+            previous: HashMap::new(),
+            sampled_at: None,
+        }
+    }
+
+    pub async fn get_disk_io_metrics(&mut self) -> Result<Vec<DiskIo>> {
+        let content = tokio::fs::read_to_string(DISKSTATS_PATH)
+            .await
+            .with_context(|| format!("failed to read {}", DISKSTATS_PATH))?;
+        let current = parse_diskstats(&content);
+        let now = Instant::now();
+        let elapsed = self
+            .sampled_at
+            .map(|t| now.duration_since(t).as_secs_f64())
+            .unwrap_or(0.0);
+
+        let mut metrics = current
+            .iter()
+            .map(|(device, cur)| {
+                let prev = self.previous.get(device).copied().unwrap_or(*cur);
+                let rate = |cur: u64, prev: u64| {
+                    if elapsed > 0.0 {
+                        cur.saturating_sub(prev) as f64 / elapsed
+                    } else {
+                        0.0
+                    }
+                };
+                // iostat の r_await / w_await と同じく、費やした時間を完了した回数で割る
+                let await_ms = |ticks: u64, prev_ticks: u64, count: u64, prev_count: u64| {
+                    let count = count.saturating_sub(prev_count);
+                    if count > 0 {
+                        ticks.saturating_sub(prev_ticks) as f64 / count as f64
+                    } else {
+                        0.0
+                    }
+                };
+                let elapsed_ms = elapsed * 1000.0;
+                DiskIo {
+                    device: device.clone(),
+                    read_bytes_per_sec: rate(cur.sectors_read, prev.sectors_read) * SECTOR_SIZE as f64,
+                    write_bytes_per_sec: rate(cur.sectors_written, prev.sectors_written) * SECTOR_SIZE as f64,
+                    read_iops: rate(cur.reads_completed, prev.reads_completed),
+                    write_iops: rate(cur.writes_completed, prev.writes_completed),
+                    read_await_ms: await_ms(cur.read_ticks_ms, prev.read_ticks_ms, cur.reads_completed, prev.reads_completed),
+                    write_await_ms: await_ms(cur.write_ticks_ms, prev.write_ticks_ms, cur.writes_completed, prev.writes_completed),
+                    avg_queue_depth: if elapsed_ms > 0.0 {
+                        cur.weighted_io_ticks_ms.saturating_sub(prev.weighted_io_ticks_ms) as f64 / elapsed_ms
+                    } else {
+                        0.0
+                    },
+                    utilization_percent: if elapsed_ms > 0.0 {
+                        (cur.io_ticks_ms.saturating_sub(prev.io_ticks_ms) as f64 * 100.0 / elapsed_ms).min(100.0)
+                    } else {
+                        0.0
+                    },
+                }
+            })
+            .collect::<Vec<DiskIo>>();
+        metrics.sort_by(|a, b| a.device.cmp(&b.device));
+
+        self.previous = current;
+        self.sampled_at = Some(now);
+        Ok(metrics)
+    }
+}
+
+fn parse_diskstats(content: &str) -> HashMap<String, Counters> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            let device = columns.nth(2)?.to_string();
+            let fields = columns
+                .map(|v| v.parse::<u64>().unwrap_or(0))
+                .collect::<Vec<u64>>();
+            if fields.len() < 11 {
+                return None;
+            }
+            // 一度も使われていない loop / ram デバイスなどは除外する
+            if fields[0] == 0 && fields[4] == 0 {
+                return None;
+            }
+            Some((
+                device,
+                Counters {
+                    reads_completed: fields[0],
+                    sectors_read: fields[2],
+                    read_ticks_ms: fields[3],
+                    writes_completed: fields[4],
+                    sectors_written: fields[6],
+                    write_ticks_ms: fields[7],
+                    io_ticks_ms: fields[9],
+                    weighted_io_ticks_ms: fields[10],
+                },
+            ))
+        })
+        .collect()
+}
@@ -0,0 +1,13 @@
+use common::agent::metrics::Memory;
+
+use anyhow::Result;
+use sysinfo::System;
+
+pub async fn get_memory_metrics(sys: &mut System) -> Result<Memory> {
+    sys.refresh_memory();
+    Ok(Memory {
+        total_bytes: sys.total_memory(),
+        used_bytes: sys.used_memory(),
+        free_bytes: sys.free_memory(),
+    })
+}
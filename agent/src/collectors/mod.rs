@@ -0,0 +1,56 @@
+pub mod cpu;
+pub mod disk;
+pub mod disk_io;
+pub mod memory;
+pub mod network;
+pub mod system;
+
+use common::agent::metrics::ServerMetrics;
+
+use anyhow::Result;
+use sysinfo::System;
+
+pub struct Collector {
+    sys: System,
+    cpu: cpu::CpuCollector,
+    disk_io: disk_io::DiskIoCollector,
+    network: network::NetworkCollector,
+}
+
+impl Collector {
+    pub fn new() -> Self {
+        Self {
+            sys: System::new_all(),
+            cpu: cpu::CpuCollector::new(),
+            disk_io: disk_io::DiskIoCollector::new(),
+            network: network::NetworkCollector::new(),
+        }
+    }
+
+    pub async fn collect(&mut self) -> Result<ServerMetrics> {
+        let cpu_metrics = self.cpu.get_cpu_metrics(&mut self.sys).await?;
+        let memory_metrics = memory::get_memory_metrics(&mut self.sys).await?;
+        let disk_metrics = disk::get_disk_metrics().await?;
+        let disk_io_metrics = self.disk_io.get_disk_io_metrics().await?;
+        let inode_metrics = disk::get_inode_metrics().await?;
+        let network_metrics = self.network.get_network_metrics().await?;
+        let load_average = system::get_load_average().await?;
+        let logged_in_users = system::get_logged_in_users().await.unwrap_or_else(|e| {
+            tracing::warn!("Failed to read utmp: {}", e);
+            0
+        });
+
+        Ok(ServerMetrics {
+            cpu: cpu_metrics,
+            memory: memory_metrics,
+            disk: disk_metrics,
+            disk_io: disk_io_metrics,
+            inodes: inode_metrics,
+            network: network_metrics,
+            uptime_seconds: System::uptime(),
+            boot_time: System::boot_time(),
+            load_average,
+            logged_in_users,
+        })
+    }
+}
@@ -0,0 +1,106 @@
+use common::agent::metrics::Network;
+use std::{collections::HashMap, time::Instant};
+
+use anyhow::{Context, Result};
+
+const NET_DEV_PATH: &str = "/proc/net/dev";
+
+#[derive(Clone, Copy)]
+struct Counters {
+    rx_bytes: u64,
+    rx_packets: u64,
+    rx_errors: u64,
+    rx_dropped: u64,
+    tx_bytes: u64,
+    tx_packets: u64,
+    tx_errors: u64,
+    tx_dropped: u64,
+}
+
+/// 前回のサンプルとの差分から秒間レートを求める
+pub struct NetworkCollector {
+    previous: HashMap<String, Counters>,
+    sampled_at: Option<Instant>,
+}
+
+impl NetworkCollector {
+    pub fn new() -> Self {
+        Self {
+            previous: HashMap::new(),
+            sampled_at: None,
+        }
+    }
+
+    pub async fn get_network_metrics(&mut self) -> Result<Vec<Network>> {
+        let content = tokio::fs::read_to_string(NET_DEV_PATH)
+            .await
+            .with_context(|| format!("failed to read {}", NET_DEV_PATH))?;
+        let current = parse_net_dev(&content);
+        let now = Instant::now();
+        let elapsed = self
+            .sampled_at
+            .map(|t| now.duration_since(t).as_secs_f64())
+            .unwrap_or(0.0);
+
+        let mut metrics = current
+            .iter()
+            .map(|(name, cur)| {
+                let prev = self.previous.get(name).copied().unwrap_or(*cur);
+                let rate = |cur: u64, prev: u64| {
+                    if elapsed > 0.0 {
+                        cur.saturating_sub(prev) as f64 / elapsed
+                    } else {
+                        0.0
+                    }
+                };
+                Network {
+                    interface: name.clone(),
+                    rx_bytes_per_sec: rate(cur.rx_bytes, prev.rx_bytes),
+                    tx_bytes_per_sec: rate(cur.tx_bytes, prev.tx_bytes),
+                    rx_packets_per_sec: rate(cur.rx_packets, prev.rx_packets),
+                    tx_packets_per_sec: rate(cur.tx_packets, prev.tx_packets),
+                    rx_errors_per_sec: rate(cur.rx_errors, prev.rx_errors),
+                    tx_errors_per_sec: rate(cur.tx_errors, prev.tx_errors),
+                    rx_dropped_per_sec: rate(cur.rx_dropped, prev.rx_dropped),
+                    tx_dropped_per_sec: rate(cur.tx_dropped, prev.tx_dropped),
+                }
+            })
+            .collect::<Vec<Network>>();
+        metrics.sort_by(|a, b| a.interface.cmp(&b.interface));
+
+        self.previous = current;
+        self.sampled_at = Some(now);
+        Ok(metrics)
+    }
+}
+
+fn parse_net_dev(content: &str) -> HashMap<String, Counters> {
+    // 先頭2行はヘッダー
+    content
+        .lines()
+        .skip(2)
+        .filter_map(|line| {
+            let (name, rest) = line.split_once(':')?;
+            let fields = rest
+                .split_whitespace()
+                .map(|v| v.parse::<u64>().unwrap_or(0))
+                .collect::<Vec<u64>>();
+            if fields.len() < 16 {
+                return None;
+            }
+            Some((
+                name.trim().to_string(),
+                Counters {
+                    rx_bytes: fields[0],
+                    rx_packets: fields[1],
+                    rx_errors: fields[2],
+                    rx_dropped: fields[3],
+                    tx_bytes: fields[8],
+                    tx_packets: fields[9],
+                    tx_errors: fields[10],
+                    tx_dropped: fields[11],
+                },
+            ))
+        })
+        .collect()
+}
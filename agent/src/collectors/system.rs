@@ -0,0 +1,36 @@
+use common::agent::metrics::LoadAverage;
+
+use anyhow::Result;
+use sysinfo::System;
+
+const UTMP_PATH: &str = "/var/run/utmp";
+const UTMP_RECORD_SIZE: usize = 384;
+const UTMP_USER_OFFSET: usize = 44;
+const USER_PROCESS: i16 = 7;
+
+pub async fn get_load_average() -> Result<LoadAverage> {
+    let load = System::load_average();
+    Ok(LoadAverage {
+        one: load.one,
+        five: load.five,
+        fifteen: load.fifteen,
+    })
+}
+
+/// `who` と同様に utmp の USER_PROCESS レコードを数える
+pub async fn get_logged_in_users() -> Result<u32> {
+    let data = match tokio::fs::read(UTMP_PATH).await {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+
+    let count = data
+        .chunks_exact(UTMP_RECORD_SIZE)
+        .filter(|record| {
+            i16::from_ne_bytes([record[0], record[1]]) == USER_PROCESS
+                && record[UTMP_USER_OFFSET] != 0
+        })
+        .count();
+    Ok(count as u32)
+}
@@ -0,0 +1,164 @@
+pub mod write;
+
+use crate::app::config::FilesConfig;
+use common::agent::file::{FileEntry, FileKind};
+use std::{
+    collections::HashMap,
+    fs::{self, Metadata},
+    io,
+    os::unix::fs::MetadataExt,
+    path::{Component, Path, PathBuf},
+};
+
+use chrono::DateTime;
+use sysinfo::{Groups, Users};
+
+/// GUI が固まらないよう、1つのディレクトリで返すエントリー数の上限
+pub const MAX_ENTRIES: usize = 5000;
+
+pub enum FilePathError {
+    Invalid,
+    NotAllowed,
+    NotFound,
+    Io(io::Error),
+}
+
+impl FilePathError {
+    pub fn message(&self) -> String {
+        match self {
+            FilePathError::Invalid => "path must be absolute and must not contain `..`".to_string(),
+            FilePathError::NotAllowed => "this path is outside of the allowed roots".to_string(),
+            FilePathError::NotFound => "file not found".to_string(),
+            FilePathError::Io(e) => e.to_string(),
+        }
+    }
+}
+
+impl From<io::Error> for FilePathError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::NotFound => FilePathError::NotFound,
+            _ => FilePathError::Io(e),
+        }
+    }
+}
+
+/// パスを root 配下に閉じ込め、シンボリックリンクを解決した実体のパスを返す。
+/// `follow` が false の場合、末尾のシンボリックリンクはリンク自体を指したままにする
+pub async fn resolve_path(config: &FilesConfig, path: &str, follow: bool) -> Result<PathBuf, FilePathError> {
+    let path = Path::new(path);
+    if !path.is_absolute() || path.components().any(|c| c == Component::ParentDir) {
+        return Err(FilePathError::Invalid);
+    }
+
+    let resolved = match (follow, path.parent(), path.file_name()) {
+        (false, Some(parent), Some(name)) => tokio::fs::canonicalize(parent).await?.join(name),
+        _ => tokio::fs::canonicalize(path).await?,
+    };
+
+    // root 自体がシンボリックリンクの場合もあるので、解決後のパス同士で比べる
+    let mut allowed = false;
+    for root in &config.roots {
+        if let Ok(root) = tokio::fs::canonicalize(root).await
+            && resolved.starts_with(&root)
+        {
+            allowed = true;
+            break;
+        }
+    }
+    if !allowed {
+        return Err(FilePathError::NotAllowed);
+    }
+
+    Ok(resolved)
+}
+
+/// uid / gid からユーザー名・グループ名を引く
+pub struct Owners {
+    users: HashMap<u32, String>,
+    groups: HashMap<u32, String>,
+}
+
+impl Owners {
+    pub fn load() -> Self {
+        let users = Users::new_with_refreshed_list()
+            .iter()
+            .map(|user| (**user.id(), user.name().to_string()))
+            .collect();
+        let groups = Groups::new_with_refreshed_list()
+            .iter()
+            .map(|group| (**group.id(), group.name().to_string()))
+            .collect();
+        Self { users, groups }
+    }
+}
+
+pub fn file_entry(path: &Path, owners: &Owners) -> io::Result<FileEntry> {
+    let metadata = fs::symlink_metadata(path)?;
+    let file_type = metadata.file_type();
+    let kind = if file_type.is_symlink() {
+        FileKind::Symlink
+    } else if file_type.is_dir() {
+        FileKind::Directory
+    } else if file_type.is_file() {
+        FileKind::File
+    } else {
+        FileKind::Other
+    };
+    let symlink_target = match kind {
+        FileKind::Symlink => fs::read_link(path).ok().map(|target| target.to_string_lossy().to_string()),
+        _ => None,
+    };
+
+    Ok(FileEntry {
+        name: path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "/".to_string()),
+        path: path.to_string_lossy().to_string(),
+        kind,
+        size: metadata.len(),
+        mode: metadata.mode() & 0o7777,
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+        owner: owners.users.get(&metadata.uid()).cloned(),
+        group: owners.groups.get(&metadata.gid()).cloned(),
+        modified: DateTime::from_timestamp(metadata.mtime(), metadata.mtime_nsec() as u32),
+        symlink_target,
+        etag: file_type.is_file().then(|| etag(&metadata)),
+    })
+}
+
+/// inode・サイズ・更新時刻から作る。いずれかが変われば別の値になる
+pub fn etag(metadata: &Metadata) -> String {
+    let modified = metadata.mtime() as i128 * 1_000_000_000 + metadata.mtime_nsec() as i128;
+    format!("\"{:x}-{:x}-{:x}\"", metadata.ino(), metadata.len(), modified)
+}
+
+/// ディレクトリを先、その後ファイルを名前順に並べて返す。上限を超えた場合は `true` も返す
+pub fn list_directory(path: &Path) -> io::Result<(Vec<FileEntry>, bool)> {
+    let owners = Owners::load();
+    let mut entries = Vec::new();
+    let mut truncated = false;
+
+    for entry in fs::read_dir(path)? {
+        if entries.len() >= MAX_ENTRIES {
+            truncated = true;
+            break;
+        }
+        // 列挙中に削除されたエントリーは飛ばす
+        let Ok(entry) = entry else {
+            continue;
+        };
+        if let Ok(entry) = file_entry(&entry.path(), &owners) {
+            entries.push(entry);
+        }
+    }
+
+    entries.sort_by(|a, b| {
+        (a.kind != FileKind::Directory)
+            .cmp(&(b.kind != FileKind::Directory))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    Ok((entries, truncated))
+}
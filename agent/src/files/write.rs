@@ -0,0 +1,361 @@
+use crate::{
+    app::config::FilesConfig,
+    files::{FilePathError, Owners, etag, file_entry},
+};
+use common::agent::file::FileWriteResult;
+use std::{
+    fs::{self, DirBuilder, File, OpenOptions, Permissions},
+    io,
+    os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt, fchown},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use chrono::Utc;
+
+/// 新規作成するファイルの権限
+const DEFAULT_MODE: u32 = 0o644;
+
+/// 前提条件の確認からリネームまでを、エージェント内の書き込み同士で直列化する
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+/// 読み込んだ時点から変更されていないことを確認するための条件
+pub enum Precondition {
+    /// `If-Match`。いずれかの ETag（または `*`）と一致すること
+    Match(Vec<String>),
+    /// `If-None-Match: *`。まだ存在しないこと
+    NotExists,
+}
+
+pub enum WriteError {
+    PreconditionFailed,
+    NotRegularFile,
+    Path(FilePathError),
+}
+
+impl From<io::Error> for WriteError {
+    fn from(e: io::Error) -> Self {
+        WriteError::Path(e.into())
+    }
+}
+
+impl Precondition {
+    pub fn check(&self, current: Option<&fs::Metadata>) -> Result<(), WriteError> {
+        let satisfied = match (self, current) {
+            (Precondition::NotExists, current) => current.is_none(),
+            (Precondition::Match(_), None) => false,
+            (Precondition::Match(tags), Some(metadata)) => {
+                let current = etag(metadata);
+                tags.iter().any(|tag| tag == "*" || *tag == current)
+            }
+        };
+        if satisfied { Ok(()) } else { Err(WriteError::PreconditionFailed) }
+    }
+}
+
+/// 書き込み先の現在の状態。シンボリックリンクであればリンク先を対象にする
+pub fn current_metadata(path: &Path) -> Result<Option<fs::Metadata>, WriteError> {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.is_file() => Ok(Some(metadata)),
+        Ok(_) => Err(WriteError::NotRegularFile),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// 置き換える前の一時ファイル。`commit` が成功しないまま捨てられたら消す。
+/// クライアントが送信の途中で切断し、リクエストの Future ごと破棄された場合も残らない
+pub struct TempFile {
+    path: PathBuf,
+    committed: bool,
+}
+
+impl TempFile {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// 書き込み先と同じディレクトリに一時ファイルを作る（rename が同一ファイルシステム内で完結するように）
+pub fn create_temp(target: &Path) -> io::Result<(TempFile, File)> {
+    let (Some(dir), Some(name)) = (target.parent(), target.file_name()) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid target path"));
+    };
+    let nanos = Utc::now().timestamp_nanos_opt().unwrap_or_default();
+    let temp = dir.join(format!(".{}.{}-{:x}.tmp", name.to_string_lossy(), std::process::id(), nanos));
+    let file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&temp)?;
+    Ok((TempFile { path: temp, committed: false }, file))
+}
+
+/// 一時ファイルを書き込み先に置き換える。失敗した場合は一時ファイルを消す
+pub fn commit(config: &FilesConfig, target: &Path, mut temp: TempFile, precondition: &Precondition) -> Result<FileWriteResult, WriteError> {
+    let result = replace(config, target, temp.path(), precondition);
+    temp.committed = result.is_ok();
+    result
+}
+
+fn replace(config: &FilesConfig, target: &Path, temp: &Path, precondition: &Precondition) -> Result<FileWriteResult, WriteError> {
+    let _guard = WRITE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let current = current_metadata(target)?;
+    precondition.check(current.as_ref())?;
+
+    let file = OpenOptions::new().write(true).open(temp)?;
+    match &current {
+        Some(metadata) => {
+            fchown(&file, Some(metadata.uid()), Some(metadata.gid()))?;
+            file.set_permissions(Permissions::from_mode(metadata.mode() & 0o7777))?;
+        }
+        None => file.set_permissions(Permissions::from_mode(DEFAULT_MODE))?,
+    }
+    file.sync_all()?;
+
+    let backup = match current {
+        Some(_) => Some(backup(config, target)?),
+        None => None,
+    };
+
+    fs::rename(temp, target)?;
+    if let Some(dir) = target.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+
+    if let Some(backup) = &backup {
+        prune_backups(config, backup);
+    }
+
+    Ok(FileWriteResult {
+        entry: file_entry(target, &Owners::load())?,
+        backup: backup.map(|backup| backup.to_string_lossy().to_string()),
+    })
+}
+
+/// `<backup_dir>/<元のパス>.<時刻>` に旧版を残す。可能ならハードリンクでコピーを省く
+fn backup(config: &FilesConfig, target: &Path) -> io::Result<PathBuf> {
+    let relative = target.strip_prefix("/").unwrap_or(target);
+    let base = Path::new(&config.backup_dir).join(relative);
+    let timestamp = Utc::now().format("%Y%m%dT%H%M%S%3fZ");
+    let backup = PathBuf::from(format!("{}.{}", base.to_string_lossy(), timestamp));
+
+    if let Some(dir) = backup.parent() {
+        // /etc/shadow などの旧版も置くため、他のユーザーからは見えないようにする
+        DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    }
+    if fs::hard_link(target, &backup).is_err() {
+        fs::copy(target, &backup)?;
+    }
+    Ok(backup)
+}
+
+/// 同じファイルのバックアップを新しいものから `backup_keep` 件だけ残す
+fn prune_backups(config: &FilesConfig, latest: &Path) {
+    let (Some(dir), Some(name)) = (latest.parent(), latest.file_name().and_then(|name| name.to_str())) else {
+        return;
+    };
+    let Some((prefix, _)) = name.rsplit_once('.') else {
+        return;
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    let mut backups = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| {
+            name.strip_prefix(prefix)
+                .and_then(|rest| rest.strip_prefix('.'))
+                .is_some_and(|timestamp| timestamp.len() == 19 && timestamp.ends_with('Z') && !timestamp.contains('.'))
+        })
+        .collect::<Vec<String>>();
+    backups.sort();
+
+    let excess = backups.len().saturating_sub(config.backup_keep.max(1));
+    for name in &backups[..excess] {
+        if let Err(e) = fs::remove_file(dir.join(name)) {
+            tracing::warn!("Failed to remove old backup {}: {}", name, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Write, os::unix::fs::chown, thread, time::Duration};
+
+    fn config(backup_dir: &Path, backup_keep: usize) -> FilesConfig {
+        FilesConfig {
+            backup_dir: backup_dir.to_string_lossy().to_string(),
+            backup_keep,
+            ..FilesConfig::default()
+        }
+    }
+
+    /// 一時ファイルに内容を書き、書き込み先へ置き換える
+    fn write(config: &FilesConfig, target: &Path, content: &[u8], precondition: &Precondition) -> Result<FileWriteResult, WriteError> {
+        let (temp, mut file) = create_temp(target).unwrap();
+        file.write_all(content).unwrap();
+        drop(file);
+        commit(config, target, temp, precondition)
+    }
+
+    fn backups(dir: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .map(|entries| entries.filter_map(|entry| entry.ok()?.file_name().into_string().ok()).collect())
+            .unwrap_or_else(|_| Vec::new());
+        names.sort();
+        names
+    }
+
+    fn temp_files(dir: &Path) -> Vec<String> {
+        backups(dir).into_iter().filter(|name| name.ends_with(".tmp")).collect()
+    }
+
+    #[test]
+    fn precondition_compares_etags() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        fs::write(&path, "a").unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        let current = etag(&metadata);
+
+        assert!(Precondition::Match(vec![current.clone()]).check(Some(&metadata)).is_ok());
+        assert!(Precondition::Match(vec!["\"other\"".to_string(), current]).check(Some(&metadata)).is_ok());
+        assert!(Precondition::Match(vec!["*".to_string()]).check(Some(&metadata)).is_ok());
+        assert!(Precondition::Match(vec!["\"other\"".to_string()]).check(Some(&metadata)).is_err());
+        assert!(Precondition::Match(vec!["*".to_string()]).check(None).is_err());
+        assert!(Precondition::NotExists.check(None).is_ok());
+        assert!(Precondition::NotExists.check(Some(&metadata)).is_err());
+    }
+
+    #[test]
+    fn stale_etag_leaves_file_and_removes_temp() {
+        let dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let config = config(backup_dir.path(), 3);
+        let target = dir.path().join("file");
+        fs::write(&target, "old").unwrap();
+        let stale = etag(&fs::metadata(&target).unwrap());
+        fs::write(&target, "changed elsewhere").unwrap();
+
+        let result = write(&config, &target, b"new", &Precondition::Match(vec![stale]));
+        assert!(matches!(result, Err(WriteError::PreconditionFailed)));
+        assert_eq!(fs::read_to_string(&target).unwrap(), "changed elsewhere");
+        assert!(temp_files(dir.path()).is_empty());
+        assert!(backups(backup_dir.path()).is_empty());
+    }
+
+    #[test]
+    fn abandoned_temp_file_is_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("file");
+
+        let (temp, mut file) = create_temp(&target).unwrap();
+        file.write_all(b"partial upload").unwrap();
+        assert_eq!(temp_files(dir.path()).len(), 1);
+        // 受信の途中でリクエストが破棄されたときと同じく、commit せずに捨てる
+        drop(file);
+        drop(temp);
+        assert!(temp_files(dir.path()).is_empty());
+        assert!(!target.exists());
+    }
+
+    #[test]
+    fn creates_new_file_through_temp_and_rename() {
+        let dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let config = config(backup_dir.path(), 3);
+        let target = dir.path().join("file");
+
+        let (temp, mut file) = create_temp(&target).unwrap();
+        let temp_path = temp.path().to_path_buf();
+        assert_eq!(temp_path.parent(), Some(dir.path()));
+        assert_eq!(fs::metadata(&temp_path).unwrap().mode() & 0o777, 0o600);
+        file.write_all(b"new").unwrap();
+        drop(file);
+        // rename するまでは書き込み先に現れない
+        assert!(!target.exists());
+
+        let result = commit(&config, &target, temp, &Precondition::NotExists).ok().unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        assert_eq!(fs::metadata(&target).unwrap().mode() & 0o7777, DEFAULT_MODE);
+        assert!(!temp_path.exists());
+        assert!(result.backup.is_none());
+
+        let result = write(&config, &target, b"again", &Precondition::NotExists);
+        assert!(matches!(result, Err(WriteError::PreconditionFailed)));
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+    }
+
+    #[test]
+    fn keeps_owner_and_mode_and_creates_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let config = config(backup_dir.path(), 3);
+        let target = dir.path().join("file");
+        fs::write(&target, "old").unwrap();
+        fs::set_permissions(&target, Permissions::from_mode(0o640)).unwrap();
+        // root で動いている場合だけ、別の所有者が引き継がれることまで確かめる
+        if unsafe { libc::geteuid() } == 0 {
+            chown(&target, Some(1234), Some(2345)).unwrap();
+        }
+        let before = fs::metadata(&target).unwrap();
+        let tag = etag(&before);
+
+        let result = write(&config, &target, b"new", &Precondition::Match(vec![tag])).ok().unwrap();
+        let after = fs::metadata(&target).unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        assert_eq!(after.mode() & 0o7777, 0o640);
+        assert_eq!((after.uid(), after.gid()), (before.uid(), before.gid()));
+        assert_ne!(after.ino(), before.ino());
+
+        let backup = PathBuf::from(result.backup.unwrap());
+        assert!(backup.starts_with(backup_dir.path()));
+        assert!(backup.to_string_lossy().contains(&target.strip_prefix("/").unwrap().to_string_lossy().to_string()));
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "old");
+        assert_eq!(fs::metadata(backup.parent().unwrap()).unwrap().mode() & 0o777, 0o700);
+        assert!(temp_files(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn prunes_backups_beyond_backup_keep() {
+        let dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let config = config(backup_dir.path(), 2);
+        let target = dir.path().join("file");
+        let other = dir.path().join("file-other");
+        fs::write(&target, "0").unwrap();
+        fs::write(&other, "x").unwrap();
+        write(&config, &other, b"y", &Precondition::Match(vec!["*".to_string()])).ok().unwrap();
+
+        let mut created = Vec::new();
+        for i in 1..=4 {
+            // バックアップ名はミリ秒単位の時刻なので、重ならないよう少し待つ
+            thread::sleep(Duration::from_millis(5));
+            let result = write(&config, &target, i.to_string().as_bytes(), &Precondition::Match(vec!["*".to_string()])).ok().unwrap();
+            created.push(PathBuf::from(result.backup.unwrap()));
+        }
+
+        let backup_parent = created[0].parent().unwrap();
+        let kept = backups(backup_parent)
+            .into_iter()
+            .filter(|name| name.starts_with("file."))
+            .collect::<Vec<String>>();
+        let expected = created[2..]
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(kept, expected);
+        assert_eq!(fs::read_to_string(&created[3]).unwrap(), "3");
+        // 名前が前方一致するだけの別ファイルのバックアップは消さない
+        assert_eq!(backups(backup_parent).iter().filter(|name| name.starts_with("file-other.")).count(), 1);
+    }
+}
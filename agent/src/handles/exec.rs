@@ -0,0 +1,25 @@
+use crate::utils::exec::stream_with_timeout;
+use common::agent::exec::ExecRequest;
+use std::time::Duration;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Json},
+};
+use serde_json::json;
+use tokio::process::Command;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 60;
+const MAX_TIMEOUT_SECS: u64 = 3600;
+
+pub async fn run_command(Json(json): Json<ExecRequest>) -> impl IntoResponse {
+    if json.command.trim().is_empty() {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "command is required"}))).into_response();
+    }
+    let timeout = json.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS).clamp(1, MAX_TIMEOUT_SECS);
+
+    tracing::info!("Running command: {}", json.command);
+    let mut command = Command::new("/bin/sh");
+    command.arg("-c").arg(&json.command);
+    stream_with_timeout(vec![command], Some(Duration::from_secs(timeout)))
+}
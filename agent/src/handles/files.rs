@@ -0,0 +1,231 @@
+use crate::{
+    app::state::AppState,
+    files::{
+        self, FilePathError, Owners, resolve_path,
+        write::{self, Precondition, WriteError},
+    },
+};
+use common::agent::file::{DirectoryListing, FileQuery};
+use std::{fmt::Write, io};
+
+use axum::{
+    body::Body,
+    extract::{Query, Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Json, Response},
+};
+use futures::StreamExt;
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use tower_http::services::ServeFile;
+
+pub async fn list_directory(
+    State(state): State<AppState>,
+    Query(query): Query<FileQuery>,
+) -> impl IntoResponse {
+    let path = match resolve_path(&state.config.files, &query.path, true).await {
+        Ok(path) => path,
+        Err(e) => return path_error_response(e),
+    };
+
+    let result = tokio::task::spawn_blocking(move || {
+        files::list_directory(&path).map(|(entries, truncated)| DirectoryListing {
+            path: path.to_string_lossy().to_string(),
+            entries,
+            truncated,
+        })
+    })
+    .await;
+    match result {
+        Ok(Ok(listing)) => (StatusCode::OK, Json(listing)).into_response(),
+        Ok(Err(e)) if e.kind() == io::ErrorKind::NotADirectory => {
+            (StatusCode::BAD_REQUEST, Json(json!({"error": "not a directory"}))).into_response()
+        }
+        Ok(Err(e)) => path_error_response(e.into()),
+        Err(e) => {
+            tracing::error!("Directory listing panicked: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn stat_file(
+    State(state): State<AppState>,
+    Query(query): Query<FileQuery>,
+) -> impl IntoResponse {
+    let path = match resolve_path(&state.config.files, &query.path, false).await {
+        Ok(path) => path,
+        Err(e) => return path_error_response(e),
+    };
+
+    let result = tokio::task::spawn_blocking(move || files::file_entry(&path, &Owners::load())).await;
+    match result {
+        Ok(Ok(entry)) => (StatusCode::OK, Json(entry)).into_response(),
+        Ok(Err(e)) => path_error_response(e.into()),
+        Err(e) => {
+            tracing::error!("File stat panicked: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Range / If-Modified-Since の処理は `ServeFile` に任せる
+pub async fn download_file(
+    State(state): State<AppState>,
+    Query(query): Query<FileQuery>,
+    request: Request,
+) -> impl IntoResponse {
+    let path = match resolve_path(&state.config.files, &query.path, true).await {
+        Ok(path) => path,
+        Err(e) => return path_error_response(e),
+    };
+    let etag = match tokio::fs::metadata(&path).await {
+        Ok(metadata) if metadata.is_file() => files::etag(&metadata),
+        Ok(_) => return (StatusCode::BAD_REQUEST, Json(json!({"error": "not a regular file"}))).into_response(),
+        Err(e) => return path_error_response(e.into()),
+    };
+
+    let file_name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    match ServeFile::new(&path).try_call(request).await {
+        Ok(response) => {
+            let mut response = response.map(Body::new);
+            if let Ok(value) = HeaderValue::from_str(&content_disposition(&file_name)) {
+                response.headers_mut().insert(header::CONTENT_DISPOSITION, value);
+            }
+            if let Ok(value) = HeaderValue::from_str(&etag) {
+                response.headers_mut().insert(header::ETAG, value);
+            }
+            response
+        }
+        Err(e) => {
+            tracing::error!("Failed to serve {}: {}", path.display(), e);
+            path_error_response(e.into())
+        }
+    }
+}
+
+/// 本文をそのまま書き込む。`If-Match`（既存ファイルの ETag）か `If-None-Match: *`（新規作成）が必須
+pub async fn write_file(
+    State(state): State<AppState>,
+    Query(query): Query<FileQuery>,
+    headers: HeaderMap,
+    body: Body,
+) -> impl IntoResponse {
+    let Some(precondition) = precondition(&headers) else {
+        return (
+            StatusCode::PRECONDITION_REQUIRED,
+            Json(json!({"error": "If-Match or If-None-Match: * is required"})),
+        )
+            .into_response();
+    };
+    let config = &state.config.files;
+
+    // 既存のシンボリックリンクはリンク先のファイルを書き換える
+    let mut target = match resolve_path(config, &query.path, false).await {
+        Ok(path) => path,
+        Err(e) => return path_error_response(e),
+    };
+    if tokio::fs::symlink_metadata(&target).await.is_ok_and(|metadata| metadata.is_symlink()) {
+        target = match resolve_path(config, &query.path, true).await {
+            Ok(path) => path,
+            Err(e) => return path_error_response(e),
+        };
+    }
+
+    // 本文を受け取る前に分かる失敗は先に返す
+    if let Err(e) = write::current_metadata(&target).and_then(|current| precondition.check(current.as_ref())) {
+        return write_error_response(e);
+    }
+    let content_length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if content_length.is_some_and(|length| length > config.max_upload_bytes) {
+        return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+    }
+
+    let (temp, file) = match write::create_temp(&target) {
+        Ok(temp) => temp,
+        Err(e) => return path_error_response(e.into()),
+    };
+    // 失敗や切断で `temp` が捨てられると一時ファイルも消える。置き換えの途中で消されないよう、commit へ渡す
+    if let Err(response) = receive(body, tokio::fs::File::from_std(file), config.max_upload_bytes).await {
+        return response;
+    }
+
+    let config = config.clone();
+    let result = tokio::task::spawn_blocking(move || write::commit(&config, &target, temp, &precondition)).await;
+    match result {
+        Ok(Ok(result)) => (StatusCode::OK, Json(result)).into_response(),
+        Ok(Err(e)) => write_error_response(e),
+        Err(e) => {
+            tracing::error!("File write panicked: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+fn precondition(headers: &HeaderMap) -> Option<Precondition> {
+    if let Some(value) = headers.get(header::IF_NONE_MATCH) {
+        return (value.as_bytes() == b"*").then_some(Precondition::NotExists);
+    }
+    let value = headers.get(header::IF_MATCH)?.to_str().ok()?;
+    let tags = value.split(',').map(|tag| tag.trim().to_string()).filter(|tag| !tag.is_empty()).collect::<Vec<String>>();
+    (!tags.is_empty()).then_some(Precondition::Match(tags))
+}
+
+async fn receive(body: Body, mut file: tokio::fs::File, max_bytes: u64) -> Result<(), Response> {
+    let mut stream = body.into_data_stream();
+    let mut written = 0u64;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| {
+            (StatusCode::BAD_REQUEST, Json(json!({"error": format!("failed to read request body: {}", e)}))).into_response()
+        })?;
+        written += chunk.len() as u64;
+        if written > max_bytes {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE.into_response());
+        }
+        file.write_all(&chunk).await.map_err(|e| path_error_response(e.into()))?;
+    }
+    file.flush().await.map_err(|e| path_error_response(e.into()))
+}
+
+fn write_error_response(e: WriteError) -> Response {
+    match e {
+        WriteError::PreconditionFailed => (
+            StatusCode::PRECONDITION_FAILED,
+            Json(json!({"error": "file has been modified since it was read"})),
+        )
+            .into_response(),
+        WriteError::NotRegularFile => (StatusCode::BAD_REQUEST, Json(json!({"error": "not a regular file"}))).into_response(),
+        WriteError::Path(e) => path_error_response(e),
+    }
+}
+
+/// 非 ASCII のファイル名は RFC 5987 形式でも渡す
+fn content_disposition(file_name: &str) -> String {
+    let fallback = file_name
+        .chars()
+        .map(|c| if c.is_ascii_graphic() && c != '"' && c != '\\' || c == ' ' { c } else { '_' })
+        .collect::<String>();
+    let encoded = file_name.bytes().fold(String::new(), |mut s, b| {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            s.push(b as char);
+        } else {
+            let _ = write!(s, "%{:02X}", b);
+        }
+        s
+    });
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, encoded)
+}
+
+fn path_error_response(e: FilePathError) -> Response {
+    let status = match &e {
+        FilePathError::Invalid => StatusCode::BAD_REQUEST,
+        FilePathError::NotAllowed => StatusCode::FORBIDDEN,
+        FilePathError::NotFound => StatusCode::NOT_FOUND,
+        FilePathError::Io(e) if e.kind() == io::ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+        FilePathError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, Json(json!({"error": e.message()}))).into_response()
+}
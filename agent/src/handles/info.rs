@@ -4,7 +4,7 @@ use anyhow::Result;
 use axum::{
     response::{IntoResponse, Json},
 };
-use sysinfo::{Disks, System};
+use sysinfo::{Disks, IpNetwork, Networks, System};
 use wgpu::{Backends, Instance};
 
 pub async fn get_server_information() -> impl IntoResponse {
@@ -15,13 +15,15 @@ pub async fn get_server_information() -> impl IntoResponse {
     let memory_info = get_memory_information(&mut sys).await.unwrap();
     let disk_info = get_disk_information().await.unwrap();
     let gpu_info = get_gpu_information().await.unwrap();
+    let network_info = get_network_information().await.unwrap();
 
     Json(ServerInformation {
         device: device_info,
         cpu: cpu_info,
         memory: memory_info,
         disk: disk_info,
-        gpu: gpu_info
+        gpu: gpu_info,
+        network: network_info,
     })
 }
 
@@ -65,6 +67,40 @@ async fn get_disk_information() -> Result<Vec<Disk>> {
     Ok(storage)
 }
 
+async fn get_network_information() -> Result<Vec<NetworkInterface>> {
+    let networks = Networks::new_with_refreshed_list();
+
+    let mut interfaces = networks
+        .iter()
+        .map(|(name, data)| {
+            let (ipv4, ipv6): (Vec<&IpNetwork>, Vec<&IpNetwork>) = data
+                .ip_networks()
+                .iter()
+                .partition(|network| network.addr.is_ipv4());
+            NetworkInterface {
+                name: name.to_string(),
+                mac_address: data.mac_address().to_string(),
+                ipv4_addresses: ipv4.iter().map(|n| format!("{}/{}", n.addr, n.prefix)).collect(),
+                ipv6_addresses: ipv6.iter().map(|n| format!("{}/{}", n.addr, n.prefix)).collect(),
+                mtu: data.mtu(),
+                link_speed_mbps: get_link_speed(name),
+            }
+        })
+        .collect::<Vec<NetworkInterface>>();
+    interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(interfaces)
+}
+
+/// 仮想インターフェースやリンクダウン時は -1 などが返るため None とする
+fn get_link_speed(interface: &str) -> Option<u32> {
+    std::fs::read_to_string(format!("/sys/class/net/{}/speed", interface))
+        .ok()
+        .and_then(|s| s.trim().parse::<i64>().ok())
+        .filter(|speed| *speed > 0)
+        .map(|speed| speed as u32)
+}
+
 // ToDo: 正確な値を返せるようにする
 async fn get_gpu_information() -> Result<Vec<Gpu>> {
     let instance = Instance::new(&wgpu::InstanceDescriptor {
@@ -0,0 +1,193 @@
+use crate::{
+    app::state::AppState,
+    logs::{LogPathError, journal, resolve_path, search, tail::Follower},
+};
+use common::agent::log::{JournalEntry, JournalQuery, LogSearchQuery, LogTailEvent};
+use std::{
+    convert::Infallible,
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{
+        IntoResponse, Json, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use futures::{StreamExt, stream};
+use regex::RegexBuilder;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+const DEFAULT_TAIL_LINES: usize = 50;
+const MAX_TAIL_LINES: usize = 1000;
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+/// リクエストのタイムアウト（10秒）より前に打ち切って途中までの結果を返す
+const SEARCH_TIME_BUDGET: Duration = Duration::from_secs(8);
+const JOURNAL_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize)]
+pub struct TailQuery {
+    path: String,
+    lines: Option<usize>,
+}
+
+pub async fn tail_log(
+    State(state): State<AppState>,
+    Query(query): Query<TailQuery>,
+) -> impl IntoResponse {
+    let path = match resolve_path(&state.config.logs, &query.path).await {
+        Ok(path) => path,
+        Err(e) => return path_error_response(e),
+    };
+    let mut follower = match Follower::open(path).await {
+        Ok(follower) => follower,
+        Err(e) => return path_error_response(LogPathError::Io(e)),
+    };
+
+    let count = query.lines.unwrap_or(DEFAULT_TAIL_LINES).min(MAX_TAIL_LINES);
+    let lines = match follower.read_last_lines(count).await {
+        Ok(lines) => lines,
+        Err(e) => {
+            tracing::error!("Failed to read {}: {}", query.path, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response();
+        }
+    };
+
+    // クライアントが切断すると受信側が破棄され、追従も終了する
+    let (tx, rx) = mpsc::channel(256);
+    tokio::spawn(follower.run(tx));
+
+    let stream = stream::iter(lines.into_iter().map(|line| LogTailEvent::Line { line }))
+        .chain(ReceiverStream::new(rx))
+        .filter_map(|event| async move {
+            let data = serde_json::to_string(&event).ok()?;
+            Some(Ok::<_, Infallible>(Event::default().data(data)))
+        });
+
+    Sse::new(stream).keep_alive(KeepAlive::default()).into_response()
+}
+
+pub async fn search_log(
+    State(state): State<AppState>,
+    Query(query): Query<LogSearchQuery>,
+) -> impl IntoResponse {
+    let regex = match RegexBuilder::new(&query.pattern)
+        .case_insensitive(query.ignore_case)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+    {
+        Ok(regex) => regex,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(json!({"error": e.to_string()}))).into_response(),
+    };
+    let path = match resolve_path(&state.config.logs, &query.path).await {
+        Ok(path) => path,
+        Err(e) => return path_error_response(e),
+    };
+
+    let deadline = Instant::now() + SEARCH_TIME_BUDGET;
+    let result = tokio::task::spawn_blocking(move || search::search(&path, &query, &regex, deadline)).await;
+    match result {
+        Ok(Ok(result)) => (StatusCode::OK, Json(result)).into_response(),
+        Ok(Err(e)) => {
+            tracing::error!("Failed to search log: {:#}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": format!("{:#}", e)}))).into_response()
+        }
+        Err(e) => {
+            tracing::error!("Log search panicked: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn read_journal(
+    State(state): State<AppState>,
+    Query(query): Query<JournalQuery>,
+) -> impl IntoResponse {
+    let invalid_cursor = [&query.after, &query.before]
+        .into_iter()
+        .flatten()
+        .any(|cursor| journal::parse_cursor(cursor).is_none());
+    if invalid_cursor {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "invalid cursor"}))).into_response();
+    }
+
+    let directories = Arc::new(state.config.logs.journal_directories.clone());
+    let query = Arc::new(query);
+    let page = {
+        let (directories, query) = (directories.clone(), query.clone());
+        tokio::task::spawn_blocking(move || journal::read_page(&directories, &query)).await
+    };
+    let page = match page {
+        Ok(Ok(page)) => page,
+        Ok(Err(e)) => {
+            tracing::error!("Failed to read journal: {:#}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": format!("{:#}", e)}))).into_response();
+        }
+        Err(e) => {
+            tracing::error!("Journal reader panicked: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    if !query.follow {
+        return (StatusCode::OK, Json(page)).into_response();
+    }
+
+    let mut position = page
+        .entries
+        .last()
+        .and_then(|entry| journal::parse_cursor(&entry.cursor))
+        .unwrap_or_else(journal::now_position);
+    let (tx, rx) = mpsc::channel::<JournalEntry>(256);
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(JOURNAL_POLL_INTERVAL).await;
+            if tx.is_closed() {
+                return;
+            }
+
+            let (directories, query) = (directories.clone(), query.clone());
+            let result = tokio::task::spawn_blocking(move || journal::read_after(&directories, &query, position)).await;
+            match result {
+                Ok(Ok((entries, last))) => {
+                    position = last;
+                    for entry in entries {
+                        if tx.send(entry).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                Ok(Err(e)) => tracing::warn!("Failed to follow journal: {:#}", e),
+                Err(e) => tracing::error!("Journal reader panicked: {}", e),
+            }
+        }
+    });
+
+    let stream = stream::iter(page.entries)
+        .chain(ReceiverStream::new(rx))
+        .filter_map(|entry| async move {
+            let data = serde_json::to_string(&entry).ok()?;
+            Some(Ok::<_, Infallible>(Event::default().data(data)))
+        });
+
+    Sse::new(stream).keep_alive(KeepAlive::default()).into_response()
+}
+
+fn path_error_response(e: LogPathError) -> Response {
+    let status = match &e {
+        LogPathError::Invalid => StatusCode::BAD_REQUEST,
+        LogPathError::NotAllowed => StatusCode::FORBIDDEN,
+        LogPathError::NotFound => StatusCode::NOT_FOUND,
+        LogPathError::Io(e) if e.kind() == io::ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+        LogPathError::Io(e) if e.kind() == io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+        LogPathError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, Json(json!({"error": e.message()}))).into_response()
+}
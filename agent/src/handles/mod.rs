@@ -1,2 +1,10 @@
 pub mod metrics;
 pub mod info;
+pub mod exec;
+pub mod files;
+pub mod logs;
+pub mod packages;
+pub mod processes;
+pub mod services;
+pub mod terminal;
+pub mod wol;
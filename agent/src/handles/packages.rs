@@ -0,0 +1,40 @@
+use crate::{packages::jobs, utils::exec::stream_commands};
+use common::agent::package::PackageJobRequest;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Json},
+};
+use serde_json::json;
+
+pub async fn get_packages() -> impl IntoResponse {
+    match crate::packages::get_inventory().await {
+        Ok(inventory) => (StatusCode::OK, Json(inventory)).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to read installed packages: {:#}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response()
+        }
+    }
+}
+
+pub async fn get_package_updates() -> impl IntoResponse {
+    match crate::packages::updates::get_updates().await {
+        Ok(updates) => (StatusCode::OK, Json(updates)).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to check package updates: {:#}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response()
+        }
+    }
+}
+
+pub async fn run_package_job(Json(json): Json<PackageJobRequest>) -> impl IntoResponse {
+    if let Err(e) = jobs::validate_package_names(json.action, &json.packages) {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": e.to_string()}))).into_response();
+    }
+    let Some(manager) = crate::packages::detect_package_manager() else {
+        return (StatusCode::NOT_IMPLEMENTED, Json(json!({"error": "no supported package manager found"}))).into_response();
+    };
+
+    tracing::info!("Running package job: {} {:?}", json.action.as_str(), json.packages);
+    stream_commands(jobs::build_commands(manager, json.action, &json.packages))
+}
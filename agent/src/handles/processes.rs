@@ -0,0 +1,313 @@
+use crate::app::state::AppState;
+use common::agent::process::{Process, ProcessSignal, SignalRequest};
+use std::{
+    fs, io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    path::Path as FsPath,
+};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Json},
+};
+use serde::Deserialize;
+use serde_json::json;
+use sysinfo::{MINIMUM_CPU_UPDATE_INTERVAL, ProcessRefreshKind, ProcessesToUpdate, System, UpdateKind, Users};
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    #[default]
+    Cpu,
+    Memory,
+    Pid,
+    StartTime,
+    Name,
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+#[derive(Deserialize)]
+pub struct ProcessQuery {
+    #[serde(default)]
+    sort: SortKey,
+    #[serde(default)]
+    order: SortOrder,
+    user: Option<String>,
+    name: Option<String>,
+    limit: Option<usize>,
+}
+
+pub async fn get_processes(Query(query): Query<ProcessQuery>) -> impl IntoResponse {
+    let mut sys = System::new();
+    let refresh_kind = ProcessRefreshKind::nothing()
+        .with_cpu()
+        .with_memory()
+        .with_user(UpdateKind::OnlyIfNotSet)
+        .with_cmd(UpdateKind::OnlyIfNotSet);
+
+    // CPU 使用率は2回の計測の差分から求まるため、少し待ってから再取得する
+    sys.refresh_processes_specifics(ProcessesToUpdate::All, true, refresh_kind);
+    tokio::time::sleep(MINIMUM_CPU_UPDATE_INTERVAL).await;
+    sys.refresh_processes_specifics(ProcessesToUpdate::All, true, refresh_kind);
+
+    let users = Users::new_with_refreshed_list();
+
+    let mut processes = sys
+        .processes()
+        .values()
+        .filter(|process| process.thread_kind().is_none())
+        .map(|process| {
+            let command = process
+                .cmd()
+                .iter()
+                .map(|arg| arg.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(" ");
+            Process {
+                pid: process.pid().as_u32(),
+                ppid: process.parent().map(|pid| pid.as_u32()),
+                user: process
+                    .user_id()
+                    .and_then(|uid| users.get_user_by_id(uid))
+                    .map(|user| user.name().to_string()),
+                name: process.name().to_string_lossy().to_string(),
+                command,
+                state: process.status().to_string(),
+                cpu_percent: process.cpu_usage(),
+                rss_bytes: process.memory(),
+                start_time: process.start_time(),
+                start_ticks: read_stat(process.pid().as_u32()).map(|stat| stat.start_ticks),
+                open_fds: process.open_files().map(|count| count as u64),
+            }
+        })
+        .filter(|process| match &query.user {
+            Some(user) => process.user.as_deref() == Some(user.as_str()),
+            None => true,
+        })
+        .filter(|process| match &query.name {
+            Some(name) => process.name.contains(name.as_str()) || process.command.contains(name.as_str()),
+            None => true,
+        })
+        .collect::<Vec<Process>>();
+
+    processes.sort_by(|a, b| {
+        let ordering = match query.sort {
+            SortKey::Cpu => a.cpu_percent.total_cmp(&b.cpu_percent),
+            SortKey::Memory => a.rss_bytes.cmp(&b.rss_bytes),
+            SortKey::Pid => a.pid.cmp(&b.pid),
+            SortKey::StartTime => a.start_time.cmp(&b.start_time),
+            SortKey::Name => a.name.cmp(&b.name),
+        };
+        match query.order {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        }
+    });
+
+    if let Some(limit) = query.limit {
+        processes.truncate(limit);
+    }
+
+    Json(processes)
+}
+
+pub async fn signal_process(
+    State(state): State<AppState>,
+    Path(pid): Path<u32>,
+    Json(json): Json<SignalRequest>,
+) -> impl IntoResponse {
+    let signal = match json.signal {
+        ProcessSignal::Sigterm => libc::SIGTERM,
+        ProcessSignal::Sigkill => libc::SIGKILL,
+        ProcessSignal::Sighup => libc::SIGHUP,
+        ProcessSignal::Sigstop => libc::SIGSTOP,
+        ProcessSignal::Sigcont => libc::SIGCONT,
+    };
+
+    match send_signal(pid, json.start_ticks, signal, &state.config.processes.deny_names) {
+        Ok(name) => {
+            tracing::info!("Sent {:?} to {} ({})", json.signal, pid, name);
+            StatusCode::OK.into_response()
+        }
+        Err(SignalError::Forbidden(message)) => (StatusCode::FORBIDDEN, Json(json!({"error": message}))).into_response(),
+        Err(SignalError::NotFound) => (StatusCode::NOT_FOUND, Json(json!({"error": "process not found"}))).into_response(),
+        Err(SignalError::StartTimeMismatch) => {
+            (StatusCode::CONFLICT, Json(json!({"error": "process start time does not match"}))).into_response()
+        }
+        Err(SignalError::Os(e)) => {
+            tracing::error!("Failed to send {:?} to {}: {}", json.signal, pid, e);
+            let status = match e.raw_os_error() {
+                Some(libc::EPERM) => StatusCode::FORBIDDEN,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            (status, Json(json!({"error": e.to_string()}))).into_response()
+        }
+    }
+}
+
+#[derive(Debug)]
+enum SignalError {
+    Forbidden(String),
+    NotFound,
+    StartTimeMismatch,
+    Os(io::Error),
+}
+
+/// 先に pidfd を開いてから起動時刻と名前を確かめ、その pidfd へシグナルを送る。
+/// 確かめた後に PID が再利用されても、pidfd は元のプロセスを指したままなので別のプロセスには届かない
+fn send_signal(pid: u32, start_ticks: u64, signal: libc::c_int, deny_names: &[String]) -> Result<String, SignalError> {
+    if pid == 1 || pid == std::process::id() {
+        return Err(SignalError::Forbidden("signalling this process is not allowed".to_string()));
+    }
+
+    let pidfd = pidfd_open(pid).map_err(|e| match e.raw_os_error() {
+        Some(libc::ESRCH) => SignalError::NotFound,
+        _ => SignalError::Os(e),
+    })?;
+    let stat = read_stat(pid).ok_or(SignalError::NotFound)?;
+    if stat.start_ticks != start_ticks {
+        return Err(SignalError::StartTimeMismatch);
+    }
+
+    // comm は 15 文字で切られるので、実行ファイルの名前とも比べる
+    let exe = fs::read_link(format!("/proc/{}/exe", pid))
+        .ok()
+        .and_then(|path| FsPath::new(&path).file_name().map(|name| name.to_string_lossy().to_string()));
+    if deny_names.iter().any(|deny| *deny == stat.name || Some(deny) == exe.as_ref()) {
+        return Err(SignalError::Forbidden(format!("signalling {} is not allowed", stat.name)));
+    }
+
+    pidfd_send_signal(&pidfd, signal).map_err(|e| match e.raw_os_error() {
+        Some(libc::ESRCH) => SignalError::NotFound,
+        _ => SignalError::Os(e),
+    })?;
+    Ok(stat.name)
+}
+
+struct ProcessStat {
+    name: String,
+    start_ticks: u64,
+}
+
+fn read_stat(pid: u32) -> Option<ProcessStat> {
+    parse_stat(&fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?)
+}
+
+/// `/proc/<pid>/stat` から comm と 22 番目のフィールド（起動時刻）を読む。
+/// comm には空白や括弧も入りうるので、最後の `)` より後を数える
+fn parse_stat(stat: &str) -> Option<ProcessStat> {
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    let name = stat.get(open + 1..close)?.to_string();
+    let start_ticks = stat[close + 1..].split_whitespace().nth(19)?.parse().ok()?;
+    Some(ProcessStat { name, start_ticks })
+}
+
+fn pidfd_open(pid: u32) -> io::Result<OwnedFd> {
+    // SAFETY: pidfd_open(2) は整数だけを受け取り、成功すると新しいファイルディスクリプタを返す
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0 as libc::c_uint) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: 直前に開いたファイルディスクリプタで、ほかに所有者はいない
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) })
+}
+
+fn pidfd_send_signal(pidfd: &OwnedFd, signal: libc::c_int) -> io::Result<()> {
+    // SAFETY: siginfo に NULL を渡すと kill(2) と同じ内容で送られる。pidfd は呼び出しの間有効
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_pidfd_send_signal,
+            pidfd.as_raw_fd(),
+            signal,
+            std::ptr::null::<libc::siginfo_t>(),
+            0 as libc::c_uint,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        os::unix::process::ExitStatusExt,
+        process::{Child, Command},
+    };
+
+    /// exec が終わって名前が `sleep` になるまで待つ
+    fn sleeper() -> (Child, u64) {
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        for _ in 0..500 {
+            let stat = read_stat(child.id()).unwrap();
+            if stat.name == "sleep" {
+                return (child, stat.start_ticks);
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let _ = child.kill();
+        let _ = child.wait();
+        panic!("sleep did not start");
+    }
+
+    #[test]
+    fn parses_stat_with_awkward_names() {
+        let stat = "1234 (tmux: server) S 1 1234 1234 0 -1 4194560 1071 0 0 0 52 31 0 0 20 0 1 0 98765 12345678 900 18446744073709551615";
+        let parsed = parse_stat(stat).unwrap();
+        assert_eq!(parsed.name, "tmux: server");
+        assert_eq!(parsed.start_ticks, 98765);
+
+        let parsed = parse_stat("7 (a) b) S 1 7 7 0 -1 0 0 0 0 0 0 0 0 0 20 0 1 0 42 0").unwrap();
+        assert_eq!(parsed.name, "a) b");
+        assert_eq!(parsed.start_ticks, 42);
+        assert!(parse_stat("7 (sleep) S 1 7").is_none());
+    }
+
+    #[test]
+    fn refuses_a_process_whose_start_time_differs() {
+        let (mut child, start_ticks) = sleeper();
+        let result = send_signal(child.id(), start_ticks + 1, libc::SIGKILL, &[]);
+        assert!(matches!(result, Err(SignalError::StartTimeMismatch)), "{:?}", result);
+        assert!(child.try_wait().unwrap().is_none());
+
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+
+    #[test]
+    fn refuses_denied_and_protected_processes() {
+        let (mut child, start_ticks) = sleeper();
+        let result = send_signal(child.id(), start_ticks, libc::SIGKILL, &["sleep".to_string()]);
+        assert!(matches!(result, Err(SignalError::Forbidden(_))), "{:?}", result);
+        assert!(child.try_wait().unwrap().is_none());
+
+        for pid in [1, std::process::id()] {
+            let start_ticks = read_stat(pid).map(|stat| stat.start_ticks).unwrap_or(0);
+            assert!(matches!(send_signal(pid, start_ticks, 0, &[]), Err(SignalError::Forbidden(_))));
+        }
+
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+
+    #[test]
+    fn signals_the_verified_process() {
+        let (mut child, start_ticks) = sleeper();
+        assert_eq!(send_signal(child.id(), start_ticks, libc::SIGKILL, &["sshd".to_string()]).unwrap(), "sleep");
+        assert_eq!(child.wait().unwrap().signal(), Some(libc::SIGKILL));
+
+        // 終了したプロセスには送れない
+        assert!(matches!(send_signal(child.id(), start_ticks, libc::SIGKILL, &[]), Err(SignalError::NotFound)));
+    }
+}
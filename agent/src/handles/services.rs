@@ -0,0 +1,108 @@
+use crate::{app::state::AppState, systemd::{self, JobOutcome}};
+use common::agent::service::{ServiceActionRequest, normalize_unit_name};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use serde_json::json;
+use zbus::Connection;
+
+pub async fn get_services(State(state): State<AppState>) -> impl IntoResponse {
+    let conn = match connect(&state).await {
+        Ok(conn) => conn,
+        Err(response) => return response,
+    };
+
+    match systemd::list_units(&conn, &state.config.systemd).await {
+        Ok(units) => (StatusCode::OK, Json(units)).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to list systemd units: {}", e);
+            error_response(&e)
+        }
+    }
+}
+
+pub async fn get_service(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    let Some(name) = normalize_unit_name(&name) else {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "invalid unit name"}))).into_response();
+    };
+    let conn = match connect(&state).await {
+        Ok(conn) => conn,
+        Err(response) => return response,
+    };
+
+    match systemd::get_unit(&conn, &state.config.systemd, &name).await {
+        Ok(Some(unit)) => (StatusCode::OK, Json(unit)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(json!({"error": "unit not found"}))).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to fetch systemd unit {}: {}", name, e);
+            error_response(&e)
+        }
+    }
+}
+
+pub async fn run_service_action(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(json): Json<ServiceActionRequest>,
+) -> impl IntoResponse {
+    let Some(name) = normalize_unit_name(&name) else {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "invalid unit name"}))).into_response();
+    };
+    if !systemd::is_manageable(&state.config.systemd, &name) {
+        return (StatusCode::FORBIDDEN, Json(json!({"error": format!("managing {} is not allowed", name)}))).into_response();
+    }
+    let conn = match connect(&state).await {
+        Ok(conn) => conn,
+        Err(response) => return response,
+    };
+
+    match systemd::get_unit(&conn, &state.config.systemd, &name).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return (StatusCode::NOT_FOUND, Json(json!({"error": "unit not found"}))).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to fetch systemd unit {}: {}", name, e);
+            return error_response(&e);
+        }
+    }
+
+    tracing::info!("Running {} on {}", json.action.as_str(), name);
+    match systemd::run_action(&conn, &name, json.action).await {
+        Ok(JobOutcome::Done) => StatusCode::OK.into_response(),
+        // 完了を待ちきれなかったジョブは systemd 側で継続している
+        Ok(JobOutcome::Pending) => StatusCode::ACCEPTED.into_response(),
+        Ok(JobOutcome::Failed(result)) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": format!("job finished with result: {}", result)}))).into_response()
+        }
+        Err(e) => {
+            tracing::error!("Failed to {} {}: {}", json.action.as_str(), name, e);
+            error_response(&e)
+        }
+    }
+}
+
+async fn connect(state: &AppState) -> Result<Connection, Response> {
+    systemd::connect(&state.config.systemd).await.map_err(|e| {
+        tracing::error!("Failed to connect to systemd: {}", e);
+        (StatusCode::SERVICE_UNAVAILABLE, Json(json!({"error": format!("failed to connect to systemd: {}", e)}))).into_response()
+    })
+}
+
+fn error_response(e: &zbus::Error) -> Response {
+    let status = match e {
+        zbus::Error::MethodError(name, _, _) => match name.as_str() {
+            "org.freedesktop.systemd1.NoSuchUnit" => StatusCode::NOT_FOUND,
+            "org.freedesktop.DBus.Error.AccessDenied" | "org.freedesktop.DBus.Error.InteractiveAuthorizationRequired" => {
+                StatusCode::FORBIDDEN
+            }
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        },
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, Json(json!({"error": e.to_string()}))).into_response()
+}
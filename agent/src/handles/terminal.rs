@@ -0,0 +1,61 @@
+use crate::{app::state::AppState, terminal::{self, pty::Pty}};
+use common::{agent::terminal::TerminalQuery, ws};
+use std::path::Path;
+
+use axum::{
+    extract::{Query, Request, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Json},
+};
+use hyper_util::rt::TokioIo;
+use serde_json::json;
+
+const DEFAULT_COLS: u16 = 80;
+const DEFAULT_ROWS: u16 = 24;
+const FALLBACK_SHELL: &str = "/bin/sh";
+
+/// WebSocket にアップグレードし、擬似端末上のログインシェルにつなぐ
+pub async fn open_terminal(
+    State(state): State<AppState>,
+    Query(query): Query<TerminalQuery>,
+    mut request: Request,
+) -> impl IntoResponse {
+    let headers = request.headers();
+    let Some(accept) = ws::server_accept(|name| headers.get(name).and_then(|v| v.to_str().ok()).map(|v| v.to_string())) else {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "websocket upgrade is required"}))).into_response();
+    };
+
+    let shell = match state.config.terminal.shell.as_str() {
+        shell if Path::new(shell).exists() => shell,
+        _ => FALLBACK_SHELL,
+    };
+    let cols = query.cols.filter(|cols| *cols > 0).unwrap_or(DEFAULT_COLS);
+    let rows = query.rows.filter(|rows| *rows > 0).unwrap_or(DEFAULT_ROWS);
+    let (pty, child) = match Pty::spawn(shell, cols, rows) {
+        Ok(spawned) => spawned,
+        Err(e) => {
+            tracing::error!("Failed to start terminal: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response();
+        }
+    };
+
+    let on_upgrade = hyper::upgrade::on(&mut request);
+    tokio::spawn(async move {
+        match on_upgrade.await {
+            Ok(upgraded) => terminal::run_session(TokioIo::new(upgraded), pty, child).await,
+            // 子プロセスは child の破棄時に終了する
+            Err(e) => tracing::error!("Failed to upgrade terminal connection: {}", e),
+        }
+    });
+
+    tracing::info!("Terminal session started ({})", shell);
+    (
+        StatusCode::SWITCHING_PROTOCOLS,
+        [
+            (header::UPGRADE, "websocket".to_string()),
+            (header::CONNECTION, "Upgrade".to_string()),
+            (header::SEC_WEBSOCKET_ACCEPT, accept),
+        ],
+    )
+        .into_response()
+}
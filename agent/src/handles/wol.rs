@@ -0,0 +1,29 @@
+use common::agent::wol::{WAKE_PORTS, WakeRequest, parse_mac_address, send_magic_packet};
+use std::net::Ipv4Addr;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Json},
+};
+use serde_json::json;
+
+/// central の代わりに、このエージェントの LAN へマジックパケットを送る。
+/// 任意の宛先へ UDP を送る踏み台にされないよう、宛先は LAN のブロードキャストと Wake-on-LAN のポートに限る
+pub async fn relay_magic_packet(Json(json): Json<WakeRequest>) -> impl IntoResponse {
+    let Some(mac) = parse_mac_address(&json.mac_address) else {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "invalid MAC address"}))).into_response();
+    };
+    if !WAKE_PORTS.contains(&json.port) {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": format!("port must be one of {:?}", WAKE_PORTS)}))).into_response();
+    }
+    let broadcast = Ipv4Addr::BROADCAST.to_string();
+
+    tracing::info!("Sending magic packet for {} to {}:{}", json.mac_address, broadcast, json.port);
+    match send_magic_packet(&mac, &broadcast, json.port).await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to send magic packet: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response()
+        }
+    }
+}
@@ -0,0 +1,384 @@
+use std::{
+    borrow::Cow,
+    fs::File,
+    hash::Hasher,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result, bail, ensure};
+use memmap2::Mmap;
+
+const SIGNATURE: &[u8; 8] = b"LPKSHHRH";
+const MIN_HEADER_SIZE: u64 = 208;
+
+const INCOMPATIBLE_KEYED_HASH: u32 = 1 << 2;
+const INCOMPATIBLE_COMPACT: u32 = 1 << 4;
+
+const OBJECT_HEADER_SIZE: u64 = 16;
+const OBJECT_DATA: u8 = 1;
+const OBJECT_ENTRY: u8 = 3;
+const OBJECT_ENTRY_ARRAY: u8 = 6;
+
+const OBJECT_COMPRESSED_XZ: u8 = 1 << 0;
+const OBJECT_COMPRESSED_LZ4: u8 = 1 << 1;
+const OBJECT_COMPRESSED_ZSTD: u8 = 1 << 2;
+
+/// 展開後の DATA オブジェクトの上限。壊れたファイルや極端な圧縮率でメモリを使い切らないようにする
+const MAX_DATA_SIZE: usize = 64 * 1024 * 1024;
+
+/// エントリーを並べ替えるためのヘッダー部分
+#[derive(Clone, Copy, Debug)]
+pub struct EntryMeta {
+    pub offset: u64,
+    pub seqnum: u64,
+    pub realtime: u64,
+    pub monotonic: u64,
+    pub boot_id: [u8; 16],
+    pub xor_hash: u64,
+}
+
+/// エントリー配列の連結リストで表したエントリーの一覧。ファイル全体のものと、DATA オブジェクトごとのものがある。
+/// どちらもファイル内の位置の順、つまり seqnum 順に並ぶ
+pub struct EntryList {
+    /// DATA オブジェクトに直接書かれている最初のエントリー
+    inline: Option<u64>,
+    /// エントリー配列の位置と、入るエントリーの数
+    arrays: Vec<(u64, u64)>,
+    len: u64,
+}
+
+impl EntryList {
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+}
+
+/// systemd のジャーナルファイル（https://systemd.io/JOURNAL_FILE_FORMAT/）の読み取り専用ビュー
+pub struct JournalFile {
+    map: Mmap,
+    compact: bool,
+    pub file_id: [u8; 16],
+    pub seqnum_id: [u8; 16],
+    pub head_realtime: u64,
+    pub tail_realtime: u64,
+    keyed_hash: bool,
+    data_hash_table_offset: u64,
+    data_hash_table_size: u64,
+    n_entries: u64,
+    entry_array_offset: u64,
+}
+
+impl JournalFile {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+        // SAFETY: 読み取り専用でマップし、すべての読み出しで範囲を確認している。
+        // journald は追記のみ行い、既存のオブジェクトを書き換えない
+        let map = unsafe { Mmap::map(&file) }.with_context(|| format!("failed to map {}", path.display()))?;
+
+        ensure!(map.len() as u64 >= MIN_HEADER_SIZE, "journal header is too short");
+        ensure!(&map[..8] == SIGNATURE, "not a journal file");
+
+        let mut journal = Self {
+            map,
+            compact: false,
+            file_id: [0; 16],
+            seqnum_id: [0; 16],
+            head_realtime: 0,
+            tail_realtime: 0,
+            keyed_hash: false,
+            data_hash_table_offset: 0,
+            data_hash_table_size: 0,
+            n_entries: 0,
+            entry_array_offset: 0,
+        };
+        let incompatible_flags = journal.u32_at(12)?;
+        ensure!(journal.u64_at(88)? >= MIN_HEADER_SIZE, "journal header is too short");
+
+        journal.compact = incompatible_flags & INCOMPATIBLE_COMPACT != 0;
+        journal.keyed_hash = incompatible_flags & INCOMPATIBLE_KEYED_HASH != 0;
+        journal.file_id = journal.id_at(24)?;
+        journal.data_hash_table_offset = journal.u64_at(104)?;
+        journal.data_hash_table_size = journal.u64_at(112)?;
+        journal.seqnum_id = journal.id_at(72)?;
+        journal.n_entries = journal.u64_at(152)?;
+        journal.entry_array_offset = journal.u64_at(176)?;
+        journal.head_realtime = journal.u64_at(184)?;
+        journal.tail_realtime = journal.u64_at(192)?;
+        Ok(journal)
+    }
+
+    /// ファイルのすべてのエントリー。件数は開いた時点のヘッダーの値で、その後に追記された分は含まない
+    pub fn entries(&self) -> Result<EntryList> {
+        self.entry_list(None, self.entry_array_offset, self.n_entries)
+    }
+
+    /// `NAME=value` のフィールドを持つ DATA オブジェクトを、ハッシュテーブルから探す
+    pub fn find_data(&self, field: &[u8]) -> Result<Option<u64>> {
+        let buckets = self.data_hash_table_size / 16;
+        if self.data_hash_table_offset == 0 || buckets == 0 {
+            return Ok(None);
+        }
+
+        let hash = self.hash(field);
+        let mut offset = self.u64_at(self.data_hash_table_offset + hash % buckets * 16)?;
+        while offset != 0 {
+            let (kind, _, _) = self.object_header(offset)?;
+            ensure!(kind == OBJECT_DATA, "unexpected object type {} in data hash chain", kind);
+            if self.u64_at(offset + 16)? == hash && *self.data_payload(offset)? == *field {
+                return Ok(Some(offset));
+            }
+            // 追記されたオブジェクトがチェーンの末尾につながるので、前方へのリンクしかない
+            let next = self.u64_at(offset + 24)?;
+            if next <= offset {
+                break;
+            }
+            offset = next;
+        }
+        Ok(None)
+    }
+
+    /// DATA オブジェクトを持つエントリーの一覧
+    pub fn data_entries(&self, data: u64) -> Result<EntryList> {
+        let n_entries = self.u64_at(data + 56)?;
+        if n_entries == 0 {
+            return Ok(EntryList { inline: None, arrays: Vec::new(), len: 0 });
+        }
+        self.entry_list(Some(self.u64_at(data + 40)?), self.u64_at(data + 48)?, n_entries)
+    }
+
+    /// DATA オブジェクトが持つエントリーの数。絞り込みに使う一覧を選ぶための目安
+    pub fn data_entry_count(&self, data: u64) -> Result<u64> {
+        self.u64_at(data + 56)
+    }
+
+    fn entry_list(&self, inline: Option<u64>, mut offset: u64, n_entries: u64) -> Result<EntryList> {
+        let item_size = if self.compact { 4 } else { 8 };
+        let in_arrays = n_entries - u64::from(inline.is_some());
+
+        // 配列は後ろほど大きくなるので、たどる配列の数はエントリー数の対数程度に収まる
+        let mut arrays = Vec::new();
+        let mut capacity = 0;
+        while offset != 0 && capacity < in_arrays {
+            let (kind, _, size) = self.object_header(offset)?;
+            ensure!(kind == OBJECT_ENTRY_ARRAY, "unexpected object type {} in entry array chain", kind);
+            let items = size.saturating_sub(24) / item_size;
+            arrays.push((offset, items));
+            capacity += items;
+
+            // 壊れたファイルで無限ループにならないよう、後方へのリンクは辿らない
+            let next = self.u64_at(offset + 16)?;
+            if next <= offset {
+                break;
+            }
+            offset = next;
+        }
+
+        Ok(EntryList { inline, arrays, len: in_arrays.min(capacity) + u64::from(inline.is_some()) })
+    }
+
+    /// 一覧の `index` 番目のエントリーの位置
+    pub fn entry_at(&self, list: &EntryList, index: u64) -> Result<u64> {
+        ensure!(index < list.len, "entry index {} is out of range", index);
+        let mut index = match list.inline {
+            Some(entry) if index == 0 => return Ok(entry),
+            Some(_) => index - 1,
+            None => index,
+        };
+        for (offset, items) in &list.arrays {
+            if index < *items {
+                let item = if self.compact { self.u32_at(offset + 24 + index * 4)? as u64 } else { self.u64_at(offset + 24 + index * 8)? };
+                ensure!(item != 0, "entry array item is not written yet");
+                return Ok(item);
+            }
+            index -= items;
+        }
+        bail!("entry index is out of range")
+    }
+
+    /// 一覧のうち、`before` が真になる先頭からの範囲の長さを二分探索で求める。位置は時刻順に並んでいるものとみなす
+    pub fn partition_point(&self, list: &EntryList, before: impl Fn(&EntryMeta) -> bool) -> Result<u64> {
+        let (mut low, mut high) = (0, list.len);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if before(&self.entry_meta(self.entry_at(list, middle)?)?) {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        Ok(low)
+    }
+
+    /// エントリーが持つ DATA オブジェクトの位置。フィールドを展開せずに絞り込むために使う
+    pub fn entry_data(&self, offset: u64) -> Result<Vec<u64>> {
+        let (_, _, size) = self.object_header(offset)?;
+        let item_size = if self.compact { 4 } else { 16 };
+
+        let mut data = Vec::new();
+        let mut item = offset + 64;
+        while item + item_size <= offset + size {
+            data.push(if self.compact { self.u32_at(item)? as u64 } else { self.u64_at(item)? });
+            item += item_size;
+        }
+        Ok(data)
+    }
+
+    pub fn entry_meta(&self, offset: u64) -> Result<EntryMeta> {
+        let (kind, _, _) = self.object_header(offset)?;
+        ensure!(kind == OBJECT_ENTRY, "unexpected object type {} for entry", kind);
+        Ok(EntryMeta {
+            offset,
+            seqnum: self.u64_at(offset + 16)?,
+            realtime: self.u64_at(offset + 24)?,
+            monotonic: self.u64_at(offset + 32)?,
+            boot_id: self.id_at(offset + 40)?,
+            xor_hash: self.u64_at(offset + 56)?,
+        })
+    }
+
+    /// エントリーのフィールドを `NAME=value` のペアで返す
+    pub fn entry_fields(&self, offset: u64) -> Result<Vec<(String, Vec<u8>)>> {
+        let mut fields = Vec::new();
+        for data in self.entry_data(offset)? {
+            let payload = self.data_payload(data)?;
+            if let Some(pos) = payload.iter().position(|b| *b == b'=') {
+                let name = String::from_utf8_lossy(&payload[..pos]).to_string();
+                fields.push((name, payload[pos + 1..].to_vec()));
+            }
+        }
+        Ok(fields)
+    }
+
+    fn data_payload(&self, offset: u64) -> Result<Cow<'_, [u8]>> {
+        let (kind, flags, size) = self.object_header(offset)?;
+        ensure!(kind == OBJECT_DATA, "unexpected object type {} for data", kind);
+
+        let start = offset + if self.compact { 72 } else { 64 };
+        ensure!(start <= offset + size, "data object is too short");
+        let payload = self.slice(start, offset + size - start)?;
+
+        if flags & OBJECT_COMPRESSED_LZ4 != 0 {
+            // 先頭8バイトは展開後のサイズ
+            ensure!(payload.len() >= 8, "compressed data object is too short");
+            let length = u64::from_le_bytes(payload[..8].try_into()?) as usize;
+            ensure!(length <= MAX_DATA_SIZE, "decompressed data object is too large");
+            let data = lz4_flex::block::decompress(&payload[8..], length).context("failed to decompress LZ4 data")?;
+            return Ok(Cow::Owned(data));
+        }
+        if flags & OBJECT_COMPRESSED_ZSTD != 0 {
+            let decoder = ruzstd::decoding::StreamingDecoder::new(payload).context("failed to decompress ZSTD data")?;
+            let mut data = Vec::new();
+            decoder.take(MAX_DATA_SIZE as u64 + 1).read_to_end(&mut data).context("failed to decompress ZSTD data")?;
+            ensure!(data.len() <= MAX_DATA_SIZE, "decompressed data object is too large");
+            return Ok(Cow::Owned(data));
+        }
+        if flags & OBJECT_COMPRESSED_XZ != 0 {
+            let mut data = BoundedWriter(Vec::new());
+            lzma_rs::xz_decompress(&mut &payload[..], &mut data).context("failed to decompress XZ data")?;
+            return Ok(Cow::Owned(data.0));
+        }
+        Ok(Cow::Borrowed(payload))
+    }
+
+    /// DATA オブジェクトのハッシュ。展開後の `NAME=value` から計算する
+    fn hash(&self, field: &[u8]) -> u64 {
+        if self.keyed_hash {
+            siphash24(field, &self.file_id)
+        } else {
+            jenkins_hash64(field)
+        }
+    }
+
+    fn object_header(&self, offset: u64) -> Result<(u8, u8, u64)> {
+        let header = self.slice(offset, OBJECT_HEADER_SIZE)?;
+        let size = u64::from_le_bytes(header[8..16].try_into()?);
+        if size < OBJECT_HEADER_SIZE || offset.checked_add(size).is_none_or(|end| end > self.map.len() as u64) {
+            bail!("invalid object size {} at {}", size, offset);
+        }
+        Ok((header[0], header[1], size))
+    }
+
+    fn slice(&self, offset: u64, len: u64) -> Result<&[u8]> {
+        let end = offset.checked_add(len).context("offset overflow")?;
+        self.map
+            .get(offset as usize..end as usize)
+            .with_context(|| format!("offset {} is out of range", offset))
+    }
+
+    fn u32_at(&self, offset: u64) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.slice(offset, 4)?.try_into()?))
+    }
+
+    fn u64_at(&self, offset: u64) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.slice(offset, 8)?.try_into()?))
+    }
+
+    fn id_at(&self, offset: u64) -> Result<[u8; 16]> {
+        Ok(self.slice(offset, 16)?.try_into()?)
+    }
+}
+
+/// 鍵付きハッシュの形式で使われる SipHash-2-4。鍵にはファイル ID を使う
+pub fn siphash24(data: &[u8], key: &[u8; 16]) -> u64 {
+    let (k0, k1) = key.split_at(8);
+    #[allow(deprecated)]
+    let mut hasher = std::hash::SipHasher::new_with_keys(
+        u64::from_le_bytes(k0.try_into().unwrap_or_default()),
+        u64::from_le_bytes(k1.try_into().unwrap_or_default()),
+    );
+    hasher.write(data);
+    hasher.finish()
+}
+
+/// 鍵付きハッシュより前の形式で使われる Jenkins の lookup3（hashlittle2）。上位 32 ビットが主ハッシュ
+pub fn jenkins_hash64(data: &[u8]) -> u64 {
+    const MIX: [u32; 6] = [4, 6, 8, 16, 19, 4];
+    const FINAL: [u32; 7] = [14, 11, 25, 16, 4, 14, 24];
+
+    // 12 バイトずつ a, b, c に足す。足りない分は 0 で埋める
+    let add = |v: &mut [u32; 3], block: &[u8]| {
+        let mut padded = [0u8; 12];
+        padded[..block.len()].copy_from_slice(block);
+        for (i, word) in padded.chunks_exact(4).enumerate() {
+            v[i] = v[i].wrapping_add(u32::from_le_bytes([word[0], word[1], word[2], word[3]]));
+        }
+    };
+
+    let mut v = [0xdeadbeef_u32.wrapping_add(data.len() as u32); 3];
+    let mut rest = data;
+    while rest.len() > 12 {
+        add(&mut v, &rest[..12]);
+        for (i, r) in MIX.iter().enumerate() {
+            let (p, q, s) = (i % 3, (i + 2) % 3, (i + 1) % 3);
+            v[p] = v[p].wrapping_sub(v[q]) ^ v[q].rotate_left(*r);
+            v[q] = v[q].wrapping_add(v[s]);
+        }
+        rest = &rest[12..];
+    }
+    if !rest.is_empty() {
+        add(&mut v, rest);
+        for (i, r) in FINAL.iter().enumerate() {
+            let (p, q) = ((i + 2) % 3, (i + 1) % 3);
+            v[p] = (v[p] ^ v[q]).wrapping_sub(v[q].rotate_left(*r));
+        }
+    }
+    ((v[2] as u64) << 32) | v[1] as u64
+}
+
+/// XZ の展開先。`MAX_DATA_SIZE` を超えたらエラーにする
+struct BoundedWriter(Vec<u8>);
+
+impl Write for BoundedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.0.len() + buf.len() > MAX_DATA_SIZE {
+            return Err(io::Error::other("decompressed data object is too large"));
+        }
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
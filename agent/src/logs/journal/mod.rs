@@ -0,0 +1,781 @@
+pub mod file;
+
+use common::agent::log::{JournalEntry, JournalPage, JournalQuery};
+use file::{EntryList, EntryMeta, JournalFile};
+use std::{
+    collections::HashSet,
+    fmt::Write,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use anyhow::{Result, bail};
+use chrono::{DateTime, Utc};
+
+pub const DEFAULT_LIMIT: usize = 100;
+pub const MAX_LIMIT: usize = 1000;
+
+/// エントリーの並び順（realtime, seqnum）。カーソルからもこの値を取り出す
+pub type Position = (u64, u64);
+
+/// 1つのファイルから条件に合うエントリーを読むための一覧と絞り込み
+struct Source {
+    journal: JournalFile,
+    /// 候補を並べた一覧。条件があれば、最も件数の少ない条件に合う DATA オブジェクトごとの一覧
+    lists: Vec<EntryList>,
+    /// 候補が満たすべき残りの条件。それぞれ、いずれかの DATA オブジェクトを持てばよい
+    filters: Vec<Vec<u64>>,
+}
+
+/// 一覧ごとの次に読むエントリー
+struct Head {
+    source: usize,
+    list: usize,
+    index: u64,
+    meta: EntryMeta,
+}
+
+/// すべてのファイルと一覧のエントリーを位置の順に、`forward` でなければ逆順に、1つずつ取り出す
+struct Walk<'a> {
+    sources: &'a [Source],
+    heads: Vec<Head>,
+    forward: bool,
+}
+
+/// journalctl と同じ `s=;i=;b=;m=;t=;x=` 形式のカーソルから位置を取り出す
+pub fn parse_cursor(cursor: &str) -> Option<Position> {
+    let mut realtime = None;
+    let mut seqnum = None;
+    for part in cursor.split(';') {
+        let (key, value) = part.split_once('=')?;
+        match key {
+            "t" => realtime = u64::from_str_radix(value, 16).ok(),
+            "i" => seqnum = u64::from_str_radix(value, 16).ok(),
+            _ => {}
+        }
+    }
+    Some((realtime?, seqnum?))
+}
+
+pub fn read_page(directories: &[String], query: &JournalQuery) -> Result<JournalPage> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let after = query.after.as_deref().map(cursor_position).transpose()?;
+    let before = query.before.as_deref().map(cursor_position).transpose()?;
+    let since = query.since.map(to_usec);
+    let until = query.until.map(to_usec);
+
+    let above_lower = |position: Position| after.is_none_or(|after| position > after) && since.is_none_or(|since| position >= (since, 0));
+    let below_upper = |position: Position| before.is_none_or(|before| position < before) && until.is_none_or(|until| position <= (until, u64::MAX));
+
+    let sources = open_sources(directories, query, after.map(|(realtime, _)| realtime).max(since), until);
+    let forward = after.is_some() || (before.is_none() && query.since.is_some());
+    // 前向きなら下限より前を、後ろ向きなら上限までを二分探索で飛ばし、そこから `limit` + 1 件を読む
+    let walk = if forward {
+        Walk::new(&sources, true, |meta| !above_lower(position(meta)))
+    } else {
+        Walk::new(&sources, false, |meta| below_upper(position(meta)))
+    };
+
+    let mut seen = HashSet::new();
+    let mut entries = Vec::new();
+    for (source, meta) in walk {
+        let in_range = if forward { below_upper(position(&meta)) } else { above_lower(position(&meta)) };
+        if !in_range {
+            break;
+        }
+        if let Some(entry) = sources[source].entry(&meta, &mut seen) {
+            entries.push(entry);
+            if entries.len() > limit {
+                break;
+            }
+        }
+    }
+    if !forward {
+        // 新しい方から読んだので古い順に並べ直す
+        entries.reverse();
+    }
+
+    let has_more = entries.len() > limit;
+    if has_more {
+        if forward {
+            entries.truncate(limit);
+        } else {
+            entries.remove(0);
+        }
+    }
+
+    Ok(JournalPage { entries, has_more })
+}
+
+/// `follow` 用。指定した位置より新しいエントリーをすべて返す。
+/// 読んだところまでの位置を返すので、次はその続きから読める
+pub fn read_after(directories: &[String], query: &JournalQuery, after: Position) -> Result<(Vec<JournalEntry>, Position)> {
+    let until = query.until.map(to_usec);
+    let sources = open_sources(directories, query, Some(after.0), until);
+
+    let mut last = after;
+    let mut seen = HashSet::new();
+    let mut entries = Vec::new();
+    for (source, meta) in Walk::new(&sources, true, |meta| position(meta) <= after) {
+        if until.is_some_and(|until| position(&meta) > (until, u64::MAX)) {
+            break;
+        }
+        last = last.max(position(&meta));
+        if let Some(entry) = sources[source].entry(&meta, &mut seen) {
+            entries.push(entry);
+        }
+    }
+    Ok((entries, last))
+}
+
+pub fn now_position() -> Position {
+    (to_usec(Utc::now()), u64::MAX)
+}
+
+fn cursor_position(cursor: &str) -> Result<Position> {
+    match parse_cursor(cursor) {
+        Some(position) => Ok(position),
+        None => bail!("invalid cursor"),
+    }
+}
+
+fn position(meta: &EntryMeta) -> Position {
+    (meta.realtime, meta.seqnum)
+}
+
+fn to_usec(timestamp: DateTime<Utc>) -> u64 {
+    timestamp.timestamp_micros().max(0) as u64
+}
+
+/// 期間に重なるジャーナルファイルを開き、条件に合うエントリーの一覧を用意する。
+/// 条件のフィールドは journalctl と同じく各ファイルのハッシュテーブルで探すので、エントリーを展開して調べることはない
+fn open_sources(directories: &[String], query: &JournalQuery, since: Option<u64>, until: Option<u64>) -> Vec<Source> {
+    let mut conditions = Vec::new();
+    // journalctl -u と同様に、ユニット自身の出力と systemd がそのユニットについて出したメッセージを対象にする
+    if let Some(unit) = &query.unit {
+        let unit = if unit.contains('.') { unit.clone() } else { format!("{}.service", unit) };
+        conditions.push(["_SYSTEMD_UNIT", "UNIT", "OBJECT_SYSTEMD_UNIT"].map(|name| format!("{}={}", name, unit).into_bytes()).to_vec());
+    }
+    if let Some(max) = query.priority {
+        conditions.push((0..=max.min(7)).map(|priority| format!("PRIORITY={}", priority).into_bytes()).collect());
+    }
+
+    let mut sources = Vec::new();
+    for path in journal_paths(directories) {
+        // 最後に書き込まれたのが期間より前のファイルは開かない
+        if since.is_some_and(|since| modified_usec(&path).is_some_and(|modified| modified < since)) {
+            continue;
+        }
+        let journal = match JournalFile::open(&path) {
+            Ok(journal) => journal,
+            Err(e) => {
+                tracing::warn!("Skipping journal file {}: {:#}", path.display(), e);
+                continue;
+            }
+        };
+        let before_range = since.is_some_and(|since| journal.tail_realtime != 0 && journal.tail_realtime < since);
+        let after_range = until.is_some_and(|until| journal.head_realtime > until);
+        if before_range || after_range {
+            continue;
+        }
+
+        match Source::new(journal, &conditions) {
+            Ok(Some(source)) => sources.push(source),
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Skipping journal file {}: {:#}", path.display(), e),
+        }
+    }
+    sources
+}
+
+fn modified_usec(path: &Path) -> Option<u64> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_micros() as u64)
+}
+
+/// `<dir>/<machine-id>/*.journal` と `<dir>/*.journal` を列挙する
+fn journal_paths(directories: &[String]) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for directory in directories {
+        let Ok(entries) = std::fs::read_dir(directory) else {
+            continue;
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            if path.is_dir() {
+                if let Ok(children) = std::fs::read_dir(&path) {
+                    paths.extend(children.filter_map(|child| child.ok()).map(|child| child.path()).filter(|p| is_journal(p)));
+                }
+            } else if is_journal(&path) {
+                paths.push(path);
+            }
+        }
+    }
+    paths
+}
+
+fn is_journal(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "journal" || ext == "journal~")
+}
+
+impl Source {
+    /// 条件ごとに合う DATA オブジェクトを探す。どれかの条件に合うものがファイルに1つもなければ `None`
+    fn new(journal: JournalFile, conditions: &[Vec<Vec<u8>>]) -> Result<Option<Self>> {
+        let mut matched = Vec::new();
+        for fields in conditions {
+            let mut data = Vec::new();
+            for field in fields {
+                if let Some(offset) = journal.find_data(field)? {
+                    data.push(offset);
+                }
+            }
+            if data.is_empty() {
+                return Ok(None);
+            }
+            matched.push(data);
+        }
+
+        // 候補が最も少ない条件の一覧をたどり、残りの条件はエントリーが持つ DATA オブジェクトの位置で確かめる
+        let count = |data: &[u64]| data.iter().map(|offset| journal.data_entry_count(*offset).unwrap_or(0)).sum::<u64>();
+        let primary = (0..matched.len()).min_by_key(|i| count(&matched[*i]));
+        let lists = match primary {
+            Some(primary) => matched.remove(primary).into_iter().map(|offset| journal.data_entries(offset)).collect::<Result<Vec<_>>>()?,
+            None => vec![journal.entries()?],
+        };
+        Ok(Some(Self { journal, lists, filters: matched }))
+    }
+
+    /// 残りの条件に合い、まだ返していないエントリーなら変換して返す。
+    /// アーカイブ済みファイルと現在のファイルにある同じエントリーや、複数の一覧に現れるエントリーは1つにまとめる
+    fn entry(&self, meta: &EntryMeta, seen: &mut HashSet<([u8; 16], u64)>) -> Option<JournalEntry> {
+        if !self.filters.is_empty() {
+            let data = match self.journal.entry_data(meta.offset) {
+                Ok(data) => data,
+                Err(e) => {
+                    tracing::warn!("Skipping unreadable journal entry: {:#}", e);
+                    return None;
+                }
+            };
+            if !self.filters.iter().all(|any| any.iter().any(|offset| data.contains(offset))) {
+                return None;
+            }
+        }
+        if !seen.insert((self.journal.seqnum_id, meta.seqnum)) {
+            return None;
+        }
+        to_entry(&self.journal, meta)
+    }
+}
+
+impl<'a> Walk<'a> {
+    /// 各一覧で `before` が真になる先頭の範囲を二分探索で求め、前向きならその直後から、後ろ向きならその末尾から読む
+    fn new(sources: &'a [Source], forward: bool, before: impl Fn(&EntryMeta) -> bool) -> Self {
+        let mut walk = Self { sources, heads: Vec::new(), forward };
+        for (source, opened) in sources.iter().enumerate() {
+            for (list, entries) in opened.lists.iter().enumerate() {
+                let point = match opened.journal.partition_point(entries, &before) {
+                    Ok(point) => point,
+                    Err(e) => {
+                        tracing::warn!("Skipping unreadable journal entry list: {:#}", e);
+                        continue;
+                    }
+                };
+                let index = if forward { Some(point) } else { point.checked_sub(1) };
+                if let Some(index) = index {
+                    walk.push(source, list, index);
+                }
+            }
+        }
+        walk
+    }
+
+    fn push(&mut self, source: usize, list: usize, index: u64) {
+        let journal = &self.sources[source].journal;
+        let entries = &self.sources[source].lists[list];
+        if index >= entries.len() {
+            return;
+        }
+        match journal.entry_at(entries, index).and_then(|offset| journal.entry_meta(offset)) {
+            Ok(meta) => self.heads.push(Head { source, list, index, meta }),
+            Err(e) => tracing::warn!("Skipping the rest of a journal entry list: {:#}", e),
+        }
+    }
+}
+
+impl Iterator for Walk<'_> {
+    type Item = (usize, EntryMeta);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let heads = self.heads.iter().enumerate().map(|(i, head)| (position(&head.meta), i));
+        let (_, chosen) = if self.forward { heads.min() } else { heads.max() }?;
+
+        let head = self.heads.swap_remove(chosen);
+        let next = if self.forward { Some(head.index + 1) } else { head.index.checked_sub(1) };
+        if let Some(index) = next {
+            self.push(head.source, head.list, index);
+        }
+        Some((head.source, head.meta))
+    }
+}
+
+fn to_entry(journal: &JournalFile, meta: &EntryMeta) -> Option<JournalEntry> {
+    let fields = match journal.entry_fields(meta.offset) {
+        Ok(fields) => fields,
+        Err(e) => {
+            tracing::warn!("Skipping unreadable journal entry: {:#}", e);
+            return None;
+        }
+    };
+    let field = |name: &str| {
+        fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| String::from_utf8_lossy(value).to_string())
+    };
+
+    Some(JournalEntry {
+        cursor: format!(
+            "s={};i={:x};b={};m={:x};t={:x};x={:x}",
+            hex(&journal.seqnum_id),
+            meta.seqnum,
+            hex(&meta.boot_id),
+            meta.monotonic,
+            meta.realtime,
+            meta.xor_hash
+        ),
+        realtime: DateTime::from_timestamp_micros(meta.realtime as i64).unwrap_or_default(),
+        boot_id: hex(&meta.boot_id),
+        hostname: field("_HOSTNAME"),
+        unit: field("_SYSTEMD_UNIT"),
+        identifier: field("SYSLOG_IDENTIFIER").or_else(|| field("_COMM")),
+        pid: field("_PID").and_then(|pid| pid.parse().ok()),
+        priority: field("PRIORITY").and_then(|p| p.parse::<u8>().ok()),
+        message: field("MESSAGE"),
+    })
+}
+
+fn hex(id: &[u8; 16]) -> String {
+    id.iter().fold(String::with_capacity(32), |mut s, b| {
+        let _ = write!(s, "{:02x}", b);
+        s
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use file::{jenkins_hash64, siphash24};
+    use std::{collections::HashMap, fs, io::Cursor};
+
+    /// 1つのエントリー配列に入るエントリー数。小さくして配列の連結もたどらせる
+    const ARRAY_CAPACITY: u64 = 2;
+    const HEADER_SIZE: usize = 208;
+    const BUCKETS: u64 = 16;
+
+    #[derive(Clone, Copy)]
+    enum Compression {
+        None,
+        Lz4,
+        Zstd,
+        Xz,
+    }
+
+    /// エントリー配列の連結リストへの追記位置。`slot` は最初の配列の位置を書く場所
+    #[derive(Clone, Copy)]
+    struct Chain {
+        slot: u64,
+        array: u64,
+        filled: u64,
+    }
+
+    /// 通常（compact でない）形式の小さなジャーナルファイルを組み立てる
+    struct Fixture {
+        buf: Vec<u8>,
+        keyed: bool,
+        file_id: [u8; 16],
+        hash_table: u64,
+        entries: Chain,
+        data: HashMap<String, (u64, Chain)>,
+        seqnum: u64,
+        n_entries: u64,
+    }
+
+    impl Fixture {
+        fn new(file_id: u8) -> Self {
+            Self::with_hash(file_id, true)
+        }
+
+        /// `keyed` でなければ、鍵付きハッシュより前の形式（Jenkins のハッシュ）で書く
+        fn with_hash(file_id: u8, keyed: bool) -> Self {
+            let mut buf = vec![0u8; HEADER_SIZE];
+            buf[..8].copy_from_slice(b"LPKSHHRH");
+            if keyed {
+                buf[12] = 1 << 2;
+            }
+            buf[24..40].fill(file_id);
+            buf[72..88].fill(0xAB);
+            buf[88..96].copy_from_slice(&(HEADER_SIZE as u64).to_le_bytes());
+            let mut fixture = Self {
+                buf,
+                keyed,
+                file_id: [file_id; 16],
+                hash_table: 0,
+                entries: Chain { slot: 176, array: 0, filled: 0 },
+                data: HashMap::new(),
+                seqnum: 0,
+                n_entries: 0,
+            };
+            fixture.hash_table = fixture.object(4, 0, &vec![0u8; BUCKETS as usize * 16]) + 16;
+            fixture.set_u64(104, fixture.hash_table);
+            fixture.set_u64(112, BUCKETS * 16);
+            fixture
+        }
+
+        /// 次のエントリーの seqnum を `seqnum` にする。ローテーションで前のファイルから続く番号を再現する
+        fn starting_at(mut self, seqnum: u64) -> Self {
+            self.seqnum = seqnum - 1;
+            self
+        }
+
+        fn u64_at(&self, offset: u64) -> u64 {
+            u64::from_le_bytes(self.buf[offset as usize..offset as usize + 8].try_into().unwrap())
+        }
+
+        fn set_u64(&mut self, offset: u64, value: u64) {
+            self.buf[offset as usize..offset as usize + 8].copy_from_slice(&value.to_le_bytes());
+        }
+
+        fn object(&mut self, kind: u8, flags: u8, body: &[u8]) -> u64 {
+            self.buf.resize(self.buf.len().next_multiple_of(8), 0);
+            let offset = self.buf.len() as u64;
+            self.buf.extend_from_slice(&[kind, flags, 0, 0, 0, 0, 0, 0]);
+            self.buf.extend_from_slice(&(16 + body.len() as u64).to_le_bytes());
+            self.buf.extend_from_slice(body);
+            offset
+        }
+
+        fn append(&mut self, mut chain: Chain, entry: u64) -> Chain {
+            if chain.array == 0 || chain.filled == ARRAY_CAPACITY {
+                let array = self.object(6, 0, &vec![0u8; 8 + ARRAY_CAPACITY as usize * 8]);
+                self.set_u64(if chain.array == 0 { chain.slot } else { chain.array + 16 }, array);
+                chain = Chain { slot: chain.slot, array, filled: 0 };
+            }
+            self.set_u64(chain.array + 24 + chain.filled * 8, entry);
+            chain.filled += 1;
+            chain
+        }
+
+        /// 同じフィールドは1つの DATA オブジェクトを共有し、ハッシュテーブルにつなぐ
+        fn data(&mut self, field: &str, compression: Compression) -> u64 {
+            if let Some((offset, _)) = self.data.get(field) {
+                return *offset;
+            }
+            let payload = field.as_bytes();
+            let hash = if self.keyed { siphash24(payload, &self.file_id) } else { jenkins_hash64(payload) };
+            let (flags, payload) = match compression {
+                Compression::None => (0, payload.to_vec()),
+                Compression::Lz4 => {
+                    let mut compressed = (payload.len() as u64).to_le_bytes().to_vec();
+                    compressed.extend(lz4_flex::block::compress(payload));
+                    (1 << 1, compressed)
+                }
+                Compression::Zstd => (1 << 2, ruzstd::encoding::compress_to_vec(payload, ruzstd::encoding::CompressionLevel::Fastest)),
+                Compression::Xz => {
+                    let mut compressed = Vec::new();
+                    lzma_rs::xz_compress(&mut Cursor::new(payload), &mut compressed).unwrap();
+                    (1 << 0, compressed)
+                }
+            };
+            // hash, next_hash_offset, next_field_offset, entry_offset, entry_array_offset, n_entries
+            let mut body = hash.to_le_bytes().to_vec();
+            body.extend([0u8; 40]);
+            body.extend(payload);
+            let offset = self.object(1, flags, &body);
+
+            let bucket = self.hash_table + hash % BUCKETS * 16;
+            match self.u64_at(bucket + 8) {
+                0 => self.set_u64(bucket, offset),
+                tail => self.set_u64(tail + 24, offset),
+            }
+            self.set_u64(bucket + 8, offset);
+            self.data.insert(field.to_string(), (offset, Chain { slot: offset + 48, array: 0, filled: 0 }));
+            offset
+        }
+
+        fn add(&mut self, realtime: u64, message: &str, compression: Compression) {
+            self.add_fields(realtime, &[(&format!("MESSAGE={}", message), compression), ("_SYSTEMD_UNIT=test.service", Compression::None), ("PRIORITY=6", Compression::None)]);
+        }
+
+        fn add_fields(&mut self, realtime: u64, fields: &[(&str, Compression)]) {
+            let data = fields.iter().map(|(field, compression)| self.data(field, *compression)).collect::<Vec<_>>();
+            self.seqnum += 1;
+            let mut body = Vec::new();
+            body.extend(self.seqnum.to_le_bytes());
+            body.extend(realtime.to_le_bytes());
+            body.extend(realtime.to_le_bytes());
+            body.extend([0x11; 16]);
+            body.extend(0u64.to_le_bytes());
+            for offset in &data {
+                body.extend(offset.to_le_bytes());
+                body.extend(0u64.to_le_bytes());
+            }
+            let entry = self.object(3, 0, &body);
+
+            self.entries = self.append(self.entries, entry);
+            for (field, _) in fields {
+                let (offset, chain) = self.data[*field];
+                let n_entries = self.u64_at(offset + 56);
+                if n_entries == 0 {
+                    self.set_u64(offset + 40, entry);
+                } else {
+                    let chain = self.append(chain, entry);
+                    self.data.insert(field.to_string(), (offset, chain));
+                }
+                self.set_u64(offset + 56, n_entries + 1);
+            }
+
+            if self.n_entries == 0 {
+                self.set_u64(184, realtime);
+            }
+            self.n_entries += 1;
+            self.set_u64(152, self.n_entries);
+            self.set_u64(192, realtime);
+        }
+
+        /// `MESSAGE` を `n`、時刻を `n` ミリ秒にしたエントリーを追加する
+        fn add_numbered(&mut self, n: u64, unit: &str, priority: u8) {
+            self.add_fields(n * 1_000, &[
+                (&format!("MESSAGE={}", n), Compression::None),
+                (&format!("_SYSTEMD_UNIT={}", unit), Compression::None),
+                (&format!("PRIORITY={}", priority), Compression::None),
+            ]);
+        }
+
+        fn write(&self, path: &Path) {
+            fs::write(path, &self.buf).unwrap();
+        }
+    }
+
+    fn query() -> JournalQuery {
+        JournalQuery {
+            unit: None,
+            priority: None,
+            since: None,
+            until: None,
+            after: None,
+            before: None,
+            limit: None,
+            follow: false,
+        }
+    }
+
+    fn messages(entries: &[JournalEntry]) -> Vec<String> {
+        entries.iter().filter_map(|entry| entry.message.clone()).collect()
+    }
+
+    fn numbers(page: &JournalPage) -> (Vec<u64>, bool) {
+        (page.entries.iter().filter_map(|entry| entry.message.as_deref()?.parse().ok()).collect(), page.has_more)
+    }
+
+    fn usec(usec: i64) -> Option<DateTime<Utc>> {
+        DateTime::from_timestamp_micros(usec)
+    }
+
+    /// 1〜7 のエントリーを持つジャーナルを置いたディレクトリ
+    fn numbered_journal(dir: &tempfile::TempDir) -> Vec<String> {
+        let mut fixture = Fixture::new(1);
+        for n in 1..=7 {
+            fixture.add_numbered(n, "test.service", 6);
+        }
+        fixture.write(&dir.path().join("system.journal"));
+        vec![dir.path().to_string_lossy().to_string()]
+    }
+
+    #[test]
+    fn hashes_match_reference_values() {
+        // lookup3.c の driver5 の値
+        assert_eq!(jenkins_hash64(b""), 0xdeadbeef_deadbeef);
+        assert_eq!(jenkins_hash64(b"Four score and seven years ago"), 0x17770551_ce7226e6);
+        // SipHash の論文の付録にあるテストベクター（鍵 00..0f、空の入力）
+        let key = std::array::from_fn(|i| i as u8);
+        assert_eq!(siphash24(b"", &key), 0x726fdb47dd0e0e31);
+    }
+
+    #[test]
+    fn reads_compressed_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut fixture = Fixture::new(1);
+        let long = "compressible ".repeat(64);
+        fixture.add(1_000, "plain", Compression::None);
+        fixture.add(2_000, &long, Compression::Lz4);
+        fixture.add(3_000, &format!("zstd {}", long), Compression::Zstd);
+        fixture.add(4_000, &format!("xz {}", long), Compression::Xz);
+        fixture.write(&dir.path().join("system.journal"));
+
+        let directories = vec![dir.path().to_string_lossy().to_string()];
+        let page = read_page(&directories, &query()).unwrap();
+        assert_eq!(messages(&page.entries), vec!["plain".to_string(), long.clone(), format!("zstd {}", long), format!("xz {}", long)]);
+        assert!(!page.has_more);
+        assert!(page.entries.iter().all(|entry| entry.unit.as_deref() == Some("test.service") && entry.priority == Some(6)));
+    }
+
+    #[test]
+    fn pages_with_cursors() {
+        let dir = tempfile::tempdir().unwrap();
+        let directories = numbered_journal(&dir);
+        let page = |before: Option<&JournalEntry>, after: Option<&JournalEntry>, limit: usize| {
+            let query = JournalQuery {
+                before: before.map(|entry| entry.cursor.clone()),
+                after: after.map(|entry| entry.cursor.clone()),
+                limit: Some(limit),
+                ..query()
+            };
+            read_page(&directories, &query).unwrap()
+        };
+
+        // 何も指定しなければ最新のページ
+        let latest = page(None, None, 3);
+        assert_eq!(numbers(&latest), (vec![5, 6, 7], true));
+        let older = page(Some(&latest.entries[0]), None, 3);
+        assert_eq!(numbers(&older), (vec![2, 3, 4], true));
+        let oldest = page(Some(&older.entries[0]), None, 3);
+        assert_eq!(numbers(&oldest), (vec![1], false));
+
+        let newer = page(None, Some(&oldest.entries[0]), 3);
+        assert_eq!(numbers(&newer), (vec![2, 3, 4], true));
+        assert_eq!(numbers(&page(None, Some(&newer.entries[2]), 3)), (vec![5, 6, 7], false));
+        assert_eq!(numbers(&page(None, Some(&latest.entries[2]), 3)), (vec![], false));
+
+        assert!(read_page(&directories, &JournalQuery { after: Some("t=zz".to_string()), ..query() }).is_err());
+    }
+
+    #[test]
+    fn filters_by_time_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let directories = numbered_journal(&dir);
+        let page = |since: Option<i64>, until: Option<i64>, limit: usize| {
+            let query = JournalQuery { since: since.and_then(usec), until: until.and_then(usec), limit: Some(limit), ..query() };
+            numbers(&read_page(&directories, &query).unwrap())
+        };
+
+        // 両端を含む。`since` があれば古い方から読む
+        assert_eq!(page(Some(3_000), Some(5_000), 10), (vec![3, 4, 5], false));
+        assert_eq!(page(Some(2_500), None, 2), (vec![3, 4], true));
+        // `until` だけなら新しい方から読む
+        assert_eq!(page(None, Some(4_500), 2), (vec![3, 4], true));
+        assert_eq!(page(Some(7_001), None, 10), (vec![], false));
+    }
+
+    #[test]
+    fn filters_by_unit_and_priority() {
+        // 鍵付きハッシュと Jenkins のハッシュの両方の形式で、ハッシュテーブルから探せること
+        for keyed in [true, false] {
+            let dir = tempfile::tempdir().unwrap();
+            let mut fixture = Fixture::with_hash(1, keyed);
+            fixture.add_numbered(1, "nginx.service", 6);
+            fixture.add_numbered(2, "sshd.service", 3);
+            fixture.add_numbered(3, "nginx.service", 3);
+            // systemd がユニットについて出したメッセージ
+            fixture.add_fields(4_000, &[
+                ("MESSAGE=4", Compression::None),
+                ("_SYSTEMD_UNIT=init.scope", Compression::None),
+                ("UNIT=nginx.service", Compression::None),
+                ("PRIORITY=5", Compression::None),
+            ]);
+            fixture.add_numbered(5, "cron.service", 7);
+            fixture.add_numbered(6, "nginx.service", 2);
+            fixture.add_numbered(7, "nginx.timer", 2);
+            fixture.write(&dir.path().join("system.journal"));
+            let directories = vec![dir.path().to_string_lossy().to_string()];
+            let page = |unit: Option<&str>, priority: Option<u8>| {
+                numbers(&read_page(&directories, &JournalQuery { unit: unit.map(String::from), priority, ..query() }).unwrap())
+            };
+
+            assert_eq!(page(Some("nginx"), None), (vec![1, 3, 4, 6], false));
+            assert_eq!(page(Some("nginx.timer"), None), (vec![7], false));
+            assert_eq!(page(None, Some(3)), (vec![2, 3, 6, 7], false));
+            assert_eq!(page(Some("nginx.service"), Some(5)), (vec![3, 4, 6], false));
+            assert_eq!(page(Some("missing"), None), (vec![], false));
+            assert_eq!(page(Some("cron"), Some(6)), (vec![], false));
+        }
+    }
+
+    #[test]
+    fn merges_archived_and_active_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let machine = dir.path().join("0123456789abcdef0123456789abcdef");
+        fs::create_dir(&machine).unwrap();
+
+        let mut archived = Fixture::new(1);
+        for n in 1..=4 {
+            archived.add_numbered(n * 10, "app.service", 6);
+        }
+        archived.write(&machine.join("system@abab-0000000000000001-0000000000000001.journal"));
+        // 同じ seqnum の系列で 30 と 40 を重複して持つ
+        let mut active = Fixture::new(2).starting_at(3);
+        for n in 3..=6 {
+            active.add_numbered(n * 10, if n == 5 { "other.service" } else { "app.service" }, 6);
+        }
+        active.write(&machine.join("system.journal"));
+        // 別の系列のファイルは時刻の順に混ぜる
+        let mut user = Fixture::new(3);
+        user.buf[72..88].fill(0xCD);
+        user.add_numbered(25, "app.service", 6);
+        user.write(&machine.join("user-1000.journal"));
+
+        let directories = vec![dir.path().to_string_lossy().to_string()];
+        let page = |unit: Option<&str>, limit: usize| {
+            numbers(&read_page(&directories, &JournalQuery { unit: unit.map(String::from), limit: Some(limit), ..query() }).unwrap())
+        };
+        assert_eq!(page(None, 100), (vec![10, 20, 25, 30, 40, 50, 60], false));
+        assert_eq!(page(Some("app"), 100), (vec![10, 20, 25, 30, 40, 60], false));
+        // 重複を除いた上で件数を数える
+        assert_eq!(page(None, 4), (vec![30, 40, 50, 60], true));
+
+        let (entries, last) = read_after(&directories, &query(), (25_000, u64::MAX)).unwrap();
+        assert_eq!(messages(&entries), vec!["30", "40", "50", "60"]);
+        assert_eq!(last, (60_000, 6));
+    }
+
+    #[test]
+    fn follows_appended_entries_and_replaced_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("system.journal");
+        let directories = vec![dir.path().to_string_lossy().to_string()];
+        let mut fixture = Fixture::new(3);
+        fixture.add(1_000, "first", Compression::None);
+        fixture.write(&path);
+
+        let page = read_page(&directories, &query()).unwrap();
+        let mut position = parse_cursor(&page.entries[0].cursor).unwrap();
+        let (entries, last) = read_after(&directories, &query(), position).unwrap();
+        assert!(entries.is_empty());
+        assert_eq!(last, position);
+
+        // 途中まで埋まった配列の続きと、新しく連結された配列の両方から読む
+        fixture.add(2_000, "second", Compression::Zstd);
+        fixture.add(3_000, "third", Compression::Xz);
+        fixture.write(&path);
+        let (entries, last) = read_after(&directories, &query(), position).unwrap();
+        assert_eq!(messages(&entries), vec!["second", "third"]);
+        position = last;
+
+        // 条件に合わないエントリーは一覧にないので、位置は進めずに次も二分探索で飛ばす
+        fixture.add_numbered(4, "other.service", 6);
+        fixture.write(&path);
+        let unit = JournalQuery { unit: Some("test".to_string()), ..query() };
+        let (entries, last) = read_after(&directories, &unit, position).unwrap();
+        assert!(entries.is_empty());
+        assert_eq!(last, position);
+        let (entries, last) = read_after(&directories, &query(), position).unwrap();
+        assert_eq!(messages(&entries), vec!["4"]);
+        position = last;
+
+        // 同じパスが別のファイルに置き換わったら最初から読み直す
+        let mut replaced = Fixture::new(4);
+        replaced.add(5_000, "rotated", Compression::None);
+        replaced.write(&path);
+        let (entries, _) = read_after(&directories, &query(), position).unwrap();
+        assert_eq!(messages(&entries), vec!["rotated"]);
+    }
+}
@@ -0,0 +1,67 @@
+pub mod journal;
+pub mod search;
+pub mod tail;
+pub mod timestamp;
+
+use crate::app::config::LogsConfig;
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+pub enum LogPathError {
+    Invalid,
+    NotAllowed,
+    NotFound,
+    Io(io::Error),
+}
+
+impl LogPathError {
+    pub fn message(&self) -> String {
+        match self {
+            LogPathError::Invalid => "path must be absolute and must not contain `..`".to_string(),
+            LogPathError::NotAllowed => "reading this path is not allowed".to_string(),
+            LogPathError::NotFound => "file not found".to_string(),
+            LogPathError::Io(e) => e.to_string(),
+        }
+    }
+}
+
+/// 許可されたパスかを確認し、シンボリックリンクを解決した実体のパスを返す
+pub async fn resolve_path(config: &LogsConfig, path: &str) -> Result<PathBuf, LogPathError> {
+    let path = Path::new(path);
+    if !path.is_absolute() || path.components().any(|c| c == Component::ParentDir) {
+        return Err(LogPathError::Invalid);
+    }
+
+    let allowed = allowed_paths(config)?;
+    if !allowed.is_match(path) {
+        return Err(LogPathError::NotAllowed);
+    }
+
+    // リンク先が許可範囲外を指していないかも確認する
+    let canonical = tokio::fs::canonicalize(path).await.map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => LogPathError::NotFound,
+        _ => LogPathError::Io(e),
+    })?;
+    if !allowed.is_match(&canonical) {
+        return Err(LogPathError::NotAllowed);
+    }
+
+    Ok(canonical)
+}
+
+fn allowed_paths(config: &LogsConfig) -> Result<GlobSet, LogPathError> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in &config.allowed_paths {
+        match GlobBuilder::new(pattern).literal_separator(true).build() {
+            Ok(glob) => {
+                builder.add(glob);
+            }
+            Err(e) => tracing::warn!("Ignoring invalid log path pattern {}: {}", pattern, e),
+        }
+    }
+    builder.build().map_err(|e| LogPathError::Io(io::Error::other(e)))
+}
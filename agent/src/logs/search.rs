@@ -0,0 +1,313 @@
+use crate::logs::timestamp::TimestampParser;
+use common::agent::log::{LogMatch, LogSearchQuery, LogSearchResult};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, Utc};
+use flate2::read::GzDecoder;
+use regex::Regex;
+
+pub const DEFAULT_LIMIT: usize = 100;
+pub const MAX_LIMIT: usize = 1000;
+pub const MAX_CONTEXT: usize = 10;
+
+/// 一致した行と、後続の文脈行がまだ何行必要か
+struct Pending {
+    found: LogMatch,
+    remaining: usize,
+}
+
+/// ローテーション済みのファイル（古い順）と現在のファイルを順に検索する
+pub fn search(path: &Path, query: &LogSearchQuery, regex: &Regex, deadline: Instant) -> Result<LogSearchResult> {
+    let mut files = if query.include_rotated { rotated_siblings(path)? } else { Vec::new() };
+    files.push(path.to_path_buf());
+
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let context = query.context.min(MAX_CONTEXT);
+    let has_range = query.from.is_some() || query.to.is_some();
+
+    let mut matches = Vec::new();
+    let mut pending: Vec<Pending> = Vec::new();
+    let mut seen = 0;
+    let mut has_more = false;
+    let mut timed_out = false;
+
+    'files: for file in &files {
+        let mut reader = open(file)?;
+        let name = file.to_string_lossy().to_string();
+        let parser = TimestampParser::new(modified(file));
+        let mut before = VecDeque::with_capacity(context);
+        // タイムスタンプのない行（スタックトレースなど）は直前の行の時刻を引き継ぐ
+        let mut timestamp: Option<DateTime<Utc>> = None;
+        let mut buffer = Vec::new();
+        let mut line_number = 0;
+
+        loop {
+            buffer.clear();
+            if reader.read_until(b'\n', &mut buffer).with_context(|| format!("failed to read {}", name))? == 0 {
+                break;
+            }
+            line_number += 1;
+            if line_number % 1024 == 0 && Instant::now() >= deadline {
+                timed_out = true;
+                break 'files;
+            }
+
+            let line = String::from_utf8_lossy(&buffer);
+            let line = line.trim_end_matches(['\n', '\r']).to_string();
+            // 時間範囲の指定がなければ一致した行だけ時刻を読む
+            if has_range && let Some(parsed) = parser.parse(&line) {
+                timestamp = Some(parsed);
+            }
+
+            for entry in pending.iter_mut().filter(|entry| entry.remaining > 0) {
+                entry.found.after.push(line.clone());
+                entry.remaining -= 1;
+            }
+
+            if in_range(timestamp, query) && regex.is_match(&line) {
+                if !has_range {
+                    timestamp = parser.parse(&line);
+                }
+                if seen >= query.offset + limit {
+                    has_more = true;
+                } else if seen >= query.offset {
+                    pending.push(Pending {
+                        found: LogMatch {
+                            file: name.clone(),
+                            line_number,
+                            line: line.clone(),
+                            timestamp,
+                            before: before.iter().cloned().collect(),
+                            after: Vec::new(),
+                        },
+                        remaining: context,
+                    });
+                }
+                seen += 1;
+            }
+            while pending.first().is_some_and(|entry| entry.remaining == 0) {
+                matches.push(pending.remove(0).found);
+            }
+
+            if has_more && pending.is_empty() {
+                break 'files;
+            }
+
+            if context > 0 {
+                if before.len() == context {
+                    before.pop_front();
+                }
+                before.push_back(line);
+            }
+        }
+
+        // 文脈行はファイルをまたがない
+        matches.extend(pending.drain(..).map(|entry| entry.found));
+    }
+    matches.extend(pending.into_iter().map(|entry| entry.found));
+
+    Ok(LogSearchResult {
+        files: files.iter().map(|file| file.to_string_lossy().to_string()).collect(),
+        next_offset: has_more.then_some(query.offset + limit),
+        matches,
+        timed_out,
+    })
+}
+
+fn in_range(timestamp: Option<DateTime<Utc>>, query: &LogSearchQuery) -> bool {
+    if query.from.is_none() && query.to.is_none() {
+        return true;
+    }
+    let Some(timestamp) = timestamp else {
+        return false;
+    };
+    query.from.is_none_or(|from| timestamp >= from) && query.to.is_none_or(|to| timestamp <= to)
+}
+
+/// 年のない時刻の基準にする最終更新時刻。読めなければ現在時刻
+fn modified(path: &Path) -> DateTime<Local> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map(DateTime::<Local>::from)
+        .unwrap_or_else(|_| Local::now())
+}
+
+fn open(path: &Path) -> Result<Box<dyn BufRead>> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        Ok(Box::new(BufReader::new(GzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+/// `syslog.1` や `syslog.2.gz` を番号の大きい（古い）順に返す
+fn rotated_siblings(path: &Path) -> Result<Vec<PathBuf>> {
+    let (Some(dir), Some(base)) = (path.parent(), path.file_name().and_then(|name| name.to_str())) else {
+        return Ok(Vec::new());
+    };
+
+    let mut siblings = std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read {}", dir.display()))?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let suffix = name.strip_prefix(base)?.strip_prefix('.')?;
+            let number = suffix.strip_suffix(".gz").unwrap_or(suffix).parse::<u32>().ok()?;
+            // 許可範囲外へのシンボリックリンクは辿らない
+            let file_type = entry.file_type().ok()?;
+            file_type.is_file().then(|| (number, entry.path()))
+        })
+        .collect::<Vec<(u32, PathBuf)>>();
+    siblings.sort_by_key(|(number, _)| std::cmp::Reverse(*number));
+
+    Ok(siblings.into_iter().map(|(_, path)| path).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        fs,
+        io::Write,
+        time::{Duration, SystemTime},
+    };
+
+    use chrono::TimeZone;
+    use flate2::{Compression, write::GzEncoder};
+
+    fn query(pattern: &str) -> LogSearchQuery {
+        LogSearchQuery {
+            path: String::new(),
+            pattern: pattern.to_string(),
+            ignore_case: false,
+            from: None,
+            to: None,
+            context: 0,
+            offset: 0,
+            limit: None,
+            include_rotated: true,
+        }
+    }
+
+    fn run(path: &Path, query: &LogSearchQuery) -> LogSearchResult {
+        let regex = Regex::new(&query.pattern).unwrap();
+        search(path, query, &regex, Instant::now() + Duration::from_secs(10)).unwrap()
+    }
+
+    fn lines(result: &LogSearchResult) -> Vec<&str> {
+        result.matches.iter().map(|found| found.line.as_str()).collect()
+    }
+
+    fn write_gz(path: &Path, content: &str) {
+        let mut encoder = GzEncoder::new(fs::File::create(path).unwrap(), Compression::default());
+        encoder.write_all(content.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+    }
+
+    #[test]
+    fn searches_rotated_files_from_oldest_to_newest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("syslog");
+        write_gz(&dir.path().join("syslog.10.gz"), "hit oldest\n");
+        write_gz(&dir.path().join("syslog.2.gz"), "hit older\nmiss\n");
+        fs::write(dir.path().join("syslog.1"), "hit old\n").unwrap();
+        fs::write(&path, "miss\nhit current\n").unwrap();
+        // 番号のないものや別のログは対象にしない
+        fs::write(dir.path().join("syslog.bak"), "hit backup\n").unwrap();
+        fs::write(dir.path().join("syslog.1.bak"), "hit backup\n").unwrap();
+        fs::write(dir.path().join("auth.log.1"), "hit other\n").unwrap();
+
+        let result = run(&path, &query("hit"));
+        assert_eq!(lines(&result), ["hit oldest", "hit older", "hit old", "hit current"]);
+        let files = result.files.iter().map(|file| Path::new(file).file_name().unwrap().to_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(files, ["syslog.10.gz", "syslog.2.gz", "syslog.1", "syslog"]);
+        assert_eq!(result.matches[3].line_number, 2);
+
+        let result = run(&path, &LogSearchQuery { include_rotated: false, ..query("hit") });
+        assert_eq!(lines(&result), ["hit current"]);
+    }
+
+    #[test]
+    fn overlapping_context_is_repeated_for_each_match() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        fs::write(dir.path().join("app.log.1"), "a1\na2\nERR a3\n").unwrap();
+        fs::write(&path, "l1\nl2\nERR l3\nERR l4\nl5\nl6\nl7\n").unwrap();
+
+        let result = run(&path, &LogSearchQuery { context: 2, ..query("ERR") });
+        let context = result
+            .matches
+            .iter()
+            .map(|found| (found.line.as_str(), found.before.clone(), found.after.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(context, [
+            // 文脈行はファイルをまたがない
+            ("ERR a3", vec!["a1".to_string(), "a2".to_string()], vec![]),
+            ("ERR l3", vec!["l1".to_string(), "l2".to_string()], vec!["ERR l4".to_string(), "l5".to_string()]),
+            ("ERR l4", vec!["l2".to_string(), "ERR l3".to_string()], vec!["l5".to_string(), "l6".to_string()]),
+        ]);
+
+        // 上限を超える文脈は切り詰める
+        let long = dir.path().join("long.log");
+        fs::write(&long, (1..=20).map(|i| format!("line {}\n", i)).collect::<String>()).unwrap();
+        let result = run(&long, &LogSearchQuery { context: 100, ..query("line 20") });
+        assert_eq!(result.matches[0].before.len(), MAX_CONTEXT);
+        assert_eq!(result.matches[0].before[0], "line 10");
+    }
+
+    #[test]
+    fn pages_through_matches_with_offsets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        fs::write(dir.path().join("app.log.1"), "hit 1\nhit 2\nmiss\n").unwrap();
+        fs::write(&path, "hit 3\nmiss\nhit 4\nhit 5\n").unwrap();
+
+        let mut pages = Vec::new();
+        let mut offset = Some(0);
+        while let Some(current) = offset {
+            let result = run(&path, &LogSearchQuery { offset: current, limit: Some(2), context: 1, ..query("hit") });
+            pages.push(lines(&result).into_iter().map(str::to_string).collect::<Vec<_>>());
+            offset = result.next_offset;
+        }
+        assert_eq!(pages, [vec!["hit 1", "hit 2"], vec!["hit 3", "hit 4"], vec!["hit 5"]]);
+
+        // 最後の一致がちょうど上限に収まるときは次のページがない
+        let result = run(&path, &LogSearchQuery { offset: 3, limit: Some(2), ..query("hit") });
+        assert_eq!(lines(&result), ["hit 4", "hit 5"]);
+        assert_eq!(result.next_offset, None);
+
+        let result = run(&path, &LogSearchQuery { offset: 10, ..query("hit") });
+        assert!(result.matches.is_empty());
+        assert_eq!(result.next_offset, None);
+    }
+
+    #[test]
+    fn time_range_uses_the_year_of_each_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("syslog");
+        let rotated = dir.path().join("syslog.1");
+        fs::write(&rotated, "Dec 31 23:00:00 host app: old year\n    continued\n").unwrap();
+        fs::write(&path, "Jan  1 01:00:00 host app: new year\n").unwrap();
+        let set_mtime = |path: &Path, time: DateTime<Local>| {
+            let file = fs::File::options().write(true).open(path).unwrap();
+            file.set_modified(SystemTime::from(time)).unwrap();
+        };
+        set_mtime(&rotated, Local.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap());
+        set_mtime(&path, Local.with_ymd_and_hms(2025, 1, 1, 2, 0, 0).unwrap());
+
+        let from = Local.with_ymd_and_hms(2024, 12, 31, 0, 0, 0).unwrap().with_timezone(&Utc);
+        let to = Local.with_ymd_and_hms(2024, 12, 31, 23, 59, 59).unwrap().with_timezone(&Utc);
+        let result = run(&path, &LogSearchQuery { from: Some(from), to: Some(to), ..query("") });
+        // 時刻のない行は直前の行の時刻を引き継ぐ
+        assert_eq!(lines(&result), ["Dec 31 23:00:00 host app: old year", "    continued"]);
+        assert_eq!(result.matches[0].timestamp, Some(Local.with_ymd_and_hms(2024, 12, 31, 23, 0, 0).unwrap().with_timezone(&Utc)));
+    }
+}
@@ -0,0 +1,229 @@
+use common::agent::log::LogTailEvent;
+use std::{
+    io::SeekFrom,
+    os::unix::fs::MetadataExt,
+    path::PathBuf,
+    time::Duration,
+};
+
+use anyhow::Result;
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::mpsc,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const CHUNK_SIZE: usize = 64 * 1024;
+/// 改行が来ないまま溜まり続けないよう、この長さで1行として区切る
+const MAX_LINE_BYTES: usize = 64 * 1024;
+
+/// ファイルを追従し、ローテーション（inode の変化）と切り詰めを検知する
+pub struct Follower {
+    path: PathBuf,
+    file: File,
+    inode: (u64, u64),
+    offset: u64,
+    partial: Vec<u8>,
+}
+
+impl Follower {
+    pub async fn open(path: PathBuf) -> std::io::Result<Self> {
+        let file = File::open(&path).await?;
+        let metadata = file.metadata().await?;
+        Ok(Self {
+            path,
+            file,
+            inode: (metadata.dev(), metadata.ino()),
+            offset: 0,
+            partial: Vec::new(),
+        })
+    }
+
+    /// 末尾から `count` 行を読み、以降はその位置から追従する
+    pub async fn read_last_lines(&mut self, count: usize) -> Result<Vec<String>> {
+        let size = self.file.metadata().await?.len();
+        let mut start = size;
+        let mut buffer = Vec::new();
+
+        while start > 0 && buffer.iter().filter(|b| **b == b'\n').count() <= count {
+            let next = start.saturating_sub(CHUNK_SIZE as u64);
+            let mut chunk = vec![0; (start - next) as usize];
+            self.file.seek(SeekFrom::Start(next)).await?;
+            self.file.read_exact(&mut chunk).await?;
+            chunk.extend_from_slice(&buffer);
+            buffer = chunk;
+            start = next;
+        }
+
+        self.offset = size;
+        self.file.seek(SeekFrom::Start(size)).await?;
+
+        if buffer.is_empty() {
+            return Ok(Vec::new());
+        }
+        let text = String::from_utf8_lossy(&buffer);
+        let lines = text.strip_suffix('\n').unwrap_or(&text).split('\n').collect::<Vec<&str>>();
+        Ok(lines[lines.len().saturating_sub(count)..].iter().map(|line| line.to_string()).collect())
+    }
+
+    pub async fn run(mut self, tx: mpsc::Sender<LogTailEvent>) {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            if tx.is_closed() {
+                return;
+            }
+
+            if let Err(e) = self.poll(&tx).await {
+                tracing::warn!("Failed to follow {}: {}", self.path.display(), e);
+            }
+        }
+    }
+
+    async fn poll(&mut self, tx: &mpsc::Sender<LogTailEvent>) -> Result<()> {
+        let size = self.file.metadata().await?.len();
+        if size < self.offset {
+            self.offset = 0;
+            self.partial.clear();
+            self.file.seek(SeekFrom::Start(0)).await?;
+            let _ = tx.send(LogTailEvent::Truncated).await;
+        }
+        self.read_new_lines(tx).await?;
+
+        // ファイルが置き換えられていれば旧ファイルを読み切ってから開き直す
+        let Ok(metadata) = tokio::fs::metadata(&self.path).await else {
+            return Ok(());
+        };
+        if (metadata.dev(), metadata.ino()) != self.inode {
+            self.flush_partial(tx).await;
+            self.file = File::open(&self.path).await?;
+            self.inode = (metadata.dev(), metadata.ino());
+            self.offset = 0;
+            let _ = tx.send(LogTailEvent::Rotated).await;
+            self.read_new_lines(tx).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn read_new_lines(&mut self, tx: &mpsc::Sender<LogTailEvent>) -> Result<()> {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        loop {
+            let read = self.file.read(&mut chunk).await?;
+            if read == 0 {
+                return Ok(());
+            }
+            self.offset += read as u64;
+            self.partial.extend_from_slice(&chunk[..read]);
+
+            while let Some(pos) = self.partial.iter().position(|b| *b == b'\n') {
+                let line = self.partial.drain(..=pos).collect::<Vec<u8>>();
+                let line = String::from_utf8_lossy(&line[..line.len() - 1]).to_string();
+                if tx.send(LogTailEvent::Line { line }).await.is_err() {
+                    return Ok(());
+                }
+            }
+            if self.partial.len() >= MAX_LINE_BYTES {
+                self.flush_partial(tx).await;
+            }
+        }
+    }
+
+    async fn flush_partial(&mut self, tx: &mpsc::Sender<LogTailEvent>) {
+        if self.partial.is_empty() {
+            return;
+        }
+        let line = String::from_utf8_lossy(&self.partial).to_string();
+        self.partial.clear();
+        let _ = tx.send(LogTailEvent::Line { line }).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, io::Write};
+
+    fn append(path: &std::path::Path, content: &str) {
+        fs::OpenOptions::new().append(true).open(path).unwrap().write_all(content.as_bytes()).unwrap();
+    }
+
+    fn received(rx: &mut mpsc::Receiver<LogTailEvent>) -> Vec<String> {
+        let mut events = Vec::new();
+        while let Ok(event) = rx.try_recv() {
+            events.push(match event {
+                LogTailEvent::Line { line } => line,
+                LogTailEvent::Rotated => "<rotated>".to_string(),
+                LogTailEvent::Truncated => "<truncated>".to_string(),
+            });
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn reads_the_last_lines_across_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        let long = "x".repeat(CHUNK_SIZE);
+        fs::write(&path, format!("first\n{}\nsecond\nthird\n", long)).unwrap();
+
+        let mut follower = Follower::open(path.clone()).await.unwrap();
+        assert_eq!(follower.read_last_lines(2).await.unwrap(), ["second", "third"]);
+        let mut follower = Follower::open(path.clone()).await.unwrap();
+        assert_eq!(follower.read_last_lines(4).await.unwrap(), ["first", long.as_str(), "second", "third"]);
+        assert_eq!(follower.read_last_lines(10).await.unwrap().len(), 4);
+
+        fs::write(&path, "").unwrap();
+        let mut follower = Follower::open(path).await.unwrap();
+        assert!(follower.read_last_lines(5).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn follows_appends_truncation_and_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        fs::write(&path, "old\n").unwrap();
+        let (tx, mut rx) = mpsc::channel(64);
+
+        let mut follower = Follower::open(path.clone()).await.unwrap();
+        follower.read_last_lines(0).await.unwrap();
+        follower.poll(&tx).await.unwrap();
+        assert!(received(&mut rx).is_empty());
+
+        // 改行が来るまでは行として送らない
+        append(&path, "one\ntw");
+        follower.poll(&tx).await.unwrap();
+        assert_eq!(received(&mut rx), ["one"]);
+        append(&path, "o\n");
+        follower.poll(&tx).await.unwrap();
+        assert_eq!(received(&mut rx), ["two"]);
+
+        fs::write(&path, "").unwrap();
+        append(&path, "a\n");
+        follower.poll(&tx).await.unwrap();
+        assert_eq!(received(&mut rx), ["<truncated>", "a"]);
+
+        // 旧ファイルの残りを読んでから新しいファイルへ移る
+        append(&path, "last of old\nno newline");
+        fs::rename(&path, dir.path().join("app.log.1")).unwrap();
+        fs::write(&path, "fresh\n").unwrap();
+        follower.poll(&tx).await.unwrap();
+        assert_eq!(received(&mut rx), ["last of old", "no newline", "<rotated>", "fresh"]);
+    }
+
+    #[tokio::test]
+    async fn splits_lines_that_never_end() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        fs::write(&path, "").unwrap();
+        let (tx, mut rx) = mpsc::channel(64);
+
+        let mut follower = Follower::open(path.clone()).await.unwrap();
+        append(&path, &"y".repeat(MAX_LINE_BYTES + 10));
+        follower.poll(&tx).await.unwrap();
+        let lines = received(&mut rx);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].len(), MAX_LINE_BYTES);
+    }
+}
@@ -0,0 +1,109 @@
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, TimeDelta, TimeZone, Utc};
+
+/// syslog / nginx / apache でよく使われる行頭のタイムスタンプを読み取る
+pub struct TimestampParser {
+    reference: DateTime<Local>,
+}
+
+impl TimestampParser {
+    /// `reference` はファイルの最終更新時刻。それより後に書かれた行はないので、年のない時刻の年を決めるのに使う
+    pub fn new(reference: DateTime<Local>) -> Self {
+        Self { reference }
+    }
+
+    pub fn parse(&self, line: &str) -> Option<DateTime<Utc>> {
+        parse_iso(line)
+            .or_else(|| parse_naive(line, "%Y-%m-%d %H:%M:%S"))
+            .or_else(|| parse_naive(line, "%Y/%m/%d %H:%M:%S"))
+            .or_else(|| self.parse_syslog(line))
+            .or_else(|| parse_common_log(line))
+    }
+
+    /// 従来の syslog 形式（`Oct 18 09:45:33`）には年がないため、基準の時刻より後にならない年を補う。
+    /// 時計のずれを見込んで1日までは後でもよい
+    fn parse_syslog(&self, line: &str) -> Option<DateTime<Utc>> {
+        let text = line.get(..15)?;
+        let year = self.reference.year();
+        let naive = NaiveDateTime::parse_from_str(&format!("{} {}", year, text), "%Y %b %e %H:%M:%S").ok()?;
+        let timestamp = local_to_utc(naive)?;
+        if timestamp > self.reference.with_timezone(&Utc) + TimeDelta::days(1) {
+            let naive = naive.with_year(year - 1)?;
+            return local_to_utc(naive);
+        }
+        Some(timestamp)
+    }
+}
+
+/// rsyslog の高精度形式（`2026-10-18T09:45:33.123456+09:00`）
+fn parse_iso(line: &str) -> Option<DateTime<Utc>> {
+    let token = line.split_whitespace().next()?;
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(token) {
+        return Some(timestamp.with_timezone(&Utc));
+    }
+    let naive = NaiveDateTime::parse_from_str(token, "%Y-%m-%dT%H:%M:%S%.f").ok()?;
+    local_to_utc(naive)
+}
+
+/// `2026-10-18 09:45:33` のような19文字の形式（nginx の error.log など）
+fn parse_naive(line: &str, format: &str) -> Option<DateTime<Utc>> {
+    let naive = NaiveDateTime::parse_from_str(line.get(..19)?, format).ok()?;
+    local_to_utc(naive)
+}
+
+fn local_to_utc(naive: NaiveDateTime) -> Option<DateTime<Utc>> {
+    Local.from_local_datetime(&naive).earliest().map(|t| t.with_timezone(&Utc))
+}
+
+/// nginx / apache のアクセスログ（`[18/Oct/2026:09:45:33 +0900]`）
+fn parse_common_log(line: &str) -> Option<DateTime<Utc>> {
+    let start = line.find('[')? + 1;
+    let end = start + line[start..].find(']')?;
+    DateTime::parse_from_str(&line[start..end], "%d/%b/%Y:%H:%M:%S %z")
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, Timelike};
+
+    fn local(year: i32, month: u32, day: u32, hour: u32, min: u32, sec: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(year, month, day, hour, min, sec).earliest().unwrap()
+    }
+
+    fn utc(time: DateTime<Local>) -> Option<DateTime<Utc>> {
+        Some(time.with_timezone(&Utc))
+    }
+
+    #[test]
+    fn syslog_year_comes_from_the_reference_time() {
+        let parser = TimestampParser::new(local(2026, 10, 18, 12, 0, 0));
+        assert_eq!(parser.parse("Oct 18 09:45:33 host sshd[1]: ok"), utc(local(2026, 10, 18, 9, 45, 33)));
+        assert_eq!(parser.parse("Jan  2 03:04:05 host cron[2]: ok"), utc(local(2026, 1, 2, 3, 4, 5)));
+        // 基準より後になる日付は前の年のもの。1日以内のずれは同じ年とみなす
+        assert_eq!(parser.parse("Dec 31 23:59:59 host kernel: x"), utc(local(2025, 12, 31, 23, 59, 59)));
+        assert_eq!(parser.parse("Oct 19 08:00:00 host kernel: x"), utc(local(2026, 10, 19, 8, 0, 0)));
+
+        // 年をまたいでローテーションされたファイルは、最終更新時刻の年が基準になる
+        let rotated = TimestampParser::new(local(2025, 1, 1, 0, 10, 0));
+        assert_eq!(rotated.parse("Dec 31 23:59:59 host kernel: x"), utc(local(2024, 12, 31, 23, 59, 59)));
+        assert_eq!(rotated.parse("Jan  1 00:05:00 host kernel: x"), utc(local(2025, 1, 1, 0, 5, 0)));
+    }
+
+    #[test]
+    fn parses_formats_with_a_year() {
+        let parser = TimestampParser::new(local(2026, 10, 18, 12, 0, 0));
+        let expected = DateTime::<FixedOffset>::parse_from_rfc3339("2026-10-18T09:45:33.123456+09:00").unwrap().with_timezone(&Utc);
+        assert_eq!(parser.parse("2026-10-18T09:45:33.123456+09:00 host app: x"), Some(expected));
+        assert_eq!(parser.parse("2026-10-18T09:45:33 host app: x"), utc(local(2026, 10, 18, 9, 45, 33)));
+        assert_eq!(parser.parse("2026-10-18 09:45:33 [error] 1#1: x"), utc(local(2026, 10, 18, 9, 45, 33)));
+        assert_eq!(parser.parse("2026/10/18 09:45:33 [error] 1#1: x"), utc(local(2026, 10, 18, 9, 45, 33)));
+        assert_eq!(
+            parser.parse(r#"203.0.113.1 - - [18/Oct/2026:09:45:33 +0900] "GET / HTTP/1.1" 200 612"#),
+            Some(expected.with_nanosecond(0).unwrap())
+        );
+        assert_eq!(parser.parse("    at com.example.Main.run(Main.java:42)"), None);
+        assert_eq!(parser.parse(""), None);
+    }
+}
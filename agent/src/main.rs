@@ -1,5 +1,11 @@
 mod app;
+mod collectors;
+mod files;
 mod handles;
+mod logs;
+mod packages;
+mod systemd;
+mod terminal;
 mod utils;
 
 use crate::app::config::Config;
@@ -12,9 +18,9 @@ async fn main() -> Result<()> {
     println!("  Guardian Agent\n");
 
     let config = Config::load()?;
-    let _ = init_tracing(config.clone())?;
+    init_tracing(config.clone())?;
 
-    print!("  Local: http://127.0.0.1:{}", config.server.port.clone());
+    print!("  Local: http://127.0.0.1:{}", config.server.port);
 
     let mut app = app::runner::App::new(config)?;
     app.run().await?;
@@ -0,0 +1,148 @@
+use crate::packages::updates::Candidate;
+use common::agent::package::Package;
+use std::{fs, io::Read, path::Path};
+
+use anyhow::{Context, Result};
+use flate2::read::MultiGzDecoder;
+
+pub const INSTALLED_PATH: &str = "/lib/apk/db/installed";
+const CACHE_DIRS: [&str; 2] = ["/var/cache/apk", "/etc/apk/cache"];
+const TAR_BLOCK_SIZE: usize = 512;
+
+/// apk はインストール日時を記録しないため `installed_at` は常に None
+pub fn read_installed() -> Result<Vec<Package>> {
+    let content = fs::read_to_string(INSTALLED_PATH)
+        .with_context(|| format!("failed to read {}", INSTALLED_PATH))?;
+
+    let packages = parse_records(&content)
+        .into_iter()
+        .map(|(name, version, architecture, origin)| Package {
+            name,
+            version,
+            architecture,
+            source: origin,
+            installed_at: None,
+        })
+        .collect::<Vec<Package>>();
+    Ok(packages)
+}
+
+/// キャッシュされた `APKINDEX.*.tar.gz` を読む。apk にはセキュリティ更新の区別がない
+pub fn read_candidates() -> Result<Vec<Candidate>> {
+    let mut candidates = Vec::new();
+
+    let indexes = CACHE_DIRS
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flat_map(|dir| dir.flatten())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with("APKINDEX.") && n.ends_with(".tar.gz"))
+        });
+
+    for path in indexes {
+        let content = match read_index(&path) {
+            Ok(Some(content)) => content,
+            Ok(None) => continue,
+            Err(e) => {
+                tracing::warn!("Failed to read {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        candidates.extend(parse_records(&content).into_iter().map(
+            |(name, version, architecture, _)| Candidate {
+                name,
+                version,
+                architecture,
+                source: path.file_name().map(|n| n.to_string_lossy().to_string()),
+                security: false,
+            },
+        ));
+    }
+    Ok(candidates)
+}
+
+fn parse_records(content: &str) -> Vec<(String, String, Option<String>, Option<String>)> {
+    content
+        .split("\n\n")
+        .filter_map(|record| {
+            let field = |key: &str| {
+                record
+                    .lines()
+                    .find_map(|line| line.strip_prefix(key))
+                    .map(|v| v.to_string())
+            };
+            Some((field("P:")?, field("V:")?, field("A:"), field("o:")))
+        })
+        .collect()
+}
+
+/// 署名とインデックスが連結された tar.gz から `APKINDEX` エントリを取り出す
+fn read_index(path: &Path) -> Result<Option<String>> {
+    let mut data = Vec::new();
+    MultiGzDecoder::new(fs::File::open(path)?).read_to_end(&mut data)?;
+
+    let mut offset = 0;
+    while offset + TAR_BLOCK_SIZE <= data.len() {
+        let header = &data[offset..offset + TAR_BLOCK_SIZE];
+        offset += TAR_BLOCK_SIZE;
+        if header.iter().all(|b| *b == 0) {
+            continue;
+        }
+
+        let name = String::from_utf8_lossy(&header[..100]).trim_end_matches('\0').to_string();
+        let size = std::str::from_utf8(&header[124..136])
+            .ok()
+            .map(|s| s.trim_matches(|c: char| c == '\0' || c.is_whitespace()))
+            .and_then(|s| usize::from_str_radix(s, 8).ok())
+            .context("invalid tar header")?;
+
+        if name == "APKINDEX" {
+            let end = (offset + size).min(data.len());
+            return Ok(Some(String::from_utf8_lossy(&data[offset..end]).to_string()));
+        }
+        offset += size.div_ceil(TAR_BLOCK_SIZE) * TAR_BLOCK_SIZE;
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INSTALLED: &str = "\
+C:Q1hU0ot0kqTJ3Hwf0I1qOLwnbSmeo=
+P:musl
+V:1.2.4-r2
+A:x86_64
+S:383152
+I:622592
+T:the musl c library (libc) implementation
+U:https://musl.libc.org/
+L:MIT
+o:musl
+m:Timo Teras <timo.teras@iki.fi>
+t:1695149018
+F:lib
+R:ld-musl-x86_64.so.1
+
+P:busybox-binsh
+V:1.36.1-r5
+A:x86_64
+o:busybox
+
+P:no-version
+A:x86_64
+";
+
+    #[test]
+    fn parses_installed_database_records() {
+        let records = parse_records(INSTALLED);
+        assert_eq!(records, [
+            ("musl".to_string(), "1.2.4-r2".to_string(), Some("x86_64".to_string()), Some("musl".to_string())),
+            ("busybox-binsh".to_string(), "1.36.1-r5".to_string(), Some("x86_64".to_string()), Some("busybox".to_string())),
+        ]);
+    }
+}
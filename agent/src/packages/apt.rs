@@ -0,0 +1,105 @@
+use crate::packages::updates::Candidate;
+use std::{collections::HashMap, fs, io::Read, path::Path};
+
+use anyhow::{Context, Result};
+
+const LISTS_DIR: &str = "/var/lib/apt/lists";
+
+/// Debian control 形式（空行区切りの `Key: Value`）を読む。継続行は無視する
+pub fn parse_control(content: &str) -> Vec<HashMap<&str, &str>> {
+    content
+        .split("\n\n")
+        .filter_map(|stanza| {
+            let fields = stanza
+                .lines()
+                .filter(|line| !line.starts_with([' ', '\t']))
+                .filter_map(|line| line.split_once(':'))
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .collect::<HashMap<&str, &str>>();
+            (!fields.is_empty()).then_some(fields)
+        })
+        .collect()
+}
+
+pub struct AptEntry {
+    pub name: String,
+    pub version: String,
+    pub architecture: String,
+    pub source: String,
+}
+
+/// `/var/lib/apt/lists` にある Packages インデックスをすべて読む
+pub fn read_package_lists() -> Result<Vec<AptEntry>> {
+    let mut entries = Vec::new();
+    let Ok(dir) = fs::read_dir(LISTS_DIR) else {
+        return Ok(entries);
+    };
+
+    for file in dir.flatten() {
+        let path = file.path();
+        let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let Some(source) = list_source(file_name) else {
+            continue;
+        };
+        let content = match read_list(&path) {
+            Ok(content) => content,
+            Err(e) => {
+                tracing::warn!("Failed to read {}: {}", path.display(), e);
+                continue;
+            }
+        };
+
+        entries.extend(parse_control(&content).into_iter().filter_map(|fields| {
+            Some(AptEntry {
+                name: fields.get("Package")?.to_string(),
+                version: fields.get("Version")?.to_string(),
+                architecture: fields.get("Architecture").unwrap_or(&"").to_string(),
+                source: source.clone(),
+            })
+        }));
+    }
+    Ok(entries)
+}
+
+/// `-security` スイートまたはセキュリティ用のミラーから配布されているものをセキュリティ更新とみなす
+pub fn read_candidates() -> Result<Vec<Candidate>> {
+    let candidates = read_package_lists()?
+        .into_iter()
+        .map(|entry| Candidate {
+            security: entry.source.contains("security"),
+            name: entry.name,
+            version: entry.version,
+            architecture: Some(entry.architecture),
+            source: Some(entry.source),
+        })
+        .collect::<Vec<Candidate>>();
+    Ok(candidates)
+}
+
+/// `deb.debian.org_debian_dists_bookworm-updates_main_binary-amd64_Packages` を
+/// `deb.debian.org/debian bookworm-updates/main` に変換する
+fn list_source(file_name: &str) -> Option<String> {
+    let stem = file_name
+        .strip_suffix("_Packages")
+        .or_else(|| file_name.strip_suffix("_Packages.lz4"))?;
+    let (site, dist) = stem.split_once("_dists_")?;
+    let mut parts = dist.split('_');
+    let suite = parts.next()?;
+    let component = parts.next()?;
+    Some(format!("{} {}/{}", site.replace('_', "/"), suite, component))
+}
+
+fn read_list(path: &Path) -> Result<String> {
+    let file = fs::File::open(path)?;
+    let mut content = String::new();
+    if path.extension().is_some_and(|ext| ext == "lz4") {
+        lz4_flex::frame::FrameDecoder::new(file)
+            .read_to_string(&mut content)
+            .context("failed to decompress lz4 list")?;
+    } else {
+        std::io::BufReader::new(file).read_to_string(&mut content)?;
+    }
+    Ok(content)
+}
@@ -0,0 +1,132 @@
+use crate::packages::updates::Candidate;
+use std::{
+    collections::HashSet,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use flate2::read::GzDecoder;
+
+const CACHE_DIRS: [&str; 2] = ["/var/cache/dnf", "/var/cache/yum"];
+
+/// dnf のキャッシュにある `primary.xml.gz` と `updateinfo.xml.gz` を読む
+pub fn read_candidates() -> Result<Vec<Candidate>> {
+    let mut candidates = Vec::new();
+
+    for repodata in repodata_dirs() {
+        let repo = repodata
+            .parent()
+            .and_then(|p| p.file_name())
+            .and_then(|n| n.to_str())
+            .map(|n| n.rsplit_once('-').map(|(id, _)| id).unwrap_or(n).to_string());
+
+        let mut security = HashSet::new();
+        if let Some(content) = read_metadata(&repodata, "updateinfo.xml.gz") {
+            security = parse_security_packages(&content);
+        }
+        let Some(content) = read_metadata(&repodata, "primary.xml.gz") else {
+            continue;
+        };
+
+        candidates.extend(parse_primary(&content).into_iter().map(|(name, version, architecture)| {
+            let is_security = security.contains(&(name.clone(), version.clone()));
+            Candidate {
+                name,
+                version,
+                architecture: Some(architecture),
+                source: repo.clone(),
+                security: is_security,
+            }
+        }));
+    }
+    Ok(candidates)
+}
+
+fn repodata_dirs() -> Vec<PathBuf> {
+    CACHE_DIRS
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flat_map(|dir| dir.flatten())
+        .map(|entry| entry.path().join("repodata"))
+        .filter(|path| path.is_dir())
+        .collect()
+}
+
+fn read_metadata(repodata: &Path, suffix: &str) -> Option<String> {
+    let path = fs::read_dir(repodata)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| path.to_string_lossy().ends_with(suffix))?;
+    let mut content = String::new();
+    match fs::File::open(&path).map(GzDecoder::new).and_then(|mut d| d.read_to_string(&mut content)) {
+        Ok(_) => Some(content),
+        Err(e) => {
+            tracing::warn!("Failed to read {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn parse_primary(content: &str) -> Vec<(String, String, String)> {
+    content
+        .split("<package ")
+        .skip(1)
+        .filter_map(|package| {
+            let name = element(package, "name")?;
+            let arch = element(package, "arch")?;
+            let version_tag = package.split("<version ").nth(1)?.split("/>").next()?;
+            Some((name.to_string(), evr(version_tag)?, arch.to_string()))
+        })
+        .collect()
+}
+
+fn parse_security_packages(content: &str) -> HashSet<(String, String)> {
+    content
+        .split("<update ")
+        .skip(1)
+        .filter(|update| update.split('>').next().is_some_and(|tag| tag.contains(r#"type="security""#)))
+        .flat_map(|update| {
+            update
+                .split("<package ")
+                .skip(1)
+                .filter_map(|tag| {
+                    let tag = tag.split('>').next()?;
+                    Some((attribute(tag, "name")?.to_string(), evr(tag)?))
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// `epoch="0" ver="1.0" rel="1"` または `epoch="0" version="1.0" release="1"` を `epoch:ver-rel` にする
+fn evr(tag: &str) -> Option<String> {
+    let version = attribute(tag, "ver").or_else(|| attribute(tag, "version"))?;
+    let release = attribute(tag, "rel").or_else(|| attribute(tag, "release"));
+    let epoch = attribute(tag, "epoch").filter(|e| *e != "0" && !e.is_empty());
+    Some(match (epoch, release) {
+        (Some(epoch), Some(release)) => format!("{}:{}-{}", epoch, version, release),
+        (None, Some(release)) => format!("{}-{}", version, release),
+        (Some(epoch), None) => format!("{}:{}", epoch, version),
+        (None, None) => version.to_string(),
+    })
+}
+
+fn element<'a>(content: &'a str, name: &str) -> Option<&'a str> {
+    let start = content.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = content[start..].find(&format!("</{}>", name))? + start;
+    Some(&content[start..end])
+}
+
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let pattern = format!("{}=\"", name);
+    let start = tag
+        .match_indices(&pattern)
+        .find(|(i, _)| *i == 0 || tag.as_bytes()[i - 1].is_ascii_whitespace())?
+        .0
+        + pattern.len();
+    let end = tag[start..].find('"')? + start;
+    Some(&tag[start..end])
+}
@@ -0,0 +1,110 @@
+use crate::packages::apt::{parse_control, read_package_lists};
+use common::agent::package::Package;
+use std::{collections::HashMap, fs, path::Path, time::UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+
+pub const STATUS_PATH: &str = "/var/lib/dpkg/status";
+const INFO_DIR: &str = "/var/lib/dpkg/info";
+
+pub fn read_installed() -> Result<Vec<Package>> {
+    let content = fs::read_to_string(STATUS_PATH)
+        .with_context(|| format!("failed to read {}", STATUS_PATH))?;
+
+    // 同じバージョンを配布しているリポジトリをインストール元とみなす
+    let sources = read_package_lists()?
+        .into_iter()
+        .map(|entry| ((entry.name, entry.version, entry.architecture), entry.source))
+        .collect::<HashMap<(String, String, String), String>>();
+
+    Ok(parse_status(&content, &sources))
+}
+
+/// `Status` が installed のものだけを返す。キーは (名前, バージョン, アーキテクチャ)
+fn parse_status(content: &str, sources: &HashMap<(String, String, String), String>) -> Vec<Package> {
+    parse_control(content)
+        .into_iter()
+        .filter(|fields| fields.get("Status").is_some_and(|s| s.ends_with(" installed")))
+        .filter_map(|fields| {
+            let name = fields.get("Package")?.to_string();
+            let version = fields.get("Version")?.to_string();
+            let architecture = fields.get("Architecture").map(|a| a.to_string());
+            let source = sources
+                .get(&(name.clone(), version.clone(), architecture.clone().unwrap_or_default()))
+                .cloned();
+            let installed_at = installed_at(&name, architecture.as_deref());
+            Some(Package {
+                name,
+                version,
+                architecture,
+                source,
+                installed_at,
+            })
+        })
+        .collect::<Vec<Package>>()
+}
+
+/// dpkg はインストール日時を記録しないため、ファイルリストの更新日時で代用する
+fn installed_at(name: &str, architecture: Option<&str>) -> Option<i64> {
+    let candidates = [
+        architecture.map(|arch| format!("{}/{}:{}.list", INFO_DIR, name, arch)),
+        Some(format!("{}/{}.list", INFO_DIR, name)),
+    ];
+    candidates
+        .into_iter()
+        .flatten()
+        .find_map(|path| fs::metadata(Path::new(&path)).ok())
+        .and_then(|meta| meta.modified().ok())
+        .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATUS: &str = "\
+Package: libssl3
+Status: install ok installed
+Priority: optional
+Architecture: amd64
+Multi-Arch: same
+Source: openssl
+Version: 3.0.2-0ubuntu1.10
+Description: Secure Sockets Layer toolkit - shared libraries
+ This package is part of the OpenSSL project's implementation of the SSL and
+ TLS cryptographic protocols.
+ Version: 9.9 (continuation lines are not fields)
+
+Package: old-kernel
+Status: deinstall ok config-files
+Architecture: amd64
+Version: 5.15.0-1
+
+Package: tzdata
+Status: install ok installed
+Architecture: all
+Version: 2024a-0ubuntu0.22.04
+
+Package: broken
+Status: install ok installed
+";
+
+    #[test]
+    fn parses_installed_packages_from_status() {
+        let sources = HashMap::from([(
+            ("libssl3".to_string(), "3.0.2-0ubuntu1.10".to_string(), "amd64".to_string()),
+            "jammy-updates/main".to_string(),
+        )]);
+
+        let packages = parse_status(STATUS, &sources);
+        let summary = packages
+            .iter()
+            .map(|p| (p.name.as_str(), p.version.as_str(), p.architecture.as_deref(), p.source.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, [
+            ("libssl3", "3.0.2-0ubuntu1.10", Some("amd64"), Some("jammy-updates/main")),
+            ("tzdata", "2024a-0ubuntu0.22.04", Some("all"), None),
+        ]);
+    }
+}
@@ -0,0 +1,86 @@
+use common::agent::package::{PackageAction, PackageManager};
+use std::path::Path;
+
+use anyhow::{Result, bail};
+use tokio::process::Command;
+
+/// オプションとして解釈されないよう、先頭が `-` の名前や記号を含む名前は拒否する
+pub fn validate_package_names(action: PackageAction, packages: &[String]) -> Result<()> {
+    if action != PackageAction::FullUpgrade && packages.is_empty() {
+        bail!("at least one package is required for {}", action.as_str());
+    }
+    for package in packages {
+        let valid = !package.is_empty()
+            && !package.starts_with('-')
+            && package
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || ".+-_:@~=/".contains(c));
+        if !valid {
+            bail!("invalid package name: {}", package);
+        }
+    }
+    Ok(())
+}
+
+/// 対話なしで実行するコマンド列を組み立てる
+pub fn build_commands(manager: PackageManager, action: PackageAction, packages: &[String]) -> Vec<Command> {
+    let with_packages = |args: &[&str]| -> Vec<String> {
+        args.iter().map(|a| a.to_string()).chain(packages.iter().cloned()).collect()
+    };
+    let only = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+
+    let (program, base_args, steps): (&str, &[&str], Vec<Vec<String>>) = match manager {
+        PackageManager::Dpkg => (
+            "apt-get",
+            &["-y", "-o", "Dpkg::Options::=--force-confdef", "-o", "Dpkg::Options::=--force-confold"],
+            match action {
+                PackageAction::Install => vec![with_packages(&["install"])],
+                PackageAction::Upgrade => vec![with_packages(&["install", "--only-upgrade"])],
+                PackageAction::Remove => vec![with_packages(&["remove"])],
+                PackageAction::FullUpgrade => vec![only(&["update"]), only(&["full-upgrade"])],
+            },
+        ),
+        PackageManager::Rpm => (
+            if Path::new("/usr/bin/dnf").exists() { "dnf" } else { "yum" },
+            &["-y"],
+            match action {
+                PackageAction::Install => vec![with_packages(&["install"])],
+                PackageAction::Upgrade => vec![with_packages(&["upgrade"])],
+                PackageAction::Remove => vec![with_packages(&["remove"])],
+                PackageAction::FullUpgrade => vec![only(&["upgrade", "--refresh"])],
+            },
+        ),
+        PackageManager::Apk => (
+            "apk",
+            &["--no-progress"],
+            match action {
+                PackageAction::Install => vec![with_packages(&["add"])],
+                PackageAction::Upgrade => vec![with_packages(&["upgrade"])],
+                PackageAction::Remove => vec![with_packages(&["del"])],
+                PackageAction::FullUpgrade => vec![only(&["update"]), only(&["upgrade"])],
+            },
+        ),
+        PackageManager::Pacman => (
+            "pacman",
+            &["--noconfirm"],
+            match action {
+                PackageAction::Install => vec![with_packages(&["-S", "--needed"])],
+                PackageAction::Upgrade => vec![with_packages(&["-S"])],
+                PackageAction::Remove => vec![with_packages(&["-R"])],
+                PackageAction::FullUpgrade => vec![only(&["-Syu"])],
+            },
+        ),
+    };
+
+    steps
+        .into_iter()
+        .map(|args| {
+            let mut command = Command::new(program);
+            command
+                .env("DEBIAN_FRONTEND", "noninteractive")
+                .args(base_args)
+                .args(args);
+            command
+        })
+        .collect()
+}
@@ -0,0 +1,43 @@
+pub mod apk;
+pub mod apt;
+pub mod dnf;
+pub mod dpkg;
+pub mod jobs;
+pub mod pacman;
+pub mod rpm;
+pub mod updates;
+pub mod version;
+
+use common::agent::package::{PackageInventory, PackageManager};
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+
+pub fn detect_package_manager() -> Option<PackageManager> {
+    if Path::new(dpkg::STATUS_PATH).exists() {
+        Some(PackageManager::Dpkg)
+    } else if rpm::database_path().is_some() {
+        Some(PackageManager::Rpm)
+    } else if Path::new(apk::INSTALLED_PATH).exists() {
+        Some(PackageManager::Apk)
+    } else if Path::new(pacman::LOCAL_DB_DIR).exists() {
+        Some(PackageManager::Pacman)
+    } else {
+        None
+    }
+}
+
+pub async fn get_inventory() -> Result<PackageInventory> {
+    let Some(manager) = detect_package_manager() else {
+        bail!("no supported package manager found");
+    };
+
+    let packages = match manager {
+        PackageManager::Rpm => rpm::read_installed().await?,
+        PackageManager::Dpkg => tokio::task::spawn_blocking(dpkg::read_installed).await.context("package reader panicked")??,
+        PackageManager::Apk => tokio::task::spawn_blocking(apk::read_installed).await.context("package reader panicked")??,
+        PackageManager::Pacman => tokio::task::spawn_blocking(pacman::read_installed).await.context("package reader panicked")??,
+    };
+
+    Ok(PackageInventory { manager, packages })
+}
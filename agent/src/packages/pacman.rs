@@ -0,0 +1,31 @@
+use common::agent::package::Package;
+use std::fs;
+
+use anyhow::{Context, Result};
+
+pub const LOCAL_DB_DIR: &str = "/var/lib/pacman/local";
+
+pub fn read_installed() -> Result<Vec<Package>> {
+    let dir = fs::read_dir(LOCAL_DB_DIR)
+        .with_context(|| format!("failed to read {}", LOCAL_DB_DIR))?;
+
+    let packages = dir
+        .flatten()
+        .filter_map(|entry| fs::read_to_string(entry.path().join("desc")).ok())
+        .filter_map(|desc| {
+            let field = |key: &str| {
+                let mut lines = desc.lines();
+                lines.find(|line| *line == key)?;
+                lines.next().map(|v| v.to_string())
+            };
+            Some(Package {
+                name: field("%NAME%")?,
+                version: field("%VERSION%")?,
+                architecture: field("%ARCH%"),
+                source: None,
+                installed_at: field("%INSTALLDATE%").and_then(|v| v.parse().ok()),
+            })
+        })
+        .collect::<Vec<Package>>();
+    Ok(packages)
+}
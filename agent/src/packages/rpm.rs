@@ -0,0 +1,484 @@
+use common::agent::package::Package;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use sqlx::{
+    ConnectOptions, Row,
+    sqlite::SqliteConnectOptions,
+};
+
+/// rpm のデータベース形式。RHEL 9 以降は sqlite、SUSE は ndb、それより前は Berkeley DB
+#[derive(Clone, Copy)]
+enum Backend {
+    Sqlite,
+    Ndb,
+    BerkeleyDb,
+}
+
+const DATABASES: [(&str, Backend); 6] = [
+    ("/var/lib/rpm/rpmdb.sqlite", Backend::Sqlite),
+    ("/usr/lib/sysimage/rpm/rpmdb.sqlite", Backend::Sqlite),
+    ("/var/lib/rpm/Packages.db", Backend::Ndb),
+    ("/usr/lib/sysimage/rpm/Packages.db", Backend::Ndb),
+    ("/var/lib/rpm/Packages", Backend::BerkeleyDb),
+    ("/usr/lib/sysimage/rpm/Packages", Backend::BerkeleyDb),
+];
+
+const TAG_NAME: u32 = 1000;
+const TAG_VERSION: u32 = 1001;
+const TAG_RELEASE: u32 = 1002;
+const TAG_EPOCH: u32 = 1003;
+const TAG_INSTALLTIME: u32 = 1008;
+const TAG_VENDOR: u32 = 1011;
+const TAG_ARCH: u32 = 1022;
+
+const TYPE_INT32: u32 = 4;
+const TYPE_STRING: u32 = 6;
+const TYPE_I18NSTRING: u32 = 9;
+
+const NDB_HEADER_MAGIC: u32 = u32::from_le_bytes(*b"RpmP");
+const NDB_SLOT_MAGIC: u32 = u32::from_le_bytes(*b"Slot");
+const NDB_BLOB_MAGIC: u32 = u32::from_le_bytes(*b"BlbS");
+const NDB_HEADER_SIZE: usize = 32;
+const NDB_SLOT_SIZE: usize = 16;
+const NDB_PAGE_SIZE: usize = 4096;
+const NDB_BLOCK_SIZE: usize = 16;
+const NDB_BLOB_HEADER_SIZE: usize = 16;
+
+const BDB_HASH_MAGIC: u32 = 0x061561;
+const BDB_PAGE_HEADER_SIZE: usize = 26;
+const BDB_PAGE_HASH_UNSORTED: u8 = 2;
+const BDB_PAGE_OVERFLOW: u8 = 7;
+const BDB_PAGE_HASH: u8 = 13;
+const BDB_ITEM_KEYDATA: u8 = 1;
+const BDB_ITEM_OFFPAGE: u8 = 3;
+
+fn database() -> Option<(&'static str, Backend)> {
+    DATABASES.into_iter().find(|(path, _)| Path::new(path).exists())
+}
+
+pub fn database_path() -> Option<&'static str> {
+    database().map(|(path, _)| path)
+}
+
+/// rpm データベースに格納されたヘッダーを直接読む。rpm コマンドには依存しない
+pub async fn read_installed() -> Result<Vec<Package>> {
+    let Some((path, backend)) = database() else {
+        bail!("rpm database not found");
+    };
+
+    let blobs = match backend {
+        Backend::Sqlite => read_sqlite(path).await?,
+        Backend::Ndb | Backend::BerkeleyDb => {
+            let data = tokio::fs::read(path)
+                .await
+                .with_context(|| format!("failed to read {}", path))?;
+            match backend {
+                Backend::Ndb => read_ndb(&data),
+                _ => read_berkeley_db(&data),
+            }
+            .with_context(|| format!("failed to parse {}", path))?
+        }
+    };
+
+    let packages = blobs
+        .iter()
+        .filter_map(|blob| parse_header(blob))
+        .filter(|package| package.name != "gpg-pubkey")
+        .collect::<Vec<Package>>();
+    Ok(packages)
+}
+
+async fn read_sqlite(path: &str) -> Result<Vec<Vec<u8>>> {
+    let mut conn = SqliteConnectOptions::new()
+        .filename(path)
+        .read_only(true)
+        .connect()
+        .await
+        .with_context(|| format!("failed to open {}", path))?;
+
+    let rows = sqlx::query("SELECT blob FROM Packages")
+        .fetch_all(&mut conn)
+        .await
+        .context("failed to read Packages table")?;
+
+    Ok(rows
+        .iter()
+        .filter_map(|row| row.try_get::<Vec<u8>, _>("blob").ok())
+        .collect())
+}
+
+/// ndb は先頭のスロット領域に各パッケージのブロブ位置を持つ。値はすべてリトルエンディアン
+fn read_ndb(data: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let le32 = |offset: usize| -> Option<u32> {
+        Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+    };
+
+    if le32(0) != Some(NDB_HEADER_MAGIC) || le32(4) != Some(0) {
+        bail!("not an ndb package database");
+    }
+    let slot_pages = le32(12).context("truncated ndb header")? as usize;
+    let slots_end = (slot_pages * NDB_PAGE_SIZE).min(data.len());
+
+    let mut blobs = Vec::new();
+    for slot in (NDB_HEADER_SIZE..slots_end).step_by(NDB_SLOT_SIZE) {
+        if le32(slot) != Some(NDB_SLOT_MAGIC) {
+            bail!("corrupt ndb slot at {}", slot);
+        }
+        let (Some(index), Some(block)) = (le32(slot + 4), le32(slot + 8)) else {
+            break;
+        };
+        // 未使用のスロット
+        if index == 0 {
+            continue;
+        }
+
+        let start = block as usize * NDB_BLOCK_SIZE;
+        if le32(start) != Some(NDB_BLOB_MAGIC) || le32(start + 4) != Some(index) {
+            bail!("corrupt ndb blob for package {}", index);
+        }
+        let len = le32(start + 12).context("truncated ndb blob")? as usize;
+        let body = start + NDB_BLOB_HEADER_SIZE;
+        let blob = data.get(body..body + len).context("truncated ndb blob")?;
+        blobs.push(blob.to_vec());
+    }
+    Ok(blobs)
+}
+
+/// Berkeley DB の hash 形式。ヘッダーは大きいので通常はオーバーフローページに置かれる
+fn read_berkeley_db(data: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let db = BerkeleyDb::open(data)?;
+
+    let mut blobs = Vec::new();
+    for number in 1..=db.last_page {
+        let Some(page) = db.page(number) else {
+            break;
+        };
+        if !matches!(page[25], BDB_PAGE_HASH | BDB_PAGE_HASH_UNSORTED) {
+            continue;
+        }
+
+        // キーと値が交互に並び、アイテムはページの末尾から詰められる
+        let entries = db.u16(page, 20).unwrap_or(0) as usize;
+        for index in (1..entries).step_by(2) {
+            let item = |i: usize| db.u16(page, BDB_PAGE_HEADER_SIZE + i * 2).map(|o| o as usize);
+            let (Some(offset), Some(end)) = (item(index), item(index - 1)) else {
+                break;
+            };
+            match page.get(offset) {
+                Some(&BDB_ITEM_KEYDATA) => {
+                    if let Some(value) = page.get(offset + 1..end) {
+                        blobs.push(value.to_vec());
+                    }
+                }
+                Some(&BDB_ITEM_OFFPAGE) => {
+                    let (Some(first), Some(len)) = (db.u32(page, offset + 4), db.u32(page, offset + 8)) else {
+                        continue;
+                    };
+                    let value = db
+                        .overflow(first, len as usize)
+                        .with_context(|| format!("broken overflow chain at page {}", first))?;
+                    blobs.push(value);
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(blobs)
+}
+
+/// バイトオーダーは書き込んだホストに従うため、マジックナンバーから判定する
+struct BerkeleyDb<'a> {
+    data: &'a [u8],
+    page_size: usize,
+    last_page: u32,
+    big_endian: bool,
+}
+
+impl<'a> BerkeleyDb<'a> {
+    fn open(data: &'a [u8]) -> Result<Self> {
+        let magic = data.get(12..16).context("truncated Berkeley DB file")?;
+        let big_endian = if magic == BDB_HASH_MAGIC.to_le_bytes() {
+            false
+        } else if magic == BDB_HASH_MAGIC.to_be_bytes() {
+            true
+        } else {
+            bail!("not a Berkeley DB hash database");
+        };
+
+        let mut db = BerkeleyDb { data, page_size: 0, last_page: 0, big_endian };
+        let page_size = db.u32(data, 20).context("truncated Berkeley DB file")? as usize;
+        if !(512..=65536).contains(&page_size) || !page_size.is_power_of_two() {
+            bail!("invalid Berkeley DB page size {}", page_size);
+        }
+        db.page_size = page_size;
+        db.last_page = db.u32(data, 32).context("truncated Berkeley DB file")?;
+        Ok(db)
+    }
+
+    fn page(&self, number: u32) -> Option<&'a [u8]> {
+        let start = number as usize * self.page_size;
+        self.data.get(start..start + self.page_size)
+    }
+
+    fn u16(&self, bytes: &[u8], offset: usize) -> Option<u16> {
+        let raw = bytes.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.big_endian { u16::from_be_bytes(raw) } else { u16::from_le_bytes(raw) })
+    }
+
+    fn u32(&self, bytes: &[u8], offset: usize) -> Option<u32> {
+        let raw = bytes.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.big_endian { u32::from_be_bytes(raw) } else { u32::from_le_bytes(raw) })
+    }
+
+    /// オーバーフローページを辿って値を組み立てる。各ページの使用量はヘッダーの hf_offset にある
+    fn overflow(&self, first: u32, len: usize) -> Option<Vec<u8>> {
+        let mut value = Vec::with_capacity(len);
+        let mut number = first;
+        // 壊れたファイルで循環しないよう、ページ数を上限にする
+        for _ in 0..=self.last_page {
+            if number == 0 || value.len() >= len {
+                break;
+            }
+            let page = self.page(number)?;
+            if page[25] != BDB_PAGE_OVERFLOW {
+                return None;
+            }
+            let used = self.u16(page, 22)? as usize;
+            value.extend_from_slice(page.get(BDB_PAGE_HEADER_SIZE..BDB_PAGE_HEADER_SIZE + used)?);
+            number = self.u32(page, 16)?;
+        }
+        if value.len() < len {
+            return None;
+        }
+        value.truncate(len);
+        Some(value)
+    }
+}
+
+fn parse_header(blob: &[u8]) -> Option<Package> {
+    let be32 = |offset: usize| -> Option<u32> {
+        Some(u32::from_be_bytes(blob.get(offset..offset + 4)?.try_into().ok()?))
+    };
+
+    let index_count = be32(0)? as usize;
+    let data_start = 8 + index_count * 16;
+    let data = blob.get(data_start..)?;
+
+    let entry = |tag: u32| -> Option<(u32, usize)> {
+        (0..index_count).find_map(|i| {
+            let base = 8 + i * 16;
+            (be32(base)? == tag).then_some((be32(base + 4)?, be32(base + 8)? as usize))
+        })
+    };
+    let string = |tag: u32| -> Option<String> {
+        let (kind, offset) = entry(tag)?;
+        if kind != TYPE_STRING && kind != TYPE_I18NSTRING {
+            return None;
+        }
+        let bytes = data.get(offset..)?;
+        let end = bytes.iter().position(|b| *b == 0)?;
+        Some(String::from_utf8_lossy(&bytes[..end]).to_string())
+    };
+    let int32 = |tag: u32| -> Option<u32> {
+        let (kind, offset) = entry(tag)?;
+        if kind != TYPE_INT32 {
+            return None;
+        }
+        Some(u32::from_be_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+    };
+
+    let version = match (int32(TAG_EPOCH), string(TAG_VERSION)?, string(TAG_RELEASE)) {
+        (Some(epoch), version, Some(release)) => format!("{}:{}-{}", epoch, version, release),
+        (None, version, Some(release)) => format!("{}-{}", version, release),
+        (_, version, None) => version,
+    };
+
+    Some(Package {
+        name: string(TAG_NAME)?,
+        version,
+        architecture: string(TAG_ARCH),
+        source: string(TAG_VENDOR),
+        installed_at: int32(TAG_INSTALLTIME).map(|t| t as i64),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(tag: u32, value: &str) -> (u32, u32, Vec<u8>) {
+        (tag, TYPE_STRING, [value.as_bytes(), &[0]].concat())
+    }
+
+    fn int32(tag: u32, value: u32) -> (u32, u32, Vec<u8>) {
+        (tag, TYPE_INT32, value.to_be_bytes().to_vec())
+    }
+
+    /// インデックスとデータ領域からなる rpm ヘッダーを組み立てる
+    fn header(entries: &[(u32, u32, Vec<u8>)]) -> Vec<u8> {
+        let mut index = Vec::new();
+        let mut store = Vec::new();
+        for (tag, kind, bytes) in entries {
+            if *kind == TYPE_INT32 {
+                store.resize(store.len().next_multiple_of(4), 0);
+            }
+            for field in [*tag, *kind, store.len() as u32, 1] {
+                index.extend(field.to_be_bytes());
+            }
+            store.extend(bytes);
+        }
+        [
+            &(entries.len() as u32).to_be_bytes()[..],
+            &(store.len() as u32).to_be_bytes(),
+            &index,
+            &store,
+        ]
+        .concat()
+    }
+
+    fn fixture(name: &str, vendor: &str) -> Vec<u8> {
+        header(&[
+            string(TAG_NAME, name),
+            string(TAG_VERSION, "1.0"),
+            string(TAG_RELEASE, "1.el8"),
+            string(TAG_ARCH, "x86_64"),
+            string(TAG_VENDOR, vendor),
+        ])
+    }
+
+    fn names(blobs: &[Vec<u8>]) -> Vec<String> {
+        blobs.iter().filter_map(|blob| parse_header(blob)).map(|p| p.name).collect()
+    }
+
+    #[test]
+    fn parses_header_fields() {
+        let blob = header(&[
+            string(TAG_NAME, "openssl"),
+            string(TAG_VERSION, "3.0.7"),
+            string(TAG_RELEASE, "27.el9"),
+            int32(TAG_EPOCH, 1),
+            string(TAG_ARCH, "x86_64"),
+            (TAG_VENDOR, TYPE_I18NSTRING, b"Red Hat, Inc.\0".to_vec()),
+            int32(TAG_INSTALLTIME, 1_700_000_000),
+        ]);
+        let package = parse_header(&blob).unwrap();
+        assert_eq!(package.name, "openssl");
+        assert_eq!(package.version, "1:3.0.7-27.el9");
+        assert_eq!(package.architecture.as_deref(), Some("x86_64"));
+        assert_eq!(package.source.as_deref(), Some("Red Hat, Inc."));
+        assert_eq!(package.installed_at, Some(1_700_000_000));
+
+        let blob = header(&[string(TAG_NAME, "bare"), string(TAG_VERSION, "2")]);
+        let package = parse_header(&blob).unwrap();
+        assert_eq!(package.version, "2");
+        assert_eq!(package.architecture, None);
+        assert_eq!(package.installed_at, None);
+
+        // 型が違うタグや途中で切れたヘッダーは読まない
+        let blob = header(&[string(TAG_NAME, "odd"), int32(TAG_VERSION, 1)]);
+        assert!(parse_header(&blob).is_none());
+        let blob = fixture("truncated", "vendor");
+        assert!(parse_header(&blob[..8 + 5 * 16 + 3]).is_none());
+        assert!(parse_header(&[0, 0, 0, 1]).is_none());
+    }
+
+    #[test]
+    fn reads_ndb_slots() {
+        let blobs = [fixture("bash", "vendor"), fixture("zlib", "vendor")];
+
+        let mut data = vec![0; NDB_PAGE_SIZE];
+        data[0..4].copy_from_slice(&NDB_HEADER_MAGIC.to_le_bytes());
+        data[12..16].copy_from_slice(&1u32.to_le_bytes());
+        for slot in (NDB_HEADER_SIZE..NDB_PAGE_SIZE).step_by(NDB_SLOT_SIZE) {
+            data[slot..slot + 4].copy_from_slice(&NDB_SLOT_MAGIC.to_le_bytes());
+        }
+        // 2 番目のスロットは空けておく
+        for (slot, (index, blob)) in [(0, (1u32, &blobs[0])), (2, (5u32, &blobs[1]))] {
+            let block = (data.len() / NDB_BLOCK_SIZE) as u32;
+            let base = NDB_HEADER_SIZE + slot * NDB_SLOT_SIZE;
+            data[base + 4..base + 8].copy_from_slice(&index.to_le_bytes());
+            data[base + 8..base + 12].copy_from_slice(&block.to_le_bytes());
+            for field in [NDB_BLOB_MAGIC, index, 0, blob.len() as u32] {
+                data.extend(field.to_le_bytes());
+            }
+            data.extend(blob);
+            data.resize(data.len().next_multiple_of(NDB_BLOCK_SIZE), 0);
+        }
+
+        assert_eq!(names(&read_ndb(&data).unwrap()), ["bash", "zlib"]);
+
+        let mut broken = data.clone();
+        broken[NDB_PAGE_SIZE..NDB_PAGE_SIZE + 4].copy_from_slice(b"XXXX");
+        assert!(read_ndb(&broken).is_err());
+        assert!(read_ndb(b"not an ndb file").is_err());
+    }
+
+    /// ページ 1 にハッシュページ、ページ 2 以降にオーバーフローページを置いた hash データベースを作る
+    fn berkeley_db(big_endian: bool, inline: &[u8], large: &[u8]) -> Vec<u8> {
+        const PAGE_SIZE: usize = 512;
+        let u16 = |v: u16| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+        let u32 = |v: u32| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+
+        let chunk = PAGE_SIZE - BDB_PAGE_HEADER_SIZE;
+        let overflow_pages = large.len().div_ceil(chunk);
+        let last_page = 1 + overflow_pages;
+        let mut data = vec![0; PAGE_SIZE * (last_page + 1)];
+
+        data[12..16].copy_from_slice(&u32(BDB_HASH_MAGIC));
+        data[20..24].copy_from_slice(&u32(PAGE_SIZE as u32));
+        data[25] = 8;
+        data[32..36].copy_from_slice(&u32(last_page as u32));
+
+        let mut offpage = vec![BDB_ITEM_OFFPAGE, 0, 0, 0];
+        offpage.extend(u32(2));
+        offpage.extend(u32(large.len() as u32));
+        let items = [
+            [&[BDB_ITEM_KEYDATA][..], &u32(1)].concat(),
+            [&[BDB_ITEM_KEYDATA][..], inline].concat(),
+            [&[BDB_ITEM_KEYDATA][..], &u32(2)].concat(),
+            offpage,
+        ];
+
+        let page = &mut data[PAGE_SIZE..PAGE_SIZE * 2];
+        page[20..22].copy_from_slice(&u16(items.len() as u16));
+        page[25] = BDB_PAGE_HASH;
+        let mut top = PAGE_SIZE;
+        for (i, item) in items.iter().enumerate() {
+            top -= item.len();
+            page[top..top + item.len()].copy_from_slice(item);
+            let slot = BDB_PAGE_HEADER_SIZE + i * 2;
+            page[slot..slot + 2].copy_from_slice(&u16(top as u16));
+        }
+
+        for (i, part) in large.chunks(chunk).enumerate() {
+            let number = 2 + i;
+            let next = if i + 1 < overflow_pages { number as u32 + 1 } else { 0 };
+            let page = &mut data[PAGE_SIZE * number..PAGE_SIZE * (number + 1)];
+            page[16..20].copy_from_slice(&u32(next));
+            page[22..24].copy_from_slice(&u16(part.len() as u16));
+            page[25] = BDB_PAGE_OVERFLOW;
+            page[BDB_PAGE_HEADER_SIZE..BDB_PAGE_HEADER_SIZE + part.len()].copy_from_slice(part);
+        }
+        data
+    }
+
+    #[test]
+    fn reads_berkeley_db_values_across_overflow_pages() {
+        let inline = fixture("tzdata", "vendor");
+        let large = fixture("kernel", &"x".repeat(1200));
+        assert!(large.len() > 2 * 512);
+
+        for big_endian in [false, true] {
+            let data = berkeley_db(big_endian, &inline, &large);
+            let blobs = read_berkeley_db(&data).unwrap();
+            assert_eq!(blobs, [inline.clone(), large.clone()]);
+            assert_eq!(names(&blobs), ["tzdata", "kernel"]);
+        }
+
+        // オーバーフローページが途中で切れていればエラーにする
+        let data = berkeley_db(false, &inline, &large);
+        assert!(read_berkeley_db(&data[..data.len() - 512]).is_err());
+        assert!(read_berkeley_db(&[0; 64]).is_err());
+    }
+}
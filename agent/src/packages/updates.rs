@@ -0,0 +1,79 @@
+use crate::packages::{apk, apt, dnf, get_inventory, version};
+use common::agent::package::{PackageManager, PackageUpdate, PackageUpdates};
+use std::{cmp::Ordering, collections::HashMap};
+
+use anyhow::{Context, Result, bail};
+
+/// リポジトリのメタデータに載っているインストール候補
+pub struct Candidate {
+    pub name: String,
+    pub version: String,
+    pub architecture: Option<String>,
+    pub source: Option<String>,
+    pub security: bool,
+}
+
+/// ディスク上にあるリポジトリのメタデータと比較するだけで、メタデータの更新は行わない
+pub async fn get_updates() -> Result<PackageUpdates> {
+    let inventory = get_inventory().await?;
+    let manager = inventory.manager;
+
+    let candidates = tokio::task::spawn_blocking(move || match manager {
+        PackageManager::Dpkg => apt::read_candidates(),
+        PackageManager::Rpm => dnf::read_candidates(),
+        PackageManager::Apk => apk::read_candidates(),
+        PackageManager::Pacman => bail!("update checks are not supported for pacman"),
+    })
+    .await
+    .context("candidate reader panicked")??;
+
+    let compare: fn(&str, &str) -> Ordering = match manager {
+        PackageManager::Dpkg => version::compare_dpkg,
+        PackageManager::Rpm => version::compare_rpm,
+        PackageManager::Apk | PackageManager::Pacman => version::compare_apk,
+    };
+
+    let mut by_name: HashMap<&str, Vec<&Candidate>> = HashMap::new();
+    for candidate in &candidates {
+        by_name.entry(candidate.name.as_str()).or_default().push(candidate);
+    }
+
+    let mut updates = inventory
+        .packages
+        .iter()
+        .filter_map(|package| {
+            let newer = by_name
+                .get(package.name.as_str())?
+                .iter()
+                .filter(|c| same_architecture(package.architecture.as_deref(), c.architecture.as_deref()))
+                .filter(|c| compare(&c.version, &package.version) == Ordering::Greater)
+                .collect::<Vec<_>>();
+            let best = newer.iter().max_by(|a, b| compare(&a.version, &b.version))?;
+            Some(PackageUpdate {
+                name: package.name.clone(),
+                installed_version: package.version.clone(),
+                candidate_version: best.version.clone(),
+                architecture: package.architecture.clone(),
+                source: best.source.clone(),
+                security: newer.iter().any(|c| c.security),
+            })
+        })
+        .collect::<Vec<PackageUpdate>>();
+    updates.sort_by(|a, b| b.security.cmp(&a.security).then_with(|| a.name.cmp(&b.name)));
+
+    Ok(PackageUpdates {
+        manager,
+        pending_count: updates.len() as u64,
+        security_count: updates.iter().filter(|u| u.security).count() as u64,
+        updates,
+    })
+}
+
+fn same_architecture(installed: Option<&str>, candidate: Option<&str>) -> bool {
+    match (installed, candidate) {
+        (Some(installed), Some(candidate)) => {
+            installed == candidate || matches!(candidate, "all" | "noarch")
+        }
+        _ => true,
+    }
+}
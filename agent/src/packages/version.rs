@@ -0,0 +1,328 @@
+use std::cmp::Ordering;
+
+/// dpkg のバージョン比較（`epoch:upstream-revision`）
+pub fn compare_dpkg(a: &str, b: &str) -> Ordering {
+    let (a_epoch, a_upstream, a_revision) = split_dpkg(a);
+    let (b_epoch, b_upstream, b_revision) = split_dpkg(b);
+    a_epoch
+        .cmp(&b_epoch)
+        .then_with(|| verrevcmp(a_upstream, b_upstream))
+        .then_with(|| verrevcmp(a_revision, b_revision))
+}
+
+fn split_dpkg(version: &str) -> (u64, &str, &str) {
+    let (epoch, rest) = match version.split_once(':') {
+        Some((epoch, rest)) => (epoch.parse().unwrap_or(0), rest),
+        None => (0, version),
+    };
+    match rest.rsplit_once('-') {
+        Some((upstream, revision)) => (epoch, upstream, revision),
+        None => (epoch, rest, ""),
+    }
+}
+
+fn verrevcmp(a: &str, b: &str) -> Ordering {
+    // '~' は空文字列よりも前、英字はそれ以外の記号よりも前に並ぶ
+    fn order(c: Option<u8>) -> i32 {
+        match c {
+            None => 0,
+            Some(b'~') => -1,
+            Some(c) if c.is_ascii_digit() => 0,
+            Some(c) if c.is_ascii_alphabetic() => c as i32,
+            Some(c) => c as i32 + 256,
+        }
+    }
+
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        while (i < a.len() && !a[i].is_ascii_digit()) || (j < b.len() && !b[j].is_ascii_digit()) {
+            let ac = order(a.get(i).copied());
+            let bc = order(b.get(j).copied());
+            if ac != bc {
+                return ac.cmp(&bc);
+            }
+            i += 1;
+            j += 1;
+        }
+
+        let (a_num, next_i) = take_digits(a, i);
+        let (b_num, next_j) = take_digits(b, j);
+        let ordering = compare_numeric(a_num, b_num);
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+        i = next_i;
+        j = next_j;
+    }
+    Ordering::Equal
+}
+
+/// rpm のバージョン比較（`epoch:version-release`）
+pub fn compare_rpm(a: &str, b: &str) -> Ordering {
+    let (a_epoch, a_version, a_release) = split_rpm(a);
+    let (b_epoch, b_version, b_release) = split_rpm(b);
+    a_epoch
+        .cmp(&b_epoch)
+        .then_with(|| rpmvercmp(a_version, b_version))
+        .then_with(|| rpmvercmp(a_release, b_release))
+}
+
+fn split_rpm(version: &str) -> (u64, &str, &str) {
+    split_dpkg(version)
+}
+
+fn rpmvercmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let (mut i, mut j) = (0, 0);
+    loop {
+        while i < a.len() && !a[i].is_ascii_alphanumeric() && a[i] != b'~' && a[i] != b'^' {
+            i += 1;
+        }
+        while j < b.len() && !b[j].is_ascii_alphanumeric() && b[j] != b'~' && b[j] != b'^' {
+            j += 1;
+        }
+
+        // '~' は何よりも前に並ぶ
+        match (a.get(i), b.get(j)) {
+            (Some(b'~'), Some(b'~')) => {
+                i += 1;
+                j += 1;
+                continue;
+            }
+            (Some(b'~'), _) => return Ordering::Less,
+            (_, Some(b'~')) => return Ordering::Greater,
+            _ => {}
+        }
+
+        // '^' は末尾より後、それ以外より前に並ぶ
+        match (a.get(i), b.get(j)) {
+            (Some(b'^'), Some(b'^')) => {
+                i += 1;
+                j += 1;
+                continue;
+            }
+            (Some(b'^'), None) => return Ordering::Greater,
+            (None, Some(b'^')) => return Ordering::Less,
+            (Some(b'^'), _) => return Ordering::Less,
+            (_, Some(b'^')) => return Ordering::Greater,
+            _ => {}
+        }
+
+        if i >= a.len() || j >= b.len() {
+            break;
+        }
+
+        let ordering = if a[i].is_ascii_digit() {
+            let (a_num, next_i) = take_digits(a, i);
+            let (b_num, next_j) = take_digits(b, j);
+            if b_num.is_empty() {
+                // 数字の区切りは英字の区切りよりも新しい
+                return Ordering::Greater;
+            }
+            i = next_i;
+            j = next_j;
+            compare_numeric(a_num, b_num)
+        } else {
+            let (a_alpha, next_i) = take_alpha(a, i);
+            let (b_alpha, next_j) = take_alpha(b, j);
+            if b_alpha.is_empty() {
+                return Ordering::Less;
+            }
+            i = next_i;
+            j = next_j;
+            a_alpha.cmp(b_alpha)
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    (a.len() - i.min(a.len())).cmp(&(b.len() - j.min(b.len())))
+}
+
+/// apk のバージョン比較（`1.2.3_rc1-r0` 形式）
+pub fn compare_apk(a: &str, b: &str) -> Ordering {
+    let (a_version, a_release) = split_apk(a);
+    let (b_version, b_release) = split_apk(b);
+    compare_apk_version(a_version, b_version).then_with(|| a_release.cmp(&b_release))
+}
+
+fn split_apk(version: &str) -> (&str, u64) {
+    match version.rsplit_once("-r") {
+        Some((version, release)) if release.chars().all(|c| c.is_ascii_digit()) => {
+            (version, release.parse().unwrap_or(0))
+        }
+        _ => (version, 0),
+    }
+}
+
+fn compare_apk_version(a: &str, b: &str) -> Ordering {
+    // サフィックスなしを 0 とし、pre-release 系は負、パッチ系は正の順位を持つ
+    fn suffix_rank(suffix: &str) -> i32 {
+        match suffix {
+            "alpha" => -4,
+            "beta" => -3,
+            "pre" => -2,
+            "rc" => -1,
+            "cvs" => 1,
+            "svn" => 2,
+            "git" => 3,
+            "hg" => 4,
+            "p" => 5,
+            _ => 0,
+        }
+    }
+
+    let (a_main, a_suffixes) = a.split_once('_').unwrap_or((a, ""));
+    let (b_main, b_suffixes) = b.split_once('_').unwrap_or((b, ""));
+
+    let ordering = verrevcmp(a_main, b_main);
+    if ordering != Ordering::Equal {
+        return ordering;
+    }
+
+    let mut a_parts = a_suffixes.split('_').filter(|s| !s.is_empty());
+    let mut b_parts = b_suffixes.split('_').filter(|s| !s.is_empty());
+    loop {
+        match (a_parts.next(), b_parts.next()) {
+            (None, None) => return Ordering::Equal,
+            (a_part, b_part) => {
+                let split = |part: Option<&str>| {
+                    let part = part.unwrap_or("");
+                    let index = part.find(|c: char| c.is_ascii_digit()).unwrap_or(part.len());
+                    let (name, number) = part.split_at(index);
+                    (suffix_rank(name), number.parse::<u64>().unwrap_or(0))
+                };
+                let ordering = split(a_part).cmp(&split(b_part));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+        }
+    }
+}
+
+fn take_digits(s: &[u8], start: usize) -> (&[u8], usize) {
+    let end = s[start.min(s.len())..]
+        .iter()
+        .position(|c| !c.is_ascii_digit())
+        .map(|p| start + p)
+        .unwrap_or(s.len());
+    (&s[start.min(s.len())..end.max(start.min(s.len()))], end)
+}
+
+fn take_alpha(s: &[u8], start: usize) -> (&[u8], usize) {
+    let end = s[start..]
+        .iter()
+        .position(|c| !c.is_ascii_alphabetic())
+        .map(|p| start + p)
+        .unwrap_or(s.len());
+    (&s[start..end], end)
+}
+
+fn compare_numeric(a: &[u8], b: &[u8]) -> Ordering {
+    let trim = |s: &[u8]| -> usize { s.iter().position(|c| *c != b'0').unwrap_or(s.len()) };
+    let a = &a[trim(a)..];
+    let b = &b[trim(b)..];
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cmp::Ordering::{Equal, Greater, Less};
+
+    fn check(compare: fn(&str, &str) -> Ordering, cases: &[(&str, &str, Ordering)]) {
+        for (a, b, expected) in cases {
+            assert_eq!(compare(a, b), *expected, "{} vs {}", a, b);
+            assert_eq!(compare(b, a), expected.reverse(), "{} vs {}", b, a);
+        }
+    }
+
+    #[test]
+    fn dpkg_versions() {
+        check(compare_dpkg, &[
+            ("1.0", "1.0", Equal),
+            ("1.0", "1.00", Equal),
+            ("1.9", "1.10", Less),
+            ("1.0", "1.0.1", Less),
+            // epoch は他のどの部分よりも優先する
+            ("1:1.0", "2.0", Greater),
+            ("0:1.0", "1.0", Equal),
+            ("1:1.0-1", "1:1.0-2", Less),
+            // '~' は空文字列よりも前に並ぶ
+            ("1.0~rc1", "1.0", Less),
+            ("1.0~rc1", "1.0~rc2", Less),
+            ("1.0~~", "1.0~", Less),
+            ("1.0~", "1.0", Less),
+            // 英字は記号よりも前、末尾よりも後に並ぶ
+            ("1.0a", "1.0", Greater),
+            ("1.0a", "1.0+", Less),
+            ("1.0+b1", "1.0", Greater),
+            // revision はハイフンの最後で分ける
+            ("1.0-beta-1", "1.0-beta-2", Less),
+            ("2.30-10ubuntu1", "2.30-9ubuntu5", Greater),
+            ("2.30-10ubuntu1", "2.30-10ubuntu1.1", Less),
+        ]);
+    }
+
+    #[test]
+    fn rpm_versions() {
+        check(compare_rpm, &[
+            ("1.0", "1.0", Equal),
+            ("1.0", "1.00", Equal),
+            ("1.9", "1.10", Less),
+            ("1.0", "1.0.1", Less),
+            ("1.0-1", "1.0-2", Less),
+            ("1:1.0", "2.0", Greater),
+            // 区切り記号の種類は区別しない
+            ("1.0_1", "1.0.1", Equal),
+            // 数字の区切りは英字の区切りよりも新しい
+            ("1.0a", "1.0.1", Less),
+            ("1.0a", "1.0", Greater),
+            ("1.0.a", "1.0.1", Less),
+            // '~' は何よりも前に並ぶ
+            ("1.0~rc1", "1.0", Less),
+            ("1.0~rc1", "1.0~rc2", Less),
+            ("1.0~rc1", "1.0~", Greater),
+            // '^' は末尾よりも後、それ以外よりも前に並ぶ
+            ("1.0^", "1.0", Greater),
+            ("1.0^git1", "1.0", Greater),
+            ("1.0^git1", "1.0.1", Less),
+            ("1.0^git1", "1.0^git2", Less),
+            ("1.0~rc1^git1", "1.0~rc1", Greater),
+            ("1.0~rc1^git1", "1.0", Less),
+        ]);
+    }
+
+    #[test]
+    fn apk_versions() {
+        check(compare_apk, &[
+            ("1.2.3", "1.2.3", Equal),
+            ("1.2.3", "1.2.10", Less),
+            ("1.2.3-r0", "1.2.3", Equal),
+            ("1.2.3-r1", "1.2.3-r0", Greater),
+            ("1.2.3-r10", "1.2.3-r9", Greater),
+            ("1.2.3-r1", "1.2.4-r0", Less),
+            ("1.2a", "1.2", Greater),
+            ("1.2a", "1.2b", Less),
+            // pre-release 系のサフィックスは付いていない版よりも前に並ぶ
+            ("1.2.3_alpha", "1.2.3_beta", Less),
+            ("1.2.3_beta2", "1.2.3_pre1", Less),
+            ("1.2.3_rc1", "1.2.3", Less),
+            ("1.2.3_rc1", "1.2.3_rc2", Less),
+            ("1.2.3_rc2-r5", "1.2.3-r0", Less),
+            // パッチ系のサフィックスは後に並ぶ
+            ("1.2.3_p1", "1.2.3", Greater),
+            ("1.2.3_p1", "1.2.3_p2", Less),
+            ("1.2.3_git20240101", "1.2.3_p1", Less),
+            ("1.2.3_rc1_p1", "1.2.3_rc1", Greater),
+            ("1.2.3_rc1_p1", "1.2.3", Less),
+        ]);
+    }
+}
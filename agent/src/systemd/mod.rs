@@ -0,0 +1,216 @@
+pub mod proxy;
+#[cfg(test)]
+mod tests;
+
+use crate::app::config::SystemdConfig;
+use common::agent::service::{ServiceAction, ServiceUnit};
+use std::{collections::HashMap, time::Duration};
+
+use futures::{StreamExt, stream};
+use proxy::{ManagerProxy, ServiceProxy, UnitProxy};
+use zbus::{Connection, proxy::CacheProperties, zvariant::OwnedObjectPath};
+
+const MEMORY_CONCURRENCY: usize = 16;
+const JOB_TIMEOUT: Duration = Duration::from_secs(8);
+
+/// ジョブの完了を待った結果
+pub enum JobOutcome {
+    Done,
+    Failed(String),
+    Pending,
+}
+
+/// `bus_address` が指定されていればそのバスに、なければシステムバスに接続する
+pub async fn connect(config: &SystemdConfig) -> zbus::Result<Connection> {
+    match &config.bus_address {
+        Some(address) => zbus::connection::Builder::address(address.as_str())?.build().await,
+        None => Connection::system().await,
+    }
+}
+
+/// 許可リストは完全一致か `*` を含むパターンで指定する
+pub fn is_manageable(config: &SystemdConfig, name: &str) -> bool {
+    config.allowed_units.iter().any(|pattern| matches_pattern(pattern, name))
+}
+
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts = parts.collect::<Vec<&str>>();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+pub async fn list_units(conn: &Connection, config: &SystemdConfig) -> zbus::Result<Vec<ServiceUnit>> {
+    let manager = ManagerProxy::new(conn).await?;
+    let loaded = manager.list_units_by_patterns(&[], &["*.service"]).await?;
+    let unit_files = manager.list_unit_files_by_patterns(&[], &["*.service"]).await?;
+
+    let mut file_states = unit_files
+        .into_iter()
+        .filter_map(|(path, state)| {
+            let name = path.rsplit('/').next()?.to_string();
+            Some((name, state))
+        })
+        .collect::<HashMap<String, String>>();
+
+    let active = loaded
+        .iter()
+        .filter(|unit| unit.3 == "active")
+        .map(|unit| (unit.0.clone(), unit.6.clone()))
+        .collect::<Vec<(String, OwnedObjectPath)>>();
+    let memory = stream::iter(active)
+        .map(|(name, path)| async move { (name, read_memory(conn, &path).await) })
+        .buffer_unordered(MEMORY_CONCURRENCY)
+        .collect::<HashMap<String, Option<u64>>>()
+        .await;
+
+    let mut units = loaded
+        .into_iter()
+        .map(|(name, description, load_state, active_state, sub_state, ..)| ServiceUnit {
+            unit_file_state: file_states.remove(&name),
+            memory_bytes: memory.get(&name).copied().flatten(),
+            manageable: is_manageable(config, &name),
+            name,
+            description,
+            load_state,
+            active_state,
+            sub_state,
+        })
+        .collect::<Vec<ServiceUnit>>();
+
+    // 停止中で読み込まれていないユニットもユニットファイルから補う（テンプレートは除く）
+    units.extend(
+        file_states
+            .into_iter()
+            .filter(|(name, _)| !name.ends_with("@.service"))
+            .map(|(name, state)| ServiceUnit {
+                manageable: is_manageable(config, &name),
+                name,
+                description: String::new(),
+                load_state: "not-loaded".to_string(),
+                active_state: "inactive".to_string(),
+                sub_state: "dead".to_string(),
+                unit_file_state: Some(state),
+                memory_bytes: None,
+            }),
+    );
+    units.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(units)
+}
+
+/// ユニットが存在しない場合は `None` を返す
+pub async fn get_unit(conn: &Connection, config: &SystemdConfig, name: &str) -> zbus::Result<Option<ServiceUnit>> {
+    let manager = ManagerProxy::new(conn).await?;
+    let path = manager.load_unit(name).await?;
+    let unit = UnitProxy::builder(conn)
+        .path(&path)?
+        .cache_properties(CacheProperties::No)
+        .build()
+        .await?;
+
+    let load_state = unit.load_state().await?;
+    if load_state == "not-found" {
+        return Ok(None);
+    }
+    let active_state = unit.active_state().await?;
+    let memory_bytes = if active_state == "active" { read_memory(conn, &path).await } else { None };
+
+    Ok(Some(ServiceUnit {
+        name: unit.id().await?,
+        description: unit.description().await?,
+        sub_state: unit.sub_state().await?,
+        unit_file_state: unit.unit_file_state().await.ok().filter(|state| !state.is_empty()),
+        manageable: is_manageable(config, name),
+        load_state,
+        active_state,
+        memory_bytes,
+    }))
+}
+
+async fn read_memory(conn: &Connection, path: &OwnedObjectPath) -> Option<u64> {
+    let service = ServiceProxy::builder(conn)
+        .path(path)
+        .ok()?
+        .cache_properties(CacheProperties::No)
+        .build()
+        .await
+        .ok()?;
+    // 取得できない場合は u64::MAX（[not set]）が返る
+    service.memory_current().await.ok().filter(|bytes| *bytes != u64::MAX)
+}
+
+/// ジョブとして投入し、完了を待つ操作
+#[derive(Clone, Copy)]
+enum UnitJob {
+    Start,
+    Stop,
+    Restart,
+    Reload,
+}
+
+pub async fn run_action(conn: &Connection, name: &str, action: ServiceAction) -> zbus::Result<JobOutcome> {
+    let manager = ManagerProxy::new(conn).await?;
+
+    // 有効化・無効化はジョブを作らず、ユニットファイルのリンクを張り替えるだけ
+    let job = match action {
+        ServiceAction::Enable => {
+            manager.enable_unit_files(&[name], false, false).await?;
+            manager.reload().await?;
+            return Ok(JobOutcome::Done);
+        }
+        ServiceAction::Disable => {
+            manager.disable_unit_files(&[name], false).await?;
+            manager.reload().await?;
+            return Ok(JobOutcome::Done);
+        }
+        ServiceAction::Start => UnitJob::Start,
+        ServiceAction::Stop => UnitJob::Stop,
+        ServiceAction::Restart => UnitJob::Restart,
+        ServiceAction::Reload => UnitJob::Reload,
+    };
+    run_job(&manager, name, job).await
+}
+
+async fn run_job(manager: &ManagerProxy<'_>, name: &str, job: UnitJob) -> zbus::Result<JobOutcome> {
+    // ジョブの完了通知を取りこぼさないよう、ジョブを投入する前に購読しておく
+    manager.subscribe().await?;
+    let mut removed = manager.receive_job_removed().await?;
+
+    let job = match job {
+        UnitJob::Start => manager.start_unit(name, "replace").await?,
+        UnitJob::Stop => manager.stop_unit(name, "replace").await?,
+        UnitJob::Restart => manager.restart_unit(name, "replace").await?,
+        UnitJob::Reload => manager.reload_unit(name, "replace").await?,
+    };
+
+    let wait = async {
+        while let Some(signal) = removed.next().await {
+            let Ok(args) = signal.args() else {
+                continue;
+            };
+            if args.job == job {
+                return match args.result.as_str() {
+                    "done" | "skipped" => JobOutcome::Done,
+                    result => JobOutcome::Failed(result.to_string()),
+                };
+            }
+        }
+        JobOutcome::Pending
+    };
+
+    Ok(tokio::time::timeout(JOB_TIMEOUT, wait).await.unwrap_or(JobOutcome::Pending))
+}
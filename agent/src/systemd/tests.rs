@@ -0,0 +1,323 @@
+//! dbus-daemon を一時的に起動し、systemd の代わりになるオブジェクトを置いて `bus_address` から接続する
+use super::*;
+use proxy::UnitStatus;
+use std::{
+    process::Stdio,
+    sync::{Arc, Mutex},
+};
+
+use tempfile::TempDir;
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::{Child, Command},
+};
+use zbus::{interface, object_server::SignalEmitter, zvariant::ObjectPath};
+
+const MANAGER_PATH: &str = "/org/freedesktop/systemd1";
+const NGINX_PATH: &str = "/org/freedesktop/systemd1/unit/nginx_2eservice";
+const OLD_PATH: &str = "/org/freedesktop/systemd1/unit/old_2eservice";
+const MISSING_PATH: &str = "/org/freedesktop/systemd1/unit/missing_2eservice";
+const NGINX_MEMORY: u64 = 12 * 1024 * 1024;
+
+struct Bus {
+    address: String,
+    _daemon: Child,
+    _dir: TempDir,
+}
+
+/// dbus-daemon が無い環境では `None` を返し、テストを飛ばす
+async fn start_bus() -> Option<Bus> {
+    let dir = tempfile::tempdir().unwrap();
+    let address = format!("unix:path={}", dir.path().join("bus").display());
+    let mut daemon = match Command::new("dbus-daemon")
+        .args(["--session", "--nofork", "--print-address"])
+        .arg(format!("--address={}", address))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+    {
+        Ok(daemon) => daemon,
+        Err(e) => {
+            eprintln!("skipping: dbus-daemon is not available: {}", e);
+            return None;
+        }
+    };
+    // アドレスが出力された時点で接続を受け付けている
+    let mut line = String::new();
+    BufReader::new(daemon.stdout.take().unwrap()).read_line(&mut line).await.unwrap();
+    assert!(!line.is_empty(), "dbus-daemon exited before printing its address");
+
+    Some(Bus { address, _daemon: daemon, _dir: dir })
+}
+
+/// 呼ばれたメソッドを順に記録する
+#[derive(Clone, Default)]
+struct Calls(Arc<Mutex<Vec<String>>>);
+
+impl Calls {
+    fn push(&self, call: String) {
+        self.0.lock().unwrap().push(call);
+    }
+
+    fn take(&self) -> Vec<String> {
+        std::mem::take(&mut self.0.lock().unwrap())
+    }
+}
+
+struct MockManager {
+    calls: Calls,
+    next_job: u32,
+}
+
+impl MockManager {
+    /// ジョブを作り、結果の通知を返り値より先に送る。無関係なジョブの通知も混ぜる
+    async fn job(&mut self, call: &str, name: &str, result: &str, emitter: &SignalEmitter<'_>) -> zbus::fdo::Result<OwnedObjectPath> {
+        self.calls.push(format!("{} {}", call, name));
+        self.next_job += 1;
+        let job = OwnedObjectPath::try_from(format!("{}/job/{}", MANAGER_PATH, self.next_job)).unwrap();
+        let other = ObjectPath::try_from(format!("{}/job/{}", MANAGER_PATH, self.next_job + 1000)).unwrap();
+        Self::job_removed(emitter, self.next_job + 1000, other, "other.service", "failed").await?;
+        Self::job_removed(emitter, self.next_job, job.as_ref(), name, result).await?;
+        Ok(job)
+    }
+}
+
+#[interface(name = "org.freedesktop.systemd1.Manager")]
+impl MockManager {
+    fn subscribe(&self) {
+        self.calls.push("Subscribe".to_string());
+    }
+
+    fn list_units_by_patterns(&self, _states: Vec<String>, _patterns: Vec<String>) -> Vec<UnitStatus> {
+        let root = OwnedObjectPath::try_from("/").unwrap();
+        let unit = |name: &str, description: &str, active: &str, sub: &str, path: &str| {
+            (
+                name.to_string(),
+                description.to_string(),
+                "loaded".to_string(),
+                active.to_string(),
+                sub.to_string(),
+                String::new(),
+                OwnedObjectPath::try_from(path).unwrap(),
+                0,
+                String::new(),
+                root.clone(),
+            )
+        };
+        vec![
+            unit("old.service", "Old service", "inactive", "dead", OLD_PATH),
+            unit("nginx.service", "A high performance web server", "active", "running", NGINX_PATH),
+        ]
+    }
+
+    fn list_unit_files_by_patterns(&self, _states: Vec<String>, _patterns: Vec<String>) -> Vec<(String, String)> {
+        [
+            ("/usr/lib/systemd/system/nginx.service", "enabled"),
+            ("/usr/lib/systemd/system/stopped.service", "disabled"),
+            ("/usr/lib/systemd/system/getty@.service", "static"),
+        ]
+        .into_iter()
+        .map(|(path, state)| (path.to_string(), state.to_string()))
+        .collect()
+    }
+
+    fn load_unit(&self, name: String) -> OwnedObjectPath {
+        let path = if name == "nginx.service" { NGINX_PATH } else { MISSING_PATH };
+        OwnedObjectPath::try_from(path).unwrap()
+    }
+
+    async fn start_unit(&mut self, name: String, _mode: String, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) -> zbus::fdo::Result<OwnedObjectPath> {
+        self.job("StartUnit", &name, "done", &emitter).await
+    }
+
+    async fn stop_unit(&mut self, name: String, _mode: String, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) -> zbus::fdo::Result<OwnedObjectPath> {
+        self.job("StopUnit", &name, "done", &emitter).await
+    }
+
+    async fn restart_unit(&mut self, name: String, _mode: String, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) -> zbus::fdo::Result<OwnedObjectPath> {
+        self.job("RestartUnit", &name, "failed", &emitter).await
+    }
+
+    async fn reload_unit(&mut self, name: String, _mode: String, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) -> zbus::fdo::Result<OwnedObjectPath> {
+        self.job("ReloadUnit", &name, "skipped", &emitter).await
+    }
+
+    fn enable_unit_files(&self, files: Vec<String>, runtime: bool, force: bool) -> (bool, Vec<(String, String, String)>) {
+        self.calls.push(format!("EnableUnitFiles {} {} {}", files.join(","), runtime, force));
+        (true, Vec::new())
+    }
+
+    fn disable_unit_files(&self, files: Vec<String>, runtime: bool) -> Vec<(String, String, String)> {
+        self.calls.push(format!("DisableUnitFiles {} {}", files.join(","), runtime));
+        Vec::new()
+    }
+
+    fn reload(&self) {
+        self.calls.push("Reload".to_string());
+    }
+
+    #[zbus(signal)]
+    async fn job_removed(emitter: &SignalEmitter<'_>, id: u32, job: ObjectPath<'_>, unit: &str, result: &str) -> zbus::Result<()>;
+}
+
+struct MockUnit {
+    id: &'static str,
+    load_state: &'static str,
+    active_state: &'static str,
+}
+
+#[interface(name = "org.freedesktop.systemd1.Unit")]
+impl MockUnit {
+    #[zbus(property)]
+    fn id(&self) -> String {
+        self.id.to_string()
+    }
+
+    #[zbus(property)]
+    fn description(&self) -> String {
+        format!("{} description", self.id)
+    }
+
+    #[zbus(property)]
+    fn load_state(&self) -> String {
+        self.load_state.to_string()
+    }
+
+    #[zbus(property)]
+    fn active_state(&self) -> String {
+        self.active_state.to_string()
+    }
+
+    #[zbus(property)]
+    fn sub_state(&self) -> String {
+        if self.active_state == "active" { "running" } else { "dead" }.to_string()
+    }
+
+    #[zbus(property)]
+    fn unit_file_state(&self) -> String {
+        if self.load_state == "loaded" { "enabled" } else { "" }.to_string()
+    }
+}
+
+struct MockService {
+    memory_current: u64,
+}
+
+#[interface(name = "org.freedesktop.systemd1.Service")]
+impl MockService {
+    #[zbus(property)]
+    fn memory_current(&self) -> u64 {
+        self.memory_current
+    }
+}
+
+/// 偽の systemd をバスに置き、エージェントと同じ設定で接続する
+async fn setup() -> Option<(Bus, zbus::Connection, Connection, SystemdConfig, Calls)> {
+    let bus = start_bus().await?;
+    let calls = Calls::default();
+    let systemd = zbus::connection::Builder::address(bus.address.as_str())
+        .unwrap()
+        .name("org.freedesktop.systemd1")
+        .unwrap()
+        .serve_at(MANAGER_PATH, MockManager { calls: calls.clone(), next_job: 0 })
+        .unwrap()
+        .serve_at(NGINX_PATH, MockUnit { id: "nginx.service", load_state: "loaded", active_state: "active" })
+        .unwrap()
+        .serve_at(NGINX_PATH, MockService { memory_current: NGINX_MEMORY })
+        .unwrap()
+        .serve_at(OLD_PATH, MockUnit { id: "old.service", load_state: "loaded", active_state: "inactive" })
+        .unwrap()
+        .serve_at(OLD_PATH, MockService { memory_current: u64::MAX })
+        .unwrap()
+        .serve_at(MISSING_PATH, MockUnit { id: "missing.service", load_state: "not-found", active_state: "inactive" })
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+
+    let config = SystemdConfig {
+        allowed_units: vec!["nginx*".to_string()],
+        bus_address: Some(bus.address.clone()),
+    };
+    let conn = connect(&config).await.unwrap();
+    Some((bus, systemd, conn, config, calls))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lists_loaded_units_and_unit_files() {
+    let Some((_bus, _systemd, conn, config, _)) = setup().await else {
+        return;
+    };
+
+    let units = list_units(&conn, &config).await.unwrap();
+    let summary = units
+        .iter()
+        .map(|unit| (unit.name.as_str(), unit.active_state.as_str(), unit.unit_file_state.as_deref(), unit.memory_bytes, unit.manageable))
+        .collect::<Vec<_>>();
+    // テンプレートのユニットファイルは含めず、読み込まれていないユニットはファイルから補う
+    assert_eq!(
+        summary,
+        vec![
+            ("nginx.service", "active", Some("enabled"), Some(NGINX_MEMORY), true),
+            ("old.service", "inactive", None, None, false),
+            ("stopped.service", "inactive", Some("disabled"), None, false),
+        ]
+    );
+    assert_eq!(units[2].load_state, "not-loaded");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn gets_single_unit() {
+    let Some((_bus, _systemd, conn, config, _)) = setup().await else {
+        return;
+    };
+
+    let unit = get_unit(&conn, &config, "nginx.service").await.unwrap().unwrap();
+    assert_eq!(unit.name, "nginx.service");
+    assert_eq!(unit.sub_state, "running");
+    assert_eq!(unit.unit_file_state.as_deref(), Some("enabled"));
+    assert_eq!(unit.memory_bytes, Some(NGINX_MEMORY));
+    assert!(unit.manageable);
+
+    assert!(get_unit(&conn, &config, "missing.service").await.unwrap().is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn waits_for_the_submitted_job() {
+    let Some((_bus, _systemd, conn, _, calls)) = setup().await else {
+        return;
+    };
+
+    let outcome = run_action(&conn, "nginx.service", ServiceAction::Start).await.unwrap();
+    assert!(matches!(outcome, JobOutcome::Done));
+    assert_eq!(calls.take(), vec!["Subscribe", "StartUnit nginx.service"]);
+
+    let outcome = run_action(&conn, "nginx.service", ServiceAction::Restart).await.unwrap();
+    assert!(matches!(outcome, JobOutcome::Failed(result) if result == "failed"));
+
+    for action in [ServiceAction::Stop, ServiceAction::Reload] {
+        let outcome = run_action(&conn, "nginx.service", action).await.unwrap();
+        assert!(matches!(outcome, JobOutcome::Done));
+    }
+    assert_eq!(
+        calls.take(),
+        vec!["Subscribe", "RestartUnit nginx.service", "Subscribe", "StopUnit nginx.service", "Subscribe", "ReloadUnit nginx.service"]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn enables_and_disables_without_jobs() {
+    let Some((_bus, _systemd, conn, _, calls)) = setup().await else {
+        return;
+    };
+
+    let outcome = run_action(&conn, "nginx.service", ServiceAction::Enable).await.unwrap();
+    assert!(matches!(outcome, JobOutcome::Done));
+    let outcome = run_action(&conn, "nginx.service", ServiceAction::Disable).await.unwrap();
+    assert!(matches!(outcome, JobOutcome::Done));
+    assert_eq!(
+        calls.take(),
+        vec!["EnableUnitFiles nginx.service false false", "Reload", "DisableUnitFiles nginx.service false", "Reload"]
+    );
+}
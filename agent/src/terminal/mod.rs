@@ -0,0 +1,115 @@
+pub mod pty;
+
+use common::{
+    agent::terminal::{TerminalClientMessage, TerminalServerMessage},
+    ws::{self, Message},
+};
+use pty::Pty;
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, BufReader},
+    process::Child,
+    sync::mpsc,
+};
+
+const READ_BUFFER_SIZE: usize = 16 * 1024;
+/// シェルの終了後、残っている出力を読み切るまで待つ時間
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// WebSocket と擬似端末を中継する。どちらかが閉じたらシェルのセッションごと終了させる
+pub async fn run_session<S>(stream: S, pty: Pty, mut child: Child)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let pty = Arc::new(pty);
+    let (out_tx, mut out_rx) = mpsc::channel::<Message>(16);
+
+    // 入力の書き込みが詰まっても出力の中継は止めないよう、受信は別タスクで処理する
+    let input = tokio::spawn(forward_input(BufReader::new(reader), pty.clone(), out_tx));
+
+    let mut buf = vec![0u8; READ_BUFFER_SIZE];
+    let exit = loop {
+        tokio::select! {
+            result = pty.read(&mut buf) => match result {
+                Ok(n) if n > 0 => {
+                    if ws::write_message(&mut writer, &Message::Binary(buf[..n].to_vec()), false).await.is_err() {
+                        break None;
+                    }
+                }
+                _ => break Some(child.wait().await.ok().and_then(|status| status.code())),
+            },
+            status = child.wait() => {
+                while let Ok(Ok(n)) = tokio::time::timeout(DRAIN_TIMEOUT, pty.read(&mut buf)).await
+                    && n > 0
+                {
+                    if ws::write_message(&mut writer, &Message::Binary(buf[..n].to_vec()), false).await.is_err() {
+                        break;
+                    }
+                }
+                break Some(status.ok().and_then(|status| status.code()));
+            }
+            message = out_rx.recv() => match message {
+                Some(message) => {
+                    if ws::write_message(&mut writer, &message, false).await.is_err() {
+                        break None;
+                    }
+                }
+                None => break None,
+            },
+        }
+    };
+
+    if let Some(code) = exit {
+        let message = serde_json::to_string(&TerminalServerMessage::Exit { code }).unwrap_or_default();
+        let _ = ws::write_message(&mut writer, &Message::Text(message), false).await;
+        let _ = ws::write_message(&mut writer, &Message::Close, false).await;
+    } else if let Some(pid) = child.id() {
+        // 端末を閉じたときと同じく、セッション全体に SIGHUP を送る
+        // SAFETY: 自分で起動したセッションのプロセスグループにシグナルを送るだけ
+        unsafe { libc::kill(-(pid as i32), libc::SIGHUP) };
+    }
+    input.abort();
+    let _ = child.kill().await;
+}
+
+async fn forward_input<R>(reader: R, pty: Arc<Pty>, out_tx: mpsc::Sender<Message>)
+where
+    R: AsyncRead + Unpin,
+{
+    // ブラウザ（クライアント）からのフレームはマスクされている
+    let mut reader = ws::MessageReader::new(reader, true);
+    loop {
+        let message = match reader.read_message().await {
+            Ok(Some(message)) => message,
+            Ok(None) => return,
+            Err(e) => {
+                tracing::debug!("Terminal connection closed: {}", e);
+                return;
+            }
+        };
+
+        let result = match message {
+            Message::Binary(data) => pty.write_all(&data).await,
+            Message::Text(text) => match serde_json::from_str::<TerminalClientMessage>(&text) {
+                Ok(TerminalClientMessage::Input { data }) => pty.write_all(data.as_bytes()).await,
+                Ok(TerminalClientMessage::Resize { cols, rows }) => pty.resize(cols, rows),
+                Err(e) => {
+                    tracing::warn!("Ignoring invalid terminal message: {}", e);
+                    Ok(())
+                }
+            },
+            Message::Ping(data) => {
+                let _ = out_tx.send(Message::Pong(data)).await;
+                Ok(())
+            }
+            Message::Pong(_) => Ok(()),
+            Message::Close => return,
+        };
+        if let Err(e) = result {
+            tracing::warn!("Failed to write to terminal: {}", e);
+            return;
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use std::{
+    ffi::{CStr, OsStr},
+    fs::OpenOptions,
+    io,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::{ffi::OsStrExt, fs::OpenOptionsExt},
+    },
+    path::Path,
+    process::Stdio,
+};
+
+use tokio::{
+    io::unix::AsyncFd,
+    process::{Child, Command},
+};
+
+/// ログインシェルが /etc/profile を読むまでの間に使う PATH
+const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// 擬似端末のマスター側。読み書きはノンブロッキングで行う
+pub struct Pty {
+    master: AsyncFd<OwnedFd>,
+}
+
+impl Pty {
+    /// 新しいセッションで `shell` をログインシェルとして起動し、擬似端末を制御端末にする
+    pub fn spawn(shell: &str, cols: u16, rows: u16) -> io::Result<(Self, Child)> {
+        let master = open_master()?;
+        let slave = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(slave_path(&master)?)?;
+        set_window_size(&master, cols, rows)?;
+
+        let name = Path::new(shell).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        let (user, home) = current_user();
+        let mut command = Command::new(shell);
+        // エージェントに渡されたトークンなどの環境変数をシェルへ引き継がない
+        command
+            .arg0(format!("-{}", name))
+            .env_clear()
+            .env("TERM", "xterm-256color")
+            .env("HOME", &home)
+            .env("PATH", DEFAULT_PATH)
+            .env("USER", &user)
+            .current_dir(&home)
+            .stdin(Stdio::from(slave.try_clone()?))
+            .stdout(Stdio::from(slave.try_clone()?))
+            .stderr(Stdio::from(slave))
+            .kill_on_drop(true);
+        // SAFETY: fork 後の子プロセスでは async-signal-safe な関数だけを呼ぶ
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = command.spawn()?;
+
+        // SAFETY: 有効なファイルディスクリプターに対するフラグの変更のみ
+        unsafe {
+            let flags = libc::fcntl(master.as_raw_fd(), libc::F_GETFL);
+            if flags < 0 || libc::fcntl(master.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok((Self { master: AsyncFd::new(master)? }, child))
+    }
+
+    /// シェル側がすべて閉じられると EIO になるので、その場合は終端として 0 を返す
+    pub async fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let mut guard = self.master.readable().await?;
+            // SAFETY: buf の長さを超えて書き込まない
+            let result = guard.try_io(|fd| {
+                let n = unsafe { libc::read(fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+                if n < 0 { Err(io::Error::last_os_error()) } else { Ok(n as usize) }
+            });
+            match result {
+                Ok(Err(e)) if e.raw_os_error() == Some(libc::EIO) => return Ok(0),
+                Ok(result) => return result,
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    pub async fn write_all(&self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            let mut guard = self.master.writable().await?;
+            // SAFETY: data の範囲だけを読む
+            let result = guard.try_io(|fd| {
+                let n = unsafe { libc::write(fd.as_raw_fd(), data.as_ptr().cast(), data.len()) };
+                if n < 0 { Err(io::Error::last_os_error()) } else { Ok(n as usize) }
+            });
+            match result {
+                Ok(Ok(n)) => data = &data[n..],
+                Ok(Err(e)) => return Err(e),
+                Err(_would_block) => continue,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn resize(&self, cols: u16, rows: u16) -> io::Result<()> {
+        set_window_size(self.master.get_ref(), cols, rows)
+    }
+}
+
+fn open_master() -> io::Result<OwnedFd> {
+    // SAFETY: 戻り値を確認してから所有権を持つ
+    unsafe {
+        let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let master = OwnedFd::from_raw_fd(fd);
+        if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(master)
+    }
+}
+
+fn slave_path(master: &OwnedFd) -> io::Result<std::path::PathBuf> {
+    let mut name = [0 as libc::c_char; 128];
+    // SAFETY: バッファの長さを渡し、成功時は NUL 終端されている
+    unsafe {
+        if libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Path::new(OsStr::from_bytes(CStr::from_ptr(name.as_ptr()).to_bytes())).to_path_buf())
+    }
+}
+
+fn set_window_size(master: &OwnedFd, cols: u16, rows: u16) -> io::Result<()> {
+    let size = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: winsize を読むだけの ioctl
+    if unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// エージェントを動かしているユーザーの名前とホームディレクトリ。引けなければ環境変数か既定値を使う
+fn current_user() -> (String, String) {
+    let mut passwd = unsafe { std::mem::zeroed::<libc::passwd>() };
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut result = std::ptr::null_mut();
+    // SAFETY: passwd と buf は呼び出しの間有効で、結果の文字列は buf を指す
+    let found = unsafe { libc::getpwuid_r(libc::geteuid(), &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result) } == 0 && !result.is_null();
+    if found {
+        // SAFETY: getpwuid_r が成功した場合、pw_name と pw_dir は NUL 終端の文字列を指す
+        let (user, home) = unsafe { (CStr::from_ptr(passwd.pw_name), CStr::from_ptr(passwd.pw_dir)) };
+        return (user.to_string_lossy().to_string(), home.to_string_lossy().to_string());
+    }
+    (
+        std::env::var("USER").unwrap_or_else(|_| "root".to_string()),
+        std::env::var("HOME").unwrap_or_else(|_| "/".to_string()),
+    )
+}
@@ -0,0 +1,163 @@
+use common::agent::exec::ExecEvent;
+use std::{convert::Infallible, process::Stdio, time::Duration};
+
+use axum::{
+    body::Body,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::Command,
+    sync::mpsc,
+};
+use futures::{StreamExt, stream};
+
+/// central は無通信が続くと読み込みを打ち切るので、出力のないコマンドの間もこの間隔で `Keepalive` を送る
+#[cfg(not(test))]
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+#[cfg(test)]
+const KEEPALIVE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// コマンドを順番に実行し、出力を NDJSON でストリーミングする。
+/// 途中のコマンドが失敗した場合はそこで止める。クライアントが切断してもコマンドは最後まで実行する
+pub fn stream_commands(commands: Vec<Command>) -> Response {
+    stream_with_timeout(commands, None)
+}
+
+/// `stream_commands` と同じだが、各コマンドが `timeout` を超えたらプロセスグループごと強制終了する
+pub fn stream_with_timeout(commands: Vec<Command>, timeout: Option<Duration>) -> Response {
+    let (tx, rx) = mpsc::channel::<ExecEvent>(64);
+
+    tokio::spawn(async move {
+        let count = commands.len();
+        for (i, command) in commands.into_iter().enumerate() {
+            let code = run_command(command, &tx, timeout).await;
+            if code != Some(0) || i + 1 == count {
+                let _ = tx.send(ExecEvent::Exit { code }).await;
+                break;
+            }
+        }
+    });
+
+    let events = stream::unfold(rx, |mut rx| async move {
+        let event = match tokio::time::timeout(KEEPALIVE_INTERVAL, rx.recv()).await {
+            Ok(Some(event)) => event,
+            Ok(None) => return None,
+            Err(_) => ExecEvent::Keepalive,
+        };
+        Some((event, rx))
+    });
+    let stream = events.map(|event| {
+        let mut line = serde_json::to_string(&event).unwrap_or_default();
+        line.push('\n');
+        Ok::<_, Infallible>(line)
+    });
+
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(stream),
+    )
+        .into_response()
+}
+
+async fn run_command(mut command: Command, tx: &mpsc::Sender<ExecEvent>, timeout: Option<Duration>) -> Option<i32> {
+    let program = command.as_std().get_program().to_string_lossy().to_string();
+    let mut child = match command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // シェルから起動された子プロセスもまとめて止められるよう、新しいプロセスグループにする
+        .process_group(0)
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            tracing::error!("Failed to spawn {}: {}", program, e);
+            let _ = tx.send(ExecEvent::Error { message: format!("failed to spawn {}: {}", program, e) }).await;
+            return None;
+        }
+    };
+
+    let stdout = child.stdout.take().map(|out| forward_lines(out, tx.clone(), false));
+    let stderr = child.stderr.take().map(|err| forward_lines(err, tx.clone(), true));
+    let finished = async {
+        if let Some(stdout) = stdout {
+            let _ = stdout.await;
+        }
+        if let Some(stderr) = stderr {
+            let _ = stderr.await;
+        }
+        child.wait().await
+    };
+
+    let status = match timeout {
+        Some(limit) => {
+            let result = tokio::time::timeout(limit, finished).await;
+            match result {
+                Ok(status) => status,
+                Err(_) => {
+                    if let Some(pid) = child.id() {
+                        // SAFETY: 自分で起動したプロセスのグループにシグナルを送るだけ
+                        unsafe { libc::kill(-(pid as i32), libc::SIGKILL) };
+                    }
+                    let _ = child.wait().await;
+                    let _ = tx.send(ExecEvent::Error { message: format!("timed out after {} seconds", limit.as_secs()) }).await;
+                    return None;
+                }
+            }
+        }
+        None => finished.await,
+    };
+
+    match status {
+        Ok(status) => status.code(),
+        Err(e) => {
+            let _ = tx.send(ExecEvent::Error { message: format!("failed to wait for {}: {}", program, e) }).await;
+            None
+        }
+    }
+}
+
+fn forward_lines<R>(reader: R, tx: mpsc::Sender<ExecEvent>, stderr: bool) -> tokio::task::JoinHandle<()>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let event = if stderr {
+                ExecEvent::Stderr { line }
+            } else {
+                ExecEvent::Stdout { line }
+            };
+            // 受信側が切断していても出力は読み続ける
+            let _ = tx.send(event).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn events(response: Response) -> Vec<ExecEvent> {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        body.split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn sends_keepalives_while_the_command_is_quiet() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "sleep 0.35; echo done"]);
+        let events = events(stream_commands(vec![command])).await;
+
+        let keepalives = events.iter().filter(|event| matches!(event, ExecEvent::Keepalive)).count();
+        assert!(keepalives >= 2, "{:?}", events);
+        assert!(matches!(&events[events.len() - 2], ExecEvent::Stdout { line } if line == "done"), "{:?}", events);
+        assert!(matches!(events.last(), Some(ExecEvent::Exit { code: Some(0) })), "{:?}", events);
+    }
+}
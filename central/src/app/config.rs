@@ -5,10 +5,58 @@ use serde::Deserialize;
 fn default_bind_port() -> u16 {
     3000
 }
+fn default_upload_timeout_secs() -> u64 {
+    600
+}
+
 fn default_database_url() -> String {
     "sqlite:./guardian.db".to_string()
 }
 
+fn default_service_poll_interval_secs() -> u64 {
+    60
+}
+
+fn default_terminal_idle_timeout_secs() -> u64 {
+    900
+}
+
+fn default_ssh_connect_timeout_secs() -> u64 {
+    10
+}
+
+fn default_wol_broadcast_address() -> String {
+    "255.255.255.255".to_string()
+}
+
+fn default_wol_port() -> u16 {
+    9
+}
+
+fn default_wol_wake_timeout_secs() -> u64 {
+    300
+}
+
+fn default_wol_poll_interval_secs() -> u64 {
+    5
+}
+
+fn default_health_poll_interval_secs() -> u64 {
+    30
+}
+
+fn default_health_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_health_caution_latency_ms() -> u64 {
+    1000
+}
+
+fn default_health_flap_threshold() -> u32 {
+    3
+}
+
 fn default_log_level() -> String {
     "info".to_string()
 }
@@ -17,12 +65,131 @@ fn default_log_level() -> String {
 pub struct ServerConfig {
     #[serde(default = "default_bind_port")]
     pub port: u16,
+
+    /// ファイルを書き込むリクエストの制限時間。他のリクエストは 10 秒で打ち切る
+    #[serde(default = "default_upload_timeout_secs")]
+    pub upload_timeout_secs: u64,
 }
 
 impl Default for ServerConfig {
     fn default() -> Self {
         Self {
             port: default_bind_port(),
+            upload_timeout_secs: default_upload_timeout_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServicesConfig {
+    #[serde(default = "default_service_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+}
+
+impl Default for ServicesConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval_secs: default_service_poll_interval_secs(),
+        }
+    }
+}
+
+/// Web 端末の設定。idle_timeout_secs が 0 のときは無操作による切断を行わない
+#[derive(Debug, Clone, Deserialize)]
+pub struct TerminalConfig {
+    #[serde(default = "default_terminal_idle_timeout_secs")]
+    pub idle_timeout_secs: u64,
+
+    /// 設定するとセッションを asciicast v2 形式で記録する
+    #[serde(default)]
+    pub recording_dir: Option<String>,
+}
+
+impl Default for TerminalConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout_secs: default_terminal_idle_timeout_secs(),
+            recording_dir: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SshConfig {
+    /// TCP 接続から認証が終わるまでの制限時間
+    #[serde(default = "default_ssh_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+
+    /// 認証プロファイルの秘密情報を暗号化する鍵（32 バイトを base64 にしたもの）。
+    /// 環境変数 `SSH__MASTER_KEY` でも指定できる
+    #[serde(default)]
+    pub master_key: Option<String>,
+}
+
+impl Default for SshConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: default_ssh_connect_timeout_secs(),
+            master_key: None,
+        }
+    }
+}
+
+/// Wake-on-LAN の設定。中継するエージェントを指定したサーバーでは broadcast_address は使わない
+#[derive(Debug, Clone, Deserialize)]
+pub struct WolConfig {
+    #[serde(default = "default_wol_broadcast_address")]
+    pub broadcast_address: String,
+
+    /// 中継するエージェントは 7 と 9 しか受け付けない
+    #[serde(default = "default_wol_port")]
+    pub port: u16,
+
+    /// パケットを送ってからエージェントが応答するまで待つ時間
+    #[serde(default = "default_wol_wake_timeout_secs")]
+    pub wake_timeout_secs: u64,
+
+    #[serde(default = "default_wol_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+}
+
+impl Default for WolConfig {
+    fn default() -> Self {
+        Self {
+            broadcast_address: default_wol_broadcast_address(),
+            port: default_wol_port(),
+            wake_timeout_secs: default_wol_wake_timeout_secs(),
+            poll_interval_secs: default_wol_poll_interval_secs(),
+        }
+    }
+}
+
+/// 全サーバーを巡回するヘルスチェックの設定
+#[derive(Debug, Clone, Deserialize)]
+pub struct HealthConfig {
+    #[serde(default = "default_health_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+
+    /// これを過ぎても応答がなければ Offline とみなす
+    #[serde(default = "default_health_timeout_ms")]
+    pub timeout_ms: u64,
+
+    /// 応答がこれより遅ければ Caution とみなす
+    #[serde(default = "default_health_caution_latency_ms")]
+    pub caution_latency_ms: u64,
+
+    /// 同じ結果がこの回数続いたときに状態を切り替える。1 なら毎回の結果をそのまま使う
+    #[serde(default = "default_health_flap_threshold")]
+    pub flap_threshold: u32,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval_secs: default_health_poll_interval_secs(),
+            timeout_ms: default_health_timeout_ms(),
+            caution_latency_ms: default_health_caution_latency_ms(),
+            flap_threshold: default_health_flap_threshold(),
         }
     }
 }
@@ -32,6 +199,21 @@ pub struct Config {
     #[serde(default)]
     pub server: ServerConfig,
 
+    #[serde(default)]
+    pub services: ServicesConfig,
+
+    #[serde(default)]
+    pub terminal: TerminalConfig,
+
+    #[serde(default)]
+    pub ssh: SshConfig,
+
+    #[serde(default)]
+    pub wol: WolConfig,
+
+    #[serde(default)]
+    pub health: HealthConfig,
+
     #[serde(rename = "log_level", default = "default_log_level")]
     pub log_level: String,
 
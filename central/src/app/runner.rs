@@ -1,22 +1,23 @@
-use crate::app::{config::Config, shutdown::shutdown_signal};
+use crate::app::{config::Config, shutdown::shutdown_signal, state::AppState};
+use crate::utils::{bastion::AgentTunnels, jobs::JobHub, secret::SecretBox, ssh::{BastionClients, SshContext}};
 use std::{
     net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
     time::Duration,
 };
 
 use anyhow::{Context, Result};
 use axum::{
     Router,
-    routing::get,
+    routing::{get, post, put},
     http::StatusCode,
 };
-use axum::response::IntoResponse;
 use indicatif::{ProgressBar, ProgressStyle};
 use owo_colors::OwoColorize;
 use sqlx::sqlite::SqlitePoolOptions;
 use tokio::net::TcpListener;
 use tower_http::{services::ServeDir, timeout::TimeoutLayer, trace::TraceLayer};
-use tracing::info;
+use tracing::{info, warn};
 
 pub struct App {
     config: Config,
@@ -44,6 +45,43 @@ impl App {
             .await
             .context("failed to connect to database")?;
 
+        crate::handles::manage::package_jobs::mark_interrupted_jobs(&pool)
+            .await
+            .context("failed to recover package jobs")?;
+        crate::handles::manage::command_jobs::mark_interrupted_jobs(&pool)
+            .await
+            .context("failed to recover command jobs")?;
+
+        let secrets = SecretBox::from_config(self.config.ssh.master_key.as_deref())
+            .context("invalid ssh.master_key")?;
+        if !secrets.is_configured() {
+            warn!("ssh.master_key is not set; auth profiles with secrets cannot be saved or used");
+        }
+
+        let state = AppState {
+            pool,
+            jobs: Arc::new(JobHub::default()),
+            terminal: Arc::new(self.config.terminal.clone()),
+            ssh: Arc::new(SshContext {
+                config: self.config.ssh.clone(),
+                secrets,
+                agent_tunnels: AgentTunnels::default(),
+                bastions: BastionClients::default(),
+            }),
+            wol: Arc::new(self.config.wol.clone()),
+        };
+
+        let poller_handle = crate::handles::manage::watched_services::spawn_poller(
+            state.pool.clone(),
+            state.ssh.clone(),
+            self.config.services.poll_interval_secs,
+        );
+        let health_poller_handle = crate::handles::manage::health::spawn_poller(
+            state.pool.clone(),
+            state.ssh.clone(),
+            self.config.health.clone(),
+        );
+
         let spa_service = ServeDir::new("./static")
             .not_found_service(tower_http::services::ServeFile::new("./static/index.html"));
 
@@ -58,28 +96,98 @@ impl App {
                        .delete(crate::handles::list::delete_server::delete_server)
             )
             .route("/servers/{id}/health", get(crate::handles::manage::health::get_server_health))
-            .route("/servers/{id}/specs", get(crate::handles::manage::specs::get_server_specs));
+            .route("/servers/{id}/status/transitions", get(crate::handles::manage::health::get_server_status_transitions))
+            .route("/servers/{id}/specs", get(crate::handles::manage::specs::get_server_specs))
+            .route("/servers/{id}/files", get(crate::handles::manage::files::list_server_directory))
+            .route("/servers/{id}/files/stat", get(crate::handles::manage::files::stat_server_file))
+            .route("/servers/{id}/files/download", get(crate::handles::manage::files::download_server_file))
+            .route("/servers/{id}/logs/tail", get(crate::handles::manage::logs::tail_server_log))
+            .route("/servers/{id}/logs/search", get(crate::handles::manage::logs::search_server_log))
+            .route("/servers/{id}/logs/journal", get(crate::handles::manage::logs::read_server_journal))
+            .route("/servers/{id}/terminal", get(crate::handles::manage::terminal::open_server_terminal))
+            .route("/servers/{id}/ssh/terminal", get(crate::handles::manage::terminal::open_server_ssh_terminal))
+            .route("/servers/{id}/ssh/exec", post(crate::handles::manage::ssh::run_server_ssh_command))
+            .route("/servers/{id}/ssh/files/download", get(crate::handles::manage::ssh::download_server_ssh_file))
+            .route("/servers/{id}/processes", get(crate::handles::manage::processes::get_server_processes))
+            .route("/servers/{id}/processes/{pid}/signal", post(crate::handles::manage::processes::signal_server_process))
+            .route("/servers/{id}/services", get(crate::handles::manage::services::get_server_services))
+            .route("/servers/{id}/watched-services",
+                   get(crate::handles::manage::watched_services::get_watched_services)
+                       .put(crate::handles::manage::watched_services::set_watched_services)
+            )
+            .route("/servers/{id}/services/{name}", get(crate::handles::manage::services::get_server_service))
+            .route("/servers/{id}/services/{name}/action", post(crate::handles::manage::services::run_server_service_action))
+            .route("/servers/{id}/packages", get(crate::handles::manage::packages::get_server_packages))
+            .route("/servers/{id}/packages/refresh", post(crate::handles::manage::packages::refresh_server_packages))
+            .route("/servers/{id}/packages/updates", get(crate::handles::manage::packages::get_server_package_updates))
+            .route("/packages/search", get(crate::handles::manage::packages::search_packages))
+            .route("/packages/updates", get(crate::handles::manage::packages::get_pending_updates))
+            .route("/packages/updates/refresh", post(crate::handles::manage::packages::refresh_pending_updates))
+            .route("/package-jobs",
+                   get(crate::handles::manage::package_jobs::get_package_jobs)
+                       .post(crate::handles::manage::package_jobs::create_package_job)
+            )
+            .route("/package-jobs/{id}", get(crate::handles::manage::package_jobs::get_package_job))
+            .route("/package-jobs/{id}/stream", get(crate::handles::manage::package_jobs::stream_package_job))
+            .route("/command-jobs",
+                   get(crate::handles::manage::command_jobs::get_command_jobs)
+                       .post(crate::handles::manage::command_jobs::create_command_job)
+            )
+            .route("/command-jobs/{id}", get(crate::handles::manage::command_jobs::get_command_job))
+            .route("/command-jobs/{id}/stream", get(crate::handles::manage::command_jobs::stream_command_job))
+            .route("/presets",
+                   get(crate::handles::manage::presets::get_presets)
+                       .post(crate::handles::manage::presets::create_preset)
+            )
+            .route("/presets/{id}",
+                   get(crate::handles::manage::presets::get_preset)
+                       .put(crate::handles::manage::presets::update_preset)
+                       .delete(crate::handles::manage::presets::delete_preset)
+            )
+            .route("/presets/{id}/run", post(crate::handles::manage::presets::run_preset))
+            .route("/auth-profiles",
+                   get(crate::handles::manage::auth_profiles::get_auth_profiles)
+                       .post(crate::handles::manage::auth_profiles::create_auth_profile)
+            )
+            .route("/auth-profiles/{id}",
+                   get(crate::handles::manage::auth_profiles::get_auth_profile)
+                       .put(crate::handles::manage::auth_profiles::update_auth_profile)
+                       .delete(crate::handles::manage::auth_profiles::delete_auth_profile)
+            )
+            .route("/servers/{id}/wake", post(crate::handles::manage::wake::wake_server))
+            .route("/servers/{id}/audit", get(crate::handles::manage::audit::get_server_audit_logs))
+            .layer(TimeoutLayer::with_status_code(StatusCode::REQUEST_TIMEOUT, Duration::from_secs(10)));
+        // アップロードは本文をエージェントへ流し終えるまで応答できないので、別の制限時間にする
+        let upload_router = Router::new()
+            .route("/servers/{id}/files/content", put(crate::handles::manage::files::write_server_file))
+            .route("/servers/{id}/ssh/files/content", put(crate::handles::manage::ssh::upload_server_ssh_file))
+            .layer(TimeoutLayer::with_status_code(
+                StatusCode::REQUEST_TIMEOUT,
+                Duration::from_secs(self.config.server.upload_timeout_secs.max(1)),
+            ));
 
         let app = Router::new()
-            .nest("/api/v1", api_router)
+            .nest("/api/v1", api_router.merge(upload_router))
             .fallback_service(spa_service)
-            .layer((
-                TraceLayer::new_for_http(),
-                TimeoutLayer::with_status_code(StatusCode::REQUEST_TIMEOUT, Duration::from_secs(10)),
-            ))
-            .with_state(pool);
+            .layer(TraceLayer::new_for_http())
+            .with_state(state);
 
         let listener =
-            TcpListener::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, self.config.server.port.clone()))).await?;
+            TcpListener::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, self.config.server.port))).await?;
 
         pb.finish_and_clear();
-        println!("{}", format!("{} Ready!\n", "✔".green()));
+        println!("{} Ready!\n", "✔".green());
 
-        axum::serve(listener, app)
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>()
+        )
             .with_graceful_shutdown(shutdown_signal())
             .await
             .context("failed to start server")?;
 
+        poller_handle.abort();
+        health_poller_handle.abort();
         info!("Server shutting down gracefully.");
         Ok(())
     }
@@ -0,0 +1,44 @@
+use crate::{app::config::{TerminalConfig, WolConfig}, utils::{jobs::JobHub, ssh::SshContext}};
+use std::sync::Arc;
+
+use axum::extract::FromRef;
+use sqlx::SqlitePool;
+
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: SqlitePool,
+    pub jobs: Arc<JobHub>,
+    pub terminal: Arc<TerminalConfig>,
+    pub ssh: Arc<SshContext>,
+    pub wol: Arc<WolConfig>,
+}
+
+impl FromRef<AppState> for SqlitePool {
+    fn from_ref(state: &AppState) -> Self {
+        state.pool.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<JobHub> {
+    fn from_ref(state: &AppState) -> Self {
+        state.jobs.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<TerminalConfig> {
+    fn from_ref(state: &AppState) -> Self {
+        state.terminal.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<SshContext> {
+    fn from_ref(state: &AppState) -> Self {
+        state.ssh.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<WolConfig> {
+    fn from_ref(state: &AppState) -> Self {
+        state.wol.clone()
+    }
+}
@@ -1,3 +1,6 @@
+use crate::utils::{bastion::forget_server, ssh::SshContext};
+use std::sync::Arc;
+
 use axum::{
     extract::{State, Path},
     http::StatusCode,
@@ -6,24 +9,122 @@ use axum::{
 use serde_json::json;
 use sqlx::SqlitePool;
 
+/// サーバーごとに持つ状態のテーブル。ジョブの結果と監査ログは履歴として残す
+const DEPENDENT_TABLES: [&str; 6] = [
+    "watched_services",
+    "server_status",
+    "server_status_transitions",
+    "server_packages",
+    "package_inventories",
+    "package_update_summaries",
+];
+
+/// 他のサーバーの踏み台や WoL の中継に使われているサーバーは削除できない
 pub async fn delete_server(
     State(pool): State<SqlitePool>,
+    State(ssh): State<Arc<SshContext>>,
     Path(id): Path<String>,
 ) -> impl IntoResponse {
-    let result = sqlx::query(
-        r#"DELETE FROM servers WHERE id=?"#,
-    )
-        .bind(id)
-        .execute(&pool)
-        .await;
-
-    match result {
-        Ok(_) => {
+    match delete_with_dependents(&pool, &id).await {
+        Ok(0) => {
+            forget_server(&ssh, &id);
             StatusCode::OK.into_response()
         },
+        Ok(count) => {
+            (StatusCode::CONFLICT, Json(json!({"error": format!("server is used as bastion or wol relay by {} server(s)", count)}))).into_response()
+        },
         Err(e) => {
             tracing::error!("Failed to delete server from list: {}", e);
             (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response()
         },
     }
-}
\ No newline at end of file
+}
+
+/// サーバーとその状態をまとめて削除する。他のサーバーから参照されていれば何もせず、参照している台数を返す
+async fn delete_with_dependents(pool: &SqlitePool, id: &str) -> Result<i64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let in_use = sqlx::query_scalar::<_, i64>(r#"SELECT COUNT(*) FROM servers WHERE bastion_server_id = ? OR wol_relay_server_id = ?"#)
+        .bind(id)
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+    if in_use > 0 {
+        return Ok(in_use);
+    }
+
+    for table in DEPENDENT_TABLES {
+        sqlx::query(&format!(r#"DELETE FROM {} WHERE server_id = ?"#, table))
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    sqlx::query(r#"DELETE FROM servers WHERE id=?"#)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::{context, insert_server, pool};
+
+    async fn count(pool: &SqlitePool, table: &str, id: &str) -> i64 {
+        sqlx::query_scalar(&format!(r#"SELECT COUNT(*) FROM {} WHERE server_id = ?"#, table))
+            .bind(id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    async fn delete(pool: &SqlitePool, id: &str) -> StatusCode {
+        delete_server(State(pool.clone()), State(context()), Path(id.to_string())).await.into_response().status()
+    }
+
+    #[tokio::test]
+    async fn deletes_state_kept_per_server() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = pool(&dir).await;
+        insert_server(&pool, "web-1", "127.0.0.1:9", &[]).await;
+        insert_server(&pool, "web-2", "127.0.0.1:9", &[]).await;
+        for id in ["web-1", "web-2"] {
+            sqlx::raw_sql(&format!(
+                r#"INSERT INTO watched_services (server_id, unit_name) VALUES ('{id}', 'nginx.service');
+                INSERT INTO server_status (server_id, status, changed_at, checked_at) VALUES ('{id}', 'online', '2026-10-18T00:00:00Z', '2026-10-18T00:00:00Z');
+                INSERT INTO server_status_transitions (id, server_id, to_status, created_at) VALUES ('{id}-1', '{id}', 'online', '2026-10-18T00:00:00Z');"#
+            ))
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(delete(&pool, "web-1").await, StatusCode::OK);
+
+        for table in ["watched_services", "server_status", "server_status_transitions"] {
+            assert_eq!(count(&pool, table, "web-1").await, 0, "{}", table);
+            assert_eq!(count(&pool, table, "web-2").await, 1, "{}", table);
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_servers_used_as_bastion_or_relay() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = pool(&dir).await;
+        for id in ["bastion", "relay", "target"] {
+            insert_server(&pool, id, "127.0.0.1:9", &[]).await;
+        }
+        sqlx::query(r#"UPDATE servers SET bastion_server_id = 'bastion', wol_relay_server_id = 'relay' WHERE id = 'target'"#)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert_eq!(delete(&pool, "bastion").await, StatusCode::CONFLICT);
+        assert_eq!(delete(&pool, "relay").await, StatusCode::CONFLICT);
+        assert_eq!(delete(&pool, "target").await, StatusCode::OK);
+        assert_eq!(delete(&pool, "bastion").await, StatusCode::OK);
+    }
+}
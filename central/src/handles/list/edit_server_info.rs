@@ -1,3 +1,12 @@
+use crate::{
+    handles::manage::{auth_profiles::ensure_exists, wake::check_wake_settings},
+    utils::{
+        bastion::{check_bastion, forget_server},
+        ssh::SshContext,
+    },
+};
+use std::sync::Arc;
+
 use axum::{
     extract::{State, Path},
     http::StatusCode,
@@ -13,33 +22,56 @@ pub struct RegisterRequest {
     ip_address: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     tags: Option<Vec<String>>,
+    /// 省略するか空文字列にすると、プロファイルなし（エージェントのみ）で登録する
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auth_profile_id: Option<String>,
     port: u16,
     #[serde(skip_serializing_if = "Option::is_none")]
     bastion_server_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    wol_mac_address: Option<String>
+    wol_mac_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wol_relay_server_id: Option<String>
 }
 
 pub async fn edit_server_info(
     State(pool): State<SqlitePool>,
+    State(ssh): State<Arc<SshContext>>,
     Path(id): Path<String>,
     Json(json): Json<RegisterRequest>
 ) -> impl IntoResponse {
+    let auth_profile_id = json.auth_profile_id.filter(|id| !id.is_empty());
+    if let Some(id) = &auth_profile_id
+        && let Err(response) = ensure_exists(&pool, id).await
+    {
+        return response;
+    }
+    if let Err(response) = check_bastion(&pool, Some(&id), json.bastion_server_id.as_deref()).await {
+        return response;
+    }
+    if let Err(response) = check_wake_settings(&pool, Some(&id), json.wol_mac_address.as_deref(), json.wol_relay_server_id.as_deref()).await {
+        return response;
+    }
+
     let result = sqlx::query(
-        r#"UPDATE servers SET hostname=?, ip_address=?, tags=?, port=?, bastion_server_id=?, wol_mac_address=? WHERE id=?"#,
+        r#"UPDATE servers SET hostname=?, ip_address=?, tags=?, auth_profile_id=?, port=?, bastion_server_id=?, wol_mac_address=?, wol_relay_server_id=? WHERE id=?"#,
     )
         .bind(json.hostname)
         .bind(json.ip_address)
         .bind(json.tags.map(|t| serde_json::to_string(&t).unwrap_or_else(|_| "[]".to_string())))
+        .bind(auth_profile_id)
         .bind(json.port)
         .bind(json.bastion_server_id)
         .bind(json.wol_mac_address)
-        .bind(id)
+        .bind(json.wol_relay_server_id)
+        .bind(&id)
         .execute(&pool)
         .await;
 
     match result {
         Ok(_) => {
+            // 接続先や踏み台が変わっているかもしれないので、転送ポートと踏み台への接続を作り直させる
+            forget_server(&ssh, &id);
             StatusCode::OK.into_response()
         },
         Err(e) => {
@@ -12,7 +12,7 @@ pub async fn get_server_info(
     Path(id): Path<String>
 ) -> impl IntoResponse {
     match sqlx::query_as::<_, ServerInformation>(
-        r#"SELECT id, hostname, ip_address, os_type, tags, auth_profile_id, port, bastion_server_id, wol_mac_address FROM servers WHERE id = ?"#,
+        r#"SELECT id, hostname, ip_address, os_type, tags, auth_profile_id, port, bastion_server_id, wol_mac_address, wol_relay_server_id FROM servers WHERE id = ?"#,
     )
         .bind(id)
         .fetch_one(&pool)
@@ -29,6 +29,7 @@ pub async fn get_server_info(
                 port: row.port,
                 bastion_server_id: row.bastion_server_id,
                 wol_mac_address: row.wol_mac_address,
+                wol_relay_server_id: row.wol_relay_server_id,
             };
             (StatusCode::OK, Json(result)).into_response()
         },
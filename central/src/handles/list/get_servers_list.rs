@@ -1,4 +1,9 @@
-use common::central::information::ServerInformation;
+use common::central::{
+    information::{ServerInformation, ServerListEntry},
+    service::KeyServicesSummary,
+    status::ServerStatus,
+};
+use std::collections::HashMap;
 
 use axum::{
     extract::State,
@@ -11,24 +16,34 @@ pub async fn get_servers_list(
     State(pool): State<SqlitePool>,
 ) -> impl IntoResponse {
     match sqlx::query_as::<_, ServerInformation>(
-        r#"SELECT id, hostname, ip_address, os_type, tags, auth_profile_id, port, bastion_server_id, wol_mac_address FROM servers"#,
+        r#"SELECT id, hostname, ip_address, os_type, tags, auth_profile_id, port, bastion_server_id, wol_mac_address, wol_relay_server_id FROM servers"#,
     )
     .fetch_all(&pool)
     .await
     {
         Ok(rows) => {
-            let result: Vec<ServerInformation> = rows
+            let mut summaries = match load_key_services(&pool).await {
+                Ok(summaries) => summaries,
+                Err(e) => {
+                    tracing::error!("Failed to fetch watched services: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            };
+
+            let mut statuses = match load_statuses(&pool).await {
+                Ok(statuses) => statuses,
+                Err(e) => {
+                    tracing::error!("Failed to fetch server status: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            };
+
+            let result: Vec<ServerListEntry> = rows
                 .into_iter()
-                .map(|row| ServerInformation {
-                    id: row.id,
-                    hostname: row.hostname,
-                    ip_address: row.ip_address,
-                    os_type: row.os_type,
-                    tags: row.tags,
-                    auth_profile_id: row.auth_profile_id,
-                    port: row.port,
-                    bastion_server_id: row.bastion_server_id,
-                    wol_mac_address: row.wol_mac_address,
+                .map(|row| ServerListEntry {
+                    key_services: summaries.remove(&row.id).unwrap_or_default(),
+                    status: statuses.remove(&row.id),
+                    server: row,
                 })
                 .collect();
             (StatusCode::OK, Json(result)).into_response()
@@ -38,4 +53,44 @@ pub async fn get_servers_list(
             StatusCode::INTERNAL_SERVER_ERROR.into_response()
         },
     }
-}
\ No newline at end of file
+}
+
+async fn load_key_services(pool: &SqlitePool) -> Result<HashMap<String, KeyServicesSummary>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, (String, String, Option<String>)>(
+        r#"SELECT server_id, unit_name, active_state FROM watched_services ORDER BY unit_name"#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut summaries: HashMap<String, KeyServicesSummary> = HashMap::new();
+    for (server_id, unit_name, active_state) in rows {
+        let summary = summaries.entry(server_id).or_default();
+        summary.total += 1;
+        match active_state.as_deref() {
+            Some("active") => summary.up += 1,
+            Some(_) => {
+                summary.down += 1;
+                summary.down_units.push(unit_name);
+            }
+            None => summary.unknown += 1,
+        }
+    }
+    Ok(summaries)
+}
+
+#[derive(sqlx::FromRow)]
+struct StatusRow {
+    server_id: String,
+    #[sqlx(flatten)]
+    status: ServerStatus,
+}
+
+async fn load_statuses(pool: &SqlitePool) -> Result<HashMap<String, ServerStatus>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, StatusRow>(
+        r#"SELECT server_id, status, last_seen_at, changed_at, checked_at, latency_ms FROM server_status"#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| (row.server_id, row.status)).collect())
+}
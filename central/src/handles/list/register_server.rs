@@ -1,3 +1,7 @@
+use crate::{
+    handles::manage::{auth_profiles::ensure_exists, wake::check_wake_settings},
+    utils::bastion::check_bastion,
+};
 use common::central::information::ServerInformation;
 
 use axum::{
@@ -16,39 +20,58 @@ pub struct RegisterRequest {
     ip_address: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     tags: Option<Vec<String>>,
+    /// 省略するか空文字列にすると、プロファイルなし（エージェントのみ）で登録する
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auth_profile_id: Option<String>,
     port: u16,
     #[serde(skip_serializing_if = "Option::is_none")]
     bastion_server_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    wol_mac_address: Option<String>
+    wol_mac_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wol_relay_server_id: Option<String>
 }
 
 pub async fn register_server(
     State(pool): State<SqlitePool>,
     Json(json): Json<RegisterRequest>
 ) -> impl IntoResponse {
+    let auth_profile_id = json.auth_profile_id.filter(|id| !id.is_empty());
+    if let Some(id) = &auth_profile_id
+        && let Err(response) = ensure_exists(&pool, id).await
+    {
+        return response;
+    }
+    if let Err(response) = check_bastion(&pool, None, json.bastion_server_id.as_deref()).await {
+        return response;
+    }
+    if let Err(response) = check_wake_settings(&pool, None, json.wol_mac_address.as_deref(), json.wol_relay_server_id.as_deref()).await {
+        return response;
+    }
+
     let id = Uuid::new_v4();
     let hostname = json.hostname;
     let ip_address = json.ip_address;
     let os_type = "ubuntu"; //ToDo: 被管理サーバー用のアプリケーションから取得する
     let tags_json = json.tags.map(|t| serde_json::to_string(&t).unwrap_or_else(|_| "[]".to_string()));
-    let auth_profile_id = Uuid::new_v4(); //ToDo: 認証情報への外部キーを取得する
     let port = json.port;
     let bastion_server_id = json.bastion_server_id;
     let wol_mac_address = json.wol_mac_address;
+    let wol_relay_server_id = json.wol_relay_server_id;
     
     let result = sqlx::query(
-        r#"INSERT INTO servers (id, hostname, ip_address, os_type, tags, auth_profile_id, port, bastion_server_id, wol_mac_address) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
+        r#"INSERT INTO servers (id, hostname, ip_address, os_type, tags, auth_profile_id, port, bastion_server_id, wol_mac_address, wol_relay_server_id) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
     )
         .bind(id.to_string())
         .bind(&hostname)
         .bind(&ip_address)
         .bind(os_type)
         .bind(&tags_json)
-        .bind(auth_profile_id.to_string())
+        .bind(&auth_profile_id)
         .bind(port)
         .bind(&bastion_server_id)
         .bind(&wol_mac_address)
+        .bind(&wol_relay_server_id)
         .execute(&pool)
         .await;
 
@@ -60,10 +83,11 @@ pub async fn register_server(
                 ip_address,
                 os_type: os_type.to_string(),
                 tags: tags_json,
-                auth_profile_id: auth_profile_id.to_string(),
+                auth_profile_id,
                 port,
                 bastion_server_id,
-                wol_mac_address
+                wol_mac_address,
+                wol_relay_server_id
             };
             (StatusCode::CREATED, Json(server_info)).into_response()
         },
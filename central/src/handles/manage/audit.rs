@@ -0,0 +1,27 @@
+use common::central::audit::AuditLog;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Json},
+};
+use sqlx::SqlitePool;
+
+pub async fn get_server_audit_logs(
+    State(pool): State<SqlitePool>,
+    Path(server_uuid): Path<String>,
+) -> impl IntoResponse {
+    match sqlx::query_as::<_, AuditLog>(
+        r#"SELECT id, server_id, actor, action, target, detail, status_code, created_at FROM audit_logs WHERE server_id = ? ORDER BY created_at DESC"#,
+    )
+        .bind(server_uuid)
+        .fetch_all(&pool)
+        .await
+    {
+        Ok(rows) => (StatusCode::OK, Json(rows)).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to fetch audit logs: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        },
+    }
+}
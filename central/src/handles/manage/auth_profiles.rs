@@ -0,0 +1,311 @@
+use crate::{
+    ssh::AuthMethod,
+    utils::{
+        secret::{SecretBox, SecretError, secret_error_response},
+        ssh::{AuthProfileRow, SshContext, secret_context},
+    },
+};
+use common::central::auth_profile::AuthProfile;
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+const SELECT_PROFILE: &str = r#"SELECT id, name, username, auth_method, host_key_fingerprint, password IS NOT NULL AS has_password, private_key IS NOT NULL AS has_private_key, passphrase IS NOT NULL AS has_passphrase, created_at, updated_at FROM auth_profiles"#;
+
+/// 更新のとき、省略した秘密情報はそのまま残す。`passphrase` に空文字列を送ると消す
+#[derive(Deserialize)]
+pub struct AuthProfileRequest {
+    name: String,
+    username: String,
+    auth_method: String,
+    password: Option<String>,
+    private_key: Option<String>,
+    passphrase: Option<String>,
+    host_key_fingerprint: Option<String>,
+}
+
+/// 暗号化して保存する値
+struct SealedSecrets {
+    password: Option<String>,
+    private_key: Option<String>,
+    passphrase: Option<String>,
+}
+
+pub async fn get_auth_profiles(State(pool): State<SqlitePool>) -> impl IntoResponse {
+    match sqlx::query_as::<_, AuthProfile>(&format!("{} ORDER BY name", SELECT_PROFILE))
+        .fetch_all(&pool)
+        .await
+    {
+        Ok(rows) => (StatusCode::OK, Json(rows)).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to fetch auth profiles: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn get_auth_profile(
+    State(pool): State<SqlitePool>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match find_profile(&pool, &id).await {
+        Ok(profile) => (StatusCode::OK, Json(profile)).into_response(),
+        Err(status) => status.into_response(),
+    }
+}
+
+pub async fn create_auth_profile(
+    State(pool): State<SqlitePool>,
+    State(ssh): State<Arc<SshContext>>,
+    Json(json): Json<AuthProfileRequest>,
+) -> impl IntoResponse {
+    if let Err(e) = validate(&json) {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": e}))).into_response();
+    }
+
+    let id = Uuid::new_v4().to_string();
+    let method = match credential(&json, None, &ssh.secrets) {
+        Ok(method) => method,
+        Err((status, message)) => return (status, Json(json!({"error": message}))).into_response(),
+    };
+    let sealed = match seal(&id, &method, &ssh.secrets) {
+        Ok(sealed) => sealed,
+        Err(e) => return secret_error_response(&e),
+    };
+
+    let now = Utc::now();
+    let result = sqlx::query(
+        r#"INSERT INTO auth_profiles (id, name, username, auth_method, password, private_key, passphrase, host_key_fingerprint, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
+    )
+        .bind(&id)
+        .bind(json.name.trim())
+        .bind(json.username.trim())
+        .bind(&json.auth_method)
+        .bind(sealed.password)
+        .bind(sealed.private_key)
+        .bind(sealed.passphrase)
+        .bind(fingerprint(&json))
+        .bind(now)
+        .bind(now)
+        .execute(&pool)
+        .await;
+
+    match result {
+        Ok(_) => match find_profile(&pool, &id).await {
+            Ok(profile) => (StatusCode::CREATED, Json(profile)).into_response(),
+            Err(status) => status.into_response(),
+        },
+        Err(e) => save_error_response(e),
+    }
+}
+
+pub async fn update_auth_profile(
+    State(pool): State<SqlitePool>,
+    State(ssh): State<Arc<SshContext>>,
+    Path(id): Path<String>,
+    Json(json): Json<AuthProfileRequest>,
+) -> impl IntoResponse {
+    if let Err(e) = validate(&json) {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": e}))).into_response();
+    }
+
+    let current = match AuthProfileRow::fetch(&pool, &id).await {
+        Ok(Some(current)) => current,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to fetch auth profile: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let method = match credential(&json, Some(&current), &ssh.secrets) {
+        Ok(method) => method,
+        Err((status, message)) => return (status, Json(json!({"error": message}))).into_response(),
+    };
+    // nonce を使い回さないよう、変更していない値も暗号化し直す
+    let sealed = match seal(&id, &method, &ssh.secrets) {
+        Ok(sealed) => sealed,
+        Err(e) => return secret_error_response(&e),
+    };
+
+    let result = sqlx::query(
+        r#"UPDATE auth_profiles SET name=?, username=?, auth_method=?, password=?, private_key=?, passphrase=?, host_key_fingerprint=?, updated_at=? WHERE id=?"#,
+    )
+        .bind(json.name.trim())
+        .bind(json.username.trim())
+        .bind(&json.auth_method)
+        .bind(sealed.password)
+        .bind(sealed.private_key)
+        .bind(sealed.passphrase)
+        .bind(fingerprint(&json))
+        .bind(Utc::now())
+        .bind(&id)
+        .execute(&pool)
+        .await;
+
+    match result {
+        Ok(result) if result.rows_affected() == 0 => StatusCode::NOT_FOUND.into_response(),
+        Ok(_) => {
+            // 古い認証情報でログインした踏み台への接続を使い続けないようにする
+            ssh.bastions.forget_profile(&id);
+            match find_profile(&pool, &id).await {
+                Ok(profile) => (StatusCode::OK, Json(profile)).into_response(),
+                Err(status) => status.into_response(),
+            }
+        },
+        Err(e) => save_error_response(e),
+    }
+}
+
+/// サーバーから参照されているプロファイルは削除できない
+pub async fn delete_auth_profile(
+    State(pool): State<SqlitePool>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let in_use = sqlx::query_scalar::<_, i64>(r#"SELECT COUNT(*) FROM servers WHERE auth_profile_id = ?"#)
+        .bind(&id)
+        .fetch_one(&pool)
+        .await;
+    match in_use {
+        Ok(0) => {}
+        Ok(count) => {
+            return (StatusCode::CONFLICT, Json(json!({"error": format!("auth profile is used by {} server(s)", count)}))).into_response();
+        }
+        Err(e) => {
+            tracing::error!("Failed to count servers using auth profile: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    let result = sqlx::query(r#"DELETE FROM auth_profiles WHERE id=?"#)
+        .bind(id)
+        .execute(&pool)
+        .await;
+
+    match result {
+        Ok(result) if result.rows_affected() == 0 => StatusCode::NOT_FOUND.into_response(),
+        Ok(_) => StatusCode::OK.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to delete auth profile: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response()
+        }
+    }
+}
+
+/// サーバーの登録や編集のときに、参照先のプロファイルがあるかを確かめる
+pub async fn ensure_exists(pool: &SqlitePool, id: &str) -> Result<(), Response> {
+    match AuthProfileRow::fetch(pool, id).await {
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => Err((StatusCode::UNPROCESSABLE_ENTITY, Json(json!({"error": format!("auth profile {} does not exist", id)}))).into_response()),
+        Err(e) => {
+            tracing::error!("Failed to fetch auth profile: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+async fn find_profile(pool: &SqlitePool, id: &str) -> Result<AuthProfile, StatusCode> {
+    sqlx::query_as::<_, AuthProfile>(&format!("{} WHERE id = ?", SELECT_PROFILE))
+        .bind(id)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
+            e => {
+                tracing::error!("Failed to fetch auth profile: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        })
+}
+
+/// リクエストと保存済みの値から資格情報を組み立て、実際に使えるかを確かめる
+fn credential(json: &AuthProfileRequest, current: Option<&AuthProfileRow>, secrets: &SecretBox) -> Result<AuthMethod, (StatusCode, String)> {
+    let stored = |field| match current {
+        Some(current) => current.open(secrets, field).map_err(|e| (e.status(), e.to_string())),
+        None => Ok(None),
+    };
+
+    let method = if json.auth_method == "password" {
+        let password = match &json.password {
+            Some(password) => Some(password.clone()),
+            None => stored("password")?,
+        };
+        password.map(AuthMethod::Password)
+    } else {
+        let (key, replaced) = match &json.private_key {
+            Some(key) => (Some(key.clone()), true),
+            None => (stored("private_key")?, false),
+        };
+        // パスフレーズは鍵に付随するので、鍵を差し替えたときは引き継がない
+        let passphrase = match json.passphrase.as_deref() {
+            Some("") => None,
+            Some(passphrase) => Some(passphrase.to_string()),
+            None if replaced => None,
+            None => stored("passphrase")?,
+        };
+        key.map(|key| AuthMethod::PrivateKey { key, passphrase })
+    };
+
+    let Some(method) = method else {
+        let field = if json.auth_method == "password" { "password" } else { "private_key" };
+        return Err((StatusCode::BAD_REQUEST, format!("{} is required", field)));
+    };
+    method.check().map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    Ok(method)
+}
+
+fn seal(id: &str, method: &AuthMethod, secrets: &SecretBox) -> Result<SealedSecrets, SecretError> {
+    let seal = |field, value: &str| secrets.seal(&secret_context(id, field), value);
+    Ok(match method {
+        AuthMethod::Password(password) => SealedSecrets {
+            password: Some(seal("password", password)?),
+            private_key: None,
+            passphrase: None,
+        },
+        AuthMethod::PrivateKey { key, passphrase } => SealedSecrets {
+            password: None,
+            private_key: Some(seal("private_key", key)?),
+            passphrase: passphrase.as_deref().map(|p| seal("passphrase", p)).transpose()?,
+        },
+    })
+}
+
+fn fingerprint(json: &AuthProfileRequest) -> Option<&str> {
+    json.host_key_fingerprint.as_deref().map(str::trim).filter(|f| !f.is_empty())
+}
+
+fn save_error_response(e: sqlx::Error) -> Response {
+    if e.as_database_error().is_some_and(|e| e.is_unique_violation()) {
+        return (StatusCode::CONFLICT, Json(json!({"error": "an auth profile with this name already exists"}))).into_response();
+    }
+    tracing::error!("Failed to save auth profile: {}", e);
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response()
+}
+
+fn validate(json: &AuthProfileRequest) -> Result<(), String> {
+    if json.name.trim().is_empty() {
+        return Err("name is required".to_string());
+    }
+    if json.username.trim().is_empty() {
+        return Err("username is required".to_string());
+    }
+    if !matches!(json.auth_method.as_str(), "password" | "private_key") {
+        return Err("auth_method must be password or private_key".to_string());
+    }
+    if let Some(fingerprint) = fingerprint(json) {
+        // SHA256 の指紋は base64 で 43 文字になる
+        let hash = fingerprint.strip_prefix("SHA256:").unwrap_or(fingerprint).trim_end_matches('=');
+        if hash.len() != 43 || !hash.chars().all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '/') {
+            return Err("host_key_fingerprint must be a SHA256 fingerprint as printed by ssh-keygen -l".to_string());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,422 @@
+use crate::utils::{
+    agent::find_server,
+    audit::Actor,
+    bastion::route_agent,
+    jobs::{JobHub, TargetRun},
+    ssh::SshContext,
+};
+use common::{
+    agent::exec::{ExecEvent, ExecRequest},
+    central::{
+        information::ServerInformation,
+        job::{CommandJob, CommandJobDetail, JobEvent, JobTarget},
+    },
+};
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use chrono::Utc;
+use futures::{StreamExt, stream};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::SqlitePool;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+const TARGETS_TABLE: &str = "command_job_targets";
+const DEFAULT_CONCURRENCY: usize = 8;
+const MAX_CONCURRENCY: usize = 32;
+const DEFAULT_TIMEOUT_SECS: u64 = 60;
+const MAX_TIMEOUT_SECS: u64 = 3600;
+/// エージェント側のタイムアウトで止まらなかった場合に備え、central ではこれだけ余分に待つ
+#[cfg(not(test))]
+const TIMEOUT_GRACE: Duration = Duration::from_secs(10);
+#[cfg(test)]
+const TIMEOUT_GRACE: Duration = Duration::from_millis(200);
+
+#[derive(Deserialize)]
+pub struct CreateCommandJobRequest {
+    pub command: String,
+    #[serde(default)]
+    pub server_ids: Vec<String>,
+    pub tag: Option<String>,
+    pub timeout_secs: Option<u64>,
+    pub concurrency: Option<usize>,
+}
+
+pub async fn create_command_job(
+    State(pool): State<SqlitePool>,
+    State(hub): State<Arc<JobHub>>,
+    State(ssh): State<Arc<SshContext>>,
+    Actor(actor): Actor,
+    Json(json): Json<CreateCommandJobRequest>,
+) -> impl IntoResponse {
+    launch_job(pool, hub, ssh, &actor, json).await
+}
+
+/// 対象のサーバーを決めてジョブを開始する。
+/// `server_ids` と `tag` の両方を指定した場合は、どちらかに当てはまるサーバーすべてが対象になる
+pub async fn launch_job(pool: SqlitePool, hub: Arc<JobHub>, ssh: Arc<SshContext>, actor: &str, json: CreateCommandJobRequest) -> Response {
+    if json.command.trim().is_empty() {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "command is required"}))).into_response();
+    }
+    if json.server_ids.is_empty() && json.tag.is_none() {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "server_ids or tag is required"}))).into_response();
+    }
+
+    let mut servers = Vec::new();
+    for server_id in &json.server_ids {
+        match find_server(&pool, server_id).await {
+            Ok(server) => servers.push(server),
+            Err(StatusCode::NOT_FOUND) => {
+                return (StatusCode::NOT_FOUND, Json(json!({"error": format!("server {} not found", server_id)}))).into_response();
+            }
+            Err(status) => return status.into_response(),
+        }
+    }
+    if let Some(tag) = &json.tag {
+        match find_servers_by_tag(&pool, tag).await {
+            Ok(tagged) => servers.extend(tagged),
+            Err(e) => {
+                tracing::error!("Failed to fetch servers by tag: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    }
+    servers.sort_by(|a, b| a.id.cmp(&b.id));
+    servers.dedup_by(|a, b| a.id == b.id);
+    if servers.is_empty() {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "no servers matched"}))).into_response();
+    }
+    let servers = match servers.into_iter().map(|server| route_agent(&pool, &ssh, server)).collect::<Result<Vec<_>, _>>() {
+        Ok(servers) => servers,
+        Err(status) => return status.into_response(),
+    };
+
+    let timeout_secs = json.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS).clamp(1, MAX_TIMEOUT_SECS);
+    let concurrency = json.concurrency.unwrap_or(DEFAULT_CONCURRENCY).clamp(1, MAX_CONCURRENCY);
+
+    let job_id = Uuid::new_v4().to_string();
+    if let Err(e) = insert_job(&pool, &job_id, actor, &json, timeout_secs, &servers).await {
+        tracing::error!("Failed to create command job: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response();
+    }
+
+    let tx = hub.open(&job_id);
+    let request = ExecRequest {
+        command: json.command,
+        timeout_secs: Some(timeout_secs),
+    };
+    tokio::spawn(run_job(pool.clone(), hub, job_id.clone(), servers, request, concurrency, tx));
+
+    match load_job(&pool, &job_id).await {
+        Ok(Some(job)) => (StatusCode::CREATED, Json(job)).into_response(),
+        Ok(None) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to fetch command job: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn get_command_jobs(State(pool): State<SqlitePool>) -> impl IntoResponse {
+    match sqlx::query_as::<_, CommandJob>(
+        r#"SELECT id, command, tag, timeout_secs, created_by, status, created_at, finished_at FROM command_jobs ORDER BY created_at DESC"#,
+    )
+        .fetch_all(&pool)
+        .await
+    {
+        Ok(rows) => (StatusCode::OK, Json(rows)).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to fetch command jobs: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn get_command_job(
+    State(pool): State<SqlitePool>,
+    Path(job_id): Path<String>,
+) -> impl IntoResponse {
+    match load_job(&pool, &job_id).await {
+        Ok(Some(job)) => (StatusCode::OK, Json(job)).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to fetch command job: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// 各サーバーの出力を `server_id` 付きで混在させて配信する
+pub async fn stream_command_job(
+    State(hub): State<Arc<JobHub>>,
+    Path(job_id): Path<String>,
+) -> impl IntoResponse {
+    hub.sse_response(&job_id)
+}
+
+/// `tags` は JSON 配列の文字列で保存されている
+async fn find_servers_by_tag(pool: &SqlitePool, tag: &str) -> Result<Vec<ServerInformation>, sqlx::Error> {
+    let servers = sqlx::query_as::<_, ServerInformation>(
+        r#"SELECT id, hostname, ip_address, os_type, tags, auth_profile_id, port, bastion_server_id, wol_mac_address, wol_relay_server_id FROM servers WHERE tags IS NOT NULL"#,
+    )
+        .fetch_all(pool)
+        .await?;
+
+    Ok(servers
+        .into_iter()
+        .filter(|server| {
+            server
+                .tags
+                .as_deref()
+                .and_then(|tags| serde_json::from_str::<Vec<String>>(tags).ok())
+                .is_some_and(|tags| tags.iter().any(|t| t == tag))
+        })
+        .collect())
+}
+
+async fn run_job(
+    pool: SqlitePool,
+    hub: Arc<JobHub>,
+    job_id: String,
+    servers: Vec<ServerInformation>,
+    request: ExecRequest,
+    concurrency: usize,
+    tx: broadcast::Sender<JobEvent>,
+) {
+    let request = Arc::new(request);
+    let results = stream::iter(servers)
+        .map(|server| run_target(pool.clone(), job_id.clone(), server, request.clone(), tx.clone()))
+        .buffer_unordered(concurrency)
+        .collect::<Vec<bool>>()
+        .await;
+
+    let status = if results.iter().all(|ok| *ok) { "succeeded" } else { "failed" };
+    let result = sqlx::query(r#"UPDATE command_jobs SET status = ?, finished_at = ? WHERE id = ?"#)
+        .bind(status)
+        .bind(Utc::now())
+        .bind(&job_id)
+        .execute(&pool)
+        .await;
+    if let Err(e) = result {
+        tracing::error!("Failed to update command job {}: {}", job_id, e);
+    }
+
+    hub.close(&job_id);
+}
+
+async fn run_target(
+    pool: SqlitePool,
+    job_id: String,
+    server: ServerInformation,
+    request: Arc<ExecRequest>,
+    tx: broadcast::Sender<JobEvent>,
+) -> bool {
+    let mut run = TargetRun::start(pool, TARGETS_TABLE, job_id, server.id.clone(), tx).await;
+
+    let limit = Duration::from_secs(request.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS)) + TIMEOUT_GRACE;
+    let result = tokio::time::timeout(limit, run.execute(&server, "/exec", &*request)).await;
+    if result.is_err() {
+        run.handle(ExecEvent::Error { message: format!("no response from agent within {} seconds", limit.as_secs()) }).await;
+    }
+
+    run.finish().await
+}
+
+async fn insert_job(
+    pool: &SqlitePool,
+    job_id: &str,
+    actor: &str,
+    json: &CreateCommandJobRequest,
+    timeout_secs: u64,
+    servers: &[ServerInformation],
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(r#"INSERT INTO command_jobs (id, command, tag, timeout_secs, created_by, status, created_at) VALUES (?, ?, ?, ?, ?, 'running', ?)"#)
+        .bind(job_id)
+        .bind(&json.command)
+        .bind(&json.tag)
+        .bind(timeout_secs as i64)
+        .bind(actor)
+        .bind(Utc::now())
+        .execute(&mut *tx)
+        .await?;
+
+    for server in servers {
+        sqlx::query(r#"INSERT INTO command_job_targets (job_id, server_id, status) VALUES (?, ?, 'pending')"#)
+            .bind(job_id)
+            .bind(&server.id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await
+}
+
+async fn load_job(pool: &SqlitePool, job_id: &str) -> Result<Option<CommandJobDetail>, sqlx::Error> {
+    let Some(job) = sqlx::query_as::<_, CommandJob>(
+        r#"SELECT id, command, tag, timeout_secs, created_by, status, created_at, finished_at FROM command_jobs WHERE id = ?"#,
+    )
+        .bind(job_id)
+        .fetch_optional(pool)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    let targets = sqlx::query_as::<_, JobTarget>(
+        r#"SELECT server_id, status, exit_code, log, started_at, finished_at FROM command_job_targets WHERE job_id = ?"#,
+    )
+        .bind(job_id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(Some(CommandJobDetail { job, targets }))
+}
+
+/// central の再起動で中断されたジョブを失敗扱いにする
+pub async fn mark_interrupted_jobs(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    sqlx::query(r#"UPDATE command_job_targets SET status = 'interrupted', finished_at = ? WHERE status IN ('pending', 'running')"#)
+        .bind(Utc::now())
+        .execute(pool)
+        .await?;
+    sqlx::query(r#"UPDATE command_jobs SET status = 'interrupted', finished_at = ? WHERE status = 'running'"#)
+        .bind(Utc::now())
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::{context, insert_server, mock_agent, pool};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::{Router, routing::post};
+
+    fn request(server_ids: &[&str], tag: Option<&str>, timeout_secs: u64, concurrency: usize) -> CreateCommandJobRequest {
+        CreateCommandJobRequest {
+            command: "uptime".to_string(),
+            server_ids: server_ids.iter().map(|id| id.to_string()).collect(),
+            tag: tag.map(str::to_string),
+            timeout_secs: Some(timeout_secs),
+            concurrency: Some(concurrency),
+        }
+    }
+
+    /// ジョブを開始し、終わるまで待って結果を返す
+    async fn run(pool: &SqlitePool, json: CreateCommandJobRequest) -> CommandJobDetail {
+        let response = launch_job(pool.clone(), Arc::new(JobHub::default()), context(), "tester", json).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let job_id = serde_json::from_slice::<CommandJobDetail>(&body).unwrap().job.id;
+
+        for _ in 0..500 {
+            let job = load_job(pool, &job_id).await.unwrap().unwrap();
+            if job.job.status != "running" {
+                return job;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("job {} did not finish", job_id);
+    }
+
+    fn target<'a>(job: &'a CommandJobDetail, server_id: &str) -> &'a JobTarget {
+        job.targets.iter().find(|target| target.server_id == server_id).unwrap()
+    }
+
+    /// 出力の前に `Keepalive` を挟んで返すエージェント。同時に処理しているリクエスト数の最大を数える
+    async fn counting_agent(running: Arc<AtomicUsize>, peak: Arc<AtomicUsize>) -> String {
+        mock_agent(Router::new().route("/exec", post(move || async move {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            running.fetch_sub(1, Ordering::SeqCst);
+            "{\"type\":\"keepalive\"}\n{\"type\":\"stdout\",\"line\":\"up\"}\n{\"type\":\"exit\",\"code\":0}\n"
+        }))).await
+    }
+
+    #[tokio::test]
+    async fn fans_out_within_the_concurrency_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = pool(&dir).await;
+        let (running, peak) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let agent = counting_agent(running, peak.clone()).await;
+        let ids = (0..6).map(|i| format!("server-{}", i)).collect::<Vec<_>>();
+        for id in &ids {
+            insert_server(&pool, id, &agent, &[]).await;
+        }
+
+        let job = run(&pool, request(&ids.iter().map(String::as_str).collect::<Vec<_>>(), None, 60, 2)).await;
+
+        assert_eq!(job.job.status, "succeeded");
+        assert_eq!(job.targets.len(), 6);
+        for id in &ids {
+            let target = target(&job, id);
+            assert_eq!((target.status.as_str(), target.exit_code), ("succeeded", Some(0)));
+            // Keepalive はログに残らない
+            assert_eq!(target.log, "up\n");
+        }
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn runs_once_per_server_matched_by_ids_or_tag() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = pool(&dir).await;
+        let (running, peak) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let agent = counting_agent(running, peak).await;
+        insert_server(&pool, "web-1", &agent, &["web"]).await;
+        insert_server(&pool, "web-2", &agent, &["web", "db"]).await;
+        insert_server(&pool, "db-1", &agent, &["db"]).await;
+        insert_server(&pool, "other", &agent, &["webserver"]).await;
+
+        let job = run(&pool, request(&["web-1", "db-1", "web-1"], Some("web"), 60, 8)).await;
+
+        let mut targets = job.targets.iter().map(|target| target.server_id.as_str()).collect::<Vec<_>>();
+        targets.sort();
+        assert_eq!(targets, ["db-1", "web-1", "web-2"]);
+        assert_eq!(job.job.status, "succeeded");
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_servers_and_empty_matches() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = pool(&dir).await;
+        insert_server(&pool, "web-1", "127.0.0.1:9", &["web"]).await;
+
+        let response = launch_job(pool.clone(), Arc::new(JobHub::default()), context(), "tester", request(&["missing"], None, 60, 8)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = launch_job(pool.clone(), Arc::new(JobHub::default()), context(), "tester", request(&[], Some("db"), 60, 8)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn fails_only_the_host_that_does_not_answer_in_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = pool(&dir).await;
+        let (running, peak) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let answering = counting_agent(running, peak).await;
+        let silent = mock_agent(Router::new().route("/exec", post(|| async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            ""
+        }))).await;
+        insert_server(&pool, "answering", &answering, &[]).await;
+        insert_server(&pool, "silent", &silent, &[]).await;
+
+        let job = run(&pool, request(&["answering", "silent"], None, 1, 8)).await;
+
+        assert_eq!(job.job.status, "failed");
+        assert_eq!(target(&job, "answering").status, "succeeded");
+        let silent = target(&job, "silent");
+        assert_eq!((silent.status.as_str(), silent.exit_code), ("failed", None));
+        assert!(silent.log.contains("no response from agent within 1 seconds"), "{}", silent.log);
+    }
+}
@@ -0,0 +1,172 @@
+use crate::utils::{
+    agent::{agent_url, http_client, relay_file, relay_response, streaming_client},
+    audit::{Actor, AuditEntry, record},
+    bastion::find_agent,
+    ssh::SshContext,
+};
+use common::agent::file::{FileQuery, FileWriteResult};
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::IntoResponse,
+};
+use serde_json::json;
+use sqlx::SqlitePool;
+
+/// 再開可能なダウンロードのため、条件付きリクエストのヘッダーはエージェントへそのまま渡す
+const FORWARDED_HEADERS: [header::HeaderName; 3] = [header::RANGE, header::IF_RANGE, header::IF_MODIFIED_SINCE];
+/// 書き込みの前提条件（ETag）と本文の長さ
+const WRITE_HEADERS: [header::HeaderName; 4] = [header::IF_MATCH, header::IF_NONE_MATCH, header::CONTENT_LENGTH, header::CONTENT_TYPE];
+
+pub async fn list_server_directory(
+    State(pool): State<SqlitePool>,
+    State(ssh): State<Arc<SshContext>>,
+    Path(server_uuid): Path<String>,
+    Query(query): Query<FileQuery>,
+) -> impl IntoResponse {
+    proxy_get(&pool, &ssh, &server_uuid, "/files", &query).await
+}
+
+pub async fn stat_server_file(
+    State(pool): State<SqlitePool>,
+    State(ssh): State<Arc<SshContext>>,
+    Path(server_uuid): Path<String>,
+    Query(query): Query<FileQuery>,
+) -> impl IntoResponse {
+    proxy_get(&pool, &ssh, &server_uuid, "/files/stat", &query).await
+}
+
+pub async fn download_server_file(
+    State(pool): State<SqlitePool>,
+    State(ssh): State<Arc<SshContext>>,
+    Path(server_uuid): Path<String>,
+    Actor(actor): Actor,
+    Query(query): Query<FileQuery>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let server = match find_agent(&pool, &ssh, &server_uuid).await {
+        Ok(server) => server,
+        Err(status) => return status.into_response(),
+    };
+    let http_client = match streaming_client() {
+        Ok(client) => client,
+        Err(status) => return status.into_response(),
+    };
+
+    let mut request = http_client.get(agent_url(&server, "/files/download")).query(&query);
+    for name in FORWARDED_HEADERS {
+        if let Some(value) = headers.get(&name) {
+            request = request.header(name.as_str(), value.as_bytes());
+        }
+    }
+
+    let response = match request.send().await {
+        Ok(res) => relay_file(res),
+        Err(e) => {
+            tracing::error!("Failed to download server file: {}", e);
+            StatusCode::BAD_GATEWAY.into_response()
+        }
+    };
+
+    let range = headers.get(header::RANGE).and_then(|v| v.to_str().ok());
+    record(&pool, AuditEntry {
+        server_id: &server.id,
+        actor: &actor,
+        action: "file.download",
+        target: &query.path,
+        detail: range.map(|range| json!({"range": range})),
+        status_code: response.status(),
+    }).await;
+
+    response
+}
+
+/// アップロードと編集のどちらもここを通る。本文はバッファリングせずにエージェントへ流す
+pub async fn write_server_file(
+    State(pool): State<SqlitePool>,
+    State(ssh): State<Arc<SshContext>>,
+    Path(server_uuid): Path<String>,
+    Actor(actor): Actor,
+    Query(query): Query<FileQuery>,
+    headers: HeaderMap,
+    body: Body,
+) -> impl IntoResponse {
+    let server = match find_agent(&pool, &ssh, &server_uuid).await {
+        Ok(server) => server,
+        Err(status) => return status.into_response(),
+    };
+    let http_client = match streaming_client() {
+        Ok(client) => client,
+        Err(status) => return status.into_response(),
+    };
+
+    let mut request = http_client
+        .put(agent_url(&server, "/files/content"))
+        .query(&query)
+        .body(reqwest::Body::wrap_stream(body.into_data_stream()));
+    for name in WRITE_HEADERS {
+        if let Some(value) = headers.get(&name) {
+            request = request.header(name.as_str(), value.as_bytes());
+        }
+    }
+
+    let (response, result) = match request.send().await {
+        Ok(res) => {
+            let status = StatusCode::from_u16(res.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+            match res.bytes().await {
+                Ok(body) => {
+                    let result = serde_json::from_slice::<FileWriteResult>(&body).ok();
+                    ((status, [(header::CONTENT_TYPE, "application/json")], body).into_response(), result)
+                }
+                Err(e) => {
+                    tracing::error!("Failed to read agent response: {}", e);
+                    (StatusCode::BAD_GATEWAY.into_response(), None)
+                }
+            }
+        }
+        Err(e) => {
+            tracing::error!("Failed to write server file: {}", e);
+            (StatusCode::BAD_GATEWAY.into_response(), None)
+        }
+    };
+
+    let header = |name: header::HeaderName| headers.get(name).and_then(|v| v.to_str().ok()).map(|v| v.to_string());
+    record(&pool, AuditEntry {
+        server_id: &server.id,
+        actor: &actor,
+        action: "file.write",
+        target: &query.path,
+        detail: Some(json!({
+            "if_match": header(header::IF_MATCH),
+            "created": headers.contains_key(header::IF_NONE_MATCH),
+            "size": result.as_ref().map(|result| result.entry.size),
+            "etag": result.as_ref().and_then(|result| result.entry.etag.clone()),
+            "backup": result.and_then(|result| result.backup),
+        })),
+        status_code: response.status(),
+    }).await;
+
+    response
+}
+
+async fn proxy_get(pool: &SqlitePool, ssh: &Arc<SshContext>, server_uuid: &str, path: &str, query: &FileQuery) -> axum::response::Response {
+    let server = match find_agent(pool, ssh, server_uuid).await {
+        Ok(server) => server,
+        Err(status) => return status.into_response(),
+    };
+    let http_client = match http_client() {
+        Ok(client) => client,
+        Err(status) => return status.into_response(),
+    };
+
+    match http_client.get(agent_url(&server, path)).query(query).send().await {
+        Ok(res) => relay_response(res).await,
+        Err(e) => {
+            tracing::error!("Failed to fetch server files: {}", e);
+            StatusCode::BAD_GATEWAY.into_response()
+        }
+    }
+}
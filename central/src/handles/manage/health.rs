@@ -1,26 +1,54 @@
-use common::central::information::ServerInformation;
+use crate::{
+    app::config::HealthConfig,
+    utils::{
+        agent::{agent_endpoint, agent_url, http_client},
+        bastion::route_agent,
+        ssh::{SshContext, open_tunnel},
+    },
+};
+use common::central::{information::ServerInformation, resource::Status, status::StatusTransition};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use axum::{
     extract::{Path, State},
     http::StatusCode,
     response::{IntoResponse, Json},
 };
+use chrono::Utc;
+use futures::{StreamExt, stream};
 use reqwest::{Client as HttpClient};
-use serde_json::{Value, json};
 use sqlx::SqlitePool;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    net::TcpStream,
+    task::JoinHandle,
+    time::MissedTickBehavior,
+};
+use uuid::Uuid;
+
+const POLL_CONCURRENCY: usize = 8;
+const TRANSITIONS_LIMIT: i64 = 200;
 
 pub async fn get_server_health(
     State(pool): State<SqlitePool>,
+    State(ssh): State<Arc<SshContext>>,
     Path(server_uuid): Path<String>,
 ) -> impl IntoResponse {
     match sqlx::query_as::<_, ServerInformation>(
-        r#"SELECT id, hostname, ip_address, os_type, tags, auth_profile_id, port, bastion_server_id, wol_mac_address FROM servers WHERE id = ?"#,
+        r#"SELECT id, hostname, ip_address, os_type, tags, auth_profile_id, port, bastion_server_id, wol_mac_address, wol_relay_server_id FROM servers WHERE id = ?"#,
     )
         .bind(server_uuid)
         .fetch_one(&pool)
         .await
     {
         Ok(row) => {
+            let row = match route_agent(&pool, &ssh, row) {
+                Ok(row) => row,
+                Err(status) => return status.into_response(),
+            };
             let http_client = HttpClient::builder()
                 .timeout(std::time::Duration::from_secs(10))
                 .build()
@@ -42,4 +70,409 @@ pub async fn get_server_health(
             StatusCode::INTERNAL_SERVER_ERROR.into_response()
         },
     }
-}
\ No newline at end of file
+}
+
+/// 状態の移り変わりを新しい順に返す
+pub async fn get_server_status_transitions(
+    State(pool): State<SqlitePool>,
+    Path(server_uuid): Path<String>,
+) -> impl IntoResponse {
+    match sqlx::query_as::<_, StatusTransition>(
+        r#"SELECT from_status, to_status, created_at FROM server_status_transitions WHERE server_id = ? ORDER BY created_at DESC LIMIT ?"#,
+    )
+        .bind(server_uuid)
+        .bind(TRANSITIONS_LIMIT)
+        .fetch_all(&pool)
+        .await
+    {
+        Ok(rows) => (StatusCode::OK, Json(rows)).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to fetch status transitions: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        },
+    }
+}
+
+/// すべてのサーバーのエージェントを定期的に確かめ、状態を保存する
+pub fn spawn_poller(pool: SqlitePool, ssh: Arc<SshContext>, config: HealthConfig) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.poll_interval_secs.max(1)));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        loop {
+            interval.tick().await;
+
+            let servers = match sqlx::query_as::<_, ServerInformation>(
+                r#"SELECT id, hostname, ip_address, os_type, tags, auth_profile_id, port, bastion_server_id, wol_mac_address, wol_relay_server_id FROM servers"#,
+            )
+                .fetch_all(&pool)
+                .await
+            {
+                Ok(servers) => servers,
+                Err(e) => {
+                    tracing::error!("Failed to fetch servers to check: {}", e);
+                    continue;
+                }
+            };
+            stream::iter(servers.iter())
+                .for_each_concurrent(POLL_CONCURRENCY, |server| {
+                    let pool = pool.clone();
+                    let ssh = ssh.clone();
+                    let config = &config;
+                    async move {
+                        let (observed, latency_ms) = match route_agent(&pool, &ssh, server.clone()) {
+                            Ok(agent) => probe(&pool, &ssh, server, &agent, config).await,
+                            // 転送ポートを開けなかった理由は `route_agent` が記録している
+                            Err(_) => {
+                                tracing::warn!("Recording {} as offline: no route to its agent", server.hostname);
+                                (Status::Offline, None)
+                            }
+                        };
+                        if let Err(e) = record_probe(&pool, &server.id, observed, latency_ms, config.flap_threshold).await {
+                            tracing::error!("Failed to store status of {}: {}", server.hostname, e);
+                        }
+                    }
+                })
+                .await;
+        }
+    })
+}
+
+/// 1回のヘルスチェックで見えた状態と、応答があればその時間を返す。
+/// 応答が遅いか、エラーを返したときは Caution とする。
+/// エージェントが応答しなければ SSH のポートを確かめる。`agent` は `route_agent` を通した `server`
+async fn probe(
+    pool: &SqlitePool,
+    ssh: &SshContext,
+    server: &ServerInformation,
+    agent: &ServerInformation,
+    config: &HealthConfig,
+) -> (Status, Option<u32>) {
+    let timeout = Duration::from_millis(config.timeout_ms.max(1));
+    let status = |latency_ms: u32| {
+        if u64::from(latency_ms) < config.caution_latency_ms { Status::Online } else { Status::Caution }
+    };
+
+    if let Ok(http_client) = http_client() {
+        let started = Instant::now();
+        let result = http_client.get(agent_url(agent, "/health")).timeout(timeout).send().await;
+        let latency_ms = elapsed_ms(started);
+        match result {
+            Ok(response) if response.status().is_success() => return (status(latency_ms), Some(latency_ms)),
+            Ok(_) => return (Status::Caution, Some(latency_ms)),
+            Err(_) => {}
+        }
+    }
+
+    match probe_ssh(pool, ssh, server, timeout).await {
+        // 認証情報のあるサーバーは SSH で管理しているので、SSH が応答すれば十分とする。
+        // そうでなければエージェントだけが止まっている
+        Some(latency_ms) if server.auth_profile_id.is_some() => (status(latency_ms), Some(latency_ms)),
+        Some(latency_ms) => (Status::Caution, Some(latency_ms)),
+        None => (Status::Offline, None),
+    }
+}
+
+/// SSH のポートへつなぎ、`SSH-` で始まるバナーが届くまでの時間を返す。踏み台があれば踏み台から確かめる
+pub async fn probe_ssh(pool: &SqlitePool, ssh: &SshContext, server: &ServerInformation, timeout: Duration) -> Option<u32> {
+    let started = Instant::now();
+    let banner = async {
+        let host = agent_endpoint(&server.ip_address).0;
+        let mut stream: Box<dyn AsyncRead + Unpin + Send> = if server.bastion_server_id.is_some() {
+            Box::new(open_tunnel(pool, ssh, server, &host, server.port).await.ok()?)
+        } else {
+            Box::new(TcpStream::connect((host.as_str(), server.port)).await.ok()?)
+        };
+        let mut prefix = [0u8; 4];
+        stream.read_exact(&mut prefix).await.ok()?;
+        (&prefix == b"SSH-").then_some(())
+    };
+    tokio::time::timeout(timeout, banner).await.ok()??;
+    Some(elapsed_ms(started))
+}
+
+fn elapsed_ms(started: Instant) -> u32 {
+    started.elapsed().as_millis().min(u32::MAX as u128) as u32
+}
+
+/// 保存されている状態に1回分の観測を反映した結果
+#[derive(Debug, PartialEq)]
+enum Damped {
+    /// 状態が変わった。`from` は初めて記録する場合に `None`
+    Changed { from: Option<Status> },
+    /// 状態はそのままで、別の状態が `count` 回続いている
+    Pending { status: Option<Status>, count: u32 },
+}
+
+/// 見えた状態が `flap_threshold` 回続くまでは現在の状態を変えない。
+/// `current` は保存されている (status, pending_status, pending_count)
+fn damp(current: Option<(Status, Option<Status>, u32)>, observed: Status, flap_threshold: u32) -> Damped {
+    let Some((status, pending_status, pending_count)) = current else {
+        return Damped::Changed { from: None };
+    };
+    let count = if observed == status {
+        return Damped::Pending { status: None, count: 0 };
+    } else if pending_status == Some(observed) {
+        pending_count + 1
+    } else {
+        1
+    };
+    if count >= flap_threshold.max(1) {
+        Damped::Changed { from: Some(status) }
+    } else {
+        Damped::Pending { status: Some(observed), count }
+    }
+}
+
+/// 観測を保存する。最後に応答した時刻はばたつきに関係なく更新する。
+/// 読んでから書くまでの間に他の書き込みが割り込まないよう、最初から書き込みのロックを取る
+async fn record_probe(
+    pool: &SqlitePool,
+    server_id: &str,
+    observed: Status,
+    latency_ms: Option<u32>,
+    flap_threshold: u32,
+) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    let last_seen_at = latency_ms.map(|_| now);
+    let mut tx = pool.begin_with("BEGIN IMMEDIATE").await?;
+
+    let current = sqlx::query_as::<_, (Status, Option<Status>, u32)>(
+        r#"SELECT status, pending_status, pending_count FROM server_status WHERE server_id = ?"#,
+    )
+        .bind(server_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+    match damp(current, observed, flap_threshold) {
+        Damped::Changed { from } => {
+            sqlx::query(
+                r#"INSERT INTO server_status (server_id, status, last_seen_at, changed_at, checked_at, latency_ms, pending_status, pending_count) VALUES (?, ?, ?, ?, ?, ?, NULL, 0)
+                ON CONFLICT (server_id) DO UPDATE SET status=excluded.status, last_seen_at=COALESCE(excluded.last_seen_at, last_seen_at), changed_at=excluded.changed_at, checked_at=excluded.checked_at, latency_ms=excluded.latency_ms, pending_status=NULL, pending_count=0"#,
+            )
+                .bind(server_id)
+                .bind(observed)
+                .bind(last_seen_at)
+                .bind(now)
+                .bind(now)
+                .bind(latency_ms)
+                .execute(&mut *tx)
+                .await?;
+            insert_transition(&mut tx, server_id, from, observed).await?;
+            if let Some(from) = from {
+                tracing::info!("Server {} is now {:?} (was {:?})", server_id, observed, from);
+            }
+        }
+        Damped::Pending { status, count } => {
+            sqlx::query(
+                r#"UPDATE server_status SET last_seen_at=COALESCE(?, last_seen_at), checked_at=?, latency_ms=?, pending_status=?, pending_count=? WHERE server_id=?"#,
+            )
+                .bind(last_seen_at)
+                .bind(now)
+                .bind(latency_ms)
+                .bind(status)
+                .bind(count)
+                .bind(server_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+    }
+    tx.commit().await
+}
+
+async fn insert_transition(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    server_id: &str,
+    from_status: Option<Status>,
+    to_status: Status,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"INSERT INTO server_status_transitions (id, server_id, from_status, to_status, created_at) VALUES (?, ?, ?, ?, ?)"#,
+    )
+        .bind(Uuid::new_v4().to_string())
+        .bind(server_id)
+        .bind(from_status)
+        .bind(to_status)
+        .bind(Utc::now())
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::{context, mock_agent};
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use axum::{Router, routing::get};
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use tokio::{io::AsyncWriteExt, net::TcpListener};
+
+    const MIGRATION: &str = include_str!("../../../../migrations/20261018140000_server_status.sql");
+
+    /// 本番と同じく複数の接続を持つプールにするため、インメモリではなく一時ファイルを使う
+    async fn pool(dir: &tempfile::TempDir) -> SqlitePool {
+        let options = SqliteConnectOptions::new().filename(dir.path().join("test.db")).create_if_missing(true);
+        let pool = SqlitePoolOptions::new().max_connections(POLL_CONCURRENCY as u32).connect_with(options).await.unwrap();
+        sqlx::raw_sql(MIGRATION).execute(&pool).await.unwrap();
+        pool
+    }
+
+    async fn stored(pool: &SqlitePool, server_id: &str) -> (Status, Option<Status>, u32) {
+        sqlx::query_as(r#"SELECT status, pending_status, pending_count FROM server_status WHERE server_id = ?"#)
+            .bind(server_id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    async fn transitions(pool: &SqlitePool, server_id: &str) -> Vec<(Option<Status>, Status)> {
+        sqlx::query_as(r#"SELECT from_status, to_status FROM server_status_transitions WHERE server_id = ? ORDER BY created_at"#)
+            .bind(server_id)
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn first_observation_is_recorded_immediately() {
+        for threshold in [1, 3] {
+            assert_eq!(damp(None, Status::Offline, threshold), Damped::Changed { from: None });
+        }
+    }
+
+    #[test]
+    fn threshold_one_changes_on_first_differing_observation() {
+        assert_eq!(damp(Some((Status::Online, None, 0)), Status::Offline, 1), Damped::Changed { from: Some(Status::Online) });
+        assert_eq!(damp(Some((Status::Online, None, 0)), Status::Online, 1), Damped::Pending { status: None, count: 0 });
+        // 0 は 1 として扱う
+        assert_eq!(damp(Some((Status::Online, None, 0)), Status::Caution, 0), Damped::Changed { from: Some(Status::Online) });
+    }
+
+    #[test]
+    fn threshold_three_needs_three_consecutive_observations() {
+        let online = Some((Status::Online, None, 0));
+        assert_eq!(damp(online, Status::Offline, 3), Damped::Pending { status: Some(Status::Offline), count: 1 });
+        assert_eq!(
+            damp(Some((Status::Online, Some(Status::Offline), 1)), Status::Offline, 3),
+            Damped::Pending { status: Some(Status::Offline), count: 2 }
+        );
+        assert_eq!(
+            damp(Some((Status::Online, Some(Status::Offline), 2)), Status::Offline, 3),
+            Damped::Changed { from: Some(Status::Online) }
+        );
+        // 別の状態が見えたら数え直し、元の状態に戻ったら取り消す
+        assert_eq!(
+            damp(Some((Status::Online, Some(Status::Offline), 2)), Status::Caution, 3),
+            Damped::Pending { status: Some(Status::Caution), count: 1 }
+        );
+        assert_eq!(damp(Some((Status::Online, Some(Status::Offline), 2)), Status::Online, 3), Damped::Pending { status: None, count: 0 });
+    }
+
+    #[tokio::test]
+    async fn records_damped_transitions() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = pool(&dir).await;
+
+        for (threshold, server_id) in [(1, "one"), (3, "three")] {
+            record_probe(&pool, server_id, Status::Online, Some(10), threshold).await.unwrap();
+            for _ in 0..2 {
+                record_probe(&pool, server_id, Status::Offline, None, threshold).await.unwrap();
+            }
+            record_probe(&pool, server_id, Status::Online, Some(10), threshold).await.unwrap();
+            for _ in 0..3 {
+                record_probe(&pool, server_id, Status::Offline, None, threshold).await.unwrap();
+            }
+        }
+
+        use Status::*;
+        assert_eq!(
+            transitions(&pool, "one").await,
+            vec![(None, Online), (Some(Online), Offline), (Some(Offline), Online), (Some(Online), Offline)]
+        );
+        assert_eq!(stored(&pool, "one").await, (Offline, None, 0));
+        // 2回だけのオフラインは無視され、3回続いたところで変わる
+        assert_eq!(transitions(&pool, "three").await, vec![(None, Online), (Some(Online), Offline)]);
+        assert_eq!(stored(&pool, "three").await, (Offline, None, 0));
+    }
+
+    #[tokio::test]
+    async fn concurrent_probes_do_not_fail_with_busy() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = pool(&dir).await;
+        let servers = (0..POLL_CONCURRENCY * 2).map(|i| format!("server-{}", i)).collect::<Vec<_>>();
+
+        for round in 0..6 {
+            let observed = if round % 2 == 0 { Status::Online } else { Status::Offline };
+            let results = stream::iter(servers.iter())
+                .map(|server_id| record_probe(&pool, server_id, observed, Some(1), 1))
+                .buffer_unordered(POLL_CONCURRENCY)
+                .collect::<Vec<_>>()
+                .await;
+            assert!(results.iter().all(Result::is_ok), "{:?}", results.iter().find(|result| result.is_err()));
+        }
+        for server_id in &servers {
+            assert_eq!(transitions(&pool, server_id).await.len(), 6);
+        }
+    }
+
+    fn server(agent: &str, ssh_port: u16, auth_profile_id: Option<&str>) -> ServerInformation {
+        ServerInformation {
+            id: "server".to_string(),
+            hostname: "server.example".to_string(),
+            ip_address: agent.to_string(),
+            os_type: "linux".to_string(),
+            tags: None,
+            auth_profile_id: auth_profile_id.map(str::to_string),
+            port: ssh_port,
+            bastion_server_id: None,
+            wol_mac_address: None,
+            wol_relay_server_id: None,
+        }
+    }
+
+    /// 接続されるたびに `greeting` を送って閉じるサーバー
+    async fn greeter(greeting: &'static [u8]) -> SocketAddr {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let _ = stream.write_all(greeting).await;
+            }
+        });
+        address
+    }
+
+    /// 何も待ち受けていないポート
+    async fn closed_port() -> SocketAddr {
+        TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap().local_addr().unwrap()
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_the_ssh_port_when_the_agent_does_not_answer() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = pool(&dir).await;
+        let ssh = context();
+        let config = HealthConfig { timeout_ms: 2000, caution_latency_ms: 1000, ..HealthConfig::default() };
+        let sshd = greeter(b"SSH-2.0-OpenSSH_9.6\r\n").await.port();
+        let web = greeter(b"HTTP/1.1 400 Bad Request\r\n\r\n").await.port();
+        let nothing = closed_port().await;
+
+        let agent = mock_agent(Router::new().route("/health", get(|| async { "ok" }))).await;
+        let probe_with = |server: ServerInformation| {
+            let pool = pool.clone();
+            let ssh = ssh.clone();
+            let config = config.clone();
+            async move { probe(&pool, &ssh, &server, &server, &config).await.0 }
+        };
+
+        assert_eq!(probe_with(server(&agent, nothing.port(), None)).await, Status::Online);
+        // エージェントのないサーバーは SSH が応答すれば Online
+        assert_eq!(probe_with(server(&nothing.to_string(), sshd, Some("profile"))).await, Status::Online);
+        // 認証情報がなければ、エージェントだけが止まっているとみなす
+        assert_eq!(probe_with(server(&nothing.to_string(), sshd, None)).await, Status::Caution);
+        assert_eq!(probe_with(server(&nothing.to_string(), web, Some("profile"))).await, Status::Offline);
+        assert_eq!(probe_with(server(&nothing.to_string(), nothing.port(), Some("profile"))).await, Status::Offline);
+    }
+}
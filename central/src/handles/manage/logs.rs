@@ -0,0 +1,109 @@
+use crate::utils::{
+    agent::{agent_url, http_client, relay_response, relay_stream, streaming_client},
+    bastion::find_agent,
+    ssh::SshContext,
+};
+use common::agent::log::{JournalQuery, LogSearchQuery};
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, RawQuery, State},
+    http::StatusCode,
+    response::{IntoResponse, Json},
+};
+use regex::RegexBuilder;
+use serde_json::json;
+use sqlx::SqlitePool;
+
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// ブラウザがエージェントに直接接続しないよう、tail のストリームを中継する
+pub async fn tail_server_log(
+    State(pool): State<SqlitePool>,
+    State(ssh): State<Arc<SshContext>>,
+    Path(server_uuid): Path<String>,
+    RawQuery(query): RawQuery,
+) -> impl IntoResponse {
+    let server = match find_agent(&pool, &ssh, &server_uuid).await {
+        Ok(server) => server,
+        Err(status) => return status.into_response(),
+    };
+    let http_client = match streaming_client() {
+        Ok(client) => client,
+        Err(status) => return status.into_response(),
+    };
+
+    let mut req_address = agent_url(&server, "/logs/tail");
+    if let Some(query) = query {
+        req_address = format!("{}?{}", req_address, query);
+    }
+
+    match http_client.get(req_address).send().await {
+        Ok(res) => relay_stream(res),
+        Err(e) => {
+            tracing::error!("Failed to tail server log: {}", e);
+            StatusCode::BAD_GATEWAY.into_response()
+        }
+    }
+}
+
+/// 不正な正規表現はエージェントに送る前に弾く
+pub async fn search_server_log(
+    State(pool): State<SqlitePool>,
+    State(ssh): State<Arc<SshContext>>,
+    Path(server_uuid): Path<String>,
+    Query(query): Query<LogSearchQuery>,
+) -> impl IntoResponse {
+    if let Err(e) = RegexBuilder::new(&query.pattern)
+        .case_insensitive(query.ignore_case)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+    {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": e.to_string()}))).into_response();
+    }
+    if let (Some(from), Some(to)) = (query.from, query.to) && from > to {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "from must be before to"}))).into_response();
+    }
+
+    let server = match find_agent(&pool, &ssh, &server_uuid).await {
+        Ok(server) => server,
+        Err(status) => return status.into_response(),
+    };
+    let http_client = match http_client() {
+        Ok(client) => client,
+        Err(status) => return status.into_response(),
+    };
+
+    match http_client.get(agent_url(&server, "/logs/search")).query(&query).send().await {
+        Ok(res) => relay_response(res).await,
+        Err(e) => {
+            tracing::error!("Failed to search server log: {}", e);
+            StatusCode::BAD_GATEWAY.into_response()
+        }
+    }
+}
+
+pub async fn read_server_journal(
+    State(pool): State<SqlitePool>,
+    State(ssh): State<Arc<SshContext>>,
+    Path(server_uuid): Path<String>,
+    Query(query): Query<JournalQuery>,
+) -> impl IntoResponse {
+    let server = match find_agent(&pool, &ssh, &server_uuid).await {
+        Ok(server) => server,
+        Err(status) => return status.into_response(),
+    };
+    let http_client = match if query.follow { streaming_client() } else { http_client() } {
+        Ok(client) => client,
+        Err(status) => return status.into_response(),
+    };
+
+    match http_client.get(agent_url(&server, "/logs/journal")).query(&query).send().await {
+        Ok(res) if query.follow => relay_stream(res),
+        Ok(res) => relay_response(res).await,
+        Err(e) => {
+            tracing::error!("Failed to read server journal: {}", e);
+            StatusCode::BAD_GATEWAY.into_response()
+        }
+    }
+}
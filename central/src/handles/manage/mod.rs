@@ -1,2 +1,16 @@
+pub mod audit;
+pub mod auth_profiles;
+pub mod command_jobs;
 pub mod specs;
-pub mod health;
\ No newline at end of file
+pub mod files;
+pub mod health;
+pub mod logs;
+pub mod package_jobs;
+pub mod packages;
+pub mod presets;
+pub mod processes;
+pub mod services;
+pub mod ssh;
+pub mod terminal;
+pub mod watched_services;
+pub mod wake;
@@ -0,0 +1,234 @@
+use crate::utils::{
+    agent::find_server,
+    audit::Actor,
+    bastion::route_agent,
+    jobs::{JobHub, TargetRun},
+    ssh::SshContext,
+};
+use common::{
+    agent::package::{PackageAction, PackageJobRequest},
+    central::{
+        information::ServerInformation,
+        job::{JobEvent, JobTarget, PackageJob, PackageJobDetail},
+    },
+};
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Json},
+};
+use chrono::Utc;
+use futures::{StreamExt, stream};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::SqlitePool;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+const JOB_CONCURRENCY: usize = 8;
+
+#[derive(Deserialize)]
+pub struct CreatePackageJobRequest {
+    action: PackageAction,
+    #[serde(default)]
+    packages: Vec<String>,
+    server_ids: Vec<String>,
+}
+
+pub async fn create_package_job(
+    State(pool): State<SqlitePool>,
+    State(hub): State<Arc<JobHub>>,
+    State(ssh): State<Arc<SshContext>>,
+    Actor(actor): Actor,
+    Json(json): Json<CreatePackageJobRequest>,
+) -> impl IntoResponse {
+    if json.server_ids.is_empty() {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "at least one server is required"}))).into_response();
+    }
+    if json.action != PackageAction::FullUpgrade && json.packages.is_empty() {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "at least one package is required"}))).into_response();
+    }
+
+    let mut servers = Vec::new();
+    for server_id in &json.server_ids {
+        match find_server(&pool, server_id).await {
+            Ok(server) => servers.push(server),
+            Err(StatusCode::NOT_FOUND) => {
+                return (StatusCode::NOT_FOUND, Json(json!({"error": format!("server {} not found", server_id)}))).into_response();
+            }
+            Err(status) => return status.into_response(),
+        }
+    }
+    // 同じサーバーが重複して指定されても、1回だけ実行する
+    servers.sort_by(|a, b| a.id.cmp(&b.id));
+    servers.dedup_by(|a, b| a.id == b.id);
+    let servers = match servers.into_iter().map(|server| route_agent(&pool, &ssh, server)).collect::<Result<Vec<_>, _>>() {
+        Ok(servers) => servers,
+        Err(status) => return status.into_response(),
+    };
+
+    let job_id = Uuid::new_v4().to_string();
+    if let Err(e) = insert_job(&pool, &job_id, &actor, &json, &servers).await {
+        tracing::error!("Failed to create package job: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response();
+    }
+
+    let tx = hub.open(&job_id);
+    let request = PackageJobRequest {
+        action: json.action,
+        packages: json.packages,
+    };
+    tokio::spawn(run_job(pool.clone(), hub, job_id.clone(), servers, request, tx));
+
+    match load_job(&pool, &job_id).await {
+        Ok(Some(job)) => (StatusCode::CREATED, Json(job)).into_response(),
+        Ok(None) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to fetch package job: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn get_package_jobs(State(pool): State<SqlitePool>) -> impl IntoResponse {
+    match sqlx::query_as::<_, PackageJob>(
+        r#"SELECT id, action, packages, created_by, status, created_at, finished_at FROM package_jobs ORDER BY created_at DESC"#,
+    )
+        .fetch_all(&pool)
+        .await
+    {
+        Ok(rows) => (StatusCode::OK, Json(rows)).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to fetch package jobs: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn get_package_job(
+    State(pool): State<SqlitePool>,
+    Path(job_id): Path<String>,
+) -> impl IntoResponse {
+    match load_job(&pool, &job_id).await {
+        Ok(Some(job)) => (StatusCode::OK, Json(job)).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to fetch package job: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// 実行中のジョブの出力をリアルタイムに配信する。終了済みのジョブは `get_package_job` でログを取得する
+pub async fn stream_package_job(
+    State(hub): State<Arc<JobHub>>,
+    Path(job_id): Path<String>,
+) -> impl IntoResponse {
+    hub.sse_response(&job_id)
+}
+
+async fn run_job(
+    pool: SqlitePool,
+    hub: Arc<JobHub>,
+    job_id: String,
+    servers: Vec<ServerInformation>,
+    request: PackageJobRequest,
+    tx: broadcast::Sender<JobEvent>,
+) {
+    let request = Arc::new(request);
+    let results = stream::iter(servers)
+        .map(|server| run_target(pool.clone(), job_id.clone(), server, request.clone(), tx.clone()))
+        .buffer_unordered(JOB_CONCURRENCY)
+        .collect::<Vec<bool>>()
+        .await;
+
+    let status = if results.iter().all(|ok| *ok) { "succeeded" } else { "failed" };
+    let result = sqlx::query(r#"UPDATE package_jobs SET status = ?, finished_at = ? WHERE id = ?"#)
+        .bind(status)
+        .bind(Utc::now())
+        .bind(&job_id)
+        .execute(&pool)
+        .await;
+    if let Err(e) = result {
+        tracing::error!("Failed to update package job {}: {}", job_id, e);
+    }
+
+    hub.close(&job_id);
+}
+
+async fn run_target(
+    pool: SqlitePool,
+    job_id: String,
+    server: ServerInformation,
+    request: Arc<PackageJobRequest>,
+    tx: broadcast::Sender<JobEvent>,
+) -> bool {
+    let mut run = TargetRun::start(pool, "package_job_targets", job_id, server.id.clone(), tx).await;
+    run.execute(&server, "/packages/jobs", &*request).await;
+    run.finish().await
+}
+
+async fn insert_job(
+    pool: &SqlitePool,
+    job_id: &str,
+    actor: &str,
+    json: &CreatePackageJobRequest,
+    servers: &[ServerInformation],
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(r#"INSERT INTO package_jobs (id, action, packages, created_by, status, created_at) VALUES (?, ?, ?, ?, 'running', ?)"#)
+        .bind(job_id)
+        .bind(json.action.as_str())
+        .bind(serde_json::to_string(&json.packages).unwrap_or_else(|_| "[]".to_string()))
+        .bind(actor)
+        .bind(Utc::now())
+        .execute(&mut *tx)
+        .await?;
+
+    for server in servers {
+        sqlx::query(r#"INSERT INTO package_job_targets (job_id, server_id, status) VALUES (?, ?, 'pending')"#)
+            .bind(job_id)
+            .bind(&server.id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await
+}
+
+async fn load_job(pool: &SqlitePool, job_id: &str) -> Result<Option<PackageJobDetail>, sqlx::Error> {
+    let Some(job) = sqlx::query_as::<_, PackageJob>(
+        r#"SELECT id, action, packages, created_by, status, created_at, finished_at FROM package_jobs WHERE id = ?"#,
+    )
+        .bind(job_id)
+        .fetch_optional(pool)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    let targets = sqlx::query_as::<_, JobTarget>(
+        r#"SELECT server_id, status, exit_code, log, started_at, finished_at FROM package_job_targets WHERE job_id = ?"#,
+    )
+        .bind(job_id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(Some(PackageJobDetail { job, targets }))
+}
+
+/// central の再起動で中断されたジョブを失敗扱いにする
+pub async fn mark_interrupted_jobs(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    sqlx::query(r#"UPDATE package_job_targets SET status = 'interrupted', finished_at = ? WHERE status IN ('pending', 'running')"#)
+        .bind(Utc::now())
+        .execute(pool)
+        .await?;
+    sqlx::query(r#"UPDATE package_jobs SET status = 'interrupted', finished_at = ? WHERE status = 'running'"#)
+        .bind(Utc::now())
+        .execute(pool)
+        .await?;
+    Ok(())
+}
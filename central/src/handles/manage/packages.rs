@@ -0,0 +1,342 @@
+use crate::utils::{
+    agent::{agent_url, http_client},
+    bastion::{find_agent, route_agent},
+    ssh::SshContext,
+};
+use common::{
+    agent::package::{Package, PackageInventory, PackageUpdates},
+    central::{
+        information::ServerInformation,
+        package::{CachedPackageInventory, PackageSearchResult, PendingUpdatesSummary},
+    },
+};
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Json},
+};
+use chrono::{DateTime, Utc};
+use futures::{StreamExt, stream};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::SqlitePool;
+
+pub async fn get_server_packages(
+    State(pool): State<SqlitePool>,
+    State(ssh): State<Arc<SshContext>>,
+    Path(server_uuid): Path<String>,
+) -> impl IntoResponse {
+    match load_inventory(&pool, &server_uuid).await {
+        Ok(Some(inventory)) => (StatusCode::OK, Json(inventory)).into_response(),
+        // まだキャッシュがなければエージェントから取得する
+        Ok(None) => refresh_server_packages(State(pool), State(ssh), Path(server_uuid)).await.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to fetch package inventory: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn refresh_server_packages(
+    State(pool): State<SqlitePool>,
+    State(ssh): State<Arc<SshContext>>,
+    Path(server_uuid): Path<String>,
+) -> impl IntoResponse {
+    let server = match find_agent(&pool, &ssh, &server_uuid).await {
+        Ok(server) => server,
+        Err(status) => return status.into_response(),
+    };
+    let http_client = match http_client() {
+        Ok(client) => client,
+        Err(status) => return status.into_response(),
+    };
+
+    let req_address = agent_url(&server, "/packages");
+    let inventory = match http_client.get(req_address).send().await {
+        Ok(res) if res.status().is_success() => match res.json::<PackageInventory>().await {
+            Ok(inventory) => inventory,
+            Err(e) => {
+                tracing::error!("Failed to parse package inventory: {}", e);
+                return StatusCode::BAD_GATEWAY.into_response();
+            }
+        },
+        Ok(res) => {
+            tracing::error!("Agent returned {} for package inventory", res.status());
+            return StatusCode::BAD_GATEWAY.into_response();
+        }
+        Err(e) => {
+            tracing::error!("Failed to fetch package inventory: {}", e);
+            return StatusCode::BAD_GATEWAY.into_response();
+        }
+    };
+
+    if let Err(e) = store_inventory(&pool, &server.id, &inventory).await {
+        tracing::error!("Failed to store package inventory: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response();
+    }
+
+    match load_inventory(&pool, &server.id).await {
+        Ok(Some(inventory)) => (StatusCode::OK, Json(inventory)).into_response(),
+        Ok(None) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to fetch package inventory: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    name: String,
+    version: Option<String>,
+}
+
+/// バージョンは前方一致で検索する（`3.0.2` で `3.0.2-0ubuntu1.10` にも一致する）
+pub async fn search_packages(
+    State(pool): State<SqlitePool>,
+    Query(query): Query<SearchQuery>,
+) -> impl IntoResponse {
+    match sqlx::query_as::<_, PackageSearchResult>(
+        r#"SELECT p.server_id, s.hostname, p.name, p.version, p.architecture FROM server_packages p JOIN servers s ON s.id = p.server_id WHERE p.name = ? AND (? IS NULL OR substr(p.version, 1, length(?)) = ?) ORDER BY s.hostname"#,
+    )
+        .bind(&query.name)
+        .bind(&query.version)
+        .bind(&query.version)
+        .bind(&query.version)
+        .fetch_all(&pool)
+        .await
+    {
+        Ok(rows) => (StatusCode::OK, Json(rows)).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to search packages: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// エージェントから更新可能なパッケージを取得し、件数を保存してから一覧を返す
+pub async fn get_server_package_updates(
+    State(pool): State<SqlitePool>,
+    State(ssh): State<Arc<SshContext>>,
+    Path(server_uuid): Path<String>,
+) -> impl IntoResponse {
+    let server = match find_agent(&pool, &ssh, &server_uuid).await {
+        Ok(server) => server,
+        Err(status) => return status.into_response(),
+    };
+
+    match fetch_and_store_updates(&pool, &server).await {
+        Ok(updates) => (StatusCode::OK, Json(updates)).into_response(),
+        Err(status) => status.into_response(),
+    }
+}
+
+/// 保存済みの件数をセキュリティ更新の多い順に返す
+pub async fn get_pending_updates(State(pool): State<SqlitePool>) -> impl IntoResponse {
+    match load_pending_updates(&pool).await {
+        Ok(rows) => (StatusCode::OK, Json(rows)).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to fetch pending updates: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+const REFRESH_CONCURRENCY: usize = 8;
+
+/// 全サーバーの取り直しが動いている間は true。重ねて始めないようにする
+static REFRESHING: AtomicBool = AtomicBool::new(false);
+
+/// 取り直しが終わるか、途中で panic したときにフラグを戻す
+struct RefreshGuard;
+
+impl Drop for RefreshGuard {
+    fn drop(&mut self) {
+        REFRESHING.store(false, Ordering::Release);
+    }
+}
+
+/// 全サーバーの件数の取り直しを裏で始めて 202 を返す。結果は `GET /packages/updates` に反映される
+pub async fn refresh_pending_updates(
+    State(pool): State<SqlitePool>,
+    State(ssh): State<Arc<SshContext>>,
+) -> impl IntoResponse {
+    if REFRESHING.swap(true, Ordering::AcqRel) {
+        return (StatusCode::ACCEPTED, Json(json!({"status": "running"}))).into_response();
+    }
+    tokio::spawn(async move {
+        let _guard = RefreshGuard;
+        refresh_all_updates(&pool, &ssh).await;
+    });
+    (StatusCode::ACCEPTED, Json(json!({"status": "started"}))).into_response()
+}
+
+async fn refresh_all_updates(pool: &SqlitePool, ssh: &Arc<SshContext>) {
+    let servers = match sqlx::query_as::<_, ServerInformation>(
+        r#"SELECT id, hostname, ip_address, os_type, tags, auth_profile_id, port, bastion_server_id, wol_mac_address, wol_relay_server_id FROM servers"#,
+    )
+        .fetch_all(pool)
+        .await
+    {
+        Ok(rows) => rows,
+        Err(e) => {
+            tracing::error!("Failed to fetch servers list: {}", e);
+            return;
+        }
+    };
+
+    // 失敗したサーバーは前回の件数のまま残す
+    let servers = servers.into_iter().filter_map(|server| route_agent(pool, ssh, server).ok()).collect::<Vec<_>>();
+    stream::iter(servers.iter())
+        .for_each_concurrent(REFRESH_CONCURRENCY, |server| async move {
+            let _ = fetch_and_store_updates(pool, server).await;
+        })
+        .await;
+    tracing::info!("Refreshed pending updates of {} servers", servers.len());
+}
+
+async fn fetch_and_store_updates(pool: &SqlitePool, server: &ServerInformation) -> Result<PackageUpdates, StatusCode> {
+    let http_client = http_client()?;
+
+    let req_address = agent_url(server, "/packages/updates");
+    let updates = match http_client.get(req_address).send().await {
+        Ok(res) if res.status().is_success() => res.json::<PackageUpdates>().await.map_err(|e| {
+            tracing::error!("Failed to parse package updates from {}: {}", server.hostname, e);
+            StatusCode::BAD_GATEWAY
+        })?,
+        Ok(res) => {
+            tracing::error!("Agent on {} returned {} for package updates", server.hostname, res.status());
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+        Err(e) => {
+            tracing::error!("Failed to fetch package updates from {}: {}", server.hostname, e);
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    sqlx::query(r#"INSERT INTO package_update_summaries (server_id, manager, pending_count, security_count, refreshed_at) VALUES (?, ?, ?, ?, ?) ON CONFLICT(server_id) DO UPDATE SET manager = excluded.manager, pending_count = excluded.pending_count, security_count = excluded.security_count, refreshed_at = excluded.refreshed_at"#)
+        .bind(&server.id)
+        .bind(updates.manager.as_str())
+        .bind(updates.pending_count as i64)
+        .bind(updates.security_count as i64)
+        .bind(Utc::now())
+        .execute(pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to store package update summary: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(updates)
+}
+
+async fn load_pending_updates(pool: &SqlitePool) -> Result<Vec<PendingUpdatesSummary>, sqlx::Error> {
+    sqlx::query_as::<_, PendingUpdatesSummary>(
+        r#"SELECT u.server_id, s.hostname, u.manager, u.pending_count, u.security_count, u.refreshed_at FROM package_update_summaries u JOIN servers s ON s.id = u.server_id ORDER BY u.security_count DESC, u.pending_count DESC, s.hostname"#,
+    )
+        .fetch_all(pool)
+        .await
+}
+
+async fn store_inventory(pool: &SqlitePool, server_id: &str, inventory: &PackageInventory) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(r#"INSERT INTO package_inventories (server_id, manager, refreshed_at) VALUES (?, ?, ?) ON CONFLICT(server_id) DO UPDATE SET manager = excluded.manager, refreshed_at = excluded.refreshed_at"#)
+        .bind(server_id)
+        .bind(inventory.manager.as_str())
+        .bind(Utc::now())
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query(r#"DELETE FROM server_packages WHERE server_id = ?"#)
+        .bind(server_id)
+        .execute(&mut *tx)
+        .await?;
+
+    for package in &inventory.packages {
+        sqlx::query(r#"INSERT INTO server_packages (server_id, name, version, architecture, source, installed_at) VALUES (?, ?, ?, ?, ?, ?)"#)
+            .bind(server_id)
+            .bind(&package.name)
+            .bind(&package.version)
+            .bind(&package.architecture)
+            .bind(&package.source)
+            .bind(package.installed_at)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await
+}
+
+async fn load_inventory(pool: &SqlitePool, server_id: &str) -> Result<Option<CachedPackageInventory>, sqlx::Error> {
+    let Some((manager, refreshed_at)) = sqlx::query_as::<_, (String, DateTime<Utc>)>(
+        r#"SELECT manager, refreshed_at FROM package_inventories WHERE server_id = ?"#,
+    )
+        .bind(server_id)
+        .fetch_optional(pool)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    let packages = sqlx::query_as::<_, Package>(
+        r#"SELECT name, version, architecture, source, installed_at FROM server_packages WHERE server_id = ? ORDER BY name"#,
+    )
+        .bind(server_id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(Some(CachedPackageInventory {
+        server_id: server_id.to_string(),
+        manager,
+        refreshed_at,
+        packages,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::{insert_server, pool};
+
+    async fn search(pool: &SqlitePool, name: &str, version: Option<&str>) -> Vec<String> {
+        let query = SearchQuery { name: name.to_string(), version: version.map(str::to_string) };
+        let response = search_packages(State(pool.clone()), Query(query)).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice::<Vec<PackageSearchResult>>(&body)
+            .unwrap()
+            .into_iter()
+            .map(|row| row.server_id)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn version_search_matches_prefix_literally() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = pool(&dir).await;
+        for (server_id, version) in [("a", "3.0.2-0ubuntu1.10"), ("b", "3.0.13-1"), ("c", "3_0%x")] {
+            insert_server(&pool, server_id, "127.0.0.1", &[]).await;
+            sqlx::query(r#"INSERT INTO server_packages (server_id, name, version) VALUES (?, 'openssl', ?)"#)
+                .bind(server_id)
+                .bind(version)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(search(&pool, "openssl", None).await, ["a", "b", "c"]);
+        assert_eq!(search(&pool, "openssl", Some("3.0.2")).await, ["a"]);
+        assert_eq!(search(&pool, "openssl", Some("3.0.1")).await, ["b"]);
+        // `%` や `_` はワイルドカードとして扱わない
+        assert!(search(&pool, "openssl", Some("3.0%")).await.is_empty());
+        assert!(search(&pool, "openssl", Some("3_0.")).await.is_empty());
+        assert_eq!(search(&pool, "openssl", Some("3_0%")).await, ["c"]);
+        assert!(search(&pool, "libssl3", None).await.is_empty());
+    }
+}
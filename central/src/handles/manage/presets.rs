@@ -0,0 +1,378 @@
+use crate::{
+    handles::manage::command_jobs::{CreateCommandJobRequest, launch_job},
+    utils::{audit::Actor, jobs::JobHub, ssh::SshContext},
+};
+use common::central::preset::{CommandPreset, PresetParameter};
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct PresetRequest {
+    name: String,
+    description: Option<String>,
+    command: String,
+    timeout_secs: Option<u64>,
+    tag: Option<String>,
+    #[serde(default)]
+    parameters: Vec<PresetParameter>,
+}
+
+#[derive(Deserialize)]
+pub struct RunPresetRequest {
+    #[serde(default)]
+    server_ids: Vec<String>,
+    tag: Option<String>,
+    #[serde(default)]
+    params: HashMap<String, String>,
+    timeout_secs: Option<u64>,
+    concurrency: Option<usize>,
+}
+
+pub async fn get_presets(State(pool): State<SqlitePool>) -> impl IntoResponse {
+    match sqlx::query_as::<_, CommandPreset>(
+        r#"SELECT id, name, description, command, timeout_secs, tag, parameters, created_at, updated_at FROM command_presets ORDER BY name"#,
+    )
+        .fetch_all(&pool)
+        .await
+    {
+        Ok(rows) => (StatusCode::OK, Json(rows)).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to fetch command presets: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn get_preset(
+    State(pool): State<SqlitePool>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match find_preset(&pool, &id).await {
+        Ok(preset) => (StatusCode::OK, Json(preset)).into_response(),
+        Err(status) => status.into_response(),
+    }
+}
+
+pub async fn create_preset(
+    State(pool): State<SqlitePool>,
+    Json(json): Json<PresetRequest>,
+) -> impl IntoResponse {
+    if let Err(e) = validate(&json) {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": e}))).into_response();
+    }
+
+    let id = Uuid::new_v4().to_string();
+    let now = Utc::now();
+    let result = sqlx::query(
+        r#"INSERT INTO command_presets (id, name, description, command, timeout_secs, tag, parameters, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
+    )
+        .bind(&id)
+        .bind(json.name.trim())
+        .bind(&json.description)
+        .bind(&json.command)
+        .bind(json.timeout_secs.map(|t| t as i64))
+        .bind(&json.tag)
+        .bind(serde_json::to_string(&json.parameters).unwrap_or_else(|_| "[]".to_string()))
+        .bind(now)
+        .bind(now)
+        .execute(&pool)
+        .await;
+
+    match result {
+        Ok(_) => match find_preset(&pool, &id).await {
+            Ok(preset) => (StatusCode::CREATED, Json(preset)).into_response(),
+            Err(status) => status.into_response(),
+        },
+        Err(e) => save_error_response(e),
+    }
+}
+
+pub async fn update_preset(
+    State(pool): State<SqlitePool>,
+    Path(id): Path<String>,
+    Json(json): Json<PresetRequest>,
+) -> impl IntoResponse {
+    if let Err(e) = validate(&json) {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": e}))).into_response();
+    }
+
+    let result = sqlx::query(
+        r#"UPDATE command_presets SET name=?, description=?, command=?, timeout_secs=?, tag=?, parameters=?, updated_at=? WHERE id=?"#,
+    )
+        .bind(json.name.trim())
+        .bind(&json.description)
+        .bind(&json.command)
+        .bind(json.timeout_secs.map(|t| t as i64))
+        .bind(&json.tag)
+        .bind(serde_json::to_string(&json.parameters).unwrap_or_else(|_| "[]".to_string()))
+        .bind(Utc::now())
+        .bind(&id)
+        .execute(&pool)
+        .await;
+
+    match result {
+        Ok(result) if result.rows_affected() == 0 => StatusCode::NOT_FOUND.into_response(),
+        Ok(_) => match find_preset(&pool, &id).await {
+            Ok(preset) => (StatusCode::OK, Json(preset)).into_response(),
+            Err(status) => status.into_response(),
+        },
+        Err(e) => save_error_response(e),
+    }
+}
+
+pub async fn delete_preset(
+    State(pool): State<SqlitePool>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let result = sqlx::query(r#"DELETE FROM command_presets WHERE id=?"#)
+        .bind(id)
+        .execute(&pool)
+        .await;
+
+    match result {
+        Ok(result) if result.rows_affected() == 0 => StatusCode::NOT_FOUND.into_response(),
+        Ok(_) => StatusCode::OK.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to delete command preset: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response()
+        }
+    }
+}
+
+/// パラメーターを埋め込んだコマンドをコマンドジョブとして実行する。
+/// 対象を指定しなければプリセットのタグに当てはまるサーバーで実行する
+pub async fn run_preset(
+    State(pool): State<SqlitePool>,
+    State(hub): State<Arc<JobHub>>,
+    State(ssh): State<Arc<SshContext>>,
+    Path(id): Path<String>,
+    Actor(actor): Actor,
+    Json(json): Json<RunPresetRequest>,
+) -> impl IntoResponse {
+    let preset = match find_preset(&pool, &id).await {
+        Ok(preset) => preset,
+        Err(status) => return status.into_response(),
+    };
+    let parameters = serde_json::from_str::<Vec<PresetParameter>>(&preset.parameters).unwrap_or_default();
+
+    if let Some(unknown) = json.params.keys().find(|name| !parameters.iter().any(|p| &p.name == *name)) {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": format!("unknown parameter: {}", unknown)}))).into_response();
+    }
+    let mut values = HashMap::new();
+    for parameter in &parameters {
+        let Some(value) = json.params.get(&parameter.name).or(parameter.default.as_ref()) else {
+            return (StatusCode::BAD_REQUEST, Json(json!({"error": format!("missing parameter: {}", parameter.name)}))).into_response();
+        };
+        if value.contains('\0') {
+            return (StatusCode::BAD_REQUEST, Json(json!({"error": format!("invalid value for parameter: {}", parameter.name)}))).into_response();
+        }
+        values.insert(parameter.name.clone(), value.clone());
+    }
+
+    let targeted = !json.server_ids.is_empty() || json.tag.is_some();
+    let request = CreateCommandJobRequest {
+        command: substitute(&preset.command, &values),
+        server_ids: json.server_ids,
+        tag: if targeted { json.tag } else { preset.tag },
+        timeout_secs: json.timeout_secs.or(preset.timeout_secs),
+        concurrency: json.concurrency,
+    };
+    launch_job(pool, hub, ssh, &actor, request).await
+}
+
+async fn find_preset(pool: &SqlitePool, id: &str) -> Result<CommandPreset, StatusCode> {
+    sqlx::query_as::<_, CommandPreset>(
+        r#"SELECT id, name, description, command, timeout_secs, tag, parameters, created_at, updated_at FROM command_presets WHERE id = ?"#,
+    )
+        .bind(id)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
+            e => {
+                tracing::error!("Failed to fetch command preset: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        })
+}
+
+fn save_error_response(e: sqlx::Error) -> Response {
+    if e.as_database_error().is_some_and(|e| e.is_unique_violation()) {
+        return (StatusCode::CONFLICT, Json(json!({"error": "a preset with this name already exists"}))).into_response();
+    }
+    tracing::error!("Failed to save command preset: {}", e);
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response()
+}
+
+/// コマンド中のプレースホルダーと宣言されたパラメーターが過不足なく対応しているかを確認する
+fn validate(json: &PresetRequest) -> Result<(), String> {
+    if json.name.trim().is_empty() {
+        return Err("name is required".to_string());
+    }
+    if json.command.trim().is_empty() {
+        return Err("command is required".to_string());
+    }
+    if json.timeout_secs == Some(0) {
+        return Err("timeout_secs must be greater than 0".to_string());
+    }
+
+    let placeholders = placeholders(&json.command)?;
+    for (i, parameter) in json.parameters.iter().enumerate() {
+        if !is_valid_name(&parameter.name) {
+            return Err(format!("invalid parameter name: {}", parameter.name));
+        }
+        if json.parameters[..i].iter().any(|p| p.name == parameter.name) {
+            return Err(format!("duplicate parameter: {}", parameter.name));
+        }
+        if !placeholders.contains(&parameter.name) {
+            return Err(format!("parameter {} is not used in command", parameter.name));
+        }
+    }
+    if let Some(undeclared) = placeholders.iter().find(|name| !json.parameters.iter().any(|p| &p.name == *name)) {
+        return Err(format!("placeholder {{{{{}}}}} is not declared in parameters", undeclared));
+    }
+    Ok(())
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// `{{name}}` の名前を出現順（重複なし）に返す。
+/// 値は別の引数として渡すので、引用符の中に置かれたプレースホルダーは受け付けない
+fn placeholders(command: &str) -> Result<Vec<String>, String> {
+    let mut names = Vec::new();
+    let mut quote = None;
+    let mut escaped = false;
+    let mut skip_to = 0;
+    for (i, c) in command.char_indices() {
+        if i < skip_to {
+            continue;
+        }
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match (quote, c) {
+            (_, '{') if command[i..].starts_with("{{") => {
+                let after = &command[i + 2..];
+                let Some(end) = after.find("}}") else {
+                    return Err("unclosed placeholder in command".to_string());
+                };
+                let name = after[..end].trim();
+                if !is_valid_name(name) {
+                    return Err(format!("invalid placeholder name: {}", name));
+                }
+                if quote.is_some() {
+                    return Err(format!("placeholder {{{{{}}}}} must not be inside quotes", name));
+                }
+                if !names.iter().any(|n| n == name) {
+                    names.push(name.to_string());
+                }
+                skip_to = i + 2 + end + 2;
+            }
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => escaped = true,
+            (None, '\'' | '"') => quote = Some(c),
+            _ => {}
+        }
+    }
+    Ok(names)
+}
+
+/// コマンドに値を埋め込まず、`sh -c '<コマンド>' sh <値>...` の形にして位置パラメーターとして渡す。
+/// プレースホルダーは `"$1"` のような参照に置き換える
+fn substitute(command: &str, values: &HashMap<String, String>) -> String {
+    let names = placeholders(command).unwrap_or_default();
+    let mut script = String::with_capacity(command.len());
+    let mut rest = command;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            break;
+        };
+        script.push_str(&rest[..start]);
+        match names.iter().position(|n| n == after[..end].trim()) {
+            Some(index) => script.push_str(&format!("\"${{{}}}\"", index + 1)),
+            None => script.push_str(&rest[start..start + 2 + end + 2]),
+        }
+        rest = &after[end + 2..];
+    }
+    script.push_str(rest);
+
+    let mut result = format!("sh -c {} sh", quote(&script));
+    for name in &names {
+        result.push(' ');
+        result.push_str(&quote(values.get(name).map(String::as_str).unwrap_or_default()));
+    }
+    result
+}
+
+/// シェルの1語になるよう単一引用符で囲む
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn placeholders_are_listed_once_in_order() {
+        assert_eq!(placeholders("cp {{src}} {{ dst }} && ls {{src}}").unwrap(), vec!["src", "dst"]);
+        assert!(placeholders("echo hello").unwrap().is_empty());
+    }
+
+    #[test]
+    fn placeholders_reject_invalid_names() {
+        assert!(placeholders("echo {{bad name}}").is_err());
+        assert!(placeholders("echo {{}}").is_err());
+        assert!(placeholders("echo {{open").is_err());
+    }
+
+    #[test]
+    fn placeholders_reject_quoted_use() {
+        assert!(placeholders("echo '{{msg}}'").is_err());
+        assert!(placeholders("echo \"value: {{msg}}\"").is_err());
+        assert!(placeholders("echo 'it''s' {{msg}}").is_ok());
+        assert!(placeholders(r#"echo \" {{msg}} \""#).is_ok());
+        assert!(placeholders(r#"echo "a\"b" {{msg}}"#).is_ok());
+    }
+
+    #[test]
+    fn substitute_passes_values_as_arguments() {
+        let command = substitute("echo {{msg}} {{n}} {{msg}}", &values(&[("msg", "hi"), ("n", "2")]));
+        assert_eq!(command, r#"sh -c 'echo "${1}" "${2}" "${1}"' sh 'hi' '2'"#);
+    }
+
+    #[test]
+    fn substitute_quotes_hostile_values() {
+        let command = substitute("echo {{msg}}", &values(&[("msg", "'; rm -rf / '")]));
+        assert_eq!(command, r#"sh -c 'echo "${1}"' sh ''\''; rm -rf / '\'''"#);
+
+        let output = std::process::Command::new("/bin/sh").arg("-c").arg(&command).output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "'; rm -rf / '\n");
+    }
+
+    #[test]
+    fn substitute_escapes_quotes_in_the_command() {
+        let command = substitute("printf '%s\\n' {{msg}}", &values(&[("msg", "a b")]));
+        let output = std::process::Command::new("/bin/sh").arg("-c").arg(&command).output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "a b\n");
+    }
+}
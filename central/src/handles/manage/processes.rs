@@ -0,0 +1,82 @@
+use crate::utils::{
+    agent::{agent_url, http_client, relay_response},
+    audit::{Actor, AuditEntry, record},
+    bastion::find_agent,
+    ssh::SshContext,
+};
+use common::agent::process::SignalRequest;
+use std::sync::Arc;
+
+use axum::{
+    extract::{Json, Path, RawQuery, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use serde_json::json;
+use sqlx::SqlitePool;
+
+pub async fn get_server_processes(
+    State(pool): State<SqlitePool>,
+    State(ssh): State<Arc<SshContext>>,
+    Path(server_uuid): Path<String>,
+    RawQuery(query): RawQuery,
+) -> impl IntoResponse {
+    let server = match find_agent(&pool, &ssh, &server_uuid).await {
+        Ok(server) => server,
+        Err(status) => return status.into_response(),
+    };
+    let http_client = match http_client() {
+        Ok(client) => client,
+        Err(status) => return status.into_response(),
+    };
+
+    let mut req_address = agent_url(&server, "/processes");
+    if let Some(query) = query {
+        req_address = format!("{}?{}", req_address, query);
+    }
+
+    match http_client.get(req_address).send().await {
+        Ok(res) => relay_response(res).await,
+        Err(e) => {
+            tracing::error!("Failed to fetch server processes: {}", e);
+            StatusCode::BAD_GATEWAY.into_response()
+        }
+    }
+}
+
+pub async fn signal_server_process(
+    State(pool): State<SqlitePool>,
+    State(ssh): State<Arc<SshContext>>,
+    Path((server_uuid, pid)): Path<(String, u32)>,
+    Actor(actor): Actor,
+    Json(json): Json<SignalRequest>,
+) -> impl IntoResponse {
+    let server = match find_agent(&pool, &ssh, &server_uuid).await {
+        Ok(server) => server,
+        Err(status) => return status.into_response(),
+    };
+    let http_client = match http_client() {
+        Ok(client) => client,
+        Err(status) => return status.into_response(),
+    };
+
+    let req_address = agent_url(&server, &format!("/processes/{}/signal", pid));
+    let response = match http_client.post(req_address).json(&json).send().await {
+        Ok(res) => relay_response(res).await,
+        Err(e) => {
+            tracing::error!("Failed to signal server process: {}", e);
+            StatusCode::BAD_GATEWAY.into_response()
+        }
+    };
+
+    record(&pool, AuditEntry {
+        server_id: &server.id,
+        actor: &actor,
+        action: "process.signal",
+        target: &pid.to_string(),
+        detail: Some(json!({"signal": json.signal, "start_ticks": json.start_ticks})),
+        status_code: response.status(),
+    }).await;
+
+    response
+}
@@ -0,0 +1,108 @@
+use crate::utils::{
+    agent::{agent_url, http_client, relay_response},
+    audit::{Actor, AuditEntry, record},
+    bastion::find_agent,
+    ssh::SshContext,
+};
+use common::agent::service::ServiceActionRequest;
+use std::sync::Arc;
+
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use serde_json::json;
+use sqlx::SqlitePool;
+
+pub async fn get_server_services(
+    State(pool): State<SqlitePool>,
+    State(ssh): State<Arc<SshContext>>,
+    Path(server_uuid): Path<String>,
+) -> impl IntoResponse {
+    let server = match find_agent(&pool, &ssh, &server_uuid).await {
+        Ok(server) => server,
+        Err(status) => return status.into_response(),
+    };
+    let http_client = match http_client() {
+        Ok(client) => client,
+        Err(status) => return status.into_response(),
+    };
+
+    match http_client.get(agent_url(&server, "/services")).send().await {
+        Ok(res) => relay_response(res).await,
+        Err(e) => {
+            tracing::error!("Failed to fetch server services: {}", e);
+            StatusCode::BAD_GATEWAY.into_response()
+        }
+    }
+}
+
+pub async fn get_server_service(
+    State(pool): State<SqlitePool>,
+    State(ssh): State<Arc<SshContext>>,
+    Path((server_uuid, name)): Path<(String, String)>,
+) -> impl IntoResponse {
+    // エージェントの別のエンドポイントを指せないようにする
+    if name.contains('/') {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "invalid unit name"}))).into_response();
+    }
+    let server = match find_agent(&pool, &ssh, &server_uuid).await {
+        Ok(server) => server,
+        Err(status) => return status.into_response(),
+    };
+    let http_client = match http_client() {
+        Ok(client) => client,
+        Err(status) => return status.into_response(),
+    };
+
+    let req_address = agent_url(&server, &format!("/services/{}", name));
+    match http_client.get(req_address).send().await {
+        Ok(res) => relay_response(res).await,
+        Err(e) => {
+            tracing::error!("Failed to fetch server service: {}", e);
+            StatusCode::BAD_GATEWAY.into_response()
+        }
+    }
+}
+
+pub async fn run_server_service_action(
+    State(pool): State<SqlitePool>,
+    State(ssh): State<Arc<SshContext>>,
+    Path((server_uuid, name)): Path<(String, String)>,
+    Actor(actor): Actor,
+    Json(json): Json<ServiceActionRequest>,
+) -> impl IntoResponse {
+    // エージェントの別のエンドポイントを指せないようにする
+    if name.contains('/') {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "invalid unit name"}))).into_response();
+    }
+    let server = match find_agent(&pool, &ssh, &server_uuid).await {
+        Ok(server) => server,
+        Err(status) => return status.into_response(),
+    };
+    let http_client = match http_client() {
+        Ok(client) => client,
+        Err(status) => return status.into_response(),
+    };
+
+    let req_address = agent_url(&server, &format!("/services/{}/action", name));
+    let response = match http_client.post(req_address).json(&json).send().await {
+        Ok(res) => relay_response(res).await,
+        Err(e) => {
+            tracing::error!("Failed to run service action: {}", e);
+            StatusCode::BAD_GATEWAY.into_response()
+        }
+    };
+
+    record(&pool, AuditEntry {
+        server_id: &server.id,
+        actor: &actor,
+        action: &format!("service.{}", json.action.as_str()),
+        target: &name,
+        detail: None,
+        status_code: response.status(),
+    }).await;
+
+    response
+}
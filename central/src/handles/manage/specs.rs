@@ -1,4 +1,6 @@
+use crate::utils::{bastion::route_agent, ssh::SshContext};
 use common::central::information::ServerInformation;
+use std::sync::Arc;
 
 use axum::{
     extract::{Path, State},
@@ -11,16 +13,21 @@ use sqlx::SqlitePool;
 
 pub async fn get_server_specs(
     State(pool): State<SqlitePool>,
+    State(ssh): State<Arc<SshContext>>,
     Path(server_uuid): Path<String>,
 ) -> impl IntoResponse {
     match sqlx::query_as::<_, ServerInformation>(
-        r#"SELECT id, hostname, ip_address, os_type, tags, auth_profile_id, port, bastion_server_id, wol_mac_address FROM servers WHERE id = ?"#,
+        r#"SELECT id, hostname, ip_address, os_type, tags, auth_profile_id, port, bastion_server_id, wol_mac_address, wol_relay_server_id FROM servers WHERE id = ?"#,
     )
         .bind(server_uuid)
         .fetch_one(&pool)
         .await
     {
         Ok(row) => {
+            let row = match route_agent(&pool, &ssh, row) {
+                Ok(row) => row,
+                Err(status) => return status.into_response(),
+            };
             let http_client = HttpClient::builder()
                 .timeout(std::time::Duration::from_secs(10))
                 .build()
@@ -0,0 +1,314 @@
+use crate::{
+    ssh::{ChannelEvent, ChannelInput, ChannelRequest, SshChannel},
+    utils::{
+        agent::find_server,
+        audit::{Actor, AuditEntry, record},
+        ssh::{SshContext, connect_server, ssh_error_response},
+    },
+};
+use common::agent::{exec::{ExecEvent, ExecRequest}, file::FileQuery};
+use std::{convert::Infallible, io, sync::Arc, time::Duration};
+
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Json, Response},
+};
+use futures::{StreamExt, stream};
+use serde_json::json;
+use sqlx::SqlitePool;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 60;
+const MAX_TIMEOUT_SECS: u64 = 3600;
+
+/// エージェントの `/exec` と同じ NDJSON 形式で、SSH で実行したコマンドの出力を返す
+pub async fn run_server_ssh_command(
+    State(pool): State<SqlitePool>,
+    State(context): State<Arc<SshContext>>,
+    Path(server_uuid): Path<String>,
+    Actor(actor): Actor,
+    Json(json): Json<ExecRequest>,
+) -> impl IntoResponse {
+    if json.command.trim().is_empty() {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "command is required"}))).into_response();
+    }
+    let timeout = json.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS).clamp(1, MAX_TIMEOUT_SECS);
+
+    let (server, channel) = match open_channel(&pool, &context, &server_uuid, ChannelRequest::Exec(json.command.clone())).await {
+        Ok(opened) => opened,
+        Err(response) => return response,
+    };
+    record(&pool, AuditEntry {
+        server_id: &server,
+        actor: &actor,
+        action: "ssh.exec",
+        target: &json.command,
+        detail: Some(json!({"timeout_secs": timeout})),
+        status_code: StatusCode::OK,
+    }).await;
+
+    let (tx, rx) = mpsc::channel::<ExecEvent>(64);
+    tokio::spawn(forward_exec(channel, tx, Duration::from_secs(timeout)));
+
+    let stream = ReceiverStream::new(rx).map(|event| {
+        let mut line = serde_json::to_string(&event).unwrap_or_default();
+        line.push('\n');
+        Ok::<_, Infallible>(line)
+    });
+    ([(header::CONTENT_TYPE, "application/x-ndjson")], Body::from_stream(stream)).into_response()
+}
+
+/// 出力を行に分けて送る。制限時間を過ぎたらチャンネルを閉じる（リモートのプロセスには SIGHUP が届かないことがある）
+async fn forward_exec(mut channel: SshChannel, tx: mpsc::Sender<ExecEvent>, timeout: Duration) {
+    let _ = channel.input.send(ChannelInput::Eof).await;
+    let mut stdout = Lines::default();
+    let mut stderr = Lines::default();
+    let mut code = None;
+
+    let finished = tokio::time::timeout(timeout, async {
+        while let Some(event) = channel.events.recv().await {
+            match event {
+                ChannelEvent::Stdout(data) => {
+                    for line in stdout.push(&data) {
+                        let _ = tx.send(ExecEvent::Stdout { line }).await;
+                    }
+                }
+                ChannelEvent::Stderr(data) => {
+                    for line in stderr.push(&data) {
+                        let _ = tx.send(ExecEvent::Stderr { line }).await;
+                    }
+                }
+                ChannelEvent::Exit(exit) => code = exit,
+                ChannelEvent::Error(message) => {
+                    let _ = tx.send(ExecEvent::Error { message }).await;
+                }
+            }
+        }
+    })
+    .await;
+
+    if let Some(line) = stdout.rest() {
+        let _ = tx.send(ExecEvent::Stdout { line }).await;
+    }
+    if let Some(line) = stderr.rest() {
+        let _ = tx.send(ExecEvent::Stderr { line }).await;
+    }
+    if finished.is_err() {
+        let _ = tx.send(ExecEvent::Error { message: format!("timed out after {} seconds", timeout.as_secs()) }).await;
+        code = None;
+    }
+    let _ = tx.send(ExecEvent::Exit { code }).await;
+}
+
+/// 受け取ったバイト列を行に分ける。行末の `\r` は取り除く
+#[derive(Default)]
+struct Lines(Vec<u8>);
+
+impl Lines {
+    fn push(&mut self, data: &[u8]) -> Vec<String> {
+        self.0.extend_from_slice(data);
+        let mut lines = Vec::new();
+        while let Some(end) = self.0.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.0.drain(..=end).collect();
+            lines.push(String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string());
+        }
+        lines
+    }
+
+    fn rest(&mut self) -> Option<String> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let rest = std::mem::take(&mut self.0);
+        Some(String::from_utf8_lossy(&rest).trim_end_matches('\r').to_string())
+    }
+}
+
+/// エージェントのないサーバーからファイルを取得する
+pub async fn download_server_ssh_file(
+    State(pool): State<SqlitePool>,
+    State(context): State<Arc<SshContext>>,
+    Path(server_uuid): Path<String>,
+    Actor(actor): Actor,
+    Query(query): Query<FileQuery>,
+) -> impl IntoResponse {
+    if !query.path.starts_with('/') {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "path must be absolute"}))).into_response();
+    }
+
+    let command = format!("cat -- {}", quote(&query.path));
+    let (server, mut channel) = match open_channel(&pool, &context, &server_uuid, ChannelRequest::Exec(command)).await {
+        Ok(opened) => opened,
+        Err(response) => return response,
+    };
+    let _ = channel.input.send(ChannelInput::Eof).await;
+
+    // 最初の出力が届くまでは、失敗をステータスコードで返せる
+    let mut errors = Vec::new();
+    let first = loop {
+        match channel.events.recv().await {
+            Some(ChannelEvent::Stdout(data)) => break Ok(Some(data)),
+            Some(ChannelEvent::Stderr(data)) => errors.extend_from_slice(&data),
+            Some(ChannelEvent::Exit(Some(0))) => break Ok(None),
+            Some(ChannelEvent::Exit(_)) => break Err(command_error(&errors)),
+            Some(ChannelEvent::Error(message)) => break Err((StatusCode::BAD_GATEWAY, message)),
+            None => break Err((StatusCode::BAD_GATEWAY, "ssh channel closed unexpectedly".to_string())),
+        }
+    };
+
+    let response = match first {
+        Ok(first) => {
+            let rest = stream::unfold(channel, |mut channel| async move {
+                loop {
+                    match channel.events.recv().await? {
+                        ChannelEvent::Stdout(data) => return Some((Ok(data), channel)),
+                        ChannelEvent::Stderr(_) | ChannelEvent::Exit(Some(0)) => continue,
+                        // 途中で失敗したら接続を切って、ダウンロードが不完全なことを伝える
+                        ChannelEvent::Exit(_) => return Some((Err(io::Error::other("remote command failed")), channel)),
+                        ChannelEvent::Error(message) => return Some((Err(io::Error::other(message)), channel)),
+                    }
+                }
+            });
+            let body = stream::iter(first.map(Ok)).chain(rest);
+            (
+                [
+                    (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+                    (header::CONTENT_DISPOSITION, content_disposition(&query.path)),
+                ],
+                Body::from_stream(body),
+            )
+                .into_response()
+        }
+        Err((status, message)) => (status, Json(json!({"error": message}))).into_response(),
+    };
+
+    record(&pool, AuditEntry {
+        server_id: &server,
+        actor: &actor,
+        action: "ssh.file.download",
+        target: &query.path,
+        detail: None,
+        status_code: response.status(),
+    }).await;
+
+    response
+}
+
+/// エージェントのないサーバーへファイルを書き込む。
+/// 一時ファイルに受け取り、大きさが `Content-Length` と一致したときだけ置き換える
+pub async fn upload_server_ssh_file(
+    State(pool): State<SqlitePool>,
+    State(context): State<Arc<SshContext>>,
+    Path(server_uuid): Path<String>,
+    Actor(actor): Actor,
+    Query(query): Query<FileQuery>,
+    headers: HeaderMap,
+    body: Body,
+) -> impl IntoResponse {
+    if !query.path.starts_with('/') || query.path.ends_with('/') {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "path must be an absolute file path"}))).into_response();
+    }
+    let Some(size) = headers.get(header::CONTENT_LENGTH).and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<u64>().ok()) else {
+        return (StatusCode::LENGTH_REQUIRED, Json(json!({"error": "content-length is required"}))).into_response();
+    };
+
+    let path = quote(&query.path);
+    let command = format!(
+        "tmp=$(mktemp -- {path}.XXXXXX) || exit 1; \
+         if cat > \"$tmp\" && [ \"$(wc -c < \"$tmp\")\" -eq {size} ]; then \
+         chmod --reference={path} \"$tmp\" 2>/dev/null; mv -f -- \"$tmp\" {path}; \
+         else rm -f -- \"$tmp\"; echo 'upload was incomplete' >&2; exit 1; fi"
+    );
+    let (server, mut channel) = match open_channel(&pool, &context, &server_uuid, ChannelRequest::Exec(command)).await {
+        Ok(opened) => opened,
+        Err(response) => return response,
+    };
+
+    let mut data = body.into_data_stream();
+    let mut sent = 0u64;
+    while let Some(chunk) = data.next().await {
+        let Ok(chunk) = chunk else { break };
+        sent += chunk.len() as u64;
+        if channel.input.send(ChannelInput::Data(chunk.to_vec())).await.is_err() {
+            break;
+        }
+    }
+    let _ = channel.input.send(ChannelInput::Eof).await;
+
+    let mut errors = Vec::new();
+    let mut result = Err((StatusCode::BAD_GATEWAY, "ssh channel closed unexpectedly".to_string()));
+    while let Some(event) = channel.events.recv().await {
+        match event {
+            ChannelEvent::Stderr(data) => errors.extend_from_slice(&data),
+            ChannelEvent::Exit(Some(0)) => result = Ok(()),
+            ChannelEvent::Exit(_) => result = Err(command_error(&errors)),
+            ChannelEvent::Error(message) => result = Err((StatusCode::BAD_GATEWAY, message)),
+            ChannelEvent::Stdout(_) => {}
+        }
+    }
+
+    let response = match result {
+        Ok(()) => (StatusCode::OK, Json(json!({"path": query.path, "size": sent}))).into_response(),
+        Err((status, message)) => (status, Json(json!({"error": message}))).into_response(),
+    };
+
+    record(&pool, AuditEntry {
+        server_id: &server,
+        actor: &actor,
+        action: "ssh.file.upload",
+        target: &query.path,
+        detail: Some(json!({"size": sent})),
+        status_code: response.status(),
+    }).await;
+
+    response
+}
+
+/// 端末の中継からも使う。成功したらサーバーの ID と開いたチャンネルを返す
+pub async fn open_channel(
+    pool: &SqlitePool,
+    context: &SshContext,
+    server_uuid: &str,
+    request: ChannelRequest,
+) -> Result<(String, SshChannel), Response> {
+    let server = find_server(pool, server_uuid).await.map_err(|status| status.into_response())?;
+    let client = connect_server(pool, context, &server).await?;
+    let channel = client.open(request).await.map_err(|e| {
+        tracing::error!("Failed to open SSH channel to {}: {}", server.hostname, e);
+        ssh_error_response(&e)
+    })?;
+    Ok((server.id, channel))
+}
+
+/// リモートのコマンドが失敗したときの標準エラー出力を、分かる範囲でステータスコードにする
+fn command_error(stderr: &[u8]) -> (StatusCode, String) {
+    let message = String::from_utf8_lossy(stderr).trim().to_string();
+    let status = if message.contains("No such file or directory") {
+        StatusCode::NOT_FOUND
+    } else if message.contains("Permission denied") {
+        StatusCode::FORBIDDEN
+    } else if message.contains("Is a directory") {
+        StatusCode::CONFLICT
+    } else {
+        StatusCode::BAD_GATEWAY
+    };
+    let message = if message.is_empty() { "remote command failed".to_string() } else { message };
+    (status, message)
+}
+
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// RFC 6266 / RFC 5987 の形式でファイル名を渡す
+fn content_disposition(path: &str) -> String {
+    let name = path.rsplit('/').next().unwrap_or_default();
+    let encoded: String = name.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_' => (b as char).to_string(),
+        _ => format!("%{:02X}", b),
+    }).collect();
+    format!("attachment; filename*=UTF-8''{}", encoded)
+}
@@ -0,0 +1,443 @@
+use crate::{
+    app::config::TerminalConfig,
+    handles::manage::ssh::open_channel,
+    ssh::{ChannelEvent, ChannelInput, ChannelRequest, SshChannel},
+    utils::{
+        audit::{Actor, AuditEntry, record},
+        bastion::{find_agent, tunnel_credentials},
+        ssh::SshContext,
+    },
+};
+use common::{
+    agent::terminal::{TerminalClientMessage, TerminalQuery, TerminalServerMessage},
+    ws::{self, Message},
+};
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Json, Response},
+};
+use hyper_util::rt::TokioIo;
+use serde_json::json;
+use sqlx::SqlitePool;
+use tokio::{
+    fs::{self, File},
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter},
+    net::TcpStream,
+    sync::mpsc,
+    task::JoinHandle,
+    time::{sleep_until, timeout},
+};
+use uuid::Uuid;
+
+const AGENT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_COLS: u16 = 80;
+const DEFAULT_ROWS: u16 = 24;
+/// 無操作による切断を行わないときの待ち時間
+const NO_IDLE_TIMEOUT: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// ブラウザからの WebSocket を受け付け、エージェントの端末へ中継する
+pub async fn open_server_terminal(
+    State(pool): State<SqlitePool>,
+    State(config): State<Arc<TerminalConfig>>,
+    State(ssh): State<Arc<SshContext>>,
+    Path(server_uuid): Path<String>,
+    Actor(actor): Actor,
+    Query(query): Query<TerminalQuery>,
+    request: Request,
+) -> impl IntoResponse {
+    let Some(accept) = websocket_accept(&request) else {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "websocket upgrade is required"}))).into_response();
+    };
+
+    let server = match find_agent(&pool, &ssh, &server_uuid).await {
+        Ok(server) => server,
+        Err(status) => return status.into_response(),
+    };
+
+    let (cols, rows) = terminal_size(&query);
+    let agent = match connect_agent(&server.ip_address, cols, rows).await {
+        Ok(agent) => agent,
+        Err(e) => {
+            tracing::error!("Failed to open agent terminal: {}", e);
+            return (StatusCode::BAD_GATEWAY, Json(json!({"error": e.to_string()}))).into_response();
+        }
+    };
+
+    let session = Session { pool, server_id: server.id, actor, transport: "agent" };
+    session.start(&config, request, accept, Backend::agent(agent), cols, rows).await
+}
+
+/// エージェントのないサーバーでは、SSH のシェルへ中継する。メッセージの形式はエージェントの端末と同じ
+pub async fn open_server_ssh_terminal(
+    State(pool): State<SqlitePool>,
+    State(config): State<Arc<TerminalConfig>>,
+    State(ssh): State<Arc<SshContext>>,
+    Path(server_uuid): Path<String>,
+    Actor(actor): Actor,
+    Query(query): Query<TerminalQuery>,
+    request: Request,
+) -> impl IntoResponse {
+    let Some(accept) = websocket_accept(&request) else {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "websocket upgrade is required"}))).into_response();
+    };
+
+    let (cols, rows) = terminal_size(&query);
+    let (server_id, channel) = match open_channel(&pool, &ssh, &server_uuid, ChannelRequest::Shell { cols, rows }).await {
+        Ok(opened) => opened,
+        Err(response) => return response,
+    };
+
+    let session = Session { pool, server_id, actor, transport: "ssh" };
+    session.start(&config, request, accept, Backend::ssh(channel), cols, rows).await
+}
+
+fn websocket_accept(request: &Request) -> Option<String> {
+    let headers = request.headers();
+    ws::server_accept(|name| headers.get(name).and_then(|v| v.to_str().ok()).map(|v| v.to_string()))
+}
+
+fn terminal_size(query: &TerminalQuery) -> (u16, u16) {
+    (
+        query.cols.filter(|cols| *cols > 0).unwrap_or(DEFAULT_COLS),
+        query.rows.filter(|rows| *rows > 0).unwrap_or(DEFAULT_ROWS),
+    )
+}
+
+async fn connect_agent(address: &str, cols: u16, rows: u16) -> std::io::Result<BufReader<TcpStream>> {
+    let (address, authorization) = tunnel_credentials(address);
+    let stream = timeout(AGENT_CONNECT_TIMEOUT, TcpStream::connect(address))
+        .await
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "agent connection timed out"))??;
+    let mut agent = BufReader::new(stream);
+    let path = format!("/api/agent/v1/terminal?cols={}&rows={}", cols, rows);
+    timeout(AGENT_CONNECT_TIMEOUT, ws::client_handshake(&mut agent, address, &path, authorization.as_deref()))
+        .await
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "agent handshake timed out"))??;
+    Ok(agent)
+}
+
+/// 片側の接続からメッセージを読み続ける。読み取りが終わると送信側が破棄される。
+/// ブラウザからのフレームはマスクされ、エージェントからのフレームはマスクされない
+fn spawn_reader<R>(reader: R, masked: bool) -> (mpsc::Receiver<Message>, JoinHandle<()>)
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(64);
+    let handle = tokio::spawn(async move {
+        let mut reader = ws::MessageReader::new(reader, masked);
+        loop {
+            match reader.read_message().await {
+                Ok(Some(message)) => {
+                    if tx.send(message).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    tracing::debug!("Terminal connection closed: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+    (rx, handle)
+}
+
+/// 端末の接続先。どちらもブラウザとやり取りするのと同じ WebSocket のメッセージに揃える
+struct Backend {
+    messages: mpsc::Receiver<Message>,
+    sender: mpsc::Sender<Message>,
+    reader: JoinHandle<()>,
+}
+
+impl Backend {
+    fn agent(agent: BufReader<TcpStream>) -> Self {
+        let (reader, mut writer) = tokio::io::split(agent);
+        let (messages, reader) = spawn_reader(reader, false);
+        let (sender, mut outgoing) = mpsc::channel::<Message>(64);
+        tokio::spawn(async move {
+            while let Some(message) = outgoing.recv().await {
+                if ws::write_message(&mut writer, &message, true).await.is_err() {
+                    break;
+                }
+            }
+            let _ = writer.shutdown().await;
+        });
+        Self { messages, sender, reader }
+    }
+
+    fn ssh(channel: SshChannel) -> Self {
+        let SshChannel { input: channel_input, mut events } = channel;
+        let (tx, messages) = mpsc::channel(64);
+        let reader = tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                let message = match event {
+                    ChannelEvent::Stdout(data) | ChannelEvent::Stderr(data) => Message::Binary(data),
+                    ChannelEvent::Exit(code) => {
+                        Message::Text(serde_json::to_string(&TerminalServerMessage::Exit { code }).unwrap_or_default())
+                    }
+                    ChannelEvent::Error(message) => {
+                        tracing::error!("SSH terminal failed: {}", message);
+                        continue;
+                    }
+                };
+                if tx.send(message).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let (sender, mut outgoing) = mpsc::channel::<Message>(64);
+        tokio::spawn(async move {
+            while let Some(message) = outgoing.recv().await {
+                let input = match message {
+                    Message::Binary(data) => ChannelInput::Data(data),
+                    Message::Text(text) => match serde_json::from_str(&text) {
+                        Ok(TerminalClientMessage::Input { data }) => ChannelInput::Data(data.into_bytes()),
+                        Ok(TerminalClientMessage::Resize { cols, rows }) => ChannelInput::Resize { cols, rows },
+                        Err(_) => continue,
+                    },
+                    Message::Close => break,
+                    Message::Ping(_) | Message::Pong(_) => continue,
+                };
+                if channel_input.send(input).await.is_err() {
+                    break;
+                }
+            }
+            // input を破棄するとチャンネルが閉じる
+        });
+        Self { messages, sender, reader }
+    }
+
+    async fn send(&self, message: Message) -> Result<(), ()> {
+        self.sender.send(message).await.map_err(|_| ())
+    }
+}
+
+struct Session {
+    pool: SqlitePool,
+    server_id: String,
+    actor: String,
+    /// 監査ログに残す接続経路
+    transport: &'static str,
+}
+
+impl Session {
+    /// 記録の準備をして 101 を返し、アップグレード後に中継を始める
+    async fn start(self, config: &TerminalConfig, mut request: Request, accept: String, backend: Backend, cols: u16, rows: u16) -> Response {
+        let session_id = Uuid::new_v4().to_string();
+        let recorder = match &config.recording_dir {
+            Some(dir) => match Recorder::create(dir, &self.server_id, &session_id, cols, rows).await {
+                Ok(recorder) => Some(recorder),
+                // 記録が求められている環境では、記録できないセッションは開かない
+                Err(e) => {
+                    tracing::error!("Failed to create terminal recording: {}", e);
+                    return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": "failed to create terminal recording"}))).into_response();
+                }
+            },
+            None => None,
+        };
+
+        record(&self.pool, AuditEntry {
+            server_id: &self.server_id,
+            actor: &self.actor,
+            action: "terminal.open",
+            target: &session_id,
+            detail: Some(json!({
+                "transport": self.transport,
+                "cols": cols,
+                "rows": rows,
+                "recording": recorder.as_ref().map(|recorder| recorder.path.display().to_string()),
+            })),
+            status_code: StatusCode::SWITCHING_PROTOCOLS,
+        }).await;
+
+        let relay = Relay {
+            session: self,
+            session_id,
+            idle_timeout: match config.idle_timeout_secs {
+                0 => NO_IDLE_TIMEOUT,
+                secs => Duration::from_secs(secs),
+            },
+            recorder,
+        };
+        let on_upgrade = hyper::upgrade::on(&mut request);
+        tokio::spawn(async move {
+            match on_upgrade.await {
+                Ok(upgraded) => relay.run(TokioIo::new(upgraded), backend).await,
+                Err(e) => tracing::error!("Failed to upgrade terminal connection: {}", e),
+            }
+        });
+
+        (
+            StatusCode::SWITCHING_PROTOCOLS,
+            [
+                (header::UPGRADE, "websocket".to_string()),
+                (header::CONNECTION, "Upgrade".to_string()),
+                (header::SEC_WEBSOCKET_ACCEPT, accept),
+            ],
+        )
+            .into_response()
+    }
+}
+
+struct Relay {
+    session: Session,
+    session_id: String,
+    idle_timeout: Duration,
+    recorder: Option<Recorder>,
+}
+
+impl Relay {
+    async fn run<B>(mut self, browser: B, mut backend: Backend)
+    where
+        B: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let started = Instant::now();
+        let (browser_reader, mut browser_writer) = tokio::io::split(browser);
+        let (mut from_browser, browser_task) = spawn_reader(browser_reader, true);
+
+        let mut deadline = tokio::time::Instant::now() + self.idle_timeout;
+        let reason = loop {
+            tokio::select! {
+                message = from_browser.recv() => match message {
+                    Some(Message::Close) | None => {
+                        let _ = backend.send(Message::Close).await;
+                        break "client closed";
+                    }
+                    Some(message) => {
+                        match &message {
+                            Message::Binary(_) => deadline = tokio::time::Instant::now() + self.idle_timeout,
+                            Message::Text(text) => {
+                                deadline = tokio::time::Instant::now() + self.idle_timeout;
+                                if let (Some(recorder), Ok(TerminalClientMessage::Resize { cols, rows })) =
+                                    (self.recorder.as_mut(), serde_json::from_str(text))
+                                {
+                                    recorder.resize(cols, rows).await;
+                                }
+                            }
+                            _ => {}
+                        }
+                        if backend.send(message).await.is_err() {
+                            break "server disconnected";
+                        }
+                    }
+                },
+                message = backend.messages.recv() => match message {
+                    Some(Message::Close) | None => {
+                        let _ = ws::write_message(&mut browser_writer, &Message::Close, false).await;
+                        break "session ended";
+                    }
+                    Some(message) => {
+                        if let (Some(recorder), Message::Binary(data)) = (self.recorder.as_mut(), &message) {
+                            recorder.output(data).await;
+                        }
+                        if ws::write_message(&mut browser_writer, &message, false).await.is_err() {
+                            let _ = backend.send(Message::Close).await;
+                            break "client disconnected";
+                        }
+                    }
+                },
+                _ = sleep_until(deadline) => {
+                    let notice = serde_json::to_string(&TerminalServerMessage::IdleTimeout).unwrap_or_default();
+                    let _ = ws::write_message(&mut browser_writer, &Message::Text(notice), false).await;
+                    let _ = ws::write_message(&mut browser_writer, &Message::Close, false).await;
+                    let _ = backend.send(Message::Close).await;
+                    break "idle timeout";
+                }
+            }
+        };
+
+        browser_task.abort();
+        backend.reader.abort();
+        drop(backend);
+        let _ = browser_writer.shutdown().await;
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.finish().await;
+        }
+
+        let session = &self.session;
+        record(&session.pool, AuditEntry {
+            server_id: &session.server_id,
+            actor: &session.actor,
+            action: "terminal.close",
+            target: &self.session_id,
+            detail: Some(json!({"reason": reason, "duration_secs": started.elapsed().as_secs()})),
+            status_code: StatusCode::OK,
+        }).await;
+    }
+}
+
+/// asciicast v2 形式でセッションを記録する
+struct Recorder {
+    path: PathBuf,
+    file: BufWriter<File>,
+    started: Instant,
+    /// 出力の途中で切れた UTF-8 の文字
+    pending: Vec<u8>,
+}
+
+impl Recorder {
+    async fn create(dir: &str, server_id: &str, session_id: &str, cols: u16, rows: u16) -> std::io::Result<Self> {
+        let dir = PathBuf::from(dir).join(server_id);
+        fs::create_dir_all(&dir).await?;
+        let path = dir.join(format!("{}.cast", session_id));
+        let mut file = BufWriter::new(File::create(&path).await?);
+
+        let header = json!({
+            "version": 2,
+            "width": cols,
+            "height": rows,
+            "timestamp": chrono::Utc::now().timestamp(),
+            "env": {"TERM": "xterm-256color"},
+        });
+        file.write_all(format!("{}\n", header).as_bytes()).await?;
+        file.flush().await?;
+
+        Ok(Self { path, file, started: Instant::now(), pending: Vec::new() })
+    }
+
+    async fn output(&mut self, data: &[u8]) {
+        self.pending.extend_from_slice(data);
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            // 末尾の不完全な文字は次の出力と合わせて記録する
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => self.pending.len(),
+        };
+        let text = String::from_utf8_lossy(&self.pending[..valid]).into_owned();
+        self.pending.drain(..valid);
+        if !text.is_empty() {
+            self.event("o", &text).await;
+        }
+    }
+
+    async fn resize(&mut self, cols: u16, rows: u16) {
+        self.event("r", &format!("{}x{}", cols, rows)).await;
+    }
+
+    async fn event(&mut self, kind: &str, data: &str) {
+        let line = json!([self.started.elapsed().as_secs_f64(), kind, data]);
+        if let Err(e) = self.file.write_all(format!("{}\n", line).as_bytes()).await {
+            tracing::error!("Failed to write terminal recording {}: {}", self.path.display(), e);
+        }
+    }
+
+    async fn finish(&mut self) {
+        if !self.pending.is_empty() {
+            let text = String::from_utf8_lossy(&self.pending).into_owned();
+            self.pending.clear();
+            self.event("o", &text).await;
+        }
+        if let Err(e) = self.file.flush().await {
+            tracing::error!("Failed to write terminal recording {}: {}", self.path.display(), e);
+        }
+    }
+}
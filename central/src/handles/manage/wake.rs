@@ -0,0 +1,225 @@
+use crate::{
+    app::config::WolConfig,
+    handles::manage::health::probe_ssh,
+    utils::{
+        agent::{agent_url, find_server, http_client, relay_response},
+        audit::{Actor, AuditEntry, record},
+        bastion::{find_agent, route_agent},
+        ssh::SshContext,
+    },
+};
+use common::{
+    agent::wol::{WakeRequest, parse_mac_address, send_magic_packet},
+    central::wake::WakeEvent,
+};
+use std::{
+    convert::Infallible,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Json, Response},
+};
+use futures::StreamExt;
+use serde_json::json;
+use sqlx::SqlitePool;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// マジックパケットを送り、サーバーが応答するまでの様子を NDJSON で返す。
+/// `wol_relay_server_id` があればそのエージェントから、なければ central から送る
+pub async fn wake_server(
+    State(pool): State<SqlitePool>,
+    State(ssh): State<Arc<SshContext>>,
+    State(wol): State<Arc<WolConfig>>,
+    Path(server_uuid): Path<String>,
+    Actor(actor): Actor,
+) -> impl IntoResponse {
+    let server = match find_server(&pool, &server_uuid).await {
+        Ok(server) => server,
+        Err(status) => return status.into_response(),
+    };
+    let Some(mac_address) = server.wol_mac_address.as_deref().filter(|mac| !mac.trim().is_empty()) else {
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(json!({"error": "server has no wol_mac_address"}))).into_response();
+    };
+    let Some(mac) = parse_mac_address(mac_address) else {
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(json!({"error": format!("invalid wol_mac_address: {}", mac_address)}))).into_response();
+    };
+
+    let result = match server.wol_relay_server_id.as_deref() {
+        Some(relay_id) => send_via_relay(&pool, &ssh, relay_id, mac_address, wol.port).await,
+        None => send_magic_packet(&mac, &wol.broadcast_address, wol.port).await.map_err(|e| {
+            tracing::error!("Failed to send magic packet: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response()
+        }),
+    };
+    record(&pool, AuditEntry {
+        server_id: &server.id,
+        actor: &actor,
+        action: "server.wake",
+        target: mac_address,
+        detail: Some(json!({"relay_server_id": server.wol_relay_server_id})),
+        status_code: result.as_ref().map_or_else(|response| response.status(), |_| StatusCode::OK),
+    }).await;
+    if let Err(response) = result {
+        return response;
+    }
+
+    let (tx, rx) = mpsc::channel::<WakeEvent>(16);
+    let _ = tx.send(WakeEvent::Sent { relay_server_id: server.wol_relay_server_id.clone() }).await;
+    tokio::spawn(wait_until_up(pool, ssh, wol, server.id, tx));
+
+    let stream = ReceiverStream::new(rx).map(|event| {
+        let mut line = serde_json::to_string(&event).unwrap_or_default();
+        line.push('\n');
+        Ok::<_, Infallible>(line)
+    });
+    ([(header::CONTENT_TYPE, "application/x-ndjson")], Body::from_stream(stream)).into_response()
+}
+
+/// サーバーの登録や編集のときに、MAC アドレスの形式と中継するエージェントを確かめる。
+/// 新規登録のときは `server_id` に `None` を渡す
+pub async fn check_wake_settings(
+    pool: &SqlitePool,
+    server_id: Option<&str>,
+    mac_address: Option<&str>,
+    relay_id: Option<&str>,
+) -> Result<(), Response> {
+    if let Some(mac_address) = mac_address.filter(|mac| !mac.trim().is_empty())
+        && parse_mac_address(mac_address).is_none()
+    {
+        return Err((StatusCode::BAD_REQUEST, Json(json!({"error": format!("invalid wol_mac_address: {}", mac_address)}))).into_response());
+    }
+
+    let Some(relay_id) = relay_id else {
+        return Ok(());
+    };
+    if server_id == Some(relay_id) {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(json!({"error": "a server cannot relay its own magic packet"}))).into_response());
+    }
+    match find_server(pool, relay_id).await {
+        Ok(_) => Ok(()),
+        Err(StatusCode::NOT_FOUND) => {
+            Err((StatusCode::UNPROCESSABLE_ENTITY, Json(json!({"error": format!("relay server {} does not exist", relay_id)}))).into_response())
+        }
+        Err(status) => Err(status.into_response()),
+    }
+}
+
+/// 中継するエージェントは踏み台の経路があればそれを通る
+async fn send_via_relay(pool: &SqlitePool, ssh: &Arc<SshContext>, relay_id: &str, mac_address: &str, port: u16) -> Result<(), Response> {
+    let relay = match find_agent(pool, ssh, relay_id).await {
+        Ok(relay) => relay,
+        Err(StatusCode::NOT_FOUND) => {
+            return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(json!({"error": format!("relay server {} does not exist", relay_id)}))).into_response());
+        }
+        Err(status) => return Err(status.into_response()),
+    };
+    let http_client = http_client().map_err(IntoResponse::into_response)?;
+
+    let request = WakeRequest {
+        mac_address: mac_address.to_string(),
+        port,
+    };
+    match http_client.post(agent_url(&relay, "/wol")).json(&request).send().await {
+        Ok(response) if response.status().is_success() => Ok(()),
+        Ok(response) => Err(relay_response(response).await),
+        Err(e) => {
+            tracing::error!("Failed to reach relay agent {}: {}", relay.hostname, e);
+            Err((StatusCode::BAD_GATEWAY, Json(json!({"error": format!("relay agent is unreachable: {}", e)}))).into_response())
+        }
+    }
+}
+
+/// 一定の間隔でサーバーが起きたかを確かめ、応答するか制限時間を過ぎたら終える
+async fn wait_until_up(pool: SqlitePool, ssh: Arc<SshContext>, wol: Arc<WolConfig>, server_id: String, tx: mpsc::Sender<WakeEvent>) {
+    let started = Instant::now();
+    let interval = Duration::from_secs(wol.poll_interval_secs.max(1));
+    let timeout = Duration::from_secs(wol.wake_timeout_secs);
+
+    loop {
+        tokio::time::sleep(interval).await;
+        let up = is_up(&pool, &ssh, &server_id, interval).await;
+        let elapsed_secs = started.elapsed().as_secs();
+
+        let event = if up {
+            WakeEvent::Up { elapsed_secs }
+        } else if started.elapsed() >= timeout {
+            WakeEvent::Timeout { elapsed_secs }
+        } else {
+            WakeEvent::Waiting { elapsed_secs }
+        };
+        let done = !matches!(event, WakeEvent::Waiting { .. });
+        // クライアントが切断したら待つのをやめる
+        if tx.send(event).await.is_err() || done {
+            return;
+        }
+    }
+}
+
+/// エージェントが応答するか、エージェントのないサーバーでは SSH のポートが応答すれば起きたとみなす
+async fn is_up(pool: &SqlitePool, ssh: &Arc<SshContext>, server_id: &str, timeout: Duration) -> bool {
+    let Ok(server) = find_server(pool, server_id).await else {
+        return false;
+    };
+    if let Ok(agent) = route_agent(pool, ssh, server.clone())
+        && let Ok(http_client) = http_client()
+        && http_client
+            .get(agent_url(&agent, "/health"))
+            .timeout(timeout)
+            .send()
+            .await
+            .is_ok_and(|response| response.status().is_success())
+    {
+        return true;
+    }
+    probe_ssh(pool, ssh, &server, timeout).await.is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::{context, insert_server, mock_agent, pool};
+    use std::net::Ipv4Addr;
+
+    use axum::{Router, routing::get};
+    use tokio::{io::AsyncWriteExt, net::TcpListener};
+
+    async fn set_ssh_port(pool: &SqlitePool, server_id: &str, port: u16) {
+        sqlx::query(r#"UPDATE servers SET port = ? WHERE id = ?"#).bind(port).bind(server_id).execute(pool).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn agentless_servers_are_up_when_ssh_answers() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = pool(&dir).await;
+        let ssh = context();
+        let timeout = Duration::from_secs(2);
+
+        let sshd = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let sshd_port = sshd.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = sshd.accept().await {
+                let _ = stream.write_all(b"SSH-2.0-OpenSSH_9.6\r\n").await;
+            }
+        });
+        let closed = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap().local_addr().unwrap();
+        let agent = mock_agent(Router::new().route("/health", get(|| async { "ok" }))).await;
+
+        insert_server(&pool, "agent", &agent, &[]).await;
+        set_ssh_port(&pool, "agent", closed.port()).await;
+        insert_server(&pool, "agentless", &closed.to_string(), &[]).await;
+        set_ssh_port(&pool, "agentless", sshd_port).await;
+        insert_server(&pool, "asleep", &closed.to_string(), &[]).await;
+        set_ssh_port(&pool, "asleep", closed.port()).await;
+
+        assert!(is_up(&pool, &ssh, "agent", timeout).await);
+        assert!(is_up(&pool, &ssh, "agentless", timeout).await);
+        assert!(!is_up(&pool, &ssh, "asleep", timeout).await);
+        assert!(!is_up(&pool, &ssh, "missing", timeout).await);
+    }
+}
@@ -0,0 +1,280 @@
+use crate::utils::{
+    agent::{agent_url, http_client},
+    bastion::{find_agent, route_agent},
+    ssh::SshContext,
+};
+use common::{
+    agent::service::{ServiceUnit, normalize_unit_name},
+    central::{information::ServerInformation, service::WatchedService},
+};
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Json},
+};
+use chrono::Utc;
+use futures::{StreamExt, stream};
+use reqwest::Client as HttpClient;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::SqlitePool;
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+
+const POLL_CONCURRENCY: usize = 8;
+
+#[derive(Deserialize)]
+pub struct WatchedServicesRequest {
+    units: Vec<String>,
+}
+
+pub async fn get_watched_services(
+    State(pool): State<SqlitePool>,
+    Path(server_uuid): Path<String>,
+) -> impl IntoResponse {
+    match load_watched(&pool, &server_uuid).await {
+        Ok(rows) => (StatusCode::OK, Json(rows)).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to fetch watched services: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// 監視対象のサービスを置き換え、すぐに状態を取得する
+pub async fn set_watched_services(
+    State(pool): State<SqlitePool>,
+    State(ssh): State<Arc<SshContext>>,
+    Path(server_uuid): Path<String>,
+    Json(json): Json<WatchedServicesRequest>,
+) -> impl IntoResponse {
+    let server = match find_agent(&pool, &ssh, &server_uuid).await {
+        Ok(server) => server,
+        Err(status) => return status.into_response(),
+    };
+
+    let mut units = Vec::new();
+    for unit in &json.units {
+        match normalize_unit_name(unit.trim()) {
+            Some(unit) if !units.contains(&unit) => units.push(unit),
+            Some(_) => {}
+            None => {
+                return (StatusCode::BAD_REQUEST, Json(json!({"error": format!("invalid unit name: {}", unit)}))).into_response();
+            }
+        }
+    }
+
+    if let Err(e) = replace_watched(&pool, &server.id, &units).await {
+        tracing::error!("Failed to store watched services: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response();
+    }
+    poll_server(&pool, &server).await;
+
+    match load_watched(&pool, &server.id).await {
+        Ok(rows) => (StatusCode::OK, Json(rows)).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to fetch watched services: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// 監視対象のサービスを持つサーバーを定期的に巡回する
+pub fn spawn_poller(pool: SqlitePool, ssh: Arc<SshContext>, interval_secs: u64) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs.max(1)));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        loop {
+            interval.tick().await;
+
+            let servers = match sqlx::query_as::<_, ServerInformation>(
+                r#"SELECT id, hostname, ip_address, os_type, tags, auth_profile_id, port, bastion_server_id, wol_mac_address, wol_relay_server_id FROM servers WHERE id IN (SELECT DISTINCT server_id FROM watched_services)"#,
+            )
+                .fetch_all(&pool)
+                .await
+            {
+                Ok(servers) => servers,
+                Err(e) => {
+                    tracing::error!("Failed to fetch servers to poll: {}", e);
+                    continue;
+                }
+            };
+            let servers = servers.into_iter().filter_map(|server| route_agent(&pool, &ssh, server).ok()).collect::<Vec<_>>();
+
+            stream::iter(servers.iter())
+                .for_each_concurrent(POLL_CONCURRENCY, |server| {
+                    let pool = pool.clone();
+                    async move { poll_server(&pool, server).await }
+                })
+                .await;
+        }
+    })
+}
+
+/// 監視対象のユニットだけを1つずつ問い合わせる。`.service` 以外のユニットも見られる。
+/// エージェントに接続できない場合は状態を不明（NULL）に戻す
+async fn poll_server(pool: &SqlitePool, server: &ServerInformation) {
+    let watched = match load_watched(pool, &server.id).await {
+        Ok(watched) => watched,
+        Err(e) => {
+            tracing::error!("Failed to fetch watched services: {}", e);
+            return;
+        }
+    };
+    let http_client = http_client().ok();
+
+    let now = Utc::now();
+    let mut reachable = http_client.is_some();
+    for service in watched {
+        let state = match &http_client {
+            Some(http_client) if reachable => match fetch_unit(http_client, server, &service.unit_name).await {
+                Ok(Some(unit)) => (Some(unit.active_state), Some(unit.sub_state)),
+                Ok(None) => (Some("not-found".to_string()), None),
+                Err(status) => {
+                    // 残りのユニットも取得できないので、待たずに不明にする
+                    tracing::warn!("Failed to poll services on {}: {}", server.hostname, status);
+                    reachable = false;
+                    (None, None)
+                }
+            },
+            _ => (None, None),
+        };
+
+        let result = sqlx::query(r#"UPDATE watched_services SET active_state = ?, sub_state = ?, checked_at = ? WHERE server_id = ? AND unit_name = ?"#)
+            .bind(state.0)
+            .bind(state.1)
+            .bind(now)
+            .bind(&server.id)
+            .bind(&service.unit_name)
+            .execute(pool)
+            .await;
+        if let Err(e) = result {
+            tracing::error!("Failed to update watched service: {}", e);
+        }
+    }
+}
+
+/// ユニットが存在しない場合は `None` を返す
+async fn fetch_unit(http_client: &HttpClient, server: &ServerInformation, name: &str) -> Result<Option<ServiceUnit>, StatusCode> {
+    // `\x2d` のようなエスケープを含む名前を URL の区切りとして解釈させない
+    let req_address = agent_url(server, &format!("/services/{}", name.replace('\\', "%5C")));
+    match http_client.get(req_address).send().await {
+        Ok(res) if res.status() == StatusCode::NOT_FOUND => Ok(None),
+        Ok(res) if res.status().is_success() => res.json::<ServiceUnit>().await.map(Some).map_err(|e| {
+            tracing::error!("Failed to parse service: {}", e);
+            StatusCode::BAD_GATEWAY
+        }),
+        Ok(res) => Err(res.status()),
+        Err(_) => Err(StatusCode::BAD_GATEWAY),
+    }
+}
+
+async fn replace_watched(pool: &SqlitePool, server_id: &str, units: &[String]) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    // 引き続き監視するサービスは直前の状態を残す
+    let existing = sqlx::query_scalar::<_, String>(r#"SELECT unit_name FROM watched_services WHERE server_id = ?"#)
+        .bind(server_id)
+        .fetch_all(&mut *tx)
+        .await?;
+    for unit in existing.iter().filter(|unit| !units.contains(unit)) {
+        sqlx::query(r#"DELETE FROM watched_services WHERE server_id = ? AND unit_name = ?"#)
+            .bind(server_id)
+            .bind(unit)
+            .execute(&mut *tx)
+            .await?;
+    }
+    for unit in units.iter().filter(|unit| !existing.contains(unit)) {
+        sqlx::query(r#"INSERT INTO watched_services (server_id, unit_name) VALUES (?, ?)"#)
+            .bind(server_id)
+            .bind(unit)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await
+}
+
+async fn load_watched(pool: &SqlitePool, server_id: &str) -> Result<Vec<WatchedService>, sqlx::Error> {
+    sqlx::query_as::<_, WatchedService>(
+        r#"SELECT unit_name, active_state, sub_state, checked_at FROM watched_services WHERE server_id = ? ORDER BY unit_name"#,
+    )
+        .bind(server_id)
+        .fetch_all(pool)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::{insert_server, mock_agent, pool};
+
+    use axum::{Router, routing::get};
+
+    fn unit(name: &str, active_state: &str, sub_state: &str) -> ServiceUnit {
+        ServiceUnit {
+            name: name.to_string(),
+            description: String::new(),
+            load_state: "loaded".to_string(),
+            active_state: active_state.to_string(),
+            sub_state: sub_state.to_string(),
+            unit_file_state: None,
+            memory_bytes: None,
+            manageable: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn polls_each_watched_unit_including_non_services() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = pool(&dir).await;
+        // 一覧（`/services`）は用意しない。監視対象だけを問い合わせること
+        let agent = mock_agent(Router::new().route("/services/{name}", get(|Path(name): Path<String>| async move {
+            match name.as_str() {
+                "nginx.service" => Json(unit(&name, "active", "running")).into_response(),
+                "certbot.timer" => Json(unit(&name, "active", "waiting")).into_response(),
+                "dev-disk-by\\x2duuid.mount" => Json(unit(&name, "inactive", "dead")).into_response(),
+                _ => StatusCode::NOT_FOUND.into_response(),
+            }
+        }))).await;
+        insert_server(&pool, "web-1", &agent, &[]).await;
+        let units = ["nginx.service", "certbot.timer", "dev-disk-by\\x2duuid.mount", "missing.socket"].map(String::from);
+        replace_watched(&pool, "web-1", &units).await.unwrap();
+
+        let server = crate::utils::agent::find_server(&pool, "web-1").await.unwrap();
+        poll_server(&pool, &server).await;
+
+        let states = load_watched(&pool, "web-1")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|service| (service.unit_name, service.active_state, service.sub_state))
+            .collect::<Vec<_>>();
+        let state = |name: &str, active: Option<&str>, sub: Option<&str>| (name.to_string(), active.map(String::from), sub.map(String::from));
+        assert_eq!(states, vec![
+            state("certbot.timer", Some("active"), Some("waiting")),
+            state("dev-disk-by\\x2duuid.mount", Some("inactive"), Some("dead")),
+            state("missing.socket", Some("not-found"), None),
+            state("nginx.service", Some("active"), Some("running")),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn clears_states_when_the_agent_is_unreachable() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = pool(&dir).await;
+        insert_server(&pool, "web-1", "127.0.0.1:9", &[]).await;
+        replace_watched(&pool, "web-1", &["nginx.service".to_string(), "sshd.service".to_string()]).await.unwrap();
+        sqlx::query(r#"UPDATE watched_services SET active_state = 'active', sub_state = 'running'"#).execute(&pool).await.unwrap();
+
+        let server = crate::utils::agent::find_server(&pool, "web-1").await.unwrap();
+        poll_server(&pool, &server).await;
+
+        for service in load_watched(&pool, "web-1").await.unwrap() {
+            assert_eq!((service.active_state, service.sub_state), (None, None));
+            assert!(service.checked_at.is_some());
+        }
+    }
+}
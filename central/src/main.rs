@@ -1,6 +1,7 @@
 mod app;
 mod utils;
 mod handles;
+mod ssh;
 
 use crate::app::config::Config;
 use crate::utils::logging::init_tracing;
@@ -12,9 +13,9 @@ async fn main() -> Result<()> {
     println!("  Guardian Central\n");
 
     let config = Config::load()?;
-    let _ = init_tracing(config.clone())?;
+    init_tracing(config.clone())?;
 
-    print!("  Local: http://127.0.0.1:{}", config.server.port.clone());
+    print!("  Local: http://127.0.0.1:{}", config.server.port);
 
     let mut app = app::runner::App::new(config)?;
     app.run().await?;
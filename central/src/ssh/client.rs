@@ -0,0 +1,834 @@
+//! 認証（RFC 4252）とセッションチャンネル（RFC 4254）
+use super::{
+    AuthMethod, Credential, SshError, SshTarget,
+    transport::{
+        self, DirectionKeys, Kex, MSG_DEBUG, MSG_DISCONNECT, MSG_IGNORE, MSG_KEX_ECDH_REPLY, MSG_KEXINIT, MSG_NEWKEYS, MSG_SERVICE_ACCEPT,
+        MSG_SERVICE_REQUEST, MSG_UNIMPLEMENTED, PacketWriter, Transport, WriteHalf,
+    },
+    wire::{Reader, Writer},
+};
+use std::{collections::HashMap, io, time::Duration};
+
+use sha2::Sha512;
+use signature::{SignatureEncoding, Signer};
+use ssh_key::{Algorithm, HashAlg, PrivateKey, Signature, private::KeypairData};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream},
+    net::TcpStream,
+    sync::{mpsc, oneshot},
+};
+
+const MSG_GLOBAL_REQUEST: u8 = 80;
+const MSG_REQUEST_FAILURE: u8 = 82;
+const MSG_USERAUTH_REQUEST: u8 = 50;
+const MSG_USERAUTH_FAILURE: u8 = 51;
+const MSG_USERAUTH_SUCCESS: u8 = 52;
+const MSG_USERAUTH_BANNER: u8 = 53;
+const MSG_USERAUTH_PASSWD_CHANGEREQ: u8 = 60;
+const MSG_CHANNEL_OPEN: u8 = 90;
+const MSG_CHANNEL_OPEN_CONFIRMATION: u8 = 91;
+const MSG_CHANNEL_OPEN_FAILURE: u8 = 92;
+const MSG_CHANNEL_WINDOW_ADJUST: u8 = 93;
+const MSG_CHANNEL_DATA: u8 = 94;
+const MSG_CHANNEL_EXTENDED_DATA: u8 = 95;
+const MSG_CHANNEL_EOF: u8 = 96;
+const MSG_CHANNEL_CLOSE: u8 = 97;
+const MSG_CHANNEL_REQUEST: u8 = 98;
+const MSG_CHANNEL_SUCCESS: u8 = 99;
+const MSG_CHANNEL_FAILURE: u8 = 100;
+
+const LOCAL_WINDOW: u32 = 2 * 1024 * 1024;
+const LOCAL_MAX_PACKET: u32 = 32 * 1024;
+const EXTENDED_DATA_STDERR: u32 = 1;
+const TUNNEL_BUFFER: usize = 64 * 1024;
+/// これだけ送受信したらこちらから鍵を再交換する（RFC 4253 9節）
+#[cfg(not(test))]
+const REKEY_BYTES: u64 = 1 << 30;
+#[cfg(test)]
+const REKEY_BYTES: u64 = 64 * 1024;
+
+#[derive(Clone)]
+pub enum ChannelRequest {
+    Exec(String),
+    Shell { cols: u16, rows: u16 },
+    /// 接続先のサーバーから `host:port` への TCP 接続（ssh -W と同じ）
+    DirectTcpip { host: String, port: u16 },
+}
+
+pub enum ChannelInput {
+    Data(Vec<u8>),
+    Resize { cols: u16, rows: u16 },
+    /// 標準入力を閉じる。送った後に入力側を破棄してもチャンネルは閉じない
+    Eof,
+}
+
+#[derive(Debug)]
+pub enum ChannelEvent {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+    /// シグナルで終了したときは `None`
+    Exit(Option<i32>),
+    Error(String),
+}
+
+/// 1つのチャンネル。`events` が終わるとチャンネルは閉じている。
+/// `Eof` を送らずに `input` を破棄するか、`events` を破棄するとチャンネルを閉じる
+pub struct SshChannel {
+    pub input: mpsc::Sender<ChannelInput>,
+    pub events: mpsc::Receiver<ChannelEvent>,
+}
+
+/// 認証済みの接続。複製したハンドルから、同じ接続の上にいくつでもチャンネルを開ける。
+/// すべてのハンドルを破棄し、開いているチャンネルもなくなると接続を閉じる
+#[derive(Clone)]
+pub struct SshClient {
+    opens: mpsc::Sender<oneshot::Sender<OpenedChannel>>,
+}
+
+impl SshClient {
+    pub async fn connect(target: &SshTarget, credential: &Credential, timeout: Duration) -> Result<Self, SshError> {
+        let connect = async {
+            let stream = TcpStream::connect((target.host.as_str(), target.port)).await?;
+            stream.set_nodelay(true)?;
+            Self::establish(stream, target, credential).await
+        };
+        tokio::time::timeout(timeout, connect).await.map_err(|_| SshError::Timeout)?
+    }
+
+    /// 踏み台のチャンネルなど、既に開いている接続の上で SSH を始める
+    pub async fn connect_over<S>(stream: S, target: &SshTarget, credential: &Credential, timeout: Duration) -> Result<Self, SshError>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        tokio::time::timeout(timeout, Self::establish(stream, target, credential))
+            .await
+            .map_err(|_| SshError::Timeout)?
+    }
+
+    async fn establish<S>(stream: S, target: &SshTarget, credential: &Credential) -> Result<Self, SshError>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let mut transport = transport::handshake(stream, target).await?;
+        authenticate(&mut transport, target, credential).await?;
+        Ok(Connection::spawn(transport, target.clone()))
+    }
+
+    /// 接続が切れていれば `true`。切れたハンドルでは新しいチャンネルを開けない
+    pub fn is_closed(&self) -> bool {
+        self.opens.is_closed()
+    }
+
+    /// 同じ接続のハンドルか
+    pub fn same_connection(&self, other: &SshClient) -> bool {
+        self.opens.same_channel(&other.opens)
+    }
+
+    pub async fn open(&self, request: ChannelRequest) -> Result<SshChannel, SshError> {
+        let (reply, opened) = oneshot::channel();
+        self.opens.send(reply).await.map_err(|_| connection_closed())?;
+        let mut opened = opened.await.map_err(|_| connection_closed())?;
+        match opened.establish(request).await {
+            Ok((confirmation, early)) => Ok(ChannelTask::spawn(opened, confirmation, early)),
+            Err(e) => {
+                let _ = opened.outgoing.send(Outgoing::Closed(opened.local_id)).await;
+                Err(e)
+            }
+        }
+    }
+}
+
+/// 接続のタスクが割り当てたチャンネル番号と、そのチャンネル宛てのメッセージの受け口
+struct OpenedChannel {
+    local_id: u32,
+    packets: mpsc::UnboundedReceiver<Result<Vec<u8>, SshError>>,
+    outgoing: mpsc::Sender<Outgoing>,
+}
+
+/// チャンネルから接続のタスクへ渡すもの
+enum Outgoing {
+    Message(Writer),
+    /// チャンネルを使い終えた。以降このチャンネル宛てのメッセージは捨てる
+    Closed(u32),
+}
+
+struct Confirmation {
+    remote_id: u32,
+    remote_window: u32,
+    remote_max_packet: u32,
+}
+
+impl OpenedChannel {
+    async fn send(&self, message: Writer) -> Result<(), SshError> {
+        self.outgoing.send(Outgoing::Message(message)).await.map_err(|_| connection_closed())
+    }
+
+    async fn recv(&mut self) -> Result<Vec<u8>, SshError> {
+        self.packets.recv().await.ok_or_else(connection_closed)?
+    }
+
+    /// チャンネルを開いてリクエストを送る。返事の前に届いたチャンネルのメッセージも返す
+    async fn establish(&mut self, request: ChannelRequest) -> Result<(Confirmation, Vec<Vec<u8>>), SshError> {
+        let mut open = Writer::new(MSG_CHANNEL_OPEN);
+        match &request {
+            ChannelRequest::DirectTcpip { host, port } => {
+                open.string("direct-tcpip").u32(self.local_id).u32(LOCAL_WINDOW).u32(LOCAL_MAX_PACKET);
+                open.string(host).u32(*port as u32).string("127.0.0.1").u32(0);
+            }
+            _ => {
+                open.string("session").u32(self.local_id).u32(LOCAL_WINDOW).u32(LOCAL_MAX_PACKET);
+            }
+        }
+        self.send(open).await?;
+
+        let payload = self.recv().await?;
+        let mut reader = Reader::new(&payload[1..]);
+        let confirmation = match payload[0] {
+            MSG_CHANNEL_OPEN_CONFIRMATION => {
+                reader.u32()?;
+                Confirmation { remote_id: reader.u32()?, remote_window: reader.u32()?, remote_max_packet: reader.u32()? }
+            }
+            MSG_CHANNEL_OPEN_FAILURE => {
+                reader.u32()?;
+                reader.u32()?;
+                return Err(SshError::ChannelRejected(reader.utf8()?));
+            }
+            other => return Err(SshError::Protocol(format!("unexpected message {} while opening channel", other))),
+        };
+
+        let remote_id = confirmation.remote_id;
+        let requests = match request {
+            ChannelRequest::Exec(command) => {
+                let mut exec = channel_request(remote_id, "exec", true);
+                exec.string(command);
+                vec![("exec", exec)]
+            }
+            ChannelRequest::Shell { cols, rows } => {
+                let mut pty = channel_request(remote_id, "pty-req", true);
+                // 端末モードは指定しない（TTY_OP_END のみ）
+                pty.string("xterm-256color").u32(cols as u32).u32(rows as u32).u32(0).u32(0).string([0u8]);
+                vec![("pty-req", pty), ("shell", channel_request(remote_id, "shell", true))]
+            }
+            ChannelRequest::DirectTcpip { .. } => Vec::new(),
+        };
+        let mut early = Vec::new();
+        for (name, request) in requests {
+            self.send(request).await?;
+            // 返事の前に届いたチャンネルのメッセージは、チャンネルのタスクで処理する
+            loop {
+                let payload = self.recv().await?;
+                match payload[0] {
+                    MSG_CHANNEL_SUCCESS => break,
+                    MSG_CHANNEL_FAILURE => {
+                        let mut close = Writer::new(MSG_CHANNEL_CLOSE);
+                        close.u32(remote_id);
+                        self.send(close).await?;
+                        return Err(SshError::ChannelRejected(format!("{} request was refused", name)));
+                    }
+                    _ => early.push(payload),
+                }
+            }
+        }
+        Ok((confirmation, early))
+    }
+}
+
+impl SshChannel {
+    /// チャンネルをバイト列のストリームとして扱う。`DirectTcpip` で開いたチャンネルを
+    /// HTTP や次の SSH 接続に使うためのもので、標準エラー出力と終了状態は捨てる
+    pub fn into_stream(self) -> DuplexStream {
+        let (local, remote) = tokio::io::duplex(TUNNEL_BUFFER);
+        let (mut reader, mut writer) = tokio::io::split(remote);
+        let SshChannel { input, mut events } = self;
+
+        tokio::spawn(async move {
+            let mut buffer = vec![0u8; LOCAL_MAX_PACKET as usize];
+            loop {
+                match reader.read(&mut buffer).await {
+                    Ok(0) | Err(_) => {
+                        let _ = input.send(ChannelInput::Eof).await;
+                        break;
+                    }
+                    Ok(n) => {
+                        if input.send(ChannelInput::Data(buffer[..n].to_vec())).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                match event {
+                    ChannelEvent::Stdout(data) => {
+                        if writer.write_all(&data).await.is_err() {
+                            break;
+                        }
+                    }
+                    ChannelEvent::Error(message) => tracing::debug!("SSH tunnel closed: {}", message),
+                    ChannelEvent::Stderr(_) | ChannelEvent::Exit(_) => {}
+                }
+            }
+            let _ = writer.shutdown().await;
+        });
+
+        local
+    }
+}
+
+async fn authenticate(transport: &mut Transport, target: &SshTarget, credential: &Credential) -> Result<(), SshError> {
+    let mut service = Writer::new(MSG_SERVICE_REQUEST);
+    service.string("ssh-userauth");
+    transport.writer.write(&service).await?;
+    transport.expect(MSG_SERVICE_ACCEPT).await?;
+
+    let mut request = Writer::new(MSG_USERAUTH_REQUEST);
+    request.string(&credential.username).string("ssh-connection");
+    match &credential.method {
+        AuthMethod::Password(password) => {
+            request.string("password").bool(false).string(password);
+        }
+        AuthMethod::PrivateKey { key, passphrase } => {
+            let key = load_private_key(key, passphrase.as_deref())?;
+            let algorithm = match key.algorithm() {
+                Algorithm::Rsa { .. } => "rsa-sha2-512".to_string(),
+                other => other.as_str().to_string(),
+            };
+            let public_key = key.public_key().to_bytes().map_err(|e| SshError::Credential(e.to_string()))?;
+            request.string("publickey").bool(true).string(&algorithm).string(&public_key);
+
+            // 署名の問い合わせ（RFC 4252 7節）は省略し、最初から署名を付けて送る
+            let mut signed = Writer::default();
+            signed.string(&transport.session_id).raw(request.as_bytes());
+            let signature = sign(&key, signed.as_bytes())
+                .map_err(|e| SshError::Credential(format!("failed to sign with {} key: {}", algorithm, e)))?;
+            let mut encoded = Writer::default();
+            encoded.string(signature.algorithm().as_str()).string(signature.as_bytes());
+            request.string(encoded.as_bytes());
+        }
+    }
+    transport.writer.write(&request).await?;
+
+    loop {
+        let payload = next_message(transport, target).await?;
+        match payload[0] {
+            MSG_USERAUTH_SUCCESS => return Ok(()),
+            MSG_USERAUTH_BANNER => continue,
+            // パスワードの変更要求も失敗として扱う
+            MSG_USERAUTH_FAILURE | MSG_USERAUTH_PASSWD_CHANGEREQ => return Err(SshError::AuthenticationFailed),
+            other => return Err(SshError::Protocol(format!("unexpected message {} during authentication", other))),
+        }
+    }
+}
+
+impl AuthMethod {
+    /// 接続せずに、資格情報として使えるかを確かめる
+    pub fn check(&self) -> Result<(), SshError> {
+        match self {
+            AuthMethod::Password(password) if password.is_empty() => Err(SshError::Credential("password is empty".to_string())),
+            AuthMethod::Password(_) => Ok(()),
+            AuthMethod::PrivateKey { key, passphrase } => load_private_key(key, passphrase.as_deref()).map(|_| ()),
+        }
+    }
+}
+
+fn load_private_key(key: &str, passphrase: Option<&str>) -> Result<PrivateKey, SshError> {
+    let key = PrivateKey::from_openssh(key.trim()).map_err(|e| SshError::Credential(format!("failed to parse private key: {}", e)))?;
+    if !key.is_encrypted() {
+        return Ok(key);
+    }
+    match passphrase {
+        Some(passphrase) => key.decrypt(passphrase).map_err(|_| SshError::Credential("wrong passphrase".to_string())),
+        None => Err(SshError::Credential("private key is encrypted but no passphrase is set".to_string())),
+    }
+}
+
+fn sign(key: &PrivateKey, data: &[u8]) -> Result<Signature, signature::Error> {
+    match key.key_data() {
+        // ssh-key 0.6 は RSA の秘密鍵を組み立てるときに q の代わりに p を渡してしまうため、ここで組み立てる
+        KeypairData::Rsa(keypair) => {
+            let component = |value: &ssh_key::Mpint| rsa::BigUint::try_from(value).map_err(signature::Error::from_source);
+            let private_key = rsa::RsaPrivateKey::from_components(
+                component(&keypair.public.n)?,
+                component(&keypair.public.e)?,
+                component(&keypair.private.d)?,
+                vec![component(&keypair.private.p)?, component(&keypair.private.q)?],
+            )
+            .map_err(signature::Error::from_source)?;
+            let signature = rsa::pkcs1v15::SigningKey::<Sha512>::new(private_key).try_sign(data)?;
+            Signature::new(Algorithm::Rsa { hash: Some(HashAlg::Sha512) }, signature.to_vec()).map_err(signature::Error::from_source)
+        }
+        _ => key.try_sign(data),
+    }
+}
+
+fn connection_closed() -> SshError {
+    SshError::Protocol("connection closed".to_string())
+}
+
+/// 接続全体へのメッセージに応えたうえで、次のメッセージを返す
+async fn next_message(transport: &mut Transport, target: &SshTarget) -> Result<Vec<u8>, SshError> {
+    loop {
+        let payload = transport.read().await?;
+        match payload[0] {
+            MSG_GLOBAL_REQUEST => {
+                if let Some(reply) = reject_global_request(&payload)? {
+                    transport.writer.write(&reply).await?;
+                }
+            }
+            MSG_KEXINIT => transport.exchange_keys(target, Some(payload)).await?,
+            _ => return Ok(payload),
+        }
+    }
+}
+
+/// グローバルリクエストには対応していない。返事を求められていれば失敗を返す
+fn reject_global_request(payload: &[u8]) -> Result<Option<Writer>, SshError> {
+    let mut reader = Reader::new(&payload[1..]);
+    reader.string()?;
+    Ok(reader.bool()?.then(|| Writer::new(MSG_REQUEST_FAILURE)))
+}
+
+fn channel_request(remote_id: u32, name: &str, want_reply: bool) -> Writer {
+    let mut request = Writer::new(MSG_CHANNEL_REQUEST);
+    request.u32(remote_id).string(name).bool(want_reply);
+    request
+}
+
+/// 認証後の接続を受け持つ。受信したメッセージをチャンネルへ振り分け、
+/// チャンネルからのメッセージを送り、鍵の再交換も行う
+struct Connection {
+    writer: PacketWriter<WriteHalf>,
+    target: SshTarget,
+    session_id: Vec<u8>,
+    server_version: String,
+    strict: bool,
+    rekey: Rekey,
+    /// 鍵の再交換中に送ろうとしたメッセージ。NEWKEYS を送るまでは送れない（RFC 4253 7.1）
+    deferred: Vec<Writer>,
+    /// 受信側の新しい鍵をパケットを読むタスクへ渡す
+    reader_keys: mpsc::Sender<DirectionKeys>,
+    /// 前回の鍵交換から送受信したバイト数
+    transferred: u64,
+    /// こちらのチャンネル番号ごとの、チャンネル宛てのメッセージの送り先。
+    /// チャンネルのウィンドウを超えては届かないので、上限を設けずに溜める
+    channels: HashMap<u32, mpsc::UnboundedSender<Result<Vec<u8>, SshError>>>,
+    next_id: u32,
+    outgoing: mpsc::Sender<Outgoing>,
+}
+
+enum Rekey {
+    Idle,
+    /// こちらから KEXINIT を送り、サーバーの KEXINIT を待っている
+    Started(Kex),
+    /// KEX_ECDH_INIT を送り、返事を待っている
+    Negotiated { kex: Kex, discard_guess: bool },
+    /// NEWKEYS を送り、サーバーの NEWKEYS を待っている
+    Finishing(DirectionKeys),
+}
+
+impl Connection {
+    fn spawn(transport: Transport, target: SshTarget) -> SshClient {
+        let Transport { mut reader, writer, session_id, server_version, strict } = transport;
+        let (packet_tx, packet_rx) = mpsc::channel(16);
+        let (keys_tx, mut keys_rx) = mpsc::channel(1);
+        let reader_task = tokio::spawn(async move {
+            loop {
+                let result = reader.read().await;
+                let failed = result.is_err();
+                let new_keys = matches!(&result, Ok(payload) if payload.first() == Some(&MSG_NEWKEYS));
+                if packet_tx.send(result).await.is_err() || failed {
+                    break;
+                }
+                // 以降のパケットは新しい鍵で暗号化されているので、鍵が届くまで読まない
+                if new_keys {
+                    match keys_rx.recv().await {
+                        Some(keys) => reader.set_keys(keys, strict),
+                        None => break,
+                    }
+                }
+            }
+        });
+
+        let (opens_tx, opens_rx) = mpsc::channel(16);
+        let (outgoing_tx, outgoing_rx) = mpsc::channel(64);
+        let connection = Connection {
+            writer,
+            target,
+            session_id,
+            server_version,
+            strict,
+            rekey: Rekey::Idle,
+            deferred: Vec::new(),
+            reader_keys: keys_tx,
+            transferred: 0,
+            channels: HashMap::new(),
+            next_id: 0,
+            outgoing: outgoing_tx,
+        };
+        tokio::spawn(async move {
+            connection.run(packet_rx, opens_rx, outgoing_rx).await;
+            reader_task.abort();
+        });
+        SshClient { opens: opens_tx }
+    }
+
+    async fn run(
+        mut self,
+        packets: mpsc::Receiver<Result<Vec<u8>, SshError>>,
+        opens: mpsc::Receiver<oneshot::Sender<OpenedChannel>>,
+        outgoing: mpsc::Receiver<Outgoing>,
+    ) {
+        if let Err(e) = self.relay(packets, opens, outgoing).await {
+            tracing::warn!("SSH connection closed with error: {}", e);
+            for (_, channel) in self.channels.drain() {
+                let _ = channel.send(Err(SshError::Io(io::Error::new(io::ErrorKind::ConnectionAborted, e.to_string()))));
+            }
+        }
+        let mut disconnect = Writer::new(MSG_DISCONNECT);
+        disconnect.u32(11).string("closed").string("");
+        let _ = self.writer.write(&disconnect).await;
+    }
+
+    async fn relay(
+        &mut self,
+        mut packets: mpsc::Receiver<Result<Vec<u8>, SshError>>,
+        mut opens: mpsc::Receiver<oneshot::Sender<OpenedChannel>>,
+        mut outgoing: mpsc::Receiver<Outgoing>,
+    ) -> Result<(), SshError> {
+        let mut handles_open = true;
+        // ハンドルがすべて破棄され、チャンネルもなくなったら接続を閉じる
+        while handles_open || !self.channels.is_empty() {
+            self.rekey_if_needed().await?;
+            tokio::select! {
+                packet = packets.recv() => {
+                    let payload = packet.ok_or_else(connection_closed)??;
+                    self.handle(payload).await?;
+                }
+                message = outgoing.recv() => match message {
+                    Some(Outgoing::Message(message)) => self.send(message).await?,
+                    Some(Outgoing::Closed(local_id)) => {
+                        self.channels.remove(&local_id);
+                    }
+                    None => return Ok(()),
+                },
+                reply = opens.recv(), if handles_open => match reply {
+                    Some(reply) => self.allocate(reply),
+                    None => handles_open = false,
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// チャンネル番号を割り当て、チャンネル宛てのメッセージを受け取れるようにする
+    fn allocate(&mut self, reply: oneshot::Sender<OpenedChannel>) {
+        let local_id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let (packet_tx, packets) = mpsc::unbounded_channel();
+        let opened = OpenedChannel { local_id, packets, outgoing: self.outgoing.clone() };
+        if reply.send(opened).is_ok() {
+            self.channels.insert(local_id, packet_tx);
+        }
+    }
+
+    async fn handle(&mut self, payload: Vec<u8>) -> Result<(), SshError> {
+        self.received(payload.len());
+        match payload[0] {
+            MSG_CHANNEL_OPEN_CONFIRMATION..=MSG_CHANNEL_FAILURE => {
+                let local_id = Reader::new(&payload[1..]).u32()?;
+                let Some(channel) = self.channels.get(&local_id) else {
+                    tracing::debug!("Ignoring SSH message {} for closed channel {}", payload[0], local_id);
+                    return Ok(());
+                };
+                if let Err(mpsc::error::SendError(Ok(payload))) = channel.send(Ok(payload)) {
+                    self.channels.remove(&local_id);
+                    // 開いている途中で諦めたチャンネルは、開けた後に閉じる
+                    if payload[0] == MSG_CHANNEL_OPEN_CONFIRMATION {
+                        let mut reader = Reader::new(&payload[5..]);
+                        let mut close = Writer::new(MSG_CHANNEL_CLOSE);
+                        close.u32(reader.u32()?);
+                        self.send(close).await?;
+                    }
+                }
+            }
+            MSG_GLOBAL_REQUEST => {
+                if let Some(reply) = reject_global_request(&payload)? {
+                    self.send(reply).await?;
+                }
+            }
+            MSG_DISCONNECT => return Err(transport::disconnected(&payload)),
+            MSG_KEXINIT | MSG_NEWKEYS | 30..=49 => self.handle_kex(&payload).await?,
+            MSG_IGNORE | MSG_DEBUG | MSG_UNIMPLEMENTED => {}
+            other => tracing::debug!("Ignoring SSH message {}", other),
+        }
+        Ok(())
+    }
+
+    /// メッセージを送る。鍵の再交換中は NEWKEYS を送るまで溜めておく
+    async fn send(&mut self, message: Writer) -> Result<(), SshError> {
+        self.transferred += message.as_bytes().len() as u64;
+        if !matches!(self.rekey, Rekey::Idle | Rekey::Finishing(_)) {
+            self.deferred.push(message);
+            return Ok(());
+        }
+        self.writer.write(&message).await
+    }
+
+    fn received(&mut self, len: usize) {
+        self.transferred += len as u64;
+    }
+
+    /// 送受信した量が多くなったら、こちらから鍵の再交換を始める
+    async fn rekey_if_needed(&mut self) -> Result<(), SshError> {
+        if self.transferred >= REKEY_BYTES && matches!(self.rekey, Rekey::Idle) {
+            let kex = Kex::start();
+            self.writer.write(kex.kexinit()).await?;
+            self.rekey = Rekey::Started(kex);
+        }
+        Ok(())
+    }
+
+    /// 鍵交換のメッセージ（KEXINIT、NEWKEYS、30 から 49）を処理する
+    async fn handle_kex(&mut self, payload: &[u8]) -> Result<(), SshError> {
+        match (std::mem::replace(&mut self.rekey, Rekey::Idle), payload[0]) {
+            (Rekey::Idle, MSG_KEXINIT) => {
+                let kex = Kex::start();
+                self.writer.write(kex.kexinit()).await?;
+                self.negotiate(kex, payload).await
+            }
+            (Rekey::Started(kex), MSG_KEXINIT) => self.negotiate(kex, payload).await,
+            // サーバーが推測で送ってきた鍵交換パケットが外れていれば捨てる
+            (Rekey::Negotiated { kex, discard_guess: true }, _) => {
+                self.rekey = Rekey::Negotiated { kex, discard_guess: false };
+                Ok(())
+            }
+            (Rekey::Negotiated { kex, .. }, MSG_KEX_ECDH_REPLY) => {
+                let keys = kex.finish(payload, &self.server_version, &self.target, Some(&self.session_id))?;
+                self.writer.write(&Writer::new(MSG_NEWKEYS)).await?;
+                self.writer.set_keys(keys.client, self.strict);
+                self.rekey = Rekey::Finishing(keys.server);
+                for message in std::mem::take(&mut self.deferred) {
+                    self.writer.write(&message).await?;
+                }
+                Ok(())
+            }
+            (Rekey::Finishing(keys), MSG_NEWKEYS) => {
+                self.reader_keys.send(keys).await.map_err(|_| SshError::Protocol("connection closed".to_string()))?;
+                self.transferred = 0;
+                Ok(())
+            }
+            (_, other) => Err(SshError::Protocol(format!("unexpected message {} during key exchange", other))),
+        }
+    }
+
+    async fn negotiate(&mut self, mut kex: Kex, server_kexinit: &[u8]) -> Result<(), SshError> {
+        let init = kex.negotiate(server_kexinit.to_vec())?;
+        self.writer.write(&init).await?;
+        self.rekey = Rekey::Negotiated { discard_guess: kex.discard_guess(), kex };
+        Ok(())
+    }
+}
+
+/// チャンネルを開いた後の接続を受け持つ。フロー制御（ウィンドウ）もここで行う
+struct ChannelTask {
+    local_id: u32,
+    outgoing: mpsc::Sender<Outgoing>,
+    remote_id: u32,
+    remote_window: u64,
+    remote_max_packet: usize,
+    /// 受信したがまだウィンドウを戻していないバイト数
+    consumed: u32,
+    /// ウィンドウが足りずに送れていない入力
+    pending: Vec<u8>,
+    eof_requested: bool,
+    eof_sent: bool,
+    close_sent: bool,
+    events: mpsc::Sender<ChannelEvent>,
+}
+
+impl ChannelTask {
+    fn spawn(opened: OpenedChannel, confirmation: Confirmation, early: Vec<Vec<u8>>) -> SshChannel {
+        let OpenedChannel { local_id, packets, outgoing } = opened;
+        let (input_tx, input_rx) = mpsc::channel(64);
+        let (event_tx, event_rx) = mpsc::channel(64);
+        let channel = ChannelTask {
+            local_id,
+            outgoing,
+            remote_id: confirmation.remote_id,
+            remote_window: confirmation.remote_window as u64,
+            remote_max_packet: (confirmation.remote_max_packet as usize).min(LOCAL_MAX_PACKET as usize),
+            consumed: 0,
+            pending: Vec::new(),
+            eof_requested: false,
+            eof_sent: false,
+            close_sent: false,
+            events: event_tx,
+        };
+        tokio::spawn(channel.run(packets, input_rx, early));
+        SshChannel { input: input_tx, events: event_rx }
+    }
+
+    async fn run(
+        mut self,
+        packets: mpsc::UnboundedReceiver<Result<Vec<u8>, SshError>>,
+        input: mpsc::Receiver<ChannelInput>,
+        early: Vec<Vec<u8>>,
+    ) {
+        if let Err(e) = self.relay(packets, input, early).await {
+            tracing::warn!("SSH channel closed with error: {}", e);
+            let _ = self.events.send(ChannelEvent::Error(e.to_string())).await;
+        }
+        let _ = self.outgoing.send(Outgoing::Closed(self.local_id)).await;
+    }
+
+    async fn send(&self, message: Writer) -> Result<(), SshError> {
+        self.outgoing.send(Outgoing::Message(message)).await.map_err(|_| connection_closed())
+    }
+
+    async fn relay(
+        &mut self,
+        mut packets: mpsc::UnboundedReceiver<Result<Vec<u8>, SshError>>,
+        mut input: mpsc::Receiver<ChannelInput>,
+        early: Vec<Vec<u8>>,
+    ) -> Result<(), SshError> {
+        for payload in early {
+            if self.handle(&payload).await? {
+                return Ok(());
+            }
+        }
+
+        let events = self.events.clone();
+        let mut input_open = true;
+        loop {
+            tokio::select! {
+                packet = packets.recv() => {
+                    let payload = packet.ok_or_else(connection_closed)??;
+                    if self.handle(&payload).await? {
+                        return Ok(());
+                    }
+                }
+                message = input.recv(), if input_open && self.pending.is_empty() => match message {
+                    Some(ChannelInput::Data(data)) => {
+                        self.pending = data;
+                        self.flush().await?;
+                    }
+                    Some(ChannelInput::Resize { cols, rows }) => {
+                        let mut request = channel_request(self.remote_id, "window-change", false);
+                        request.u32(cols as u32).u32(rows as u32).u32(0).u32(0);
+                        self.send(request).await?;
+                    }
+                    Some(ChannelInput::Eof) => {
+                        self.eof_requested = true;
+                        self.flush().await?;
+                    }
+                    None => {
+                        input_open = false;
+                        if !self.eof_requested {
+                            self.close().await?;
+                        }
+                    }
+                },
+                _ = events.closed() => {
+                    self.close().await?;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// 受信したメッセージを処理する。チャンネルが閉じたら `true` を返す
+    async fn handle(&mut self, payload: &[u8]) -> Result<bool, SshError> {
+        let mut reader = Reader::new(&payload[1..]);
+        match payload[0] {
+            MSG_CHANNEL_DATA => {
+                reader.u32()?;
+                let data = reader.string()?;
+                self.consume(data.len()).await?;
+                let _ = self.events.send(ChannelEvent::Stdout(data.to_vec())).await;
+            }
+            MSG_CHANNEL_EXTENDED_DATA => {
+                reader.u32()?;
+                let kind = reader.u32()?;
+                let data = reader.string()?;
+                self.consume(data.len()).await?;
+                if kind == EXTENDED_DATA_STDERR {
+                    let _ = self.events.send(ChannelEvent::Stderr(data.to_vec())).await;
+                }
+            }
+            MSG_CHANNEL_WINDOW_ADJUST => {
+                reader.u32()?;
+                self.remote_window += reader.u32()? as u64;
+                self.flush().await?;
+            }
+            MSG_CHANNEL_REQUEST => {
+                reader.u32()?;
+                let name = reader.utf8()?;
+                let want_reply = reader.bool()?;
+                match name.as_str() {
+                    "exit-status" => {
+                        let code = reader.u32()? as i32;
+                        let _ = self.events.send(ChannelEvent::Exit(Some(code))).await;
+                    }
+                    "exit-signal" => {
+                        let _ = self.events.send(ChannelEvent::Exit(None)).await;
+                    }
+                    _ => {}
+                }
+                if want_reply {
+                    let mut reply = Writer::new(MSG_CHANNEL_FAILURE);
+                    reply.u32(self.remote_id);
+                    self.send(reply).await?;
+                }
+            }
+            MSG_CHANNEL_CLOSE => {
+                self.close().await?;
+                return Ok(true);
+            }
+            MSG_CHANNEL_EOF | MSG_CHANNEL_SUCCESS | MSG_CHANNEL_FAILURE => {}
+            other => tracing::debug!("Ignoring SSH message {}", other),
+        }
+        Ok(false)
+    }
+
+    /// 受信した分だけ相手のウィンドウを戻す。半分を使ったらまとめて戻す
+    async fn consume(&mut self, len: usize) -> Result<(), SshError> {
+        self.consumed += len as u32;
+        if self.consumed >= LOCAL_WINDOW / 2 {
+            let mut adjust = Writer::new(MSG_CHANNEL_WINDOW_ADJUST);
+            adjust.u32(self.remote_id).u32(self.consumed);
+            self.send(adjust).await?;
+            self.consumed = 0;
+        }
+        Ok(())
+    }
+
+    /// ウィンドウの範囲で保留中の入力を送る
+    async fn flush(&mut self) -> Result<(), SshError> {
+        while !self.pending.is_empty() && self.remote_window > 0 {
+            let len = self.pending.len().min(self.remote_max_packet).min(self.remote_window as usize);
+            let mut data = Writer::new(MSG_CHANNEL_DATA);
+            data.u32(self.remote_id).string(&self.pending[..len]);
+            self.send(data).await?;
+            self.pending.drain(..len);
+            self.remote_window -= len as u64;
+        }
+        if self.pending.is_empty() && self.eof_requested && !self.eof_sent {
+            let mut eof = Writer::new(MSG_CHANNEL_EOF);
+            eof.u32(self.remote_id);
+            self.send(eof).await?;
+            self.eof_sent = true;
+        }
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<(), SshError> {
+        if !self.close_sent {
+            let mut close = Writer::new(MSG_CHANNEL_CLOSE);
+            close.u32(self.remote_id);
+            self.send(close).await?;
+            self.close_sent = true;
+        }
+        Ok(())
+    }
+}
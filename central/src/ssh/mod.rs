@@ -0,0 +1,67 @@
+//! エージェントを導入していないサーバーを操作するための SSH クライアント。
+//! 鍵交換は curve25519-sha256、暗号は aes-ctr と hmac-sha2-256 のみに対応する
+mod client;
+mod transport;
+mod wire;
+
+#[cfg(test)]
+mod tests;
+
+pub use client::{ChannelEvent, ChannelInput, ChannelRequest, SshChannel, SshClient};
+
+use std::{fmt, io};
+
+pub enum AuthMethod {
+    Password(String),
+    /// OpenSSH 形式の秘密鍵
+    PrivateKey { key: String, passphrase: Option<String> },
+}
+
+pub struct Credential {
+    pub username: String,
+    pub method: AuthMethod,
+}
+
+#[derive(Clone)]
+pub struct SshTarget {
+    pub host: String,
+    pub port: u16,
+    /// 信頼するホスト鍵の指紋（`SHA256:...`）。未設定のホストには接続しない
+    pub host_key_fingerprint: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum SshError {
+    Io(io::Error),
+    Timeout,
+    Protocol(String),
+    /// 指紋が登録されていないホスト鍵。サーバーが提示した指紋を持つ
+    UnknownHostKey(String),
+    HostKeyMismatch { expected: String, actual: String },
+    Credential(String),
+    AuthenticationFailed,
+    ChannelRejected(String),
+}
+
+impl fmt::Display for SshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SshError::Io(e) => write!(f, "{}", e),
+            SshError::Timeout => write!(f, "ssh connection timed out"),
+            SshError::Protocol(message) => write!(f, "ssh protocol error: {}", message),
+            SshError::UnknownHostKey(actual) => write!(f, "host key {} is not trusted", actual),
+            SshError::HostKeyMismatch { expected, actual } => {
+                write!(f, "host key mismatch: expected {}, got {}", expected, actual)
+            }
+            SshError::Credential(message) => write!(f, "invalid credential: {}", message),
+            SshError::AuthenticationFailed => write!(f, "ssh authentication failed"),
+            SshError::ChannelRejected(message) => write!(f, "ssh channel rejected: {}", message),
+        }
+    }
+}
+
+impl From<io::Error> for SshError {
+    fn from(e: io::Error) -> Self {
+        SshError::Io(e)
+    }
+}
@@ -0,0 +1,620 @@
+//! テスト用の SSH サーバーを同じプロセスで動かし、クライアントを通しで確かめる
+use super::{
+    AuthMethod, ChannelEvent, ChannelRequest, Credential, SshClient, SshError, SshTarget,
+    transport::{
+        DirectionKeys, MSG_KEX_ECDH_INIT, MSG_KEX_ECDH_REPLY, MSG_KEXINIT, MSG_NEWKEYS, MSG_SERVICE_ACCEPT, MSG_SERVICE_REQUEST,
+        PacketReader, PacketWriter, ReadHalf, WriteHalf,
+    },
+    wire::{Reader, Writer},
+};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use signature::{Signer, Verifier};
+use ssh_key::{Algorithm, HashAlg, LineEnding, PrivateKey, PublicKey, Signature};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use x25519_dalek::EphemeralSecret;
+
+const MSG_USERAUTH_REQUEST: u8 = 50;
+const MSG_USERAUTH_FAILURE: u8 = 51;
+const MSG_USERAUTH_SUCCESS: u8 = 52;
+const MSG_CHANNEL_OPEN: u8 = 90;
+const MSG_CHANNEL_OPEN_CONFIRMATION: u8 = 91;
+const MSG_CHANNEL_OPEN_FAILURE: u8 = 92;
+const MSG_CHANNEL_DATA: u8 = 94;
+const MSG_CHANNEL_EXTENDED_DATA: u8 = 95;
+const MSG_CHANNEL_EOF: u8 = 96;
+const MSG_CHANNEL_CLOSE: u8 = 97;
+const MSG_CHANNEL_REQUEST: u8 = 98;
+const MSG_CHANNEL_SUCCESS: u8 = 99;
+const MSG_CHANNEL_FAILURE: u8 = 100;
+
+const SERVER_VERSION: &str = "SSH-2.0-GuardianTest";
+const USERNAME: &str = "tester";
+const PASSWORD: &str = "secret";
+const CIPHER: &str = "aes128-ctr";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// サーバーから鍵の再交換を始める時機
+#[derive(Clone, Copy, PartialEq)]
+enum Rekey {
+    Never,
+    /// exec の返事の前。クライアントはチャンネルを開いている途中
+    BeforeReply,
+    /// exec の返事の後。クライアントはチャンネルのタスクで受け取る
+    AfterReply,
+}
+
+struct TestServer {
+    host_key: PrivateKey,
+    authorized_key: PublicKey,
+    rekey: Rekey,
+}
+
+struct ServerConnection {
+    packets: mpsc::Receiver<Result<Vec<u8>, SshError>>,
+    reader_keys: mpsc::Sender<DirectionKeys>,
+    writer: PacketWriter<WriteHalf>,
+    client_version: String,
+    session_id: Vec<u8>,
+}
+
+impl ServerConnection {
+    async fn read(&mut self) -> Result<Vec<u8>, SshError> {
+        self.packets.recv().await.ok_or_else(|| SshError::Protocol("connection closed".to_string()))?
+    }
+
+    async fn expect(&mut self, message: u8) -> Result<Vec<u8>, SshError> {
+        let payload = self.read().await?;
+        if payload[0] != message {
+            return Err(SshError::Protocol(format!("expected message {}, got {}", message, payload[0])));
+        }
+        Ok(payload)
+    }
+
+    /// サーバー側の鍵交換。クライアントから再交換を求められたときは受け取った KEXINIT を渡す
+    async fn exchange_keys(&mut self, host_key: &PrivateKey, client_kexinit: Option<Vec<u8>>) -> Result<(), SshError> {
+        let mut cookie = [0u8; 16];
+        OsRng.fill_bytes(&mut cookie);
+        let mut kexinit = Writer::new(MSG_KEXINIT);
+        kexinit
+            .raw(&cookie)
+            .name_list(&["curve25519-sha256", "kex-strict-s-v00@openssh.com"])
+            .name_list(&["ssh-ed25519"])
+            .name_list(&[CIPHER])
+            .name_list(&[CIPHER])
+            .name_list(&["hmac-sha2-256"])
+            .name_list(&["hmac-sha2-256"])
+            .name_list(&["none"])
+            .name_list(&["none"])
+            .name_list(&[])
+            .name_list(&[])
+            .bool(false)
+            .u32(0);
+        self.writer.write(&kexinit).await?;
+        let client_kexinit = match client_kexinit {
+            Some(client_kexinit) => client_kexinit,
+            None => self.expect(MSG_KEXINIT).await?,
+        };
+
+        let init = self.expect(MSG_KEX_ECDH_INIT).await?;
+        let client_public: [u8; 32] = Reader::new(&init[1..]).string()?.try_into().expect("client sends a curve25519 key");
+        let secret = EphemeralSecret::random_from_rng(OsRng);
+        let server_public = x25519_dalek::PublicKey::from(&secret);
+        let shared = secret.diffie_hellman(&x25519_dalek::PublicKey::from(client_public));
+        let mut shared_secret = Writer::default();
+        shared_secret.mpint(shared.as_bytes());
+
+        let host_key_blob = host_key.public_key().to_bytes().expect("host key encodes");
+        let mut exchange = Writer::default();
+        exchange
+            .string(&self.client_version)
+            .string(SERVER_VERSION)
+            .string(&client_kexinit)
+            .string(kexinit.as_bytes())
+            .string(&host_key_blob)
+            .string(client_public)
+            .string(server_public.as_bytes())
+            .raw(shared_secret.as_bytes());
+        let exchange_hash = Sha256::digest(exchange.as_bytes()).to_vec();
+        if self.session_id.is_empty() {
+            self.session_id = exchange_hash.clone();
+        }
+
+        let signature: Signature = host_key.try_sign(&exchange_hash).expect("host key signs");
+        let mut signature_blob = Writer::default();
+        signature_blob.string(signature.algorithm().as_str()).string(signature.as_bytes());
+        let mut reply = Writer::new(MSG_KEX_ECDH_REPLY);
+        reply.string(&host_key_blob).string(server_public.as_bytes()).string(signature_blob.as_bytes());
+        self.writer.write(&reply).await?;
+
+        let derive = |letters| DirectionKeys::derive(CIPHER, shared_secret.as_bytes(), &exchange_hash, &self.session_id, letters);
+        let (server_keys, client_keys) = (derive(*b"BDF"), derive(*b"ACE"));
+        self.writer.write(&Writer::new(MSG_NEWKEYS)).await?;
+        self.writer.set_keys(server_keys, true);
+        self.expect(MSG_NEWKEYS).await?;
+        self.reader_keys.send(client_keys).await.map_err(|_| SshError::Protocol("reader stopped".to_string()))
+    }
+}
+
+impl TestServer {
+    fn new(rekey: Rekey, authorized_key: PublicKey) -> Self {
+        Self {
+            host_key: PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap(),
+            authorized_key,
+            rekey,
+        }
+    }
+
+    /// 待ち受けを始め、サーバーのホスト鍵を信頼する接続先を返す
+    async fn listen(self) -> SshTarget {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = SshTarget {
+            host: "127.0.0.1".to_string(),
+            port: listener.local_addr().unwrap().port(),
+            host_key_fingerprint: Some(self.host_key.public_key().fingerprint(HashAlg::Sha256).to_string()),
+        };
+        let server = Arc::new(self);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let server = server.clone();
+                tokio::spawn(async move {
+                    let _ = server.serve(stream).await;
+                });
+            }
+        });
+        target
+    }
+
+    async fn serve(&self, stream: TcpStream) -> Result<(), SshError> {
+        let (read_half, write_half) = tokio::io::split(stream);
+        let mut read_half = BufReader::new(Box::new(read_half) as ReadHalf);
+        let mut write_half = Box::new(write_half) as WriteHalf;
+        write_half.write_all(format!("{}\r\n", SERVER_VERSION).as_bytes()).await?;
+        let mut client_version = String::new();
+        read_half.read_line(&mut client_version).await?;
+
+        let (packet_tx, packets) = mpsc::channel(16);
+        let (reader_keys, mut keys_rx) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let mut reader = PacketReader::new(read_half);
+            loop {
+                let result = reader.read().await;
+                let failed = result.is_err();
+                let new_keys = matches!(&result, Ok(payload) if payload.first() == Some(&MSG_NEWKEYS));
+                if packet_tx.send(result).await.is_err() || failed {
+                    break;
+                }
+                if new_keys {
+                    match keys_rx.recv().await {
+                        Some(keys) => reader.set_keys(keys, true),
+                        None => break,
+                    }
+                }
+            }
+        });
+
+        let mut connection = ServerConnection {
+            packets,
+            reader_keys,
+            writer: PacketWriter::new(write_half),
+            client_version: client_version.trim_end().to_string(),
+            session_id: Vec::new(),
+        };
+        connection.exchange_keys(&self.host_key, None).await?;
+
+        connection.expect(MSG_SERVICE_REQUEST).await?;
+        let mut accept = Writer::new(MSG_SERVICE_ACCEPT);
+        accept.string("ssh-userauth");
+        connection.writer.write(&accept).await?;
+        loop {
+            let request = connection.expect(MSG_USERAUTH_REQUEST).await?;
+            if self.authenticate(&connection.session_id, &request)? {
+                connection.writer.write(&Writer::new(MSG_USERAUTH_SUCCESS)).await?;
+                break;
+            }
+            let mut failure = Writer::new(MSG_USERAUTH_FAILURE);
+            failure.name_list(&["password", "publickey"]).bool(false);
+            connection.writer.write(&failure).await?;
+        }
+
+        // チャンネルごとのタスクへメッセージを振り分ける。チャンネル番号はこちらで割り当てる
+        let (outgoing_tx, mut outgoing) = mpsc::unbounded_channel();
+        let mut channels = HashMap::new();
+        let mut next_id = 0u32;
+        loop {
+            tokio::select! {
+                payload = connection.read() => {
+                    let payload = payload?;
+                    match payload[0] {
+                        MSG_CHANNEL_OPEN => {
+                            let (channel_tx, channel_rx) = mpsc::unbounded_channel();
+                            channels.insert(next_id, channel_tx);
+                            tokio::spawn(open_channel(payload, next_id, self.rekey, channel_rx, outgoing_tx.clone()));
+                            next_id += 1;
+                        }
+                        MSG_CHANNEL_OPEN_CONFIRMATION..=MSG_CHANNEL_FAILURE => {
+                            let server_id = Reader::new(&payload[1..]).u32()?;
+                            if let Some(channel) = channels.get(&server_id) {
+                                let _ = channel.send(payload);
+                            }
+                        }
+                        MSG_KEXINIT => connection.exchange_keys(&self.host_key, Some(payload)).await?,
+                        _ => return Ok(()),
+                    }
+                }
+                message = outgoing.recv() => match message {
+                    Some(ServerOutgoing::Message(message)) => connection.writer.write(&message).await?,
+                    Some(ServerOutgoing::Rekey) => connection.exchange_keys(&self.host_key, None).await?,
+                    None => return Ok(()),
+                },
+            }
+        }
+    }
+
+    fn authenticate(&self, session_id: &[u8], request: &[u8]) -> Result<bool, SshError> {
+        let mut reader = Reader::new(&request[1..]);
+        let username = reader.utf8()?;
+        reader.utf8()?;
+        let method = reader.utf8()?;
+        if username != USERNAME {
+            return Ok(false);
+        }
+        match method.as_str() {
+            "password" => {
+                reader.bool()?;
+                Ok(reader.utf8()? == PASSWORD)
+            }
+            "publickey" => {
+                if !reader.bool()? {
+                    return Ok(false);
+                }
+                reader.utf8()?;
+                let key = PublicKey::from_bytes(reader.string()?).map_err(|e| SshError::Protocol(e.to_string()))?;
+                let signature_blob = reader.string()?;
+                let signature = Signature::try_from(signature_blob).map_err(|e| SshError::Protocol(e.to_string()))?;
+                let mut signed = Writer::default();
+                signed.string(session_id).raw(&request[..request.len() - 4 - signature_blob.len()]);
+                Ok(key.key_data() == self.authorized_key.key_data()
+                    && Verifier::verify(key.key_data(), signed.as_bytes(), &signature).is_ok())
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+/// チャンネルのタスクから接続へ渡すもの
+enum ServerOutgoing {
+    Message(Writer),
+    /// サーバーから鍵の再交換を始める
+    Rekey,
+}
+
+fn server_closed<T>(_: T) -> SshError {
+    SshError::Protocol("connection closed".to_string())
+}
+
+async fn open_channel(
+    open: Vec<u8>,
+    server_id: u32,
+    rekey: Rekey,
+    packets: mpsc::UnboundedReceiver<Vec<u8>>,
+    outgoing: mpsc::UnboundedSender<ServerOutgoing>,
+) -> Result<(), SshError> {
+    let mut reader = Reader::new(&open[1..]);
+    let kind = reader.utf8()?;
+    let client_id = reader.u32()?;
+    reader.u32()?;
+    reader.u32()?;
+    let mut confirmation = Writer::new(MSG_CHANNEL_OPEN_CONFIRMATION);
+    confirmation.u32(client_id).u32(server_id).u32(1024 * 1024).u32(32 * 1024);
+    let send = |message| outgoing.send(ServerOutgoing::Message(message)).map_err(server_closed);
+    match kind.as_str() {
+        "session" => {
+            send(confirmation)?;
+            serve_exec(client_id, rekey, packets, &outgoing).await
+        }
+        "direct-tcpip" => {
+            let host = reader.utf8()?;
+            let port = reader.u32()? as u16;
+            match TcpStream::connect((host.as_str(), port)).await {
+                Ok(stream) => {
+                    send(confirmation)?;
+                    serve_tunnel(client_id, packets, &outgoing, stream).await
+                }
+                Err(_) => {
+                    let mut failure = Writer::new(MSG_CHANNEL_OPEN_FAILURE);
+                    failure.u32(client_id).u32(2).string("connect failed").string("");
+                    send(failure)
+                }
+            }
+        }
+        other => Err(SshError::Protocol(format!("unexpected channel type {}", other))),
+    }
+}
+
+/// コマンドを標準出力に返し、`exit N` なら終了コード N で終える
+async fn serve_exec(
+    client_id: u32,
+    rekey: Rekey,
+    mut packets: mpsc::UnboundedReceiver<Vec<u8>>,
+    outgoing: &mpsc::UnboundedSender<ServerOutgoing>,
+) -> Result<(), SshError> {
+    let request = packets.recv().await.ok_or_else(|| server_closed(()))?;
+    let mut reader = Reader::new(&request[1..]);
+    reader.u32()?;
+    assert_eq!(reader.utf8()?, "exec");
+    reader.bool()?;
+    let command = reader.utf8()?;
+
+    let code = command.strip_prefix("exit ").and_then(|code| code.parse().ok()).unwrap_or(0);
+    let mut success = Writer::new(MSG_CHANNEL_SUCCESS);
+    success.u32(client_id);
+    let mut stdout = Writer::new(MSG_CHANNEL_DATA);
+    stdout.u32(client_id).string(format!("{}\n", command));
+    let mut stderr = Writer::new(MSG_CHANNEL_EXTENDED_DATA);
+    stderr.u32(client_id).u32(1).string("warning\n");
+    let mut status = Writer::new(MSG_CHANNEL_REQUEST);
+    status.u32(client_id).string("exit-status").bool(false).u32(code);
+    let mut eof = Writer::new(MSG_CHANNEL_EOF);
+    eof.u32(client_id);
+    let mut close = Writer::new(MSG_CHANNEL_CLOSE);
+    close.u32(client_id);
+
+    let mut messages = vec![ServerOutgoing::Message(success)];
+    match rekey {
+        Rekey::BeforeReply => messages.insert(0, ServerOutgoing::Rekey),
+        Rekey::AfterReply => messages.push(ServerOutgoing::Rekey),
+        Rekey::Never => {}
+    }
+    messages.extend([stdout, stderr, status, eof, close].map(ServerOutgoing::Message));
+    for message in messages {
+        outgoing.send(message).map_err(server_closed)?;
+    }
+    while let Some(payload) = packets.recv().await {
+        if payload[0] == MSG_CHANNEL_CLOSE {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// direct-tcpip のチャンネルと TCP 接続の間でデータを中継する
+async fn serve_tunnel(
+    client_id: u32,
+    mut packets: mpsc::UnboundedReceiver<Vec<u8>>,
+    outgoing: &mpsc::UnboundedSender<ServerOutgoing>,
+    stream: TcpStream,
+) -> Result<(), SshError> {
+    let send = |message| outgoing.send(ServerOutgoing::Message(message)).map_err(server_closed);
+    let (mut tcp_reader, mut tcp_writer) = stream.into_split();
+    let mut buffer = vec![0u8; 4096];
+    let mut tcp_open = true;
+    loop {
+        tokio::select! {
+            payload = packets.recv() => {
+                let Some(payload) = payload else {
+                    return Ok(());
+                };
+                match payload[0] {
+                    MSG_CHANNEL_DATA => {
+                        let mut reader = Reader::new(&payload[1..]);
+                        reader.u32()?;
+                        tcp_writer.write_all(reader.string()?).await?;
+                    }
+                    MSG_CHANNEL_EOF => tcp_writer.shutdown().await?,
+                    MSG_CHANNEL_CLOSE => return Ok(()),
+                    _ => {}
+                }
+            }
+            read = tcp_reader.read(&mut buffer), if tcp_open => {
+                let n = read?;
+                if n == 0 {
+                    tcp_open = false;
+                    let mut eof = Writer::new(MSG_CHANNEL_EOF);
+                    eof.u32(client_id);
+                    send(eof)?;
+                    let mut close = Writer::new(MSG_CHANNEL_CLOSE);
+                    close.u32(client_id);
+                    send(close)?;
+                } else {
+                    let mut data = Writer::new(MSG_CHANNEL_DATA);
+                    data.u32(client_id).string(&buffer[..n]);
+                    send(data)?;
+                }
+            }
+        }
+    }
+}
+
+fn password(password: &str) -> Credential {
+    Credential { username: USERNAME.to_string(), method: AuthMethod::Password(password.to_string()) }
+}
+
+fn random_key() -> PrivateKey {
+    PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap()
+}
+
+struct Output {
+    stdout: String,
+    stderr: String,
+    exit: Option<Option<i32>>,
+}
+
+async fn exec(target: &SshTarget, credential: &Credential, command: &str) -> Result<Output, SshError> {
+    let client = SshClient::connect(target, credential, TIMEOUT).await?;
+    let mut channel = client.open(ChannelRequest::Exec(command.to_string())).await?;
+    let mut output = Output { stdout: String::new(), stderr: String::new(), exit: None };
+    while let Some(event) = channel.events.recv().await {
+        match event {
+            ChannelEvent::Stdout(data) => output.stdout.push_str(&String::from_utf8_lossy(&data)),
+            ChannelEvent::Stderr(data) => output.stderr.push_str(&String::from_utf8_lossy(&data)),
+            ChannelEvent::Exit(code) => output.exit = Some(code),
+            ChannelEvent::Error(message) => return Err(SshError::Protocol(message)),
+        }
+    }
+    Ok(output)
+}
+
+#[tokio::test]
+async fn runs_commands_with_password_and_reports_exit_codes() {
+    let target = TestServer::new(Rekey::Never, random_key().public_key().clone()).listen().await;
+
+    for (command, code) in [("exit 0", 0), ("exit 3", 3), ("exit 255", 255)] {
+        let output = exec(&target, &password(PASSWORD), command).await.unwrap();
+        assert_eq!(output.stdout, format!("{}\n", command));
+        assert_eq!(output.stderr, "warning\n");
+        assert_eq!(output.exit, Some(Some(code)));
+    }
+}
+
+#[tokio::test]
+async fn authenticates_with_private_key() {
+    let client_key = random_key();
+    let mut target = TestServer::new(Rekey::Never, client_key.public_key().clone()).listen().await;
+    // ssh-keygen -l の出力から接頭辞を落としたものでも照合できる
+    target.host_key_fingerprint = target.host_key_fingerprint.map(|fingerprint| fingerprint.trim_start_matches("SHA256:").to_string());
+
+    let credential = Credential {
+        username: USERNAME.to_string(),
+        method: AuthMethod::PrivateKey { key: client_key.to_openssh(LineEnding::LF).unwrap().to_string(), passphrase: None },
+    };
+    let output = exec(&target, &credential, "exit 1").await.unwrap();
+    assert_eq!(output.exit, Some(Some(1)));
+
+    let other = Credential {
+        username: USERNAME.to_string(),
+        method: AuthMethod::PrivateKey { key: random_key().to_openssh(LineEnding::LF).unwrap().to_string(), passphrase: None },
+    };
+    assert!(matches!(SshClient::connect(&target, &other, TIMEOUT).await, Err(SshError::AuthenticationFailed)));
+}
+
+#[tokio::test]
+async fn rejects_wrong_password() {
+    let target = TestServer::new(Rekey::Never, random_key().public_key().clone()).listen().await;
+    assert!(matches!(SshClient::connect(&target, &password("wrong"), TIMEOUT).await, Err(SshError::AuthenticationFailed)));
+}
+
+#[tokio::test]
+async fn refuses_unknown_and_mismatched_host_keys() {
+    let server = TestServer::new(Rekey::Never, random_key().public_key().clone());
+    let actual = server.host_key.public_key().fingerprint(HashAlg::Sha256).to_string();
+    let target = server.listen().await;
+
+    let unknown = SshTarget { host_key_fingerprint: None, ..target.clone() };
+    match SshClient::connect(&unknown, &password(PASSWORD), TIMEOUT).await {
+        Err(SshError::UnknownHostKey(fingerprint)) => assert_eq!(fingerprint, actual),
+        other => panic!("expected an unknown host key, got {:?}", other.err()),
+    }
+
+    let expected = random_key().public_key().fingerprint(HashAlg::Sha256).to_string();
+    let mismatched = SshTarget { host_key_fingerprint: Some(expected.clone()), ..target };
+    match SshClient::connect(&mismatched, &password(PASSWORD), TIMEOUT).await {
+        Err(SshError::HostKeyMismatch { expected: e, actual: a }) => assert_eq!((e, a), (expected, actual)),
+        other => panic!("expected a host key mismatch, got {:?}", other.err()),
+    }
+}
+
+#[tokio::test]
+async fn re_exchanges_keys_requested_by_server() {
+    for rekey in [Rekey::BeforeReply, Rekey::AfterReply] {
+        let target = TestServer::new(rekey, random_key().public_key().clone()).listen().await;
+        let output = exec(&target, &password(PASSWORD), "exit 7").await.unwrap();
+        assert_eq!(output.stdout, "exit 7\n");
+        assert_eq!(output.exit, Some(Some(7)));
+    }
+}
+
+#[tokio::test]
+async fn forwards_direct_tcpip() {
+    let echo = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let echo_port = echo.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let (mut stream, _) = echo.accept().await.unwrap();
+        let (mut reader, mut writer) = stream.split();
+        tokio::io::copy(&mut reader, &mut writer).await.unwrap();
+    });
+    let target = TestServer::new(Rekey::Never, random_key().public_key().clone()).listen().await;
+
+    let client = SshClient::connect(&target, &password(PASSWORD), TIMEOUT).await.unwrap();
+    let channel = client.open(ChannelRequest::DirectTcpip { host: "127.0.0.1".to_string(), port: echo_port }).await.unwrap();
+    let (mut reader, mut writer) = tokio::io::split(channel.into_stream());
+    // 送受信の量が多いので、途中でクライアントから鍵を何度か再交換する
+    let sent: Vec<u8> = (0..512 * 1024).map(|i| (i % 251) as u8).collect();
+    let send = async {
+        writer.write_all(&sent).await.unwrap();
+        writer.shutdown().await.unwrap();
+    };
+    let mut echoed = Vec::new();
+    let (_, received) = tokio::time::timeout(TIMEOUT, async { tokio::join!(send, reader.read_to_end(&mut echoed)) }).await.unwrap();
+    received.unwrap();
+    assert!(echoed == sent, "echoed {} of {} bytes", echoed.len(), sent.len());
+}
+
+#[tokio::test]
+async fn reports_refused_direct_tcpip() {
+    let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let closed_port = closed.local_addr().unwrap().port();
+    drop(closed);
+    let target = TestServer::new(Rekey::Never, random_key().public_key().clone()).listen().await;
+
+    let client = SshClient::connect(&target, &password(PASSWORD), TIMEOUT).await.unwrap();
+    let result = client.open(ChannelRequest::DirectTcpip { host: "127.0.0.1".to_string(), port: closed_port }).await;
+    assert!(matches!(result, Err(SshError::ChannelRejected(_))));
+}
+
+#[tokio::test]
+async fn multiplexes_channels_over_one_connection() {
+    let echo = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let echo_port = echo.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = echo.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    let target = TestServer::new(Rekey::Never, random_key().public_key().clone()).listen().await;
+    let client = SshClient::connect(&target, &password(PASSWORD), TIMEOUT).await.unwrap();
+
+    let tunnels = (0..4u8).map(|i| {
+        let client = client.clone();
+        tokio::spawn(async move {
+            let request = ChannelRequest::DirectTcpip { host: "127.0.0.1".to_string(), port: echo_port };
+            let mut stream = client.open(request).await.unwrap().into_stream();
+            let sent = vec![i; 100 * 1024];
+            stream.write_all(&sent).await.unwrap();
+            let mut echoed = vec![0u8; sent.len()];
+            stream.read_exact(&mut echoed).await.unwrap();
+            assert!(echoed == sent);
+        })
+    });
+    let commands = (0..2).map(|i| {
+        let client = client.clone();
+        tokio::spawn(async move {
+            let mut channel = client.open(ChannelRequest::Exec(format!("exit {}", i))).await.unwrap();
+            let mut exit = None;
+            while let Some(event) = channel.events.recv().await {
+                if let ChannelEvent::Exit(code) = event {
+                    exit = Some(code);
+                }
+            }
+            assert_eq!(exit, Some(Some(i)));
+        })
+    });
+    let tasks = tunnels.chain(commands).collect::<Vec<_>>();
+    tokio::time::timeout(TIMEOUT, async {
+        for task in tasks {
+            task.await.unwrap();
+        }
+    })
+    .await
+    .unwrap();
+    assert!(!client.is_closed());
+}
@@ -0,0 +1,515 @@
+//! SSH のトランスポート層（RFC 4253）。鍵交換までを行い、暗号化したパケットを読み書きする
+use super::{
+    SshError, SshTarget,
+    wire::{Reader, Writer},
+};
+
+use aes::{Aes128, Aes256};
+use ctr::{
+    Ctr128BE,
+    cipher::{KeyIvInit, StreamCipher},
+};
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use signature::Verifier;
+use ssh_key::{HashAlg, PublicKey, Signature};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use x25519_dalek::EphemeralSecret;
+
+pub const MSG_DISCONNECT: u8 = 1;
+pub const MSG_IGNORE: u8 = 2;
+pub const MSG_UNIMPLEMENTED: u8 = 3;
+pub const MSG_DEBUG: u8 = 4;
+pub const MSG_SERVICE_REQUEST: u8 = 5;
+pub const MSG_SERVICE_ACCEPT: u8 = 6;
+pub const MSG_EXT_INFO: u8 = 7;
+pub const MSG_KEXINIT: u8 = 20;
+pub const MSG_NEWKEYS: u8 = 21;
+pub const MSG_KEX_ECDH_INIT: u8 = 30;
+pub const MSG_KEX_ECDH_REPLY: u8 = 31;
+
+const CLIENT_VERSION: &str = "SSH-2.0-GuardianCentral_0.1";
+/// RFC 4253 6.1 で受け付けが求められている大きさに余裕を持たせたもの
+const MAX_PACKET_LEN: usize = 256 * 1024;
+const MAC_LEN: usize = 32;
+const MAX_BANNER_LINES: usize = 32;
+
+const KEX_ALGORITHMS: [&str; 3] = ["curve25519-sha256", "curve25519-sha256@libssh.org", "kex-strict-c-v00@openssh.com"];
+const STRICT_KEX_SERVER: &str = "kex-strict-s-v00@openssh.com";
+const HOST_KEY_ALGORITHMS: [&str; 3] = ["ssh-ed25519", "rsa-sha2-512", "rsa-sha2-256"];
+const CIPHERS: [&str; 2] = ["aes256-ctr", "aes128-ctr"];
+const MACS: [&str; 1] = ["hmac-sha2-256"];
+const COMPRESSION: [&str; 1] = ["none"];
+
+/// 片方向の暗号鍵と MAC の鍵
+pub struct DirectionKeys {
+    cipher: Box<dyn StreamCipher + Send>,
+    mac_key: Vec<u8>,
+}
+
+impl DirectionKeys {
+    /// RFC 4253 7.2 の文字（IV、暗号鍵、MAC 鍵の順）から導出する
+    pub fn derive(cipher: &str, shared_secret: &[u8], exchange_hash: &[u8], session_id: &[u8], letters: [u8; 3]) -> Self {
+        let derive = |letter: u8, len: usize| derive_key(shared_secret, exchange_hash, letter, session_id, len);
+        let [iv, key, mac] = letters;
+        Self {
+            cipher: new_cipher(cipher, &derive(key, key_len(cipher)), &derive(iv, 16)),
+            mac_key: derive(mac, MAC_LEN),
+        }
+    }
+
+    fn mac(&self, sequence: u32, packet: &[u8]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.mac_key).expect("hmac accepts any key length");
+        mac.update(&sequence.to_be_bytes());
+        mac.update(packet);
+        mac
+    }
+}
+
+pub struct PacketReader<R> {
+    reader: R,
+    sequence: u32,
+    keys: Option<DirectionKeys>,
+}
+
+impl<R: AsyncRead + Unpin> PacketReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader, sequence: 0, keys: None }
+    }
+
+    /// NEWKEYS を受け取った後に呼び、以降のパケットを新しい鍵で読む
+    pub fn set_keys(&mut self, keys: DirectionKeys, strict: bool) {
+        self.keys = Some(keys);
+        if strict {
+            self.sequence = 0;
+        }
+    }
+
+    /// パケットを1つ読み、ペイロードを返す
+    pub async fn read(&mut self) -> Result<Vec<u8>, SshError> {
+        let block = if self.keys.is_some() { 16 } else { 8 };
+        let mut packet = vec![0u8; block];
+        self.reader.read_exact(&mut packet).await?;
+        if let Some(keys) = &mut self.keys {
+            keys.cipher.apply_keystream(&mut packet);
+        }
+
+        let len = u32::from_be_bytes([packet[0], packet[1], packet[2], packet[3]]) as usize;
+        if len < 5 || len + 4 > MAX_PACKET_LEN || !(len + 4).is_multiple_of(block) {
+            return Err(SshError::Protocol(format!("invalid packet length {}", len)));
+        }
+        packet.resize(len + 4, 0);
+        self.reader.read_exact(&mut packet[block..]).await?;
+
+        if let Some(keys) = &mut self.keys {
+            keys.cipher.apply_keystream(&mut packet[block..]);
+            let mut mac = [0u8; MAC_LEN];
+            self.reader.read_exact(&mut mac).await?;
+            keys.mac(self.sequence, &packet)
+                .verify_slice(&mac)
+                .map_err(|_| SshError::Protocol("message authentication failed".to_string()))?;
+        }
+        self.sequence = self.sequence.wrapping_add(1);
+
+        let padding = packet[4] as usize;
+        if padding + 1 > len {
+            return Err(SshError::Protocol("invalid padding length".to_string()));
+        }
+        packet.truncate(4 + len - padding);
+        packet.drain(..5);
+        Ok(packet)
+    }
+}
+
+pub struct PacketWriter<W> {
+    writer: W,
+    sequence: u32,
+    keys: Option<DirectionKeys>,
+}
+
+impl<W: AsyncWrite + Unpin> PacketWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, sequence: 0, keys: None }
+    }
+
+    /// NEWKEYS を送った後に呼び、以降のパケットを新しい鍵で書く
+    pub fn set_keys(&mut self, keys: DirectionKeys, strict: bool) {
+        self.keys = Some(keys);
+        if strict {
+            self.sequence = 0;
+        }
+    }
+
+    pub async fn write(&mut self, payload: &Writer) -> Result<(), SshError> {
+        let payload = payload.as_bytes();
+        let block = if self.keys.is_some() { 16 } else { 8 };
+        let mut padding = block - (payload.len() + 5) % block;
+        if padding < 4 {
+            padding += block;
+        }
+
+        let mut packet = Vec::with_capacity(payload.len() + 5 + padding + MAC_LEN);
+        packet.extend_from_slice(&((1 + payload.len() + padding) as u32).to_be_bytes());
+        packet.push(padding as u8);
+        packet.extend_from_slice(payload);
+        let start = packet.len();
+        packet.resize(start + padding, 0);
+        OsRng.fill_bytes(&mut packet[start..]);
+
+        if let Some(keys) = &mut self.keys {
+            let mac = keys.mac(self.sequence, &packet).finalize().into_bytes();
+            keys.cipher.apply_keystream(&mut packet);
+            packet.extend_from_slice(&mac);
+        }
+        self.sequence = self.sequence.wrapping_add(1);
+
+        self.writer.write_all(&packet).await?;
+        Ok(())
+    }
+}
+
+/// TCP 接続のほか、踏み台で開いたチャンネルの上でも使えるようにしておく
+pub type ReadHalf = Box<dyn AsyncRead + Send + Unpin>;
+pub type WriteHalf = Box<dyn AsyncWrite + Send + Unpin>;
+
+pub struct Transport {
+    pub reader: PacketReader<BufReader<ReadHalf>>,
+    pub writer: PacketWriter<WriteHalf>,
+    pub session_id: Vec<u8>,
+    pub server_version: String,
+    /// 最初の鍵交換で strict kex に合意したか。以降の鍵交換でも順序番号を 0 に戻す
+    pub strict: bool,
+}
+
+impl Transport {
+    /// 鍵交換中の雑多なメッセージを読み飛ばして次のメッセージを返す
+    pub async fn read(&mut self) -> Result<Vec<u8>, SshError> {
+        loop {
+            let payload = self.reader.read().await?;
+            match payload.first() {
+                Some(&MSG_IGNORE | &MSG_DEBUG | &MSG_UNIMPLEMENTED | &MSG_EXT_INFO) => continue,
+                Some(&MSG_DISCONNECT) => return Err(disconnected(&payload)),
+                Some(_) => return Ok(payload),
+                None => return Err(SshError::Protocol("empty message".to_string())),
+            }
+        }
+    }
+
+    pub async fn expect(&mut self, message: u8) -> Result<Vec<u8>, SshError> {
+        let payload = self.read().await?;
+        if payload[0] != message {
+            return Err(SshError::Protocol(format!("expected message {}, got {}", message, payload[0])));
+        }
+        Ok(payload)
+    }
+
+    /// 鍵交換を最後まで行う。サーバーから再交換を求められたときは受け取った KEXINIT を渡す
+    pub async fn exchange_keys(&mut self, target: &SshTarget, server_kexinit: Option<Vec<u8>>) -> Result<(), SshError> {
+        let mut kex = Kex::start();
+        self.writer.write(kex.kexinit()).await?;
+        let server_kexinit = match server_kexinit {
+            Some(server_kexinit) => server_kexinit,
+            None => self.expect(MSG_KEXINIT).await?,
+        };
+        let init = kex.negotiate(server_kexinit)?;
+        if kex.discard_guess() {
+            self.reader.read().await?;
+        }
+        self.writer.write(&init).await?;
+
+        let first = self.session_id.is_empty();
+        if first {
+            self.strict = kex.strict();
+        }
+        let reply = self.expect(MSG_KEX_ECDH_REPLY).await?;
+        let keys = kex.finish(&reply, &self.server_version, target, (!first).then_some(self.session_id.as_slice()))?;
+
+        self.writer.write(&Writer::new(MSG_NEWKEYS)).await?;
+        self.writer.set_keys(keys.client, self.strict);
+        self.expect(MSG_NEWKEYS).await?;
+        self.reader.set_keys(keys.server, self.strict);
+        if first {
+            self.session_id = keys.exchange_hash;
+        }
+        Ok(())
+    }
+}
+
+pub fn disconnected(payload: &[u8]) -> SshError {
+    let mut reader = Reader::new(&payload[1..]);
+    let description = reader.u32().and_then(|_| reader.utf8()).unwrap_or_default();
+    SshError::Protocol(format!("disconnected by server: {}", description))
+}
+
+/// バージョン交換と鍵交換を行い、ホスト鍵を `target` の指紋と照合する
+pub async fn handshake<S>(stream: S, target: &SshTarget) -> Result<Transport, SshError>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let (read_half, write_half) = tokio::io::split(stream);
+    let mut read_half = BufReader::new(Box::new(read_half) as ReadHalf);
+    let mut write_half = Box::new(write_half) as WriteHalf;
+
+    write_half.write_all(format!("{}\r\n", CLIENT_VERSION).as_bytes()).await?;
+    let server_version = read_version(&mut read_half).await?;
+
+    let mut transport = Transport {
+        reader: PacketReader::new(read_half),
+        writer: PacketWriter::new(write_half),
+        session_id: Vec::new(),
+        server_version,
+        strict: false,
+    };
+    transport.exchange_keys(target, None).await?;
+    Ok(transport)
+}
+
+/// 鍵交換1回分の状態。最初の鍵交換と接続中の再交換の両方で使う
+pub struct Kex {
+    kexinit: Writer,
+    secret: EphemeralSecret,
+    server_kexinit: Vec<u8>,
+    algorithms: Option<Algorithms>,
+}
+
+/// 鍵交換で得た新しい鍵。`client` はクライアントが送る向き
+pub struct NewKeys {
+    pub exchange_hash: Vec<u8>,
+    pub client: DirectionKeys,
+    pub server: DirectionKeys,
+}
+
+impl Kex {
+    /// こちらの KEXINIT と一時鍵を用意する
+    pub fn start() -> Self {
+        let mut cookie = [0u8; 16];
+        OsRng.fill_bytes(&mut cookie);
+        let mut kexinit = Writer::new(MSG_KEXINIT);
+        kexinit
+            .raw(&cookie)
+            .name_list(&KEX_ALGORITHMS)
+            .name_list(&HOST_KEY_ALGORITHMS)
+            .name_list(&CIPHERS)
+            .name_list(&CIPHERS)
+            .name_list(&MACS)
+            .name_list(&MACS)
+            .name_list(&COMPRESSION)
+            .name_list(&COMPRESSION)
+            .name_list(&[])
+            .name_list(&[])
+            .bool(false)
+            .u32(0);
+        Self {
+            kexinit,
+            secret: EphemeralSecret::random_from_rng(OsRng),
+            server_kexinit: Vec::new(),
+            algorithms: None,
+        }
+    }
+
+    /// こちらから送る KEXINIT
+    pub fn kexinit(&self) -> &Writer {
+        &self.kexinit
+    }
+
+    /// サーバーの KEXINIT からアルゴリズムを決め、次に送る KEX_ECDH_INIT を返す
+    pub fn negotiate(&mut self, server_kexinit: Vec<u8>) -> Result<Writer, SshError> {
+        self.algorithms = Some(negotiate(&server_kexinit)?);
+        self.server_kexinit = server_kexinit;
+        let mut init = Writer::new(MSG_KEX_ECDH_INIT);
+        init.string(x25519_dalek::PublicKey::from(&self.secret).as_bytes());
+        Ok(init)
+    }
+
+    /// サーバーが推測で送ってくる鍵交換パケットが外れていて、捨てる必要があるか
+    pub fn discard_guess(&self) -> bool {
+        self.algorithms.as_ref().is_some_and(|algorithms| algorithms.discard_guess)
+    }
+
+    fn strict(&self) -> bool {
+        self.algorithms.as_ref().is_some_and(|algorithms| algorithms.strict)
+    }
+
+    /// KEX_ECDH_REPLY のホスト鍵と署名を確かめて新しい鍵を導出する。
+    /// 最初の鍵交換では `session_id` に `None` を渡し、交換ハッシュをセッション ID とする
+    pub fn finish(
+        self,
+        reply: &[u8],
+        server_version: &str,
+        target: &SshTarget,
+        session_id: Option<&[u8]>,
+    ) -> Result<NewKeys, SshError> {
+        let algorithms = self.algorithms
+            .ok_or_else(|| SshError::Protocol("key exchange reply before KEXINIT".to_string()))?;
+        let client_public = x25519_dalek::PublicKey::from(&self.secret);
+
+        let mut reader = Reader::new(&reply[1..]);
+        let host_key_blob = reader.string()?;
+        let server_public: [u8; 32] = reader.string()?
+            .try_into()
+            .map_err(|_| SshError::Protocol("invalid ephemeral key".to_string()))?;
+        let signature_blob = reader.string()?;
+
+        let shared = self.secret.diffie_hellman(&x25519_dalek::PublicKey::from(server_public));
+        if !shared.was_contributory() {
+            return Err(SshError::Protocol("invalid ephemeral key".to_string()));
+        }
+        let mut shared_secret = Writer::default();
+        shared_secret.mpint(shared.as_bytes());
+
+        let mut exchange = Writer::default();
+        exchange
+            .string(CLIENT_VERSION)
+            .string(server_version)
+            .string(self.kexinit.as_bytes())
+            .string(&self.server_kexinit)
+            .string(host_key_blob)
+            .string(client_public.as_bytes())
+            .string(server_public)
+            .raw(shared_secret.as_bytes());
+        let exchange_hash = Sha256::digest(exchange.as_bytes()).to_vec();
+
+        verify_host_key(target, &algorithms.host_key, host_key_blob, signature_blob, &exchange_hash)?;
+
+        let session_id = session_id.unwrap_or(&exchange_hash);
+        Ok(NewKeys {
+            client: DirectionKeys::derive(&algorithms.client_cipher, shared_secret.as_bytes(), &exchange_hash, session_id, *b"ACE"),
+            server: DirectionKeys::derive(&algorithms.server_cipher, shared_secret.as_bytes(), &exchange_hash, session_id, *b"BDF"),
+            exchange_hash,
+        })
+    }
+}
+
+/// サーバーはバージョンの前に任意の行を送ってよい（RFC 4253 4.2）
+async fn read_version<R: AsyncRead + Unpin>(reader: &mut BufReader<R>) -> Result<String, SshError> {
+    for _ in 0..MAX_BANNER_LINES {
+        let mut line = Vec::new();
+        (&mut *reader).take(256).read_until(b'\n', &mut line).await?;
+        if line.is_empty() {
+            return Err(SshError::Protocol("connection closed during version exchange".to_string()));
+        }
+        let line = String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string();
+        if line.starts_with("SSH-") {
+            if !line.starts_with("SSH-2.0-") && !line.starts_with("SSH-1.99-") {
+                return Err(SshError::Protocol(format!("unsupported protocol version {}", line)));
+            }
+            return Ok(line);
+        }
+    }
+    Err(SshError::Protocol("no version string from server".to_string()))
+}
+
+struct Algorithms {
+    host_key: String,
+    client_cipher: String,
+    server_cipher: String,
+    strict: bool,
+    discard_guess: bool,
+}
+
+fn negotiate(server_kexinit: &[u8]) -> Result<Algorithms, SshError> {
+    let mut reader = Reader::new(&server_kexinit[1..]);
+    reader.skip(16)?;
+    let kex = reader.name_list()?;
+    let host_key = reader.name_list()?;
+    let client_cipher = reader.name_list()?;
+    let server_cipher = reader.name_list()?;
+    let client_mac = reader.name_list()?;
+    let server_mac = reader.name_list()?;
+    let client_compression = reader.name_list()?;
+    let server_compression = reader.name_list()?;
+    reader.name_list()?;
+    reader.name_list()?;
+    let guess_follows = reader.bool()?;
+
+    let chosen_kex = choose("key exchange", &KEX_ALGORITHMS[..2], &kex)?;
+    let chosen_host_key = choose("host key", &HOST_KEY_ALGORITHMS, &host_key)?;
+    choose("mac", &MACS, &client_mac)?;
+    choose("mac", &MACS, &server_mac)?;
+    choose("compression", &COMPRESSION, &client_compression)?;
+    choose("compression", &COMPRESSION, &server_compression)?;
+
+    Ok(Algorithms {
+        discard_guess: guess_follows
+            && (kex.first() != Some(&chosen_kex) || host_key.first() != Some(&chosen_host_key)),
+        host_key: chosen_host_key,
+        client_cipher: choose("cipher", &CIPHERS, &client_cipher)?,
+        server_cipher: choose("cipher", &CIPHERS, &server_cipher)?,
+        strict: kex.iter().any(|name| name == STRICT_KEX_SERVER),
+    })
+}
+
+/// クライアント側の優先順で、サーバーも対応しているものを選ぶ
+fn choose(kind: &str, client: &[&str], server: &[String]) -> Result<String, SshError> {
+    client.iter()
+        .find(|name| server.iter().any(|s| s == *name))
+        .map(|name| name.to_string())
+        .ok_or_else(|| SshError::Protocol(format!("no common {} algorithm (server offers {})", kind, server.join(","))))
+}
+
+fn verify_host_key(
+    target: &SshTarget,
+    algorithm: &str,
+    host_key_blob: &[u8],
+    signature_blob: &[u8],
+    exchange_hash: &[u8],
+) -> Result<(), SshError> {
+    let host_key = PublicKey::from_bytes(host_key_blob)
+        .map_err(|e| SshError::Protocol(format!("invalid host key: {}", e)))?;
+    let signature = Signature::try_from(signature_blob)
+        .map_err(|e| SshError::Protocol(format!("invalid host key signature: {}", e)))?;
+    if signature.algorithm().as_str() != algorithm {
+        return Err(SshError::Protocol(format!("unexpected host key signature algorithm {}", signature.algorithm())));
+    }
+    Verifier::verify(host_key.key_data(), exchange_hash, &signature)
+        .map_err(|_| SshError::Protocol("host key signature verification failed".to_string()))?;
+
+    let actual = host_key.fingerprint(HashAlg::Sha256).to_string();
+    match &target.host_key_fingerprint {
+        None => Err(SshError::UnknownHostKey(actual)),
+        Some(expected) if normalize_fingerprint(expected) != normalize_fingerprint(&actual) => {
+            Err(SshError::HostKeyMismatch { expected: expected.clone(), actual })
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+/// `ssh-keygen -l` の出力と比べやすいよう、接頭辞と末尾の `=` の有無を無視する
+fn normalize_fingerprint(fingerprint: &str) -> &str {
+    let fingerprint = fingerprint.trim();
+    fingerprint.strip_prefix("SHA256:").unwrap_or(fingerprint).trim_end_matches('=')
+}
+
+/// RFC 4253 7.2 の鍵導出
+fn derive_key(shared_secret: &[u8], exchange_hash: &[u8], letter: u8, session_id: &[u8], len: usize) -> Vec<u8> {
+    let mut key = Sha256::new()
+        .chain_update(shared_secret)
+        .chain_update(exchange_hash)
+        .chain_update([letter])
+        .chain_update(session_id)
+        .finalize()
+        .to_vec();
+    while key.len() < len {
+        let next = Sha256::new()
+            .chain_update(shared_secret)
+            .chain_update(exchange_hash)
+            .chain_update(&key)
+            .finalize();
+        key.extend_from_slice(&next);
+    }
+    key.truncate(len);
+    key
+}
+
+fn key_len(cipher: &str) -> usize {
+    if cipher == "aes128-ctr" { 16 } else { 32 }
+}
+
+fn new_cipher(cipher: &str, key: &[u8], iv: &[u8]) -> Box<dyn StreamCipher + Send> {
+    if cipher == "aes128-ctr" {
+        Box::new(Ctr128BE::<Aes128>::new_from_slices(key, iv).expect("key and iv lengths match the cipher"))
+    } else {
+        Box::new(Ctr128BE::<Aes256>::new_from_slices(key, iv).expect("key and iv lengths match the cipher"))
+    }
+}
@@ -0,0 +1,109 @@
+//! SSH のデータ型（RFC 4251 5節）の読み書き
+use super::SshError;
+
+#[derive(Default)]
+pub struct Writer(Vec<u8>);
+
+impl Writer {
+    pub fn new(message: u8) -> Self {
+        Self(vec![message])
+    }
+
+    pub fn byte(&mut self, value: u8) -> &mut Self {
+        self.0.push(value);
+        self
+    }
+
+    pub fn bool(&mut self, value: bool) -> &mut Self {
+        self.byte(value as u8)
+    }
+
+    pub fn u32(&mut self, value: u32) -> &mut Self {
+        self.0.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn string(&mut self, value: impl AsRef<[u8]>) -> &mut Self {
+        let value = value.as_ref();
+        self.u32(value.len() as u32);
+        self.0.extend_from_slice(value);
+        self
+    }
+
+    pub fn name_list(&mut self, names: &[&str]) -> &mut Self {
+        self.string(names.join(","))
+    }
+
+    /// 符号なしの big-endian の値を mpint として書く
+    pub fn mpint(&mut self, value: &[u8]) -> &mut Self {
+        let start = value.iter().position(|b| *b != 0).unwrap_or(value.len());
+        let value = &value[start..];
+        if value.first().is_some_and(|b| b & 0x80 != 0) {
+            self.u32(value.len() as u32 + 1);
+            self.0.push(0);
+            self.0.extend_from_slice(value);
+            self
+        } else {
+            self.string(value)
+        }
+    }
+
+    pub fn raw(&mut self, value: &[u8]) -> &mut Self {
+        self.0.extend_from_slice(value);
+        self
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SshError> {
+        let end = self.position.checked_add(len).filter(|end| *end <= self.data.len())
+            .ok_or_else(|| SshError::Protocol("truncated message".to_string()))?;
+        let value = &self.data[self.position..end];
+        self.position = end;
+        Ok(value)
+    }
+
+    pub fn byte(&mut self) -> Result<u8, SshError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, SshError> {
+        Ok(self.byte()? != 0)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SshError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn string(&mut self) -> Result<&'a [u8], SshError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    pub fn utf8(&mut self) -> Result<String, SshError> {
+        Ok(String::from_utf8_lossy(self.string()?).into_owned())
+    }
+
+    pub fn name_list(&mut self) -> Result<Vec<String>, SshError> {
+        let names = self.utf8()?;
+        Ok(names.split(',').filter(|name| !name.is_empty()).map(|name| name.to_string()).collect())
+    }
+
+    pub fn skip(&mut self, len: usize) -> Result<(), SshError> {
+        self.take(len).map(|_| ())
+    }
+}
@@ -0,0 +1,170 @@
+use common::{agent::exec::ExecEvent, central::information::ServerInformation};
+use std::{
+    collections::VecDeque,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use axum::{
+    body::Body,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use futures::{Stream, StreamExt, stream};
+use reqwest::Client as HttpClient;
+use sqlx::SqlitePool;
+
+const AGENT_TIMEOUT: Duration = Duration::from_secs(10);
+const STREAM_READ_TIMEOUT: Duration = Duration::from_secs(300);
+
+pub async fn find_server(pool: &SqlitePool, id: &str) -> Result<ServerInformation, StatusCode> {
+    sqlx::query_as::<_, ServerInformation>(
+        r#"SELECT id, hostname, ip_address, os_type, tags, auth_profile_id, port, bastion_server_id, wol_mac_address, wol_relay_server_id FROM servers WHERE id = ?"#,
+    )
+        .bind(id)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
+            e => {
+                tracing::error!("Failed to fetch server's information: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        })
+}
+
+pub fn agent_url(server: &ServerInformation, path: &str) -> String {
+    format!("http://{}/api/agent/v1{}", server.ip_address, path)
+}
+
+/// `ip_address` はエージェントの `host:port`。ポートがなければ HTTP の既定のポート
+pub fn agent_endpoint(address: &str) -> (String, u16) {
+    if let Ok(addr) = address.parse::<SocketAddr>() {
+        return (addr.ip().to_string(), addr.port());
+    }
+    if address.parse::<IpAddr>().is_ok() {
+        return (address.to_string(), 80);
+    }
+    match address.rsplit_once(':') {
+        Some((host, port)) => match port.parse::<u16>() {
+            Ok(port) => (host.to_string(), port),
+            Err(_) => (address.to_string(), 80),
+        },
+        None => (address.to_string(), 80),
+    }
+}
+
+pub fn http_client() -> Result<HttpClient, StatusCode> {
+    HttpClient::builder()
+        .timeout(AGENT_TIMEOUT)
+        .build()
+        .map_err(|e| {
+            tracing::error!("Failed to build HTTP client: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// 長時間のストリーミング用。全体のタイムアウトは設けず、無通信の時間だけを制限する。
+/// `/exec` などのエージェントは出力がなくても `Keepalive` を送るので、静かなコマンドでも切れない
+pub fn streaming_client() -> Result<HttpClient, StatusCode> {
+    HttpClient::builder()
+        .connect_timeout(AGENT_TIMEOUT)
+        .read_timeout(STREAM_READ_TIMEOUT)
+        .build()
+        .map_err(|e| {
+            tracing::error!("Failed to build HTTP client: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// エージェントが返す NDJSON を `ExecEvent` のストリームに変換する
+pub fn exec_events(response: reqwest::Response) -> impl Stream<Item = ExecEvent> + Send {
+    let state = (response.bytes_stream(), Vec::<u8>::new(), VecDeque::<ExecEvent>::new(), false);
+
+    stream::unfold(state, |(mut bytes, mut buffer, mut pending, mut done)| async move {
+        loop {
+            if let Some(event) = pending.pop_front() {
+                return Some((event, (bytes, buffer, pending, done)));
+            }
+            if done {
+                return None;
+            }
+
+            match bytes.next().await {
+                Some(Ok(chunk)) => {
+                    buffer.extend_from_slice(&chunk);
+                    while let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+                        let line = buffer.drain(..=pos).collect::<Vec<u8>>();
+                        match serde_json::from_slice::<ExecEvent>(&line) {
+                            Ok(ExecEvent::Keepalive) => {}
+                            Ok(event) => pending.push_back(event),
+                            Err(e) => tracing::warn!("Failed to parse agent event: {}", e),
+                        }
+                    }
+                }
+                Some(Err(e)) => {
+                    pending.push_back(ExecEvent::Error { message: format!("connection to agent lost: {}", e) });
+                    done = true;
+                }
+                None => done = true,
+            }
+        }
+    })
+}
+
+/// エージェントからのレスポンスのステータスとボディをそのまま返す
+pub async fn relay_response(response: reqwest::Response) -> Response {
+    let status = StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/json")
+        .to_string();
+    match response.bytes().await {
+        Ok(body) => (status, [(header::CONTENT_TYPE, content_type)], body).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to read agent response: {}", e);
+            StatusCode::BAD_GATEWAY.into_response()
+        }
+    }
+}
+
+/// SSE などのストリームをバッファリングせずにそのまま中継する
+pub fn relay_stream(response: reqwest::Response) -> Response {
+    let status = StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/json")
+        .to_string();
+    (
+        status,
+        [(header::CONTENT_TYPE, content_type), (header::CACHE_CONTROL, "no-cache".to_string())],
+        Body::from_stream(response.bytes_stream()),
+    )
+        .into_response()
+}
+
+/// ファイルのダウンロードを中継する。Range や ETag に関わるヘッダーも引き継ぐ
+pub fn relay_file(response: reqwest::Response) -> Response {
+    let status = StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+    let mut headers = HeaderMap::new();
+    for name in [
+        header::CONTENT_TYPE,
+        header::CONTENT_LENGTH,
+        header::CONTENT_RANGE,
+        header::CONTENT_DISPOSITION,
+        header::ACCEPT_RANGES,
+        header::LAST_MODIFIED,
+        header::ETAG,
+    ] {
+        if let Some(value) = response.headers().get(name.as_str())
+            && let Ok(value) = HeaderValue::from_bytes(value.as_bytes())
+        {
+            headers.insert(name, value);
+        }
+    }
+    (status, headers, Body::from_stream(response.bytes_stream())).into_response()
+}
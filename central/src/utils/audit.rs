@@ -0,0 +1,65 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{StatusCode, request::Parts},
+};
+use chrono::Utc;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+const OPERATOR_HEADER: &str = "x-operator";
+
+/// 操作を行った人。`X-Operator` ヘッダーがなければ接続元 IP アドレスを使う
+pub struct Actor(pub String);
+
+impl<S: Send + Sync> FromRequestParts<S> for Actor {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(operator) = parts
+            .headers
+            .get(OPERATOR_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.trim().is_empty())
+        {
+            return Ok(Actor(operator.trim().to_string()));
+        }
+
+        let actor = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        Ok(Actor(actor))
+    }
+}
+
+pub struct AuditEntry<'a> {
+    pub server_id: &'a str,
+    pub actor: &'a str,
+    pub action: &'a str,
+    pub target: &'a str,
+    pub detail: Option<serde_json::Value>,
+    pub status_code: StatusCode,
+}
+
+pub async fn record(pool: &SqlitePool, entry: AuditEntry<'_>) {
+    let result = sqlx::query(
+        r#"INSERT INTO audit_logs (id, server_id, actor, action, target, detail, status_code, created_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?)"#,
+    )
+        .bind(Uuid::new_v4().to_string())
+        .bind(entry.server_id)
+        .bind(entry.actor)
+        .bind(entry.action)
+        .bind(entry.target)
+        .bind(entry.detail.map(|d| d.to_string()))
+        .bind(entry.status_code.as_u16())
+        .bind(Utc::now())
+        .execute(pool)
+        .await;
+
+    if let Err(e) = result {
+        tracing::error!("Failed to record audit log: {}", e);
+    }
+}
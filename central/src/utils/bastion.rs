@@ -0,0 +1,425 @@
+//! 踏み台（`bastion_server_id`）を経由した接続。
+//! SSH は踏み台ごとに direct-tcpip のチャンネルを開いて次の SSH をその上で始める。
+//! エージェントへの HTTP は、踏み台経由のサーバーごとにローカルの転送ポートを用意して中継する。
+//! 転送ポートはサーバーごとのトークンを `Authorization` に持つリクエストだけを受け付ける。
+//! 踏み台への接続は経路ごとに使い回し、転送ポートへの接続ごとにチャンネルだけを開く
+use crate::utils::{
+    agent::{agent_endpoint, find_server},
+    ssh::{SshContext, open_tunnel},
+};
+use common::central::information::ServerInformation;
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use rand_core::{OsRng, RngCore};
+use serde_json::json;
+use sqlx::SqlitePool;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::AbortHandle,
+};
+
+/// これより長い経路は設定の誤りとみなす
+const MAX_HOPS: usize = 8;
+/// 転送ポートの URL に入れるユーザー名。パスワードの位置にトークンを置く
+const TUNNEL_USER: &str = "tunnel";
+const TUNNEL_HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_TUNNEL_HEAD_BYTES: usize = 16 * 1024;
+const ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(100);
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub enum BastionError {
+    /// 経路がループしている。ループを構成するサーバーの ID を順に持つ
+    Loop(Vec<String>),
+    Missing(String),
+    TooLong,
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for BastionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BastionError::Loop(ids) => write!(f, "bastion chain forms a loop: {}", ids.join(" -> ")),
+            BastionError::Missing(id) => write!(f, "bastion server {} does not exist", id),
+            BastionError::TooLong => write!(f, "bastion chain is longer than {} hops", MAX_HOPS),
+            BastionError::Database(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl IntoResponse for BastionError {
+    fn into_response(self) -> Response {
+        if let BastionError::Database(e) = &self {
+            tracing::error!("Failed to resolve bastion chain: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        (StatusCode::UNPROCESSABLE_ENTITY, Json(json!({"error": self.to_string()}))).into_response()
+    }
+}
+
+/// `server` へ到達するために経由する踏み台を、central に近い順に返す。踏み台がなければ空
+pub async fn bastion_chain(pool: &SqlitePool, server: &ServerInformation) -> Result<Vec<ServerInformation>, BastionError> {
+    let mut visited = vec![server.id.clone()];
+    let mut chain = Vec::new();
+    let mut next = server.bastion_server_id.clone();
+
+    while let Some(id) = next {
+        if let Some(start) = visited.iter().position(|v| *v == id) {
+            let mut cycle = visited[start..].to_vec();
+            cycle.push(id);
+            return Err(BastionError::Loop(cycle));
+        }
+        if chain.len() == MAX_HOPS {
+            return Err(BastionError::TooLong);
+        }
+        let bastion = sqlx::query_as::<_, ServerInformation>(
+            r#"SELECT id, hostname, ip_address, os_type, tags, auth_profile_id, port, bastion_server_id, wol_mac_address, wol_relay_server_id FROM servers WHERE id = ?"#,
+        )
+            .bind(&id)
+            .fetch_optional(pool)
+            .await
+            .map_err(BastionError::Database)?
+            .ok_or_else(|| BastionError::Missing(id.clone()))?;
+        visited.push(id);
+        next = bastion.bastion_server_id.clone();
+        chain.push(bastion);
+    }
+
+    chain.reverse();
+    Ok(chain)
+}
+
+/// サーバーの登録や編集のときに、踏み台が存在し経路がループしないことを確かめる。
+/// 新規登録のときは `server_id` に `None` を渡す
+pub async fn check_bastion(pool: &SqlitePool, server_id: Option<&str>, bastion_id: Option<&str>) -> Result<(), Response> {
+    let Some(bastion_id) = bastion_id else {
+        return Ok(());
+    };
+    if server_id == Some(bastion_id) {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(json!({"error": "a server cannot be its own bastion"}))).into_response());
+    }
+
+    // 編集後の自分自身を経路の終点に置いて辿る
+    let server = ServerInformation {
+        id: server_id.unwrap_or_default().to_string(),
+        hostname: String::new(),
+        ip_address: String::new(),
+        os_type: String::new(),
+        tags: None,
+        auth_profile_id: None,
+        port: 0,
+        bastion_server_id: Some(bastion_id.to_string()),
+        wol_mac_address: None,
+        wol_relay_server_id: None,
+    };
+    bastion_chain(pool, &server).await.map(|_| ()).map_err(IntoResponse::into_response)
+}
+
+/// 踏み台経由のサーバーのエージェントへ中継するローカルの待ち受けポート
+#[derive(Default)]
+pub struct AgentTunnels {
+    listeners: Mutex<HashMap<String, Tunnel>>,
+}
+
+/// 127.0.0.1 には同じホストの誰でもつなげるので、転送ポートごとのトークンを知っている central だけを中継する
+struct Tunnel {
+    address: SocketAddr,
+    token: String,
+    task: AbortHandle,
+}
+
+/// サーバーの編集や削除の後に呼び、そのサーバーの転送ポートを閉じて、サーバーを経由する踏み台への接続を捨てる。
+/// 転送ポートは次に使うときに開き直す
+pub fn forget_server(context: &SshContext, server_id: &str) {
+    let mut listeners = context.agent_tunnels.listeners.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(tunnel) = listeners.remove(server_id) {
+        tunnel.task.abort();
+    }
+    context.bastions.forget_server(server_id);
+}
+
+/// `find_server` と同じだが、踏み台経由のサーバーでは `ip_address` をローカルの転送先に置き換える
+pub async fn find_agent(pool: &SqlitePool, context: &Arc<SshContext>, id: &str) -> Result<ServerInformation, StatusCode> {
+    let server = find_server(pool, id).await?;
+    route_agent(pool, context, server)
+}
+
+/// エージェントへの接続先を決める。踏み台の経路は接続するたびに調べ直すので、編集もすぐに反映される。
+/// 転送先は `tunnel:<トークン>@127.0.0.1:<ポート>` の形になり、reqwest はこれを `Authorization` ヘッダーにして送る
+pub fn route_agent(pool: &SqlitePool, context: &Arc<SshContext>, mut server: ServerInformation) -> Result<ServerInformation, StatusCode> {
+    if server.bastion_server_id.is_none() {
+        return Ok(server);
+    }
+
+    let mut listeners = context.agent_tunnels.listeners.lock().unwrap_or_else(|e| e.into_inner());
+    let (address, token) = match listeners.get(&server.id) {
+        Some(tunnel) => (tunnel.address, tunnel.token.clone()),
+        None => {
+            let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+                .and_then(|listener| {
+                    listener.set_nonblocking(true)?;
+                    TcpListener::from_std(listener)
+                })
+                .map_err(|e| {
+                    tracing::error!("Failed to open agent tunnel for {}: {}", server.hostname, e);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?;
+            let address = listener.local_addr().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            let mut bytes = [0u8; 16];
+            OsRng.fill_bytes(&mut bytes);
+            let token = bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+            let task = tokio::spawn(forward_agent(listener, token.clone(), pool.clone(), context.clone(), server.id.clone()));
+            listeners.insert(server.id.clone(), Tunnel { address, token: token.clone(), task: task.abort_handle() });
+            (address, token)
+        }
+    };
+
+    server.ip_address = format!("{}:{}@{}", TUNNEL_USER, token, address);
+    Ok(server)
+}
+
+/// `route_agent` の返す転送先を、接続先の `host:port` と `Authorization` ヘッダーの値に分ける。
+/// reqwest を使わずにエージェントへつなぐときに使う
+pub fn tunnel_credentials(address: &str) -> (&str, Option<String>) {
+    match address.rsplit_once('@') {
+        Some((credentials, host)) => (host, Some(format!("Basic {}", STANDARD.encode(credentials)))),
+        None => (address, None),
+    }
+}
+
+async fn forward_agent(listener: TcpListener, token: String, pool: SqlitePool, context: Arc<SshContext>, server_id: String) {
+    let mut backoff = ACCEPT_BACKOFF_MIN;
+    loop {
+        let local = match listener.accept().await {
+            Ok((local, _)) => {
+                backoff = ACCEPT_BACKOFF_MIN;
+                local
+            }
+            Err(e) => {
+                // ファイルディスクリプタが尽きたときなどは、すぐに再試行しても同じエラーになる
+                tracing::warn!("Failed to accept agent tunnel connection: {}; retrying in {:?}", e, backoff);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(ACCEPT_BACKOFF_MAX);
+                continue;
+            }
+        };
+        tokio::spawn(relay(local, token.clone(), pool.clone(), context.clone(), server_id.clone()));
+    }
+}
+
+/// 最初のリクエストのヘッダーでトークンを確かめてからエージェントへ中継する。
+/// 1 回の接続で運ぶリクエストを 1 つにするため、WebSocket 以外は `Connection: close` に書き換える
+async fn relay(mut local: TcpStream, token: String, pool: SqlitePool, context: Arc<SshContext>, server_id: String) {
+    let request = match tokio::time::timeout(TUNNEL_HEAD_TIMEOUT, read_head(&mut local)).await {
+        Ok(Ok(request)) => request,
+        Ok(Err(e)) => {
+            tracing::debug!("Failed to read agent tunnel request: {}", e);
+            return;
+        }
+        Err(_) => return,
+    };
+    let Some(request) = authorize(&request, &token) else {
+        tracing::warn!("Refused agent tunnel connection to {}: missing or wrong token", server_id);
+        let _ = local.write_all(b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await;
+        return;
+    };
+
+    let Ok(server) = find_server(&pool, &server_id).await else {
+        return;
+    };
+    let (host, port) = agent_endpoint(&server.ip_address);
+    let result = if server.bastion_server_id.is_some() {
+        match open_tunnel(&pool, &context, &server, &host, port).await {
+            Ok(mut remote) => match remote.write_all(&request).await {
+                Ok(()) => tokio::io::copy_bidirectional(&mut local, &mut remote).await.map(|_| ()),
+                Err(e) => Err(e),
+            },
+            // 失敗の内容は `open_tunnel` が記録している
+            Err(_) => return,
+        }
+    } else {
+        // 踏み台の設定を外した後も古い転送先が使われることがあるので、直接つなぐ
+        match TcpStream::connect((host.as_str(), port)).await {
+            Ok(mut remote) => match remote.write_all(&request).await {
+                Ok(()) => tokio::io::copy_bidirectional(&mut local, &mut remote).await.map(|_| ()),
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        }
+    };
+    if let Err(e) = result {
+        tracing::debug!("Agent tunnel to {} closed: {}", server.hostname, e);
+    }
+}
+
+/// ヘッダーの終わりまで読む。ボディの先頭も含まれることがある
+async fn read_head(stream: &mut TcpStream) -> std::io::Result<Vec<u8>> {
+    let mut request = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        request.extend_from_slice(&chunk[..n]);
+        if head_end(&request).is_some() {
+            return Ok(request);
+        }
+        if request.len() > MAX_TUNNEL_HEAD_BYTES {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "request header too large"));
+        }
+    }
+}
+
+fn head_end(request: &[u8]) -> Option<usize> {
+    request.windows(4).position(|w| w == b"\r\n\r\n")
+}
+
+/// トークンが正しければ、`Authorization` を取り除いてエージェントへ送るリクエストを返す
+fn authorize(request: &[u8], token: &str) -> Option<Vec<u8>> {
+    let end = head_end(request)?;
+    let head = std::str::from_utf8(&request[..end]).ok()?;
+    let expected = format!("Basic {}", STANDARD.encode(format!("{}:{}", TUNNEL_USER, token)));
+
+    let mut lines = head.split("\r\n");
+    let mut forwarded = vec![lines.next()?];
+    let mut authorized = false;
+    let mut upgrade = false;
+    let mut connection = Vec::new();
+    for line in lines {
+        let (name, value) = line.split_once(':')?;
+        if name.eq_ignore_ascii_case("authorization") {
+            authorized |= constant_time_eq(value.trim().as_bytes(), expected.as_bytes());
+        } else if name.eq_ignore_ascii_case("connection") || name.eq_ignore_ascii_case("keep-alive") {
+            connection.push(line);
+        } else {
+            upgrade |= name.eq_ignore_ascii_case("upgrade");
+            forwarded.push(line);
+        }
+    }
+    if !authorized {
+        return None;
+    }
+    if upgrade {
+        forwarded.extend(connection);
+    } else {
+        forwarded.push("Connection: close");
+    }
+
+    let mut rewritten = forwarded.join("\r\n").into_bytes();
+    rewritten.extend_from_slice(&request[end..]);
+    Some(rewritten)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::{context, insert_server, mock_agent, pool};
+
+    use axum::{Router, routing::get};
+
+    const TOKEN: &str = "0123456789abcdef";
+
+    fn request(headers: &str) -> Vec<u8> {
+        format!("GET /api/agent/v1/health HTTP/1.1\r\nHost: 127.0.0.1\r\n{}\r\nbody", headers).into_bytes()
+    }
+
+    fn authorization(token: &str) -> String {
+        format!("Authorization: Basic {}\r\n", STANDARD.encode(format!("{}:{}", TUNNEL_USER, token)))
+    }
+
+    #[test]
+    fn authorizes_with_the_tunnel_token_and_strips_it() {
+        let forwarded = authorize(&request(&format!("{}Connection: keep-alive\r\n", authorization(TOKEN))), TOKEN).unwrap();
+        assert_eq!(
+            String::from_utf8(forwarded).unwrap(),
+            "GET /api/agent/v1/health HTTP/1.1\r\nHost: 127.0.0.1\r\nConnection: close\r\n\r\nbody"
+        );
+
+        // WebSocket はアップグレードのヘッダーをそのまま渡す
+        let upgrade = format!("Upgrade: websocket\r\nConnection: Upgrade\r\n{}", authorization(TOKEN));
+        let forwarded = String::from_utf8(authorize(&request(&upgrade), TOKEN).unwrap()).unwrap();
+        assert!(forwarded.contains("Upgrade: websocket\r\nConnection: Upgrade\r\n"));
+        assert!(!forwarded.contains("Authorization"));
+
+        assert!(authorize(&request(""), TOKEN).is_none());
+        assert!(authorize(&request(&authorization("fedcba9876543210")), TOKEN).is_none());
+        assert!(authorize(&request(&authorization(TOKEN))[..20], TOKEN).is_none());
+    }
+
+    #[tokio::test]
+    async fn relays_only_requests_carrying_the_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = pool(&dir).await;
+        let agent = mock_agent(Router::new().route("/health", get(|| async { "ok" }))).await;
+        insert_server(&pool, "a", &agent, &[]).await;
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let task = tokio::spawn(forward_agent(listener, TOKEN.to_string(), pool.clone(), context(), "a".to_string()));
+
+        let client = reqwest::Client::new();
+        let get = |credentials: &str| client.get(format!("http://{}{}/api/agent/v1/health", credentials, address)).send();
+        // 1 回の接続で 1 つのリクエストしか運ばないので、続けて送っても確かめ直される
+        for _ in 0..3 {
+            let response = get(&format!("{}:{}@", TUNNEL_USER, TOKEN)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.text().await.unwrap(), "ok");
+        }
+        assert_eq!(get("").await.unwrap().status(), StatusCode::FORBIDDEN);
+        assert_eq!(get(&format!("{}:wrong@", TUNNEL_USER)).await.unwrap().status(), StatusCode::FORBIDDEN);
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn routes_bastion_servers_through_one_tunnel_per_server() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = pool(&dir).await;
+        let context = context();
+        let server = |id: &str, bastion: Option<&str>| ServerInformation {
+            id: id.to_string(),
+            hostname: id.to_string(),
+            ip_address: "10.0.0.1:8080".to_string(),
+            os_type: "linux".to_string(),
+            tags: None,
+            auth_profile_id: None,
+            port: 22,
+            bastion_server_id: bastion.map(str::to_string),
+            wol_mac_address: None,
+            wol_relay_server_id: None,
+        };
+
+        assert_eq!(route_agent(&pool, &context, server("direct", None)).unwrap().ip_address, "10.0.0.1:8080");
+
+        let first = route_agent(&pool, &context, server("a", Some("b"))).unwrap().ip_address;
+        let again = route_agent(&pool, &context, server("a", Some("b"))).unwrap().ip_address;
+        let other = route_agent(&pool, &context, server("c", Some("b"))).unwrap().ip_address;
+        assert_eq!(first, again);
+        assert_ne!(first, other);
+
+        let (host, authorization) = tunnel_credentials(&first);
+        assert!(host.starts_with("127.0.0.1:"));
+        assert!(authorization.unwrap().starts_with("Basic "));
+        assert_eq!(tunnel_credentials("10.0.0.1:8080"), ("10.0.0.1:8080", None));
+
+        // 編集後は別のトークンで開き直す
+        forget_server(&context, "a");
+        assert_ne!(route_agent(&pool, &context, server("a", Some("b"))).unwrap().ip_address, first);
+    }
+}
@@ -0,0 +1,199 @@
+use crate::utils::agent::{agent_url, exec_events, streaming_client};
+use common::{
+    agent::exec::ExecEvent,
+    central::{information::ServerInformation, job::JobEvent},
+};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    http::StatusCode,
+    response::{
+        IntoResponse, Json, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use chrono::Utc;
+use futures::StreamExt;
+use serde::Serialize;
+use serde_json::json;
+use sqlx::SqlitePool;
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::BroadcastStream;
+
+const CHANNEL_CAPACITY: usize = 256;
+const LOG_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 実行中のジョブごとに出力を配信するチャンネルを持つ
+#[derive(Default)]
+pub struct JobHub {
+    channels: Mutex<HashMap<String, broadcast::Sender<JobEvent>>>,
+}
+
+impl JobHub {
+    pub fn open(&self, job_id: &str) -> broadcast::Sender<JobEvent> {
+        let (tx, _) = broadcast::channel(CHANNEL_CAPACITY);
+        self.channels
+            .lock()
+            .unwrap()
+            .insert(job_id.to_string(), tx.clone());
+        tx
+    }
+
+    pub fn subscribe(&self, job_id: &str) -> Option<broadcast::Receiver<JobEvent>> {
+        self.channels
+            .lock()
+            .unwrap()
+            .get(job_id)
+            .map(|tx| tx.subscribe())
+    }
+
+    /// 送信側をすべて破棄すると購読者のストリームも終了する
+    pub fn close(&self, job_id: &str) {
+        self.channels.lock().unwrap().remove(job_id);
+    }
+
+    /// 実行中のジョブの出力を SSE で配信する。終了済みのジョブは保存されたログを参照する
+    pub fn sse_response(&self, job_id: &str) -> Response {
+        let Some(rx) = self.subscribe(job_id) else {
+            return (StatusCode::NOT_FOUND, Json(json!({"error": "job is not running"}))).into_response();
+        };
+
+        let stream = BroadcastStream::new(rx).filter_map(|event| async move {
+            let event = event.ok()?;
+            let data = serde_json::to_string(&event).ok()?;
+            Some(Ok::<_, Infallible>(Event::default().data(data)))
+        });
+
+        Sse::new(stream).keep_alive(KeepAlive::default()).into_response()
+    }
+}
+
+/// 1台分の実行結果を蓄積し、ログを定期的に保存する。`table` は対象ごとの結果を持つ `*_job_targets` テーブル
+pub struct TargetRun {
+    pool: SqlitePool,
+    table: &'static str,
+    job_id: String,
+    server_id: String,
+    tx: broadcast::Sender<JobEvent>,
+    log: String,
+    flushed_at: Instant,
+    exit_code: Option<i32>,
+}
+
+impl TargetRun {
+    pub async fn start(
+        pool: SqlitePool,
+        table: &'static str,
+        job_id: String,
+        server_id: String,
+        tx: broadcast::Sender<JobEvent>,
+    ) -> Self {
+        let result = sqlx::query(&format!(r#"UPDATE {} SET status = 'running', started_at = ? WHERE job_id = ? AND server_id = ?"#, table))
+            .bind(Utc::now())
+            .bind(&job_id)
+            .bind(&server_id)
+            .execute(&pool)
+            .await;
+        if let Err(e) = result {
+            tracing::error!("Failed to update job target: {}", e);
+        }
+
+        Self {
+            pool,
+            table,
+            job_id,
+            server_id,
+            tx,
+            log: String::new(),
+            flushed_at: Instant::now(),
+            exit_code: None,
+        }
+    }
+
+    pub async fn handle(&mut self, event: ExecEvent) {
+        match &event {
+            ExecEvent::Stdout { line } | ExecEvent::Stderr { line } => {
+                self.log.push_str(line);
+                self.log.push('\n');
+            }
+            ExecEvent::Error { message } => {
+                self.log.push_str(&format!("[error] {}\n", message));
+            }
+            ExecEvent::Exit { code } => self.exit_code = *code,
+            // `exec_events` で読み捨てているので届かない
+            ExecEvent::Keepalive => return,
+        }
+        let _ = self.tx.send(JobEvent {
+            server_id: self.server_id.clone(),
+            event,
+        });
+
+        // ページを再読み込みしても途中までのログを見られるよう定期的に保存する
+        if self.flushed_at.elapsed() >= LOG_FLUSH_INTERVAL {
+            self.flush_log().await;
+            self.flushed_at = Instant::now();
+        }
+    }
+
+    /// エージェントに実行を依頼し、NDJSON で返ってくる出力をすべて処理する
+    pub async fn execute<T: Serialize>(&mut self, server: &ServerInformation, path: &str, body: &T) {
+        let Ok(client) = streaming_client() else {
+            self.handle(ExecEvent::Error { message: "failed to build HTTP client".to_string() }).await;
+            return;
+        };
+
+        match client.post(agent_url(server, path)).json(body).send().await {
+            Ok(res) if res.status().is_success() => {
+                let mut events = Box::pin(exec_events(res));
+                while let Some(event) = events.next().await {
+                    self.handle(event).await;
+                }
+            }
+            Ok(res) => {
+                let status = res.status();
+                let body = res.text().await.unwrap_or_default();
+                self.handle(ExecEvent::Error { message: format!("agent returned {}: {}", status, body) }).await;
+            }
+            Err(e) => {
+                self.handle(ExecEvent::Error { message: format!("failed to reach agent: {}", e) }).await;
+            }
+        }
+    }
+
+    /// 終了コードから結果を決めて保存する。成功したかを返す
+    pub async fn finish(self) -> bool {
+        let succeeded = self.exit_code == Some(0);
+        let status = if succeeded { "succeeded" } else { "failed" };
+        let result = sqlx::query(&format!(r#"UPDATE {} SET status = ?, exit_code = ?, log = ?, finished_at = ? WHERE job_id = ? AND server_id = ?"#, self.table))
+            .bind(status)
+            .bind(self.exit_code)
+            .bind(&self.log)
+            .bind(Utc::now())
+            .bind(&self.job_id)
+            .bind(&self.server_id)
+            .execute(&self.pool)
+            .await;
+        if let Err(e) = result {
+            tracing::error!("Failed to update job target: {}", e);
+        }
+
+        succeeded
+    }
+
+    async fn flush_log(&self) {
+        let result = sqlx::query(&format!(r#"UPDATE {} SET log = ? WHERE job_id = ? AND server_id = ?"#, self.table))
+            .bind(&self.log)
+            .bind(&self.job_id)
+            .bind(&self.server_id)
+            .execute(&self.pool)
+            .await;
+        if let Err(e) = result {
+            tracing::error!("Failed to save job log: {}", e);
+        }
+    }
+}
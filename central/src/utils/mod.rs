@@ -1 +1,9 @@
+pub mod agent;
+pub mod audit;
+pub mod bastion;
+pub mod jobs;
 pub mod logging;
+pub mod secret;
+pub mod ssh;
+#[cfg(test)]
+pub mod testing;
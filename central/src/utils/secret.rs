@@ -0,0 +1,97 @@
+//! 認証プロファイルの秘密情報を保存するときの暗号化
+use std::fmt;
+
+use aes_gcm::{
+    Aes256Gcm, Key, Nonce,
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+};
+use anyhow::{Context, Result, bail};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde_json::json;
+
+const PREFIX: &str = "v1:";
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug)]
+pub enum SecretError {
+    /// マスターキーが設定されていない
+    MissingKey,
+    /// 鍵が違う、または値が壊れている
+    Corrupted,
+}
+
+impl SecretError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            SecretError::MissingKey => StatusCode::SERVICE_UNAVAILABLE,
+            SecretError::Corrupted => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretError::MissingKey => write!(f, "master key is not configured"),
+            SecretError::Corrupted => write!(f, "stored secret cannot be decrypted with the configured master key"),
+        }
+    }
+}
+
+/// マスターキーによる AES-256-GCM での暗号化と復号。
+/// 保存値は `v1:` に続けて nonce と暗号文を base64 にしたもの
+pub struct SecretBox {
+    cipher: Option<Aes256Gcm>,
+}
+
+impl SecretBox {
+    pub fn from_config(master_key: Option<&str>) -> Result<Self> {
+        let Some(master_key) = master_key.map(str::trim).filter(|k| !k.is_empty()) else {
+            return Ok(Self { cipher: None });
+        };
+        let key = STANDARD.decode(master_key).context("master key is not valid base64")?;
+        if key.len() != 32 {
+            bail!("master key must be 32 bytes, got {}", key.len());
+        }
+        Ok(Self { cipher: Some(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))) })
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    /// `context` には値の持ち主と項目名を渡す。別の行や項目にコピーされた値は復号できない
+    pub fn seal(&self, context: &str, plaintext: &str) -> Result<String, SecretError> {
+        let cipher = self.cipher.as_ref().ok_or(SecretError::MissingKey)?;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, Payload { msg: plaintext.as_bytes(), aad: context.as_bytes() })
+            .map_err(|_| SecretError::Corrupted)?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(format!("{}{}", PREFIX, STANDARD.encode(sealed)))
+    }
+
+    pub fn open(&self, context: &str, sealed: &str) -> Result<String, SecretError> {
+        let cipher = self.cipher.as_ref().ok_or(SecretError::MissingKey)?;
+        let sealed = sealed
+            .strip_prefix(PREFIX)
+            .and_then(|s| STANDARD.decode(s).ok())
+            .filter(|s| s.len() > NONCE_LEN)
+            .ok_or(SecretError::Corrupted)?;
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: context.as_bytes() })
+            .map_err(|_| SecretError::Corrupted)?;
+        String::from_utf8(plaintext).map_err(|_| SecretError::Corrupted)
+    }
+}
+
+pub fn secret_error_response(error: &SecretError) -> Response {
+    (error.status(), Json(json!({"error": error.to_string()}))).into_response()
+}
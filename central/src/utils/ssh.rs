@@ -0,0 +1,298 @@
+use crate::{
+    app::config::SshConfig,
+    ssh::{AuthMethod, ChannelRequest, Credential, SshChannel, SshClient, SshError, SshTarget},
+    utils::{
+        agent::agent_endpoint,
+        bastion::{AgentTunnels, bastion_chain},
+        secret::{SecretBox, SecretError, secret_error_response},
+    },
+};
+use common::central::information::ServerInformation;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use serde_json::json;
+use sqlx::SqlitePool;
+use tokio::io::DuplexStream;
+
+/// SSH 接続に使う設定と、認証プロファイルの秘密情報を復号する鍵。踏み台経由のエージェントへの転送もここで持つ
+pub struct SshContext {
+    pub config: SshConfig,
+    pub secrets: SecretBox,
+    pub agent_tunnels: AgentTunnels,
+    pub bastions: BastionClients,
+}
+
+/// 踏み台への接続を経路ごとに使い回す。経路のサーバーの接続先や認証プロファイルが変われば別の経路になる
+#[derive(Default)]
+pub struct BastionClients {
+    clients: Mutex<HashMap<Vec<Hop>, Slot>>,
+}
+
+type Slot = Arc<tokio::sync::Mutex<Option<SshClient>>>;
+
+#[derive(PartialEq, Eq, Hash)]
+struct Hop {
+    server_id: String,
+    address: String,
+    port: u16,
+    auth_profile_id: Option<String>,
+}
+
+impl BastionClients {
+    /// 経路の接続を入れておく場所。同じ経路へ同時に接続し直さないよう、接続する間はロックしておく
+    fn slot(&self, chain: &[ServerInformation]) -> Slot {
+        let key = chain
+            .iter()
+            .map(|hop| Hop {
+                server_id: hop.id.clone(),
+                address: hop.ip_address.clone(),
+                port: hop.port,
+                auth_profile_id: hop.auth_profile_id.clone(),
+            })
+            .collect();
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        clients.entry(key).or_default().clone()
+    }
+
+    /// サーバーを経由する接続を捨てる。使用中のチャンネルは閉じるまで使える
+    pub fn forget_server(&self, server_id: &str) {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        clients.retain(|chain, _| chain.iter().all(|hop| hop.server_id != server_id));
+    }
+
+    /// 認証プロファイルを使う接続を捨てる。ホスト鍵の指紋の変更などをすぐに反映するため
+    pub fn forget_profile(&self, profile_id: &str) {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        clients.retain(|chain, _| chain.iter().all(|hop| hop.auth_profile_id.as_deref() != Some(profile_id)));
+    }
+}
+
+#[derive(sqlx::FromRow)]
+pub struct AuthProfileRow {
+    pub id: String,
+    pub username: String,
+    pub auth_method: String,
+    pub password: Option<String>,
+    pub private_key: Option<String>,
+    pub passphrase: Option<String>,
+    pub host_key_fingerprint: Option<String>,
+}
+
+impl AuthProfileRow {
+    pub async fn fetch(pool: &SqlitePool, id: &str) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, AuthProfileRow>(
+            r#"SELECT id, username, auth_method, password, private_key, passphrase, host_key_fingerprint FROM auth_profiles WHERE id = ?"#,
+        )
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    /// 暗号化して保存されている項目を復号する
+    pub fn open(&self, secrets: &SecretBox, field: &str) -> Result<Option<String>, SecretError> {
+        let value = match field {
+            "password" => &self.password,
+            "private_key" => &self.private_key,
+            "passphrase" => &self.passphrase,
+            _ => &None,
+        };
+        value.as_deref().map(|v| secrets.open(&secret_context(&self.id, field), v)).transpose()
+    }
+
+    /// 復号した資格情報。認証方式に必要な値がなければ `None`
+    pub fn auth_method(&self, secrets: &SecretBox) -> Result<Option<AuthMethod>, SecretError> {
+        Ok(match self.auth_method.as_str() {
+            "password" => self.open(secrets, "password")?.map(AuthMethod::Password),
+            "private_key" => match self.open(secrets, "private_key")? {
+                Some(key) => Some(AuthMethod::PrivateKey { key, passphrase: self.open(secrets, "passphrase")? }),
+                None => None,
+            },
+            _ => None,
+        })
+    }
+}
+
+/// 暗号化に使う関連データ。値を別のプロファイルや項目へ移しても復号できないようにする
+pub fn secret_context(profile_id: &str, field: &str) -> String {
+    format!("auth_profiles:{}:{}", profile_id, field)
+}
+
+/// サーバーに設定された認証プロファイルで SSH 接続する。踏み台があれば、使い回している踏み台への接続を経由する。
+/// 失敗したときはそのまま返せるレスポンスを返す
+pub async fn connect_server(pool: &SqlitePool, context: &SshContext, server: &ServerInformation) -> Result<SshClient, Response> {
+    let chain = bastion_chain(pool, server).await.map_err(IntoResponse::into_response)?;
+    let via = if chain.is_empty() {
+        None
+    } else {
+        Some(open_through(pool, context, &chain, ssh_endpoint(server)).await?)
+    };
+    connect_hop(pool, context, server, via).await
+}
+
+/// 最後の踏み台から `host:port` への TCP 接続を開く。`server` には踏み台が設定されていること
+pub async fn open_tunnel(
+    pool: &SqlitePool,
+    context: &SshContext,
+    server: &ServerInformation,
+    host: &str,
+    port: u16,
+) -> Result<DuplexStream, Response> {
+    let chain = bastion_chain(pool, server).await.map_err(|e| {
+        tracing::error!("Failed to route to {}: {}", server.hostname, e);
+        e.into_response()
+    })?;
+    if chain.is_empty() {
+        return Err(unprocessable("server has no bastion"));
+    }
+
+    let request = ChannelRequest::DirectTcpip { host: host.to_string(), port };
+    let channel = open_through(pool, context, &chain, request).await?;
+    Ok(channel.into_stream())
+}
+
+/// 踏み台の経路の接続からチャンネルを開く。使い回した接続が切れていれば、接続し直して一度だけやり直す
+async fn open_through(
+    pool: &SqlitePool,
+    context: &SshContext,
+    chain: &[ServerInformation],
+    request: ChannelRequest,
+) -> Result<SshChannel, Response> {
+    let timeout = Duration::from_secs(context.config.connect_timeout_secs);
+    let last = chain.last().expect("the chain has at least one bastion");
+    let slot = context.bastions.slot(chain);
+
+    let cached = slot.lock().await.clone();
+    if let Some(client) = cached.filter(|client| !client.is_closed()) {
+        match open_within(&client, request.clone(), timeout).await {
+            Ok(channel) => return Ok(channel),
+            Err(e @ SshError::ChannelRejected(_)) => return Err(channel_error(last, &request, &e)),
+            Err(e) => tracing::warn!("Reconnecting to bastion {}: {}", last.hostname, e),
+        }
+        let mut cached = slot.lock().await;
+        if cached.as_ref().is_some_and(|cached| cached.same_connection(&client)) {
+            *cached = None;
+        }
+    }
+
+    let client = {
+        let mut cached = slot.lock().await;
+        // 待っている間に他のリクエストが接続し直していれば、それを使う
+        match cached.clone().filter(|client| !client.is_closed()) {
+            Some(client) => client,
+            None => {
+                let client = connect_chain(pool, context, chain, timeout).await?;
+                *cached = Some(client.clone());
+                client
+            }
+        }
+    };
+    open_within(&client, request.clone(), timeout).await.map_err(|e| channel_error(last, &request, &e))
+}
+
+/// 踏み台を順に経由して、最後の踏み台へ接続する
+async fn connect_chain(
+    pool: &SqlitePool,
+    context: &SshContext,
+    chain: &[ServerInformation],
+    timeout: Duration,
+) -> Result<SshClient, Response> {
+    let mut client: Option<SshClient> = None;
+    for hop in chain {
+        let via = match &client {
+            Some(client) => {
+                let request = ssh_endpoint(hop);
+                Some(open_within(client, request.clone(), timeout).await.map_err(|e| channel_error(hop, &request, &e))?)
+            }
+            None => None,
+        };
+        client = Some(connect_hop(pool, context, hop, via).await?);
+    }
+    Ok(client.expect("the chain has at least one bastion"))
+}
+
+/// 切れたまま応答のない接続で待ち続けないよう、チャンネルを開くのにも接続と同じ制限時間を設ける
+async fn open_within(client: &SshClient, request: ChannelRequest, timeout: Duration) -> Result<SshChannel, SshError> {
+    tokio::time::timeout(timeout, client.open(request)).await.map_err(|_| SshError::Timeout)?
+}
+
+fn ssh_endpoint(server: &ServerInformation) -> ChannelRequest {
+    ChannelRequest::DirectTcpip { host: agent_endpoint(&server.ip_address).0, port: server.port }
+}
+
+fn channel_error(bastion: &ServerInformation, request: &ChannelRequest, error: &SshError) -> Response {
+    if let ChannelRequest::DirectTcpip { host, port } = request {
+        tracing::error!("Failed to open tunnel to {}:{} via {}: {}", host, port, bastion.hostname, error);
+    }
+    ssh_error_response(error)
+}
+
+/// `via` があればその踏み台のチャンネルの上で、なければ直接 `server` へ SSH 接続する
+async fn connect_hop(
+    pool: &SqlitePool,
+    context: &SshContext,
+    server: &ServerInformation,
+    via: Option<SshChannel>,
+) -> Result<SshClient, Response> {
+    let Some(profile_id) = server.auth_profile_id.as_deref() else {
+        return Err(unprocessable(&format!("{} has no auth profile", server.hostname)));
+    };
+    let profile = AuthProfileRow::fetch(pool, profile_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to fetch auth profile: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        })?
+        .ok_or_else(|| unprocessable(&format!("{} has no auth profile", server.hostname)))?;
+
+    let method = profile
+        .auth_method(&context.secrets)
+        .map_err(|e| {
+            tracing::error!("Failed to decrypt auth profile {}: {}", profile.id, e);
+            secret_error_response(&e)
+        })?
+        .ok_or_else(|| unprocessable("auth profile has no usable credential"))?;
+    let credential = Credential { username: profile.username, method };
+    let target = SshTarget {
+        host: agent_endpoint(&server.ip_address).0,
+        port: server.port,
+        host_key_fingerprint: profile.host_key_fingerprint,
+    };
+    let timeout = Duration::from_secs(context.config.connect_timeout_secs);
+
+    let result = match via {
+        Some(channel) => SshClient::connect_over(channel.into_stream(), &target, &credential, timeout).await,
+        None => SshClient::connect(&target, &credential, timeout).await,
+    };
+    result.map_err(|e| {
+        tracing::error!("Failed to connect to {} over SSH: {}", server.hostname, e);
+        ssh_error_response(&e)
+    })
+}
+
+pub fn ssh_error_response(error: &SshError) -> Response {
+    let status = match error {
+        SshError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        SshError::Credential(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        _ => StatusCode::BAD_GATEWAY,
+    };
+    let body = match error {
+        // 管理者が確認して登録できるよう、サーバーが提示した指紋を返す
+        SshError::UnknownHostKey(fingerprint) | SshError::HostKeyMismatch { actual: fingerprint, .. } => {
+            json!({"error": error.to_string(), "fingerprint": fingerprint})
+        }
+        _ => json!({"error": error.to_string()}),
+    };
+    (status, Json(body)).into_response()
+}
+
+fn unprocessable(message: &str) -> Response {
+    (StatusCode::UNPROCESSABLE_ENTITY, Json(json!({"error": message}))).into_response()
+}
@@ -0,0 +1,51 @@
+//! テストで使う DB と、エージェントの代わりに応答する HTTP サーバー
+use crate::{
+    app::config::SshConfig,
+    utils::{bastion::AgentTunnels, secret::SecretBox, ssh::{BastionClients, SshContext}},
+};
+use std::{net::Ipv4Addr, sync::Arc};
+
+use axum::Router;
+use sqlx::{
+    SqlitePool,
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+};
+use tokio::net::TcpListener;
+
+/// マイグレーションをすべて適用したプール。本番と同じく複数の接続を持つよう、一時ファイルに作る
+pub async fn pool(dir: &tempfile::TempDir) -> SqlitePool {
+    let options = SqliteConnectOptions::new().filename(dir.path().join("test.db")).create_if_missing(true);
+    let pool = SqlitePoolOptions::new().max_connections(8).connect_with(options).await.unwrap();
+    sqlx::migrate!("../migrations").run(&pool).await.unwrap();
+    pool
+}
+
+/// エージェントだけで管理するサーバーを登録する。`ip_address` は `mock_agent` の返すアドレス
+pub async fn insert_server(pool: &SqlitePool, id: &str, ip_address: &str, tags: &[&str]) {
+    sqlx::query(r#"INSERT INTO servers (id, hostname, ip_address, os_type, tags, port) VALUES (?, ?, ?, 'linux', ?, 22)"#)
+        .bind(id)
+        .bind(format!("{}.example", id))
+        .bind(ip_address)
+        .bind(serde_json::to_string(tags).unwrap())
+        .execute(pool)
+        .await
+        .unwrap();
+}
+
+/// `routes` を `/api/agent/v1` の下で動かし、サーバーの `ip_address` に入れる `host:port` を返す
+pub async fn mock_agent(routes: Router) -> String {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, Router::new().nest("/api/agent/v1", routes)).await });
+    address.to_string()
+}
+
+/// 秘密情報の鍵を持たない SSH の設定
+pub fn context() -> Arc<SshContext> {
+    Arc::new(SshContext {
+        config: SshConfig::default(),
+        secrets: SecretBox::from_config(None).unwrap(),
+        agent_tunnels: AgentTunnels::default(),
+        bastions: BastionClients::default(),
+    })
+}
@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+/// エージェントが実行したコマンドの出力。NDJSON として1行ずつ送られる
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ExecEvent {
+    Stdout { line: String },
+    Stderr { line: String },
+    Exit { code: Option<i32> },
+    Error { message: String },
+    /// 出力のない間も接続が生きていることを知らせる。central は読み捨てる
+    Keepalive
+}
+
+/// `sh -c` で実行する任意のコマンド
+#[derive(Deserialize, Serialize)]
+pub struct ExecRequest {
+    pub command: String,
+    pub timeout_secs: Option<u64>
+}
@@ -0,0 +1,47 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FileKind {
+    File,
+    Directory,
+    Symlink,
+    Other
+}
+
+/// シンボリックリンクは辿らずにリンク自体の情報を返す
+#[derive(Deserialize, Serialize)]
+pub struct FileEntry {
+    pub name: String,
+    pub path: String,
+    pub kind: FileKind,
+    pub size: u64,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+    pub modified: Option<DateTime<Utc>>,
+    pub symlink_target: Option<String>,
+    /// 通常のファイルのみ。書き込み時の `If-Match` にそのまま使う
+    pub etag: Option<String>
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct DirectoryListing {
+    pub path: String,
+    pub entries: Vec<FileEntry>,
+    pub truncated: bool
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct FileQuery {
+    pub path: String
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct FileWriteResult {
+    pub entry: FileEntry,
+    pub backup: Option<String>
+}
@@ -35,6 +35,16 @@ pub struct Gpu {
     pub driver_version: String
 }
 
+#[derive(Deserialize, Serialize)]
+pub struct NetworkInterface {
+    pub name: String,
+    pub mac_address: String,
+    pub ipv4_addresses: Vec<String>,
+    pub ipv6_addresses: Vec<String>,
+    pub mtu: u64,
+    pub link_speed_mbps: Option<u32>
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct ServerInformation {
     pub device: Device,
@@ -42,4 +52,5 @@ pub struct ServerInformation {
     pub memory: Memory,
     pub disk: Vec<Disk>,
     pub gpu: Vec<Gpu>,
+    pub network: Vec<NetworkInterface>,
 }
\ No newline at end of file
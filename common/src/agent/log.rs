@@ -0,0 +1,85 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// `/logs/tail` が SSE で送るイベント
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LogTailEvent {
+    Line { line: String },
+    Rotated,
+    Truncated
+}
+
+fn default_include_rotated() -> bool {
+    true
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct LogSearchQuery {
+    pub path: String,
+    pub pattern: String,
+    #[serde(default)]
+    pub ignore_case: bool,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub context: usize,
+    #[serde(default)]
+    pub offset: usize,
+    pub limit: Option<usize>,
+    #[serde(default = "default_include_rotated")]
+    pub include_rotated: bool
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct LogMatch {
+    pub file: String,
+    pub line_number: u64,
+    pub line: String,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub before: Vec<String>,
+    pub after: Vec<String>
+}
+
+/// `next_offset` が `None` ならそれ以上の一致はない
+#[derive(Deserialize, Serialize)]
+pub struct LogSearchResult {
+    pub files: Vec<String>,
+    pub matches: Vec<LogMatch>,
+    pub next_offset: Option<usize>,
+    pub timed_out: bool
+}
+
+/// `after` / `before` にはエントリーの `cursor` を渡す。どちらもなければ `since` から、それもなければ最新の `limit` 件を返す
+#[derive(Deserialize, Serialize)]
+pub struct JournalQuery {
+    pub unit: Option<String>,
+    pub priority: Option<u8>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub after: Option<String>,
+    pub before: Option<String>,
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub follow: bool
+}
+
+/// 圧縮されたフィールド（LZ4 / zstd / xz）は展開して返す。エントリーに無いフィールドは `None` になる
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct JournalEntry {
+    pub cursor: String,
+    pub realtime: DateTime<Utc>,
+    pub boot_id: String,
+    pub hostname: Option<String>,
+    pub unit: Option<String>,
+    pub identifier: Option<String>,
+    pub pid: Option<u32>,
+    pub priority: Option<u8>,
+    pub message: Option<String>
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct JournalPage {
+    pub entries: Vec<JournalEntry>,
+    pub has_more: bool
+}
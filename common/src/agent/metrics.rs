@@ -1,10 +1,31 @@
 use serde::{Deserialize, Serialize};
 
+#[derive(Deserialize, Serialize)]
+pub struct CpuBreakdown {
+    pub user_percent: f32,
+    pub nice_percent: f32,
+    pub system_percent: f32,
+    pub idle_percent: f32,
+    pub iowait_percent: f32,
+    pub irq_percent: f32,
+    pub softirq_percent: f32,
+    pub steal_percent: f32
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct CpuCore {
+    pub name: String,
+    pub usage_percent: f32,
+    pub frequency_mhz: u64
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct Cpu {
     pub usage_percent: f32,
     pub cores: u64,
     pub threads: u64,
+    pub breakdown: CpuBreakdown,
+    pub per_core: Vec<CpuCore>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -23,10 +44,64 @@ pub struct Disk {
     pub device: String
 }
 
+#[derive(Deserialize, Serialize)]
+pub struct DiskIo {
+    pub device: String,
+    pub read_bytes_per_sec: f64,
+    pub write_bytes_per_sec: f64,
+    pub read_iops: f64,
+    pub write_iops: f64,
+    /// 読み込み1回あたりの平均所要時間（キュー待ちを含む）。期間内に読み込みがなければ 0
+    pub read_await_ms: f64,
+    pub write_await_ms: f64,
+    pub avg_queue_depth: f64,
+    pub utilization_percent: f64
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Inode {
+    pub mount: String,
+    pub total: u64,
+    pub used: u64,
+    pub free: u64
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Network {
+    pub interface: String,
+    pub rx_bytes_per_sec: f64,
+    pub tx_bytes_per_sec: f64,
+    pub rx_packets_per_sec: f64,
+    pub tx_packets_per_sec: f64,
+    pub rx_errors_per_sec: f64,
+    pub tx_errors_per_sec: f64,
+    pub rx_dropped_per_sec: f64,
+    pub tx_dropped_per_sec: f64
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct LoadAverage {
+    pub one: f64,
+    pub five: f64,
+    pub fifteen: f64
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct ServerMetrics {
     pub cpu: Cpu,
     pub memory: Memory,
     pub disk: Vec<Disk>,
-    pub uptime_seconds: u64
-}
\ No newline at end of file
+    pub disk_io: Vec<DiskIo>,
+    pub inodes: Vec<Inode>,
+    pub network: Vec<Network>,
+    pub uptime_seconds: u64,
+    pub boot_time: u64,
+    pub load_average: LoadAverage,
+    pub logged_in_users: u32
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SamplerStats {
+    pub subscribers: u64,
+    pub dropped_messages: u64
+}
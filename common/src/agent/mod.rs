@@ -1,2 +1,10 @@
+pub mod exec;
+pub mod file;
 pub mod metrics;
-pub mod information;
\ No newline at end of file
+pub mod information;
+pub mod log;
+pub mod package;
+pub mod process;
+pub mod service;
+pub mod terminal;
+pub mod wol;
@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PackageManager {
+    Dpkg,
+    Rpm,
+    Apk,
+    Pacman
+}
+
+#[derive(Deserialize, Serialize, sqlx::FromRow)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    pub architecture: Option<String>,
+    pub source: Option<String>,
+    pub installed_at: Option<i64>
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PackageInventory {
+    pub manager: PackageManager,
+    pub packages: Vec<Package>
+}
+
+impl PackageManager {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PackageManager::Dpkg => "dpkg",
+            PackageManager::Rpm => "rpm",
+            PackageManager::Apk => "apk",
+            PackageManager::Pacman => "pacman",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PackageUpdate {
+    pub name: String,
+    pub installed_version: String,
+    pub candidate_version: String,
+    pub architecture: Option<String>,
+    pub source: Option<String>,
+    pub security: bool
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PackageUpdates {
+    pub manager: PackageManager,
+    pub pending_count: u64,
+    pub security_count: u64,
+    pub updates: Vec<PackageUpdate>
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PackageAction {
+    Install,
+    Upgrade,
+    Remove,
+    FullUpgrade
+}
+
+impl PackageAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PackageAction::Install => "install",
+            PackageAction::Upgrade => "upgrade",
+            PackageAction::Remove => "remove",
+            PackageAction::FullUpgrade => "full_upgrade",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PackageJobRequest {
+    pub action: PackageAction,
+    #[serde(default)]
+    pub packages: Vec<String>
+}
@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize)]
+pub struct Process {
+    pub pid: u32,
+    pub ppid: Option<u32>,
+    pub user: Option<String>,
+    pub name: String,
+    pub command: String,
+    pub state: String,
+    pub cpu_percent: f32,
+    pub rss_bytes: u64,
+    pub start_time: u64,
+    /// ブートからの clock tick 数で表した起動時刻。シグナルを送るときに PID の再利用を見分けるのに使う
+    pub start_ticks: Option<u64>,
+    pub open_fds: Option<u64>
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ProcessSignal {
+    Sigterm,
+    Sigkill,
+    Sighup,
+    Sigstop,
+    Sigcont
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SignalRequest {
+    pub signal: ProcessSignal,
+    /// 一覧で返した `start_ticks`
+    pub start_ticks: u64
+}
@@ -0,0 +1,60 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize)]
+pub struct ServiceUnit {
+    pub name: String,
+    pub description: String,
+    pub load_state: String,
+    pub active_state: String,
+    pub sub_state: String,
+    pub unit_file_state: Option<String>,
+    pub memory_bytes: Option<u64>,
+    pub manageable: bool
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceAction {
+    Start,
+    Stop,
+    Restart,
+    Reload,
+    Enable,
+    Disable
+}
+
+impl ServiceAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServiceAction::Start => "start",
+            ServiceAction::Stop => "stop",
+            ServiceAction::Restart => "restart",
+            ServiceAction::Reload => "reload",
+            ServiceAction::Enable => "enable",
+            ServiceAction::Disable => "disable",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ServiceActionRequest {
+    pub action: ServiceAction
+}
+
+/// `nginx` のように種別が省略された場合は `.service` とみなす。エージェントと central で同じ名前に揃えるために使う
+pub fn normalize_unit_name(name: &str) -> Option<String> {
+    let valid = !name.is_empty()
+        && name.len() <= 255
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, ':' | '-' | '_' | '.' | '@' | '\\'));
+    if !valid || name.starts_with('.') {
+        return None;
+    }
+
+    if name.contains('.') {
+        Some(name.to_string())
+    } else {
+        Some(format!("{}.service", name))
+    }
+}
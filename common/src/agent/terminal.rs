@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+
+/// ブラウザから送るテキストメッセージ。バイナリメッセージはそのまま端末への入力として扱う
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TerminalClientMessage {
+    Input { data: String },
+    Resize { cols: u16, rows: u16 }
+}
+
+/// 端末の出力はバイナリメッセージで送り、それ以外の通知はこのテキストメッセージで送る
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TerminalServerMessage {
+    Exit { code: Option<i32> },
+    IdleTimeout
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct TerminalQuery {
+    pub cols: Option<u16>,
+    pub rows: Option<u16>
+}
@@ -0,0 +1,111 @@
+use std::{io, net::Ipv4Addr};
+
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+
+/// Wake-on-LAN で使われるポート（echo と discard）
+pub const WAKE_PORTS: [u16; 2] = [7, 9];
+
+/// マジックパケットの送信を依頼する。別のネットワークにあるサーバーを起こすときに、同じ LAN のエージェントへ送る。
+/// 送り先はエージェントの LAN 全体（255.255.255.255）に決まっている
+#[derive(Deserialize, Serialize)]
+pub struct WakeRequest {
+    pub mac_address: String,
+    /// `WAKE_PORTS` のいずれか
+    pub port: u16
+}
+
+/// `aa:bb:cc:dd:ee:ff`、`aa-bb-cc-dd-ee-ff`、`aabbccddeeff` の形式を受け付ける
+pub fn parse_mac_address(value: &str) -> Option<[u8; 6]> {
+    let value = value.trim();
+    // 多バイト文字があるとバイト数での切り出しが文字の途中にかかるので、先に弾く
+    if !value.is_ascii() {
+        return None;
+    }
+    let parts = if value.len() == 12 {
+        (0..6).map(|i| &value[i * 2..i * 2 + 2]).collect::<Vec<_>>()
+    } else {
+        value.split([':', '-']).collect()
+    };
+    if parts.len() != 6 {
+        return None;
+    }
+
+    let mut mac = [0u8; 6];
+    for (byte, part) in mac.iter_mut().zip(parts) {
+        // from_str_radix は先頭の `+` も受け付けるので、16進数の文字だけであることを確かめる
+        if part.len() != 2 || !part.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        *byte = u8::from_str_radix(part, 16).ok()?;
+    }
+    Some(mac)
+}
+
+/// `address` はブロードキャストアドレス。UDP なので相手が受け取ったかどうかはわからない
+pub async fn send_magic_packet(mac: &[u8; 6], address: &str, port: u16) -> io::Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.set_broadcast(true)?;
+    socket.send_to(&magic_packet(mac), (address, port)).await?;
+    Ok(())
+}
+
+/// 0xFF を6バイト並べ、その後に MAC アドレスを16回繰り返したもの
+fn magic_packet(mac: &[u8; 6]) -> Vec<u8> {
+    let mut packet = vec![0xFF; 6];
+    for _ in 0..16 {
+        packet.extend_from_slice(mac);
+    }
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0xAA, 0xBB, 0xCC, 0x01, 0x02, 0xEF];
+
+    #[test]
+    fn parses_supported_formats() {
+        assert_eq!(parse_mac_address("aa:bb:cc:01:02:ef"), Some(MAC));
+        assert_eq!(parse_mac_address("AA-BB-CC-01-02-EF"), Some(MAC));
+        assert_eq!(parse_mac_address("aabbcc0102ef"), Some(MAC));
+        assert_eq!(parse_mac_address("  aa:bb:cc:01:02:ef\n"), Some(MAC));
+    }
+
+    #[test]
+    fn rejects_malformed_addresses() {
+        for value in [
+            "",
+            "aa:bb:cc:01:02",
+            "aa:bb:cc:01:02:ef:00",
+            "aabbcc0102e",
+            "aabbcc0102efa",
+            "a:bb:cc:01:02:eff",
+            "gg:bb:cc:01:02:ef",
+            "+a:bb:cc:01:02:ef",
+            "+abbcc0102ef",
+            "aa::bb:cc:01:02",
+        ] {
+            assert_eq!(parse_mac_address(value), None, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn rejects_multibyte_input_without_panicking() {
+        // いずれも12バイトだが、文字の境界が2バイトごとではない
+        for value in ["あいうえ", "éaabbccddee", "aabbccddeeé", "ａａbbccdd"] {
+            assert_eq!(value.len(), 12);
+            assert_eq!(parse_mac_address(value), None, "{:?}", value);
+        }
+        assert_eq!(parse_mac_address("aa:bb:cc:dd:ee:fé"), None);
+    }
+
+    #[test]
+    fn magic_packet_repeats_mac_after_sync_stream() {
+        let packet = magic_packet(&MAC);
+        assert_eq!(packet.len(), 102);
+        assert_eq!(&packet[..6], &[0xFF; 6]);
+        assert!(packet[6..].chunks(6).all(|chunk| chunk == MAC));
+    }
+}
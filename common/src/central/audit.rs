@@ -0,0 +1,14 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, sqlx::FromRow)]
+pub struct AuditLog {
+    pub id: String,
+    pub server_id: String,
+    pub actor: String,
+    pub action: String,
+    pub target: String,
+    pub detail: Option<String>,
+    pub status_code: u16,
+    pub created_at: DateTime<Utc>
+}
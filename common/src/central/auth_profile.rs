@@ -0,0 +1,17 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// 秘密情報そのものは返さず、設定されているかどうかだけを返す
+#[derive(Deserialize, Serialize, sqlx::FromRow)]
+pub struct AuthProfile {
+    pub id: String,
+    pub name: String,
+    pub username: String,
+    pub auth_method: String,
+    pub host_key_fingerprint: Option<String>,
+    pub has_password: bool,
+    pub has_private_key: bool,
+    pub has_passphrase: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>
+}
@@ -1,14 +1,28 @@
+use crate::central::{service::KeyServicesSummary, status::ServerStatus};
+
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Serialize, sqlx::FromRow)]
+#[derive(Deserialize, Serialize, Clone, sqlx::FromRow)]
 pub struct ServerInformation {
     pub id: String,
     pub hostname: String,
     pub ip_address: String,
     pub os_type: String,
     pub tags: Option<String>,
-    pub auth_profile_id: String,
+    /// エージェントだけで管理するサーバーでは `None`。SSH を使う機能にはプロファイルが要る
+    pub auth_profile_id: Option<String>,
     pub port: u16,
     pub bastion_server_id: Option<String>,
-    pub wol_mac_address: Option<String>
-}
\ No newline at end of file
+    pub wol_mac_address: Option<String>,
+    /// 別のネットワークにあるサーバーを起こすとき、マジックパケットを代わりに送るエージェント
+    pub wol_relay_server_id: Option<String>
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ServerListEntry {
+    #[serde(flatten)]
+    pub server: ServerInformation,
+    pub key_services: KeyServicesSummary,
+    /// まだ一度もヘルスチェックしていなければ `None`
+    pub status: Option<ServerStatus>
+}
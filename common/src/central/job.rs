@@ -0,0 +1,60 @@
+use crate::agent::exec::ExecEvent;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// ジョブの進行状況をブラウザへ配信するためのイベント
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct JobEvent {
+    pub server_id: String,
+    #[serde(flatten)]
+    pub event: ExecEvent
+}
+
+#[derive(Deserialize, Serialize, sqlx::FromRow)]
+pub struct PackageJob {
+    pub id: String,
+    pub action: String,
+    pub packages: String,
+    pub created_by: String,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>
+}
+
+/// 1台分の実行結果。パッケージジョブとコマンドジョブで共通
+#[derive(Deserialize, Serialize, sqlx::FromRow)]
+pub struct JobTarget {
+    pub server_id: String,
+    pub status: String,
+    pub exit_code: Option<i32>,
+    pub log: String,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PackageJobDetail {
+    #[serde(flatten)]
+    pub job: PackageJob,
+    pub targets: Vec<JobTarget>
+}
+
+#[derive(Deserialize, Serialize, sqlx::FromRow)]
+pub struct CommandJob {
+    pub id: String,
+    pub command: String,
+    pub tag: Option<String>,
+    pub timeout_secs: u64,
+    pub created_by: String,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct CommandJobDetail {
+    #[serde(flatten)]
+    pub job: CommandJob,
+    pub targets: Vec<JobTarget>
+}
@@ -1,2 +1,10 @@
+pub mod audit;
+pub mod auth_profile;
 pub mod information;
-pub mod resource;
\ No newline at end of file
+pub mod job;
+pub mod package;
+pub mod preset;
+pub mod resource;
+pub mod service;
+pub mod status;
+pub mod wake;
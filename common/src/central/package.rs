@@ -0,0 +1,31 @@
+use crate::agent::package::Package;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize)]
+pub struct CachedPackageInventory {
+    pub server_id: String,
+    pub manager: String,
+    pub refreshed_at: DateTime<Utc>,
+    pub packages: Vec<Package>
+}
+
+#[derive(Deserialize, Serialize, sqlx::FromRow)]
+pub struct PackageSearchResult {
+    pub server_id: String,
+    pub hostname: String,
+    pub name: String,
+    pub version: String,
+    pub architecture: Option<String>
+}
+
+#[derive(Deserialize, Serialize, sqlx::FromRow)]
+pub struct PendingUpdatesSummary {
+    pub server_id: String,
+    pub hostname: String,
+    pub manager: String,
+    pub pending_count: i64,
+    pub security_count: i64,
+    pub refreshed_at: DateTime<Utc>
+}
@@ -0,0 +1,23 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// `command` 中の `{{name}}` に実行時の値を埋め込む
+#[derive(Deserialize, Serialize, Clone)]
+pub struct PresetParameter {
+    pub name: String,
+    pub description: Option<String>,
+    pub default: Option<String>
+}
+
+#[derive(Deserialize, Serialize, sqlx::FromRow)]
+pub struct CommandPreset {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub command: String,
+    pub timeout_secs: Option<u64>,
+    pub tag: Option<String>,
+    pub parameters: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>
+}
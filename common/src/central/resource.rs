@@ -1,7 +1,8 @@
 use chrono::DateTime;
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Serialize)]
+/// サーバーの状態。データベースには名前をそのまま文字列で保存する
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, sqlx::Type)]
 pub enum Status {
     Online,
     Caution,
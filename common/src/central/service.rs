@@ -0,0 +1,20 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, sqlx::FromRow)]
+pub struct WatchedService {
+    pub unit_name: String,
+    pub active_state: Option<String>,
+    pub sub_state: Option<String>,
+    pub checked_at: Option<DateTime<Utc>>
+}
+
+/// 状態を取得できていないサービスは `unknown` に数える
+#[derive(Deserialize, Serialize, Default)]
+pub struct KeyServicesSummary {
+    pub total: u32,
+    pub up: u32,
+    pub down: u32,
+    pub unknown: u32,
+    pub down_units: Vec<String>
+}
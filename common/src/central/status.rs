@@ -0,0 +1,23 @@
+use crate::central::resource::Status;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// バックグラウンドのヘルスチェックで決めた現在の状態
+#[derive(Deserialize, Serialize, sqlx::FromRow)]
+pub struct ServerStatus {
+    pub status: Status,
+    /// エージェントが最後に応答した時刻
+    pub last_seen_at: Option<DateTime<Utc>>,
+    pub changed_at: DateTime<Utc>,
+    pub checked_at: DateTime<Utc>,
+    pub latency_ms: Option<u32>
+}
+
+/// 状態の移り変わり。最初のヘルスチェックでは `from_status` が `None` になる
+#[derive(Deserialize, Serialize, sqlx::FromRow)]
+pub struct StatusTransition {
+    pub from_status: Option<Status>,
+    pub to_status: Status,
+    pub created_at: DateTime<Utc>
+}
@@ -0,0 +1,12 @@
+use serde::{Deserialize, Serialize};
+
+/// `POST /servers/{id}/wake` が NDJSON として1行ずつ返す進捗
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WakeEvent {
+    /// マジックパケットを送った。`relay_server_id` は中継したエージェントのサーバー
+    Sent { relay_server_id: Option<String> },
+    Waiting { elapsed_secs: u64 },
+    Up { elapsed_secs: u64 },
+    Timeout { elapsed_secs: u64 }
+}
@@ -1,2 +1,3 @@
 pub mod central;
 pub mod agent;
+pub mod ws;
@@ -0,0 +1,356 @@
+//! 端末の中継に必要な範囲の WebSocket（RFC 6455）の実装
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io,
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// 1メッセージの上限。端末の入出力には十分な大きさ
+pub const MAX_MESSAGE_SIZE: usize = 1 << 20;
+const MAX_HEADER_BYTES: usize = 16 * 1024;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close,
+}
+
+/// `Sec-WebSocket-Key` に対する `Sec-WebSocket-Accept` の値
+pub fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.trim().as_bytes());
+    hasher.update(ACCEPT_GUID.as_bytes());
+    STANDARD.encode(hasher.finalize())
+}
+
+/// サーバー側のハンドシェイク。WebSocket へのアップグレード要求であれば `Sec-WebSocket-Accept` の値を返す
+pub fn server_accept(header: impl Fn(&str) -> Option<String>) -> Option<String> {
+    if !header("upgrade")?.eq_ignore_ascii_case("websocket") || header("sec-websocket-version")?.trim() != "13" {
+        return None;
+    }
+    Some(accept_key(&header("sec-websocket-key")?))
+}
+
+/// 予測されにくい値であればよいので、標準ライブラリのハッシュ用の乱数を流用する
+fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// クライアントとして接続する。`path` にはクエリ文字列も含める
+pub async fn client_handshake<S>(stream: &mut S, host: &str, path: &str, authorization: Option<&str>) -> io::Result<()>
+where
+    S: AsyncBufRead + AsyncWrite + Unpin,
+{
+    let mut nonce = [0u8; 16];
+    nonce[..8].copy_from_slice(&random_u64().to_le_bytes());
+    nonce[8..].copy_from_slice(&random_u64().to_le_bytes());
+    let key = STANDARD.encode(nonce);
+
+    let authorization = authorization.map(|value| format!("Authorization: {}\r\n", value)).unwrap_or_default();
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n{}\r\n",
+        path, host, key, authorization
+    );
+    stream.write_all(request.as_bytes()).await?;
+    stream.flush().await?;
+
+    let mut status = None;
+    let mut accept = None;
+    let mut read = 0;
+    loop {
+        let mut line = String::new();
+        let n = stream.read_line(&mut line).await?;
+        read += n;
+        if n == 0 || read > MAX_HEADER_BYTES {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid handshake response"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if status.is_none() {
+            status = line.split_whitespace().nth(1).and_then(|code| code.parse::<u16>().ok());
+        } else if let Some((name, value)) = line.split_once(':')
+            && name.trim().eq_ignore_ascii_case("sec-websocket-accept")
+        {
+            accept = Some(value.trim().to_string());
+        }
+    }
+
+    match status {
+        Some(101) if accept.as_deref() == Some(accept_key(&key).as_str()) => Ok(()),
+        Some(101) => Err(io::Error::new(io::ErrorKind::InvalidData, "invalid Sec-WebSocket-Accept")),
+        Some(code) => Err(io::Error::other(format!("server returned {}", code))),
+        None => Err(io::Error::new(io::ErrorKind::InvalidData, "invalid status line")),
+    }
+}
+
+/// 受信側。制御フレームは分割されたメッセージの途中にも入りうるので、読みかけのメッセージを呼び出しをまたいで保持する
+pub struct MessageReader<R> {
+    reader: R,
+    masked: bool,
+    partial: Option<(u8, Vec<u8>)>,
+}
+
+impl<R: AsyncRead + Unpin> MessageReader<R> {
+    /// サーバー側はクライアントからのフレームがマスクされていることを求めるので `masked` に true を、
+    /// クライアント側はマスクされていないことを求めるので false を渡す（RFC 6455 5.1）
+    pub fn new(reader: R, masked: bool) -> Self {
+        Self { reader, masked, partial: None }
+    }
+
+    /// 次のメッセージを読む。分割されたフレームは結合して返し、接続が閉じられていれば `None`
+    pub async fn read_message(&mut self) -> io::Result<Option<Message>> {
+        loop {
+            let mut head = [0u8; 2];
+            match self.reader.read_exact(&mut head).await {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && self.partial.is_none() => return Ok(None),
+                Err(e) => return Err(e),
+            }
+            let fin = head[0] & 0x80 != 0;
+            let opcode = head[0] & 0x0F;
+            if (head[1] & 0x80 != 0) != self.masked {
+                let reason = if self.masked { "client frame is not masked" } else { "server frame must not be masked" };
+                return Err(io::Error::new(io::ErrorKind::InvalidData, reason));
+            }
+            let length = match head[1] & 0x7F {
+                126 => self.reader.read_u16().await? as u64,
+                127 => self.reader.read_u64().await?,
+                length => length as u64,
+            };
+            let is_control = opcode & 0x8 != 0;
+            if is_control && (!fin || length > 125) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid control frame"));
+            }
+            let buffered = self.partial.as_ref().map_or(0, |(_, data)| data.len());
+            if !is_control && length > (MAX_MESSAGE_SIZE - buffered) as u64 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "message is too large"));
+            }
+            let mut mask = [0u8; 4];
+            if self.masked {
+                self.reader.read_exact(&mut mask).await?;
+            }
+            let mut payload = vec![0u8; length as usize];
+            self.reader.read_exact(&mut payload).await?;
+            if self.masked {
+                payload.iter_mut().enumerate().for_each(|(i, b)| *b ^= mask[i % 4]);
+            }
+
+            match (opcode, &mut self.partial) {
+                (OPCODE_CLOSE, _) => return Ok(Some(Message::Close)),
+                (OPCODE_PING, _) => return Ok(Some(Message::Ping(payload))),
+                (OPCODE_PONG, _) => return Ok(Some(Message::Pong(payload))),
+                (OPCODE_TEXT | OPCODE_BINARY, None) => self.partial = Some((opcode, payload)),
+                (OPCODE_CONTINUATION, Some((_, data))) => data.extend_from_slice(&payload),
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected frame")),
+            }
+
+            if fin && let Some((opcode, data)) = self.partial.take() {
+                return match opcode {
+                    OPCODE_TEXT => String::from_utf8(data)
+                        .map(|text| Some(Message::Text(text)))
+                        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "text message is not valid UTF-8")),
+                    _ => Ok(Some(Message::Binary(data))),
+                };
+            }
+        }
+    }
+}
+
+/// メッセージを1フレームで送る。クライアントからサーバーへ送る場合は `mask` を指定する
+pub async fn write_message<W: AsyncWrite + Unpin>(writer: &mut W, message: &Message, mask: bool) -> io::Result<()> {
+    let (opcode, payload): (u8, &[u8]) = match message {
+        Message::Text(text) => (OPCODE_TEXT, text.as_bytes()),
+        Message::Binary(data) => (OPCODE_BINARY, data),
+        Message::Ping(data) => (OPCODE_PING, data),
+        Message::Pong(data) => (OPCODE_PONG, data),
+        Message::Close => (OPCODE_CLOSE, &[]),
+    };
+
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+    frame.push(0x80 | opcode);
+    let mask_bit = if mask { 0x80 } else { 0 };
+    match payload.len() {
+        length if length < 126 => frame.push(mask_bit | length as u8),
+        length if length <= u16::MAX as usize => {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    if mask {
+        let key = (random_u64() as u32).to_be_bytes();
+        frame.extend_from_slice(&key);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ key[i % 4]));
+    } else {
+        frame.extend_from_slice(payload);
+    }
+
+    writer.write_all(&frame).await?;
+    writer.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 1フレームを組み立てる。`mask` があればペイロードをマスクする
+    fn frame(fin: bool, opcode: u8, payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+        let mut frame = vec![if fin { 0x80 } else { 0 } | opcode];
+        let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+        match payload.len() {
+            length if length < 126 => frame.push(mask_bit | length as u8),
+            length if length <= u16::MAX as usize => {
+                frame.push(mask_bit | 126);
+                frame.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+            length => {
+                frame.push(mask_bit | 127);
+                frame.extend_from_slice(&(length as u64).to_be_bytes());
+            }
+        }
+        match mask {
+            Some(key) => {
+                frame.extend_from_slice(&key);
+                frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ key[i % 4]));
+            }
+            None => frame.extend_from_slice(payload),
+        }
+        frame
+    }
+
+    async fn read_all(bytes: &[u8], masked: bool) -> io::Result<Vec<Message>> {
+        let mut reader = MessageReader::new(bytes, masked);
+        let mut messages = Vec::new();
+        while let Some(message) = reader.read_message().await? {
+            messages.push(message);
+        }
+        Ok(messages)
+    }
+
+    #[tokio::test]
+    async fn round_trips_each_length_encoding() {
+        // 7ビット、16ビット、64ビットの長さ表現の境界
+        for length in [0, 125, 126, 65535, 65536, 100_000] {
+            let data = (0..length).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+            for mask in [true, false] {
+                let mut bytes = Vec::new();
+                write_message(&mut bytes, &Message::Binary(data.clone()), mask).await.unwrap();
+                let header = match length {
+                    0..=125 => 2,
+                    126..=65535 => 4,
+                    _ => 10,
+                };
+                assert_eq!(bytes.len(), header + if mask { 4 } else { 0 } + length);
+
+                let messages = read_all(&bytes, mask).await.unwrap();
+                assert!(matches!(&messages[..], [Message::Binary(read)] if *read == data), "length {}", length);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn round_trips_text_and_control_messages() {
+        let mut bytes = Vec::new();
+        for message in [Message::Text("こんにちは".to_string()), Message::Ping(b"p".to_vec()), Message::Pong(b"q".to_vec()), Message::Close] {
+            write_message(&mut bytes, &message, true).await.unwrap();
+        }
+        let messages = read_all(&bytes, true).await.unwrap();
+        assert!(matches!(
+            &messages[..],
+            [Message::Text(text), Message::Ping(ping), Message::Pong(pong), Message::Close]
+                if text == "こんにちは" && ping == b"p" && pong == b"q"
+        ));
+    }
+
+    #[tokio::test]
+    async fn joins_fragments_around_control_frame() {
+        let key = Some([1, 2, 3, 4]);
+        let mut bytes = frame(false, OPCODE_TEXT, b"Hel", key);
+        bytes.extend(frame(true, OPCODE_PING, b"mid", key));
+        bytes.extend(frame(false, OPCODE_CONTINUATION, b"lo, ", key));
+        bytes.extend(frame(true, OPCODE_CONTINUATION, b"world", key));
+        bytes.extend(frame(true, OPCODE_BINARY, b"next", key));
+
+        let messages = read_all(&bytes, true).await.unwrap();
+        assert!(matches!(
+            &messages[..],
+            [Message::Ping(ping), Message::Text(text), Message::Binary(next)]
+                if ping == b"mid" && text == "Hello, world" && next == b"next"
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_unexpected_frames() {
+        let key = Some([9, 8, 7, 6]);
+        // 始まっていないメッセージの続き
+        assert!(read_all(&frame(true, OPCODE_CONTINUATION, b"x", key), true).await.is_err());
+        // 続きを待っている間に別のメッセージが始まる
+        let mut bytes = frame(false, OPCODE_TEXT, b"a", key);
+        bytes.extend(frame(true, OPCODE_TEXT, b"b", key));
+        assert!(read_all(&bytes, true).await.is_err());
+        // 分割された制御フレーム
+        assert!(read_all(&frame(false, OPCODE_PING, b"x", key), true).await.is_err());
+        // 途中で切れたメッセージ
+        assert!(read_all(&frame(false, OPCODE_TEXT, b"x", key), true).await.is_err());
+        // UTF-8 でないテキスト
+        assert!(read_all(&frame(true, OPCODE_TEXT, &[0xFF, 0xFE], key), true).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn server_rejects_unmasked_frames_and_client_rejects_masked_frames() {
+        let unmasked = frame(true, OPCODE_BINARY, b"data", None);
+        let masked = frame(true, OPCODE_BINARY, b"data", Some([1, 1, 1, 1]));
+
+        let error = read_all(&unmasked, true).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(read_all(&masked, false).await.is_err());
+        assert!(read_all(&masked, true).await.is_ok());
+        assert!(read_all(&unmasked, false).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_messages() {
+        // ヘッダーの長さだけで判断し、ペイロードを読み込む前に断る
+        let mut bytes = vec![0x80 | OPCODE_BINARY, 127];
+        bytes.extend_from_slice(&(MAX_MESSAGE_SIZE as u64 + 1).to_be_bytes());
+        let error = read_all(&bytes, false).await.unwrap_err();
+        assert_eq!(error.to_string(), "message is too large");
+
+        // 1つずつは上限以内でも、結合すると上限を超える
+        let half = vec![0u8; MAX_MESSAGE_SIZE / 2 + 1];
+        let mut bytes = frame(false, OPCODE_BINARY, &half, None);
+        bytes.extend(frame(true, OPCODE_CONTINUATION, &half, None));
+        let error = read_all(&bytes, false).await.unwrap_err();
+        assert_eq!(error.to_string(), "message is too large");
+
+        let exact = vec![0u8; MAX_MESSAGE_SIZE];
+        let messages = read_all(&frame(true, OPCODE_BINARY, &exact, None), false).await.unwrap();
+        assert!(matches!(&messages[..], [Message::Binary(data)] if data.len() == MAX_MESSAGE_SIZE));
+    }
+
+    #[test]
+    fn computes_accept_key() {
+        // RFC 6455 1.3 の例
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+}
@@ -0,0 +1,17 @@
+CREATE TABLE package_inventories (
+    server_id TEXT PRIMARY KEY NOT NULL,
+    manager TEXT NOT NULL,
+    refreshed_at TEXT NOT NULL
+);
+
+CREATE TABLE server_packages (
+    server_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    version TEXT NOT NULL,
+    architecture TEXT,
+    source TEXT,
+    installed_at INTEGER
+);
+
+CREATE INDEX idx_server_packages_server_id ON server_packages (server_id);
+CREATE INDEX idx_server_packages_name ON server_packages (name, version);
@@ -0,0 +1,7 @@
+CREATE TABLE package_update_summaries (
+    server_id TEXT PRIMARY KEY NOT NULL,
+    manager TEXT NOT NULL,
+    pending_count INTEGER NOT NULL,
+    security_count INTEGER NOT NULL,
+    refreshed_at TEXT NOT NULL
+);
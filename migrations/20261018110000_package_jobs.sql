@@ -0,0 +1,20 @@
+CREATE TABLE package_jobs (
+    id TEXT PRIMARY KEY NOT NULL,
+    action TEXT NOT NULL,
+    packages TEXT NOT NULL,
+    created_by TEXT NOT NULL,
+    status TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    finished_at TEXT
+);
+
+CREATE TABLE package_job_targets (
+    job_id TEXT NOT NULL,
+    server_id TEXT NOT NULL,
+    status TEXT NOT NULL,
+    exit_code INTEGER,
+    log TEXT NOT NULL DEFAULT '',
+    started_at TEXT,
+    finished_at TEXT,
+    PRIMARY KEY (job_id, server_id)
+);
@@ -0,0 +1,8 @@
+CREATE TABLE watched_services (
+    server_id TEXT NOT NULL,
+    unit_name TEXT NOT NULL,
+    active_state TEXT,
+    sub_state TEXT,
+    checked_at TEXT,
+    PRIMARY KEY (server_id, unit_name)
+);
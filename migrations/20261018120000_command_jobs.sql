@@ -0,0 +1,23 @@
+CREATE TABLE command_jobs (
+    id TEXT PRIMARY KEY NOT NULL,
+    command TEXT NOT NULL,
+    tag TEXT,
+    timeout_secs INTEGER NOT NULL,
+    created_by TEXT NOT NULL,
+    status TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    finished_at TEXT
+);
+
+CREATE TABLE command_job_targets (
+    job_id TEXT NOT NULL,
+    server_id TEXT NOT NULL,
+    status TEXT NOT NULL,
+    exit_code INTEGER,
+    log TEXT NOT NULL DEFAULT '',
+    started_at TEXT,
+    finished_at TEXT,
+    PRIMARY KEY (job_id, server_id)
+);
+
+CREATE INDEX idx_command_jobs_created_at ON command_jobs (created_at);
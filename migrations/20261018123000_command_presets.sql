@@ -0,0 +1,11 @@
+CREATE TABLE command_presets (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL UNIQUE,
+    description TEXT,
+    command TEXT NOT NULL,
+    timeout_secs INTEGER,
+    tag TEXT,
+    parameters TEXT NOT NULL DEFAULT '[]',
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
@@ -0,0 +1,12 @@
+CREATE TABLE auth_profiles (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL UNIQUE,
+    username TEXT NOT NULL,
+    auth_method TEXT NOT NULL,
+    password TEXT,
+    private_key TEXT,
+    passphrase TEXT,
+    host_key_fingerprint TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
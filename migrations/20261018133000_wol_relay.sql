@@ -0,0 +1 @@
+ALTER TABLE servers ADD COLUMN wol_relay_server_id TEXT;
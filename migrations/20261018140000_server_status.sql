@@ -0,0 +1,20 @@
+CREATE TABLE server_status (
+    server_id TEXT PRIMARY KEY NOT NULL,
+    status TEXT NOT NULL,
+    last_seen_at TEXT,
+    changed_at TEXT NOT NULL,
+    checked_at TEXT NOT NULL,
+    latency_ms INTEGER,
+    pending_status TEXT,
+    pending_count INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE server_status_transitions (
+    id TEXT PRIMARY KEY NOT NULL,
+    server_id TEXT NOT NULL,
+    from_status TEXT,
+    to_status TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX idx_server_status_transitions_server_id ON server_status_transitions (server_id, created_at);
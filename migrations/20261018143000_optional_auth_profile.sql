@@ -0,0 +1,31 @@
+-- エージェントだけで管理するサーバーは認証プロファイルを持たないので、NULL を許すように作り直す
+CREATE TABLE servers_new (
+    id TEXT PRIMARY KEY NOT NULL,
+    hostname TEXT NOT NULL,
+    ip_address TEXT NOT NULL,
+    os_type TEXT NOT NULL,
+    tags TEXT,
+    auth_profile_id TEXT,
+    port INTEGER NOT NULL,
+    bastion_server_id TEXT,
+    wol_mac_address TEXT,
+    wol_relay_server_id TEXT
+);
+
+-- 存在しないプロファイルを指している値（プロファイル導入前の値など）は消す
+INSERT INTO servers_new (id, hostname, ip_address, os_type, tags, auth_profile_id, port, bastion_server_id, wol_mac_address, wol_relay_server_id)
+SELECT
+    id,
+    hostname,
+    ip_address,
+    os_type,
+    tags,
+    CASE WHEN auth_profile_id IN (SELECT id FROM auth_profiles) THEN auth_profile_id END,
+    port,
+    bastion_server_id,
+    wol_mac_address,
+    wol_relay_server_id
+FROM servers;
+
+DROP TABLE servers;
+ALTER TABLE servers_new RENAME TO servers;
@@ -25,10 +25,11 @@ interface ServerInfo {
     ip_address: string,
     os_type: string,
     tags: string | null,
-    auth_profile_id: string,
+    auth_profile_id: string | null,
     port: number,
     bastion_server_id: string | null,
     wol_mac_address: string | null,
+    wol_relay_server_id: string | null,
 }
 
 function Servers() {
@@ -34,10 +34,18 @@ interface ServerInfo {
     ip_address: string,
     os_type: string,
     tags: string | null,
-    auth_profile_id: string,
+    auth_profile_id: string | null,
     port: number,
     bastion_server_id: string | null,
     wol_mac_address: string | null,
+    wol_relay_server_id: string | null,
+}
+
+interface AuthProfile {
+    id: string,
+    name: string,
+    username: string,
+    auth_method: string,
 }
 
 interface DataTableProps<TData, TValue> {
@@ -120,6 +128,7 @@ export function ServerList() {
     const [loading, setLoading] = useState(true);
     const [error, setError] = useState<string | null>(null);
     const [open, setOpen] = useState(false);
+    const [profiles, setProfiles] = useState<AuthProfile[]>([]);
 
     const fetchServerInformation = async () => {
         try {
@@ -141,6 +150,16 @@ export function ServerList() {
         fetchServerInformation();
     }, []);
 
+    useEffect(() => {
+        if (!open) {
+            return;
+        }
+        fetch('/api/v1/auth-profiles')
+            .then((response) => response.ok ? response.json() : [])
+            .then((data: AuthProfile[]) => setProfiles(data))
+            .catch((error) => console.error('Failed to fetch auth profiles:', error));
+    }, [open]);
+
     const handleSubmit = async (event: React.FormEvent<HTMLFormElement>) => {
         event.preventDefault();
         const formData = new FormData(event.currentTarget);
@@ -149,6 +168,7 @@ export function ServerList() {
         const payload = {
             ...data,
             port: Number(data.port),
+            auth_profile_id: data.auth_profile_id || null,
         };
 
         try {
@@ -208,6 +228,21 @@ export function ServerList() {
                                     <Label htmlFor="port">SSH port</Label>
                                     <Input id="port" name="port" type="number" defaultValue="22" required />
                                 </div>
+                                <div className="grid gap-3">
+                                    <Label htmlFor="auth-profile">Auth profile</Label>
+                                    <select
+                                        id="auth-profile"
+                                        name="auth_profile_id"
+                                        className="border-input h-9 w-full rounded-md border bg-transparent px-3 py-1 text-base shadow-xs md:text-sm"
+                                    >
+                                        <option value="">None (agent only)</option>
+                                        {profiles.map((profile) => (
+                                            <option key={profile.id} value={profile.id}>
+                                                {profile.name} ({profile.username})
+                                            </option>
+                                        ))}
+                                    </select>
+                                </div>
                             </div>
                             <DialogFooter>
                                 <DialogClose asChild>